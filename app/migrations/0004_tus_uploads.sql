@@ -0,0 +1,17 @@
+-- In progress resumable (tus) uploads.
+-- The bytes received so far live in files_dir/tmp_upload_{id},
+-- so the current offset is just the size of that file.
+CREATE TABLE IF NOT EXISTS uploads (
+    id              UUID PRIMARY KEY NOT NULL,
+    user_id         BIGINT NOT NULL,
+    -- Directory the file goes in once the upload is done
+    vpath           TEXT NOT NULL,
+    file_name       TEXT NOT NULL,
+    -- Total size declared by the client
+    upload_length   BIGINT NOT NULL,
+    created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_uploads_user ON uploads(user_id);
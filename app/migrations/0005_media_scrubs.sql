@@ -0,0 +1,16 @@
+-- Results of the background scrubber re-hashing stored media,
+-- one row per media that has been checked at least once
+CREATE TABLE IF NOT EXISTS media_scrubs (
+    media_id BIGINT PRIMARY KEY NOT NULL,
+    checked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- Last time the blob matched its hash, NULL if it never has
+    verified_at TIMESTAMP,
+    -- What the blob hashed to on the last check, if it didn't match
+    actual_hash TEXT,
+    -- Why the blob couldn't be read on the last check
+    error TEXT,
+
+    FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_media_scrubs_checked_at ON media_scrubs(checked_at);
@@ -0,0 +1,10 @@
+-- Files can be uploaded with an expiry, after which the reaper deletes them.
+-- media.expiring_time follows along: it's when the last file pointing
+-- at the media expires, or NULL if any of them never does.
+ALTER TABLE sfiles ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_sfiles_expires_at ON sfiles(expires_at)
+WHERE expires_at IS NOT NULL;
+
+-- Carried over to the file once a resumable upload finishes
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP;
@@ -0,0 +1,2 @@
+-- How many bytes a user can store, NULL for the server default
+ALTER TABLE users ADD COLUMN IF NOT EXISTS quota_bytes BIGINT;
@@ -0,0 +1,7 @@
+-- Deleted files go to their owner's trash. They lose their directory entry,
+-- but keep their media until the trash is purged.
+ALTER TABLE sfiles ADD COLUMN IF NOT EXISTS trashed_at TIMESTAMP;
+ALTER TABLE sfiles ADD COLUMN IF NOT EXISTS trashed_path TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_sfiles_trashed ON sfiles(user_id, trashed_at)
+WHERE trashed_at IS NOT NULL;
@@ -0,0 +1,24 @@
+-- Every media a file has pointed at, oldest first. The newest row is the
+-- file's current media; uploading to an existing path or rolling back adds one.
+CREATE TABLE IF NOT EXISTS sfile_versions (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    sfile_id BIGINT NOT NULL,
+    media_id BIGINT NOT NULL,
+    -- NULL once the uploader's account is gone
+    uploaded_by BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    FOREIGN KEY (sfile_id) REFERENCES sfiles(id) ON DELETE CASCADE,
+    FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
+    FOREIGN KEY (uploaded_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sfile_versions_sfile ON sfile_versions(sfile_id, id);
+CREATE INDEX IF NOT EXISTS idx_sfile_versions_media ON sfile_versions(media_id);
+
+-- Files from before versioning start out with their current media
+INSERT INTO sfile_versions (sfile_id, media_id, uploaded_by, created_at)
+SELECT s.id, s.media_id, s.user_id, s.modified_at
+FROM sfiles s
+WHERE s.media_id IS NOT NULL
+AND NOT EXISTS (SELECT 1 FROM sfile_versions v WHERE v.sfile_id = s.id);
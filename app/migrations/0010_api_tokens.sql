@@ -0,0 +1,18 @@
+-- Long-lived tokens for scripts and integrations. Only a hash of the token
+-- is kept, the token itself is shown once when it's created.
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    user_id BIGINT NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL, -- hex SHA-256
+    -- Any of 'read', 'write', 'delete', 'share', 'admin'
+    scopes TEXT[] NOT NULL,
+    -- e.g. 'root/backups/', the only files the token can touch
+    path_prefix TEXT,
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used TIMESTAMP,
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE (user_id, name)
+);
@@ -64,6 +64,7 @@ pub fn derive_ws_out_event(input: TokenStream) -> TokenStream {
 /// }
 /// ```
 #[proc_macro_attribute]
+#[allow(non_snake_case)]
 pub fn WsIncomingEvent(_args: TokenStream, input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let struct_name = &input.ident;
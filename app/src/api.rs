@@ -1,18 +1,30 @@
 use axum::body::to_bytes;
 use axum::{
-    body::Body,
-    http::{Method, Request, StatusCode},
+    body::{Body, Bytes},
+    http::{HeaderMap, Method, Request, StatusCode},
     Router,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use reqwest;
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use sqlx::PgPool;
 use std::convert::Infallible;
 use std::sync::Arc;
 use thiserror::Error;
 use tower::Service;
-
-use crate::server::{create_server, models::auth::*, models::files::SFile};
+use uuid::Uuid;
+
+use crate::server::{
+    create_server,
+    models::auth::*,
+    models::files::{
+        ConflictPolicy, FileVersion, HashUploadResponse, SFile, ScrubReport, StorageUsage,
+        TrashedFile, UploadResult,
+    },
+    web::archive::ArchiveFormat,
+    web::handlers::{files::TUS_VERSION, trash::PurgeResult},
+};
 
 #[derive(Debug, Error)]
 pub enum ApiError {
@@ -69,6 +81,17 @@ pub struct MoveFileRequest {
     pub to: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct HashUploadRequest {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ChangeVisibilityRequest {
     pub path: String,
@@ -79,7 +102,7 @@ pub struct ChangeVisibilityRequest {
 pub struct PermissionOperation {
     pub target_user_id: u64,
     pub relationship: String, // "owner", "editor", "viewer"
-    pub action: String, // "grant" or "revoke"
+    pub action: String,       // "grant" or "revoke"
 }
 
 #[derive(Debug, Serialize)]
@@ -455,6 +478,226 @@ impl ApiClient {
         }
     }
 
+    /// Get a file as a stream of chunks, along with its size if the server sent one
+    /// (uses stored session if available)
+    pub async fn get_file_stream(
+        &self,
+        path: &str,
+        for_user_id: Option<i64>,
+    ) -> Result<(Option<u64>, BoxStream<'static, Result<Bytes, ApiError>>), ApiError> {
+        let url = if let Some(user_id) = for_user_id {
+            format!("/files/{path}?u={user_id}")
+        } else {
+            format!("/files/{path}")
+        };
+
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let mut request = client.get(format!("{base_url}{url}"));
+
+                if let Some(session) = session_id {
+                    request = request.header("Authorization", format!("Bearer {session}"));
+                }
+
+                let response = request.send().await?;
+
+                if response.status().is_success() {
+                    let size = response.content_length();
+                    Ok((
+                        size,
+                        response.bytes_stream().map_err(ApiError::from).boxed(),
+                    ))
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let mut request_builder = Request::builder().method(Method::GET).uri(url);
+
+                if let Some(session) = session_id {
+                    request_builder =
+                        request_builder.header("Authorization", format!("Bearer {session}"));
+                }
+
+                let request = request_builder.body(Body::empty()).unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                if response.status().is_success() {
+                    let size = response
+                        .headers()
+                        .get("content-length")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse().ok());
+                    let stream = response.into_body().into_data_stream();
+                    Ok((size, stream.map_err(ApiError::from).boxed()))
+                } else {
+                    let status = response.status();
+                    let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// Get part of a file by sending a Range header (uses stored session if available).
+    /// Returns the status code (206, 200 or 416), the response headers and the body
+    pub async fn get_file_range(
+        &self,
+        path: &str,
+        for_user_id: Option<i64>,
+        range: &str,
+    ) -> Result<(StatusCode, HeaderMap, Vec<u8>), ApiError> {
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let url = if let Some(user_id) = for_user_id {
+                    format!("{base_url}/files/{path}?u={user_id}")
+                } else {
+                    format!("{base_url}/files/{path}")
+                };
+                let mut request = client.get(&url).header("Range", range);
+
+                if let Some(session) = session_id {
+                    request = request.header("Authorization", format!("Bearer {session}"));
+                }
+
+                let response = request.send().await?;
+
+                let status = response.status();
+                if status.is_success() || status == StatusCode::RANGE_NOT_SATISFIABLE {
+                    let headers = response.headers().clone();
+                    let bytes = response.bytes().await?;
+                    Ok((status, headers, bytes.to_vec()))
+                } else {
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let url = if let Some(user_id) = for_user_id {
+                    format!("/files/{path}?u={user_id}")
+                } else {
+                    format!("/files/{path}")
+                };
+                let mut request_builder = Request::builder()
+                    .method(Method::GET)
+                    .uri(url)
+                    .header("Range", range);
+
+                if let Some(session) = session_id {
+                    request_builder =
+                        request_builder.header("Authorization", format!("Bearer {session}"));
+                }
+
+                let request = request_builder.body(Body::empty()).unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                let status = response.status();
+                if status.is_success() || status == StatusCode::RANGE_NOT_SATISFIABLE {
+                    let headers = response.headers().clone();
+                    let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                    Ok((status, headers, body_bytes.to_vec()))
+                } else {
+                    let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// Get part of the HLS stream of a video (uses stored session if available).
+    /// `resource` is the playlist (`index.m3u8`), the init segment or a media segment
+    pub async fn get_stream(
+        &self,
+        path: &str,
+        resource: &str,
+        for_user_id: Option<i64>,
+    ) -> Result<Vec<u8>, ApiError> {
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let url = if let Some(user_id) = for_user_id {
+                    format!("{base_url}/stream/{path}/{resource}?u={user_id}")
+                } else {
+                    format!("{base_url}/stream/{path}/{resource}")
+                };
+                let mut request = client.get(&url);
+
+                if let Some(session) = session_id {
+                    request = request.header("Authorization", format!("Bearer {session}"));
+                }
+
+                let response = request.send().await?;
+
+                if response.status().is_success() {
+                    let bytes = response.bytes().await?;
+                    Ok(bytes.to_vec())
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let url = if let Some(user_id) = for_user_id {
+                    format!("/stream/{path}/{resource}?u={user_id}")
+                } else {
+                    format!("/stream/{path}/{resource}")
+                };
+                let mut request_builder = Request::builder().method(Method::GET).uri(url);
+
+                if let Some(session) = session_id {
+                    request_builder =
+                        request_builder.header("Authorization", format!("Bearer {session}"));
+                }
+
+                let request = request_builder.body(Body::empty()).unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                let status = response.status();
+                let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                if status.is_success() {
+                    Ok(body_bytes.to_vec())
+                } else {
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
     /// List directory contents (uses stored session if available)
     pub async fn list_directory(
         &self,
@@ -688,12 +931,75 @@ impl ApiClient {
         filename: &str,
         content: Vec<u8>,
     ) -> Result<Vec<SFile>, ApiError> {
+        self.upload_file_expiring(directory_path, filename, content, None)
+            .await
+    }
+
+    /// Upload a file that gets deleted once `expires` passes, a TTL like `1h`
+    /// or an RFC 3339 time (requires session to be set)
+    pub async fn upload_file_expiring(
+        &self,
+        directory_path: &str,
+        filename: &str,
+        content: Vec<u8>,
+        expires: Option<&str>,
+    ) -> Result<Vec<SFile>, ApiError> {
+        let params: Vec<(&str, &str)> = expires.map(|e| ("expires", e)).into_iter().collect();
+        let parts = vec![(filename.to_string(), content)];
+        Ok(self
+            .upload_parts(directory_path, parts, &params)
+            .await?
+            .files)
+    }
+
+    /// Upload several files in one request. Names can start with directories to put
+    /// the file in. If `atomic`, either every file is stored or none are, otherwise the
+    /// files that couldn't be stored are in the result's errors (requires session to be set)
+    pub async fn upload_files(
+        &self,
+        directory_path: &str,
+        files: Vec<(String, Vec<u8>)>,
+        atomic: bool,
+    ) -> Result<UploadResult, ApiError> {
+        let params: &[(&str, &str)] = if atomic { &[("atomic", "true")] } else { &[] };
+        self.upload_parts(directory_path, files, params).await
+    }
+
+    /// Upload a zip, tar or tar.gz file and unpack it into the directory.
+    /// Returns the files and directories created (requires session to be set)
+    pub async fn upload_and_extract(
+        &self,
+        directory_path: &str,
+        filename: &str,
+        content: Vec<u8>,
+    ) -> Result<Vec<SFile>, ApiError> {
+        let parts = vec![(filename.to_string(), content)];
+        Ok(self
+            .upload_parts(directory_path, parts, &[("extract", "true")])
+            .await?
+            .files)
+    }
+
+    async fn upload_parts(
+        &self,
+        directory_path: &str,
+        parts: Vec<(String, Vec<u8>)>,
+        params: &[(&str, &str)],
+    ) -> Result<UploadResult, ApiError> {
         // Ensure directory path ends with /
         let dir_path = if directory_path.ends_with('/') {
             directory_path.to_string()
         } else {
             format!("{directory_path}/")
         };
+        let query = if params.is_empty() {
+            String::new()
+        } else {
+            let encoded = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(params)
+                .finish();
+            format!("?{encoded}")
+        };
 
         match self {
             ApiClient::Http {
@@ -706,14 +1012,16 @@ impl ApiClient {
                     body: "No session set. Call set_session() first.".to_string(),
                 })?;
 
-                let url = format!("{base_url}/files/{dir_path}");
-
-                let part = reqwest::multipart::Part::bytes(content)
-                    .file_name(filename.to_string())
-                    .mime_str("application/octet-stream")
-                    .map_err(ApiError::Request)?;
+                let url = format!("{base_url}/files/{dir_path}{query}");
 
-                let form = reqwest::multipart::Form::new().part(filename.to_string(), part);
+                let mut form = reqwest::multipart::Form::new();
+                for (filename, content) in parts {
+                    let part = reqwest::multipart::Part::bytes(content)
+                        .file_name(filename.clone())
+                        .mime_str("application/octet-stream")
+                        .map_err(ApiError::Request)?;
+                    form = form.part(filename, part);
+                }
 
                 let response = client
                     .post(&url)
@@ -723,8 +1031,8 @@ impl ApiClient {
                     .await?;
 
                 if response.status().is_success() {
-                    let files = response.json::<Vec<SFile>>().await?;
-                    Ok(files)
+                    let result = response.json::<UploadResult>().await?;
+                    Ok(result)
                 } else {
                     let status = response.status();
                     let body = response.text().await.unwrap_or_default();
@@ -742,19 +1050,21 @@ impl ApiClient {
                 let boundary = "----ApiClientBoundary";
                 let mut body_content = Vec::new();
 
-                // Start boundary
-                body_content.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
-                body_content.extend_from_slice(format!("Content-Disposition: form-data; name=\"{filename}\"; filename=\"{filename}\"\r\n").as_bytes());
-                body_content.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
-                body_content.extend_from_slice(&content);
-                body_content.extend_from_slice(b"\r\n");
+                for (filename, content) in parts {
+                    body_content.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+                    body_content.extend_from_slice(format!("Content-Disposition: form-data; name=\"{filename}\"; filename=\"{filename}\"\r\n").as_bytes());
+                    body_content
+                        .extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+                    body_content.extend_from_slice(&content);
+                    body_content.extend_from_slice(b"\r\n");
+                }
 
                 // End boundary
                 body_content.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
 
                 let request = Request::builder()
                     .method(Method::POST)
-                    .uri(format!("/files/{dir_path}"))
+                    .uri(format!("/files/{dir_path}{query}"))
                     .header("Authorization", format!("Bearer {session}"))
                     .header(
                         "Content-Type",
@@ -772,8 +1082,8 @@ impl ApiClient {
 
                 if response.status().is_success() {
                     let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
-                    let files: Vec<SFile> = serde_json::from_slice(&body_bytes)?;
-                    Ok(files)
+                    let result: UploadResult = serde_json::from_slice(&body_bytes)?;
+                    Ok(result)
                 } else {
                     let status = response.status();
                     let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
@@ -784,13 +1094,104 @@ impl ApiClient {
         }
     }
 
-    /// Move/rename a file (requires session to be set)
-    pub async fn move_file(&self, from_path: &str, to_path: &str) -> Result<SFile, ApiError> {
-        let move_request = MoveFileRequest {
-            from: from_path.to_string(),
-            to: to_path.to_string(),
-        };
+    /// Create a directory along with any parents it's missing. Returns the
+    /// directories on the way, which may have existed already (requires session to be set)
+    pub async fn make_dirs(&self, path: &str) -> Result<Vec<SFile>, ApiError> {
+        let dir_path = format!("{}/", path.trim_end_matches('/'));
+        let result: UploadResult = self
+            .json_request(Method::POST, &format!("/files/{dir_path}"), None)
+            .await?;
+        Ok(result.files)
+    }
+
+    /// Ask the server which tus version and extensions it supports.
+    /// Returns the response headers (Tus-Version, Tus-Extension, Tus-Max-Size)
+    pub async fn upload_capabilities(&self) -> Result<HeaderMap, ApiError> {
+        self.tus_request(Method::OPTIONS, "/uploads", &[], Vec::new())
+            .await
+    }
+
+    /// Start a resumable (tus) upload of `length` bytes (requires session to be set).
+    /// Returns the upload URL from the Location header, relative to the server
+    pub async fn create_upload(
+        &self,
+        directory_path: &str,
+        filename: &str,
+        length: u64,
+    ) -> Result<String, ApiError> {
+        let metadata = format!(
+            "filename {},path {}",
+            BASE64.encode(filename),
+            BASE64.encode(directory_path)
+        );
+        let headers = self
+            .tus_request(
+                Method::POST,
+                "/uploads",
+                &[
+                    ("Upload-Length", length.to_string()),
+                    ("Upload-Metadata", metadata),
+                ],
+                Vec::new(),
+            )
+            .await?;
+
+        Ok(headers
+            .get("location")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    /// Get how many bytes of a resumable upload the server has (requires session to be set)
+    pub async fn upload_offset(&self, location: &str) -> Result<u64, ApiError> {
+        let headers = self
+            .tus_request(Method::HEAD, location, &[], Vec::new())
+            .await?;
+        Ok(header_u64(&headers, "upload-offset"))
+    }
+
+    /// Send the next chunk of a resumable upload, starting at `offset` (requires session to be set).
+    /// Returns the new offset
+    pub async fn append_to_upload(
+        &self,
+        location: &str,
+        offset: u64,
+        chunk: Vec<u8>,
+    ) -> Result<u64, ApiError> {
+        let headers = self
+            .tus_request(
+                Method::PATCH,
+                location,
+                &[
+                    ("Upload-Offset", offset.to_string()),
+                    (
+                        "Content-Type",
+                        "application/offset+octet-stream".to_string(),
+                    ),
+                ],
+                chunk,
+            )
+            .await?;
+        Ok(header_u64(&headers, "upload-offset"))
+    }
+
+    /// Cancel a resumable upload (requires session to be set)
+    pub async fn terminate_upload(&self, location: &str) -> Result<(), ApiError> {
+        self.tus_request(Method::DELETE, location, &[], Vec::new())
+            .await
+            .map(|_| ())
+    }
 
+    /// Sends a tus request and returns the response headers, which is where
+    /// tus puts everything
+    async fn tus_request(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> Result<HeaderMap, ApiError> {
         match self {
             ApiClient::Http {
                 client,
@@ -802,17 +1203,19 @@ impl ApiClient {
                     body: "No session set. Call set_session() first.".to_string(),
                 })?;
 
-                let url = format!("{base_url}/files");
-                let response = client
-                    .put(&url)
+                let mut request = client
+                    .request(method, format!("{base_url}{uri}"))
                     .header("Authorization", format!("Bearer {session}"))
-                    .json(&move_request)
-                    .send()
-                    .await?;
+                    .header("Tus-Resumable", TUS_VERSION)
+                    .body(body);
+                for (name, value) in headers {
+                    request = request.header(*name, value);
+                }
+
+                let response = request.send().await?;
 
                 if response.status().is_success() {
-                    let file = response.json::<SFile>().await?;
-                    Ok(file)
+                    Ok(response.headers().clone())
                 } else {
                     let status = response.status();
                     let body = response.text().await.unwrap_or_default();
@@ -825,14 +1228,322 @@ impl ApiClient {
                     body: "No session set. Call set_session() first.".to_string(),
                 })?;
 
-                let body = serde_json::to_string(&move_request)?;
-                let request = Request::builder()
-                    .method(Method::PUT)
-                    .uri("/files")
+                let mut request_builder = Request::builder()
+                    .method(method)
+                    .uri(uri)
                     .header("Authorization", format!("Bearer {session}"))
-                    .header("content-type", "application/json")
-                    .body(Body::from(body))
-                    .unwrap();
+                    .header("Tus-Resumable", TUS_VERSION);
+                for (name, value) in headers {
+                    request_builder = request_builder.header(*name, value);
+                }
+
+                let request = request_builder.body(Body::from(body)).unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                let status = response.status();
+                if status.is_success() {
+                    Ok(response.headers().clone())
+                } else {
+                    let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// Send an authenticated request with an optional JSON body, and parse the JSON response
+    async fn json_request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, ApiError> {
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let mut request = client
+                    .request(method, format!("{base_url}{uri}"))
+                    .header("Authorization", format!("Bearer {session}"));
+                if let Some(body) = &body {
+                    request = request.json(body);
+                }
+
+                let response = request.send().await?;
+
+                if response.status().is_success() {
+                    Ok(response.json::<T>().await?)
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let request_builder = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("Authorization", format!("Bearer {session}"));
+                let request = match body {
+                    Some(body) => request_builder
+                        .header("content-type", "application/json")
+                        .body(Body::from(serde_json::to_string(&body)?)),
+                    None => request_builder.body(Body::empty()),
+                }
+                .unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                let status = response.status();
+                let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                if status.is_success() {
+                    Ok(serde_json::from_slice(&body_bytes)?)
+                } else {
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// List the files in the trash, most recently deleted first (requires session to be set)
+    pub async fn list_trash(&self) -> Result<Vec<TrashedFile>, ApiError> {
+        self.json_request(Method::GET, "/trash", None).await
+    }
+
+    /// Put a file from the trash back where it was, or at `to` (requires session to be set)
+    pub async fn restore_from_trash(&self, id: u64, to: Option<&str>) -> Result<SFile, ApiError> {
+        self.json_request(
+            Method::POST,
+            &format!("/trash/{id}/restore"),
+            Some(serde_json::json!({ "to": to })),
+        )
+        .await
+    }
+
+    /// Delete a file in the trash for good, or everything in it if `id` is None
+    /// (requires session to be set). Returns how many files were deleted
+    pub async fn purge_trash(&self, id: Option<u64>) -> Result<u64, ApiError> {
+        let uri = match id {
+            Some(id) => format!("/trash/{id}"),
+            None => "/trash".to_string(),
+        };
+        let result: PurgeResult = self.json_request(Method::DELETE, &uri, None).await?;
+        Ok(result.purged)
+    }
+
+    /// List the versions of a file, newest first (requires session to be set)
+    pub async fn list_versions(&self, path: &str) -> Result<Vec<FileVersion>, ApiError> {
+        self.json_request(Method::GET, &format!("/versions/{path}"), None)
+            .await
+    }
+
+    /// Get the contents a file had at one of its versions (uses stored session if available)
+    pub async fn get_file_version(&self, path: &str, version: u64) -> Result<Vec<u8>, ApiError> {
+        self.get_file(&format!("{path}?version={version}"), None)
+            .await
+    }
+
+    /// Download a directory and everything readable in it as an archive
+    /// (uses stored session if available). `path` must end with a '/'
+    pub async fn download_archive(
+        &self,
+        path: &str,
+        format: ArchiveFormat,
+        for_user_id: Option<i64>,
+    ) -> Result<Vec<u8>, ApiError> {
+        let mut path = format!("{path}?archive={}", format.extension());
+        if let Some(user_id) = for_user_id {
+            path.push_str(&format!("&u={user_id}"));
+        }
+        self.get_file(&path, None).await
+    }
+
+    /// Give a file the contents of one of its older versions, which adds a new version
+    /// (requires session to be set)
+    pub async fn rollback(&self, path: &str, version: u64) -> Result<SFile, ApiError> {
+        self.json_request(
+            Method::POST,
+            &format!("/versions/{path}"),
+            Some(serde_json::json!({ "version": version })),
+        )
+        .await
+    }
+
+    /// Create a file from media the server already has, by its SHA-256 (requires session to be set).
+    /// Pass `answer` as (challenge id, answer) to answer a challenge from a previous call
+    pub async fn upload_by_hash(
+        &self,
+        path: &str,
+        hash: &str,
+        size: u64,
+        answer: Option<(Uuid, String)>,
+    ) -> Result<HashUploadResponse, ApiError> {
+        let (challenge_id, answer) = answer.unzip();
+        let hash_request = HashUploadRequest {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            size,
+            challenge_id,
+            answer,
+        };
+
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let url = format!("{base_url}/files/by-hash");
+                let response = client
+                    .post(&url)
+                    .header("Authorization", format!("Bearer {session}"))
+                    .json(&hash_request)
+                    .send()
+                    .await?;
+
+                if response.status().is_success() {
+                    let result = response.json::<HashUploadResponse>().await?;
+                    Ok(result)
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let body = serde_json::to_string(&hash_request)?;
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri("/files/by-hash")
+                    .header("Authorization", format!("Bearer {session}"))
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                if response.status().is_success() {
+                    let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                    let result: HashUploadResponse = serde_json::from_slice(&body_bytes)?;
+                    Ok(result)
+                } else {
+                    let status = response.status();
+                    let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// Copy a file or directory tree to `to_path`, the path of the copy (requires session to be set)
+    pub async fn copy_file(
+        &self,
+        from_path: &str,
+        to_path: &str,
+        on_conflict: ConflictPolicy,
+    ) -> Result<SFile, ApiError> {
+        self.json_request(
+            Method::POST,
+            "/files/copy",
+            Some(serde_json::json!({
+                "from": from_path,
+                "to": to_path,
+                "on_conflict": on_conflict,
+            })),
+        )
+        .await
+    }
+
+    /// Move/rename a file (requires session to be set)
+    pub async fn move_file(&self, from_path: &str, to_path: &str) -> Result<SFile, ApiError> {
+        let move_request = MoveFileRequest {
+            from: from_path.to_string(),
+            to: to_path.to_string(),
+        };
+
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let url = format!("{base_url}/files");
+                let response = client
+                    .put(&url)
+                    .header("Authorization", format!("Bearer {session}"))
+                    .json(&move_request)
+                    .send()
+                    .await?;
+
+                if response.status().is_success() {
+                    let file = response.json::<SFile>().await?;
+                    Ok(file)
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let body = serde_json::to_string(&move_request)?;
+                let request = Request::builder()
+                    .method(Method::PUT)
+                    .uri("/files")
+                    .header("Authorization", format!("Bearer {session}"))
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap();
 
                 let mut service = router.as_ref().clone();
                 let response = Service::<Request<Body>>::call(&mut service, request)
@@ -1044,4 +1755,159 @@ impl ApiClient {
         )
         .await
     }
+
+    /// What the integrity scrubber found (requires an admin session)
+    pub async fn scrub_report(&self) -> Result<ScrubReport, ApiError> {
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let url = format!("{base_url}/admin/scrub");
+                let response = client
+                    .get(&url)
+                    .header("Authorization", format!("Bearer {session}"))
+                    .send()
+                    .await?;
+
+                if response.status().is_success() {
+                    Ok(response.json::<ScrubReport>().await?)
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let request = Request::builder()
+                    .method(Method::GET)
+                    .uri("/admin/scrub")
+                    .header("Authorization", format!("Bearer {session}"))
+                    .body(Body::empty())
+                    .unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                let status = response.status();
+                let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                if status.is_success() {
+                    Ok(serde_json::from_slice(&body_bytes)?)
+                } else {
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// How much the user stores and their quota (requires session to be set)
+    pub async fn usage(&self) -> Result<StorageUsage, ApiError> {
+        match self {
+            ApiClient::Http {
+                client,
+                base_url,
+                session_id,
+            } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let url = format!("{base_url}/auth/me/usage");
+                let response = client
+                    .get(&url)
+                    .header("Authorization", format!("Bearer {session}"))
+                    .send()
+                    .await?;
+
+                if response.status().is_success() {
+                    Ok(response.json::<StorageUsage>().await?)
+                } else {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+            ApiClient::Local { router, session_id } => {
+                let session = session_id.as_ref().ok_or_else(|| ApiError::Http {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: "No session set. Call set_session() first.".to_string(),
+                })?;
+
+                let request = Request::builder()
+                    .method(Method::GET)
+                    .uri("/auth/me/usage")
+                    .header("Authorization", format!("Bearer {session}"))
+                    .body(Body::empty())
+                    .unwrap();
+
+                let mut service = router.as_ref().clone();
+                let response = Service::<Request<Body>>::call(&mut service, request)
+                    .await
+                    .map_err(|e| {
+                        ApiError::Service(Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                    })?;
+
+                let status = response.status();
+                let body_bytes = to_bytes(response.into_body(), usize::MAX).await?;
+                if status.is_success() {
+                    Ok(serde_json::from_slice(&body_bytes)?)
+                } else {
+                    let body = String::from_utf8_lossy(&body_bytes).to_string();
+                    Err(ApiError::Http { status, body })
+                }
+            }
+        }
+    }
+
+    /// Create an API token, which can be passed to set_session() like a session ID
+    /// (requires a session, not a token). The token is only ever returned here
+    pub async fn create_api_token(
+        &self,
+        request: &CreateTokenRequest,
+    ) -> Result<CreatedToken, ApiError> {
+        self.json_request(
+            Method::POST,
+            "/auth/tokens",
+            Some(serde_json::to_value(request)?),
+        )
+        .await
+    }
+
+    /// List the user's API tokens, newest first (requires a session, not a token)
+    pub async fn list_api_tokens(&self) -> Result<Vec<ApiTokenInfo>, ApiError> {
+        self.json_request(Method::GET, "/auth/tokens", None).await
+    }
+
+    /// Revoke an API token so it stops working (requires a session, not a token)
+    pub async fn revoke_api_token(&self, id: u64) -> Result<(), ApiError> {
+        let _: serde_json::Value = self
+            .json_request(Method::DELETE, &format!("/auth/tokens/{id}"), None)
+            .await?;
+        Ok(())
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> u64 {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
 }
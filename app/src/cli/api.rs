@@ -1,7 +0,0 @@
-use std::path::Path;
-
-use super::error::CliResult;
-
-pub async fn upload(upload_path: &Path) -> CliResult<String> {
-    todo!()
-}
@@ -0,0 +1,106 @@
+use inquire::{Password, PasswordDisplayMode, Text};
+
+use super::{api_client, profile};
+use crate::{
+    cli::error::{CliError, CliResult},
+    config::{YamlConfig, CLI_CONFIG},
+    server::models::auth::{LoginRequest, RegisterRequest},
+};
+
+pub async fn login(username: Option<String>) -> CliResult<()> {
+    let username = match username {
+        Some(username) => username,
+        None => prompt(Text::new("Username:").prompt())?,
+    };
+    let password = prompt(
+        Password::new("Password:")
+            .without_confirmation()
+            .with_display_mode(PasswordDisplayMode::Hidden)
+            .prompt(),
+    )?;
+
+    log_in(username, password).await
+}
+
+/// Registers an account and logs in with it.
+pub async fn register() -> CliResult<()> {
+    let username = prompt(Text::new("Username:").prompt())?;
+    let email = prompt(Text::new("Email:").prompt())?;
+    let password = prompt(
+        Password::new("Password:")
+            .with_display_mode(PasswordDisplayMode::Hidden)
+            .prompt(),
+    )?;
+
+    api_client()?
+        .register(RegisterRequest {
+            username: username.clone(),
+            email,
+            password: password.clone(),
+        })
+        .await?;
+
+    log_in(username, password).await
+}
+
+/// Ends the session on the server and forgets it.
+pub async fn logout() -> CliResult<()> {
+    if profile()?.session.is_none() {
+        return Err(CliError::NotLoggedIn);
+    }
+
+    // Forgotten either way, it may have expired already
+    let result = api_client()?.logout().await;
+    save_session(None)?;
+    result?;
+
+    println!("Logged out.");
+    Ok(())
+}
+
+pub async fn whoami() -> CliResult<()> {
+    if profile()?.session.is_none() {
+        return Err(CliError::NotLoggedIn);
+    }
+
+    let me = api_client()?.me().await?;
+    println!(
+        "{} (id {}) on {} ({})",
+        me["username"].as_str().unwrap_or_default(),
+        me["user_id"],
+        profile()?.server_url,
+        CLI_CONFIG.active_name()
+    );
+    Ok(())
+}
+
+async fn log_in(username: String, password: String) -> CliResult<()> {
+    let response = api_client()?
+        .login(LoginRequest {
+            username: username.clone(),
+            password,
+        })
+        .await?;
+    let session = response["session_id"]
+        .as_str()
+        .ok_or(CliError::NotLoggedIn)?;
+    save_session(Some(session.to_string()))?;
+
+    println!("Logged in as {username}.");
+    Ok(())
+}
+
+fn save_session(session: Option<String>) -> CliResult<()> {
+    let mut config = CLI_CONFIG.clone();
+    let name = config.active_name().to_string();
+    config
+        .profiles
+        .get_mut(&name)
+        .ok_or(CliError::NoProfile { name })?
+        .session = session;
+    Ok(config.save()?)
+}
+
+fn prompt<T>(result: Result<T, inquire::InquireError>) -> CliResult<T> {
+    result.map_err(|e| CliError::IoError { err: e.to_string() })
+}
@@ -0,0 +1,211 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use futures_util::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::StatusCode;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::{api_client, remote_path, server_url};
+use crate::{
+    api::{ApiClient, ApiError},
+    cli::error::CliResult,
+    server::models::files::SFile,
+};
+
+pub async fn ls(path: String, long: bool, json: bool) -> CliResult<()> {
+    let mut files = api_client()?
+        .list_directory(&remote_path(&path), None)
+        .await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&files).unwrap());
+        return Ok(());
+    }
+
+    files.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.top_level_name.cmp(&b.top_level_name))
+    });
+    for file in files {
+        let name = if file.is_dir {
+            format!("{}/", file.top_level_name)
+        } else {
+            file.top_level_name.clone()
+        };
+
+        if !long {
+            println!("{name}");
+            continue;
+        }
+
+        let expires = file
+            .expires_at
+            .map(|t| {
+                format!(
+                    "  (expires {})",
+                    t.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+                )
+            })
+            .unwrap_or_default();
+        println!(
+            "{}  {:<7}  {}  {name}{expires}",
+            if file.is_dir { 'd' } else { '-' },
+            if file.is_public { "public" } else { "private" },
+            file.modified_at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M"),
+        );
+    }
+
+    Ok(())
+}
+
+/// Downloads a file to `output`, or into the working directory under its own name.
+/// An `output` of `-` writes it to stdout.
+pub async fn get(path: String, output: Option<PathBuf>) -> CliResult<()> {
+    let (size, mut stream) = api_client()?
+        .get_file_stream(&remote_path(&path), None)
+        .await?;
+
+    let name = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let to_stdout = output.as_deref() == Some(Path::new("-"));
+    let mut writer: Box<dyn AsyncWrite + Unpin> = if to_stdout {
+        Box::new(tokio::io::stdout())
+    } else {
+        let output = match output {
+            Some(dir) if dir.is_dir() => dir.join(name),
+            Some(output) => output,
+            None => PathBuf::from(name),
+        };
+        Box::new(tokio::fs::File::create(output).await?)
+    };
+
+    let pb = if to_stdout {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(size.unwrap_or_default())
+    };
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+        .unwrap());
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        pb.inc(chunk.len() as u64);
+        writer.write_all(&chunk).await?;
+    }
+    writer.flush().await?;
+    pb.finish();
+
+    Ok(())
+}
+
+/// Moves files to the trash. The trash only takes files, so a directory goes one file at a time.
+pub async fn rm(paths: Vec<String>) -> CliResult<()> {
+    let client = api_client()?;
+    for path in paths {
+        let path = path.trim_end_matches('/');
+        match client.delete_file(&remote_path(path)).await {
+            Ok(()) => println!("Moved {path} to the trash."),
+            // Not a file, but it may be a directory
+            Err(e) if matches!(&e, ApiError::Http { status, .. } if *status == StatusCode::NOT_FOUND) =>
+            {
+                let files = files_under(&client, path).await?;
+                if files.is_empty() {
+                    return Err(e.into());
+                }
+                for file in files {
+                    client.delete_file(&remote_path(&file)).await?;
+                    println!("Moved {file} to the trash.");
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Every file under the remote directory `dir`, none if it was never uploaded.
+pub(super) async fn files_under(client: &ApiClient, dir: &str) -> Result<Vec<String>, ApiError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_string()];
+
+    while let Some(dir) = dirs.pop() {
+        let listing = match client.list_directory(&remote_path(&dir), None).await {
+            Ok(listing) => listing,
+            Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND => continue,
+            Err(e) => return Err(e),
+        };
+        for file in listing {
+            let path = format!("{dir}/{}", file.top_level_name);
+            if file.is_dir {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+pub async fn mv(from: String, to: String) -> CliResult<()> {
+    let file = api_client()?
+        .move_file(&remote_path(&from), &remote_path(&to))
+        .await?;
+    println!("Moved to {}", file.full_path);
+
+    Ok(())
+}
+
+pub async fn mkdir(path: String) -> CliResult<()> {
+    api_client()?.make_dirs(&remote_path(&path)).await?;
+    println!("Done.");
+
+    Ok(())
+}
+
+/// Makes a file public or private again. Public files get a link anyone can open.
+pub async fn publish(path: String, public: bool) -> CliResult<()> {
+    let file = api_client()?
+        .change_file_visibility(&remote_path(&path), public)
+        .await?;
+
+    if public {
+        println!("Anyone can open it at {}", public_link(&file)?);
+    } else {
+        println!("{} is private.", file.full_path);
+    }
+
+    Ok(())
+}
+
+pub async fn share(path: String, user_id: u64, role: String, revoke: bool) -> CliResult<()> {
+    let client = api_client()?;
+    let path = remote_path(&path);
+    if revoke {
+        client.revoke_file_permission(&path, user_id, &role).await?;
+        println!("User {user_id} is no longer a {role} of {path}.");
+    } else {
+        client.grant_file_permission(&path, user_id, &role).await?;
+        println!("User {user_id} is now a {role} of {path}.");
+    }
+
+    Ok(())
+}
+
+/// Where others can get a public file. Files are looked up in their owner's tree.
+pub(super) fn public_link(file: &SFile) -> CliResult<String> {
+    let link = format!("{}/files/{}", server_url()?, file.full_path);
+    Ok(match file.user_id {
+        Some(user_id) => format!("{link}?u={user_id}"),
+        None => link,
+    })
+}
@@ -1,2 +1,123 @@
+pub mod auth;
+pub mod files;
+pub mod paste;
+pub mod profile;
 pub mod server;
+pub mod shell;
+pub mod sync;
 pub mod upload;
+pub mod watch;
+
+use arboard::Clipboard;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Client, Url,
+};
+use tracing::error;
+
+use crate::{
+    api::ApiClient,
+    cli::error::{CliError, CliResult},
+    config::{cli::Profile, CLI_CONFIG},
+};
+
+/// The profile commands run against, the current one unless --profile or OCLOUD_PROFILE say otherwise.
+pub fn profile() -> CliResult<&'static Profile> {
+    CLI_CONFIG.active().ok_or_else(|| CliError::NoProfile {
+        name: CLI_CONFIG.active_name().to_string(),
+    })
+}
+
+/// The profile's server url, set with set-url.
+pub fn server_url() -> CliResult<&'static str> {
+    let server_url = &profile()?.server_url;
+    if let Err(e) = Url::parse(server_url) {
+        error!("Error: cloud url is invalid or does not exist. Use the set-url command to set a cloud url.");
+        return Err(e.into());
+    }
+
+    Ok(server_url)
+}
+
+/// A client for the profile's server, logged in if there's a session.
+pub fn api_client() -> CliResult<ApiClient> {
+    let mut client = ApiClient::new_http(server_url()?.to_string());
+    if let Some(session) = &profile()?.session {
+        client.set_session(session.clone());
+    }
+    Ok(client)
+}
+
+/// A plain HTTP client that sends the session with every request.
+pub fn http_client() -> Client {
+    let mut headers = HeaderMap::new();
+    if let Some(Ok(mut value)) = CLI_CONFIG
+        .active()
+        .and_then(|profile| profile.session.as_ref())
+        .map(|session| HeaderValue::from_str(&format!("Bearer {session}")))
+    {
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap_or_default()
+}
+
+/// Paths given to commands are relative to the root directory.
+pub fn remote_path(path: &str) -> String {
+    format!("root/{}", path.trim_start_matches('/'))
+}
+
+/// Set on the process [`copy_to_clipboard`] leaves holding the clipboard
+#[cfg(target_os = "linux")]
+pub const HOLD_CLIPBOARD: &str = "OCLOUD_HOLD_CLIPBOARD";
+
+/// Puts `text` on the clipboard. Fails without a display server to hold it.
+///
+/// On Linux the clipboard belongs to whoever set it, so it would empty once we
+/// exit unless a clipboard manager copies it first. A detached copy of this
+/// program keeps it until something else is copied.
+pub fn copy_to_clipboard(text: &str) -> CliResult<()> {
+    Clipboard::new()?.set_text(text)?;
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::{
+            io::Write,
+            os::unix::process::CommandExt,
+            process::{Command, Stdio},
+        };
+
+        // Its own process group, so Ctrl-C in the terminal doesn't take it along
+        let mut holder = Command::new(std::env::current_exe()?)
+            .env(HOLD_CLIPBOARD, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn()?;
+        if let Some(mut stdin) = holder.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        // Reaped when it's done if we're still running, like in watch
+        std::thread::spawn(move || holder.wait());
+    }
+
+    Ok(())
+}
+
+/// Run as the process [`copy_to_clipboard`] leaves behind: sets the text it's
+/// given on stdin and waits until something else takes the clipboard.
+#[cfg(target_os = "linux")]
+pub fn hold_clipboard() -> CliResult<()> {
+    use arboard::SetExtLinux;
+    use std::io::Read;
+
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text)?;
+    Clipboard::new()?.set().wait().text(text)?;
+    Ok(())
+}
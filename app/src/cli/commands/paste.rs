@@ -0,0 +1,70 @@
+use std::io::Cursor;
+
+use arboard::{Clipboard, ImageData};
+use chrono::Local;
+use image::{ImageFormat, RgbaImage};
+
+use super::upload;
+use crate::cli::error::{CliError, CliResult};
+
+/// Uploads what's on the clipboard, text as a .txt file and images as a .png.
+/// Returns the file's url, or its public link if `public`.
+pub async fn handler(
+    name: Option<String>,
+    dir: String,
+    expires: Option<String>,
+    public: bool,
+) -> CliResult<String> {
+    let mut clipboard = Clipboard::new()?;
+    let (contents, extension) = match clipboard.get_text() {
+        Ok(text) => (text.into_bytes(), "txt"),
+        Err(_) => (encode_png(clipboard.get_image()?)?, "png"),
+    };
+
+    let name = name.unwrap_or_else(|| {
+        format!(
+            "paste-{}.{extension}",
+            Local::now().format("%Y-%m-%d-%H%M%S")
+        )
+    });
+    upload::stream_handler(Cursor::new(contents), name, dir, expires, public).await
+}
+
+fn encode_png(image: ImageData) -> CliResult<Vec<u8>> {
+    let image = RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .ok_or(CliError::IoError {
+        err: "the clipboard image is malformed".into(),
+    })?;
+
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| CliError::IoError { err: e.to_string() })?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipboard_images_become_pngs() {
+        let pixels = [255, 0, 0, 255, 0, 0, 255, 128];
+        let png = encode_png(ImageData {
+            width: 2,
+            height: 1,
+            bytes: pixels.as_slice().into(),
+        })
+        .unwrap();
+
+        let decoded = image::load_from_memory_with_format(&png, ImageFormat::Png)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(decoded.dimensions(), (2, 1));
+        assert_eq!(decoded.into_raw(), pixels);
+    }
+}
@@ -0,0 +1,66 @@
+use super::super::error::{CliError, CliResult};
+use super::super::subcommands::ProfileCommand;
+use crate::config::{cli::Profile, YamlConfig, CLI_CONFIG};
+
+pub async fn handler(command: ProfileCommand) -> CliResult<()> {
+    let mut config = CLI_CONFIG.clone();
+
+    match command {
+        ProfileCommand::Add { name, url, r#use } => {
+            if config.profiles.contains_key(&name) {
+                return Err(CliError::ProfileExists { name });
+            }
+
+            config.profiles.insert(
+                name.clone(),
+                Profile {
+                    server_url: url.to_string().trim_matches('/').into(),
+                    session: None,
+                },
+            );
+            if r#use {
+                config.current = name.clone();
+            }
+            config.save()?;
+            println!("Added {name}.");
+        }
+        ProfileCommand::List => {
+            for (name, profile) in &config.profiles {
+                let marker = if name == config.active_name() {
+                    '*'
+                } else {
+                    ' '
+                };
+                let login = if profile.session.is_some() {
+                    ""
+                } else {
+                    " (logged out)"
+                };
+                println!("{marker} {name}  {}{login}", profile.server_url);
+            }
+        }
+        ProfileCommand::Use { name } => {
+            if !config.profiles.contains_key(&name) {
+                return Err(CliError::NoProfile { name });
+            }
+
+            config.current = name.clone();
+            config.save()?;
+            println!("Now using {name}.");
+        }
+        ProfileCommand::Remove { name } => {
+            if config.profiles.remove(&name).is_none() {
+                return Err(CliError::NoProfile { name });
+            }
+            if config.current == name {
+                // Leaving the current profile dangling would break every command
+                config.current = config.profiles.keys().next().cloned().unwrap_or_default();
+            }
+
+            config.save()?;
+            println!("Removed {name}.");
+        }
+    }
+
+    Ok(())
+}
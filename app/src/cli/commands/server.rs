@@ -5,6 +5,7 @@ use super::super::error::CliResult;
 use super::super::subcommands::ServerCommand;
 use crate::config::SETTINGS;
 use crate::server;
+use crate::server::models::files::FsckReport;
 
 pub async fn handler(command: ServerCommand) -> CliResult<()> {
     match command {
@@ -35,6 +36,64 @@ pub async fn handler(command: ServerCommand) -> CliResult<()> {
                 println!("Exit.");
             }
         }
+        ServerCommand::Fsck { fix } => {
+            let files = server::file_controller().await?;
+            let report = files.fsck().await?;
+            print_report(&report);
+            if fix && !report.is_clean() {
+                files.repair(&report).await?;
+                println!("Repaired.");
+            } else if !report.is_clean() {
+                println!("Run again with --fix to repair.");
+            }
+        }
+        ServerCommand::MakeAdmin { username } => {
+            let auth = server::auth_controller().await?;
+            auth.make_admin(&username).await?;
+            println!("{username} is now an admin.");
+        }
+        ServerCommand::SetQuota { username, bytes } => {
+            let auth = server::auth_controller().await?;
+            auth.set_quota(&username, bytes).await?;
+            match bytes {
+                Some(bytes) => println!("{username} can now store {bytes} bytes."),
+                None => println!("{username} now has the default quota."),
+            }
+        }
+        ServerCommand::Gc { fix } => {
+            let files = server::file_controller().await?;
+            let report = files.fsck().await?.garbage_only();
+            print_report(&report);
+            if fix && !report.is_clean() {
+                files.repair(&report).await?;
+                println!("Cleaned up.");
+            } else if !report.is_clean() {
+                println!("Run again with --fix to clean up.");
+            }
+        }
     }
     Ok(())
 }
+
+fn print_report(report: &FsckReport) {
+    if report.is_clean() {
+        println!("No problems found.");
+        return;
+    }
+
+    for key in &report.orphaned_blobs {
+        println!("orphaned blob: {key}");
+    }
+    for media in &report.dangling_media {
+        println!("missing blob for media {} ({})", media.id, media.blob_key());
+    }
+    for (id, path) in &report.broken_sfiles {
+        println!("file with missing contents: {path} (sfile {id})");
+    }
+    for media in &report.unreferenced_media {
+        println!("unreferenced media {} ({})", media.id, media.blob_key());
+    }
+    for path in &report.stale_temp_files {
+        println!("stale temp file: {}", path.to_string_lossy());
+    }
+}
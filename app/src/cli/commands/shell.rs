@@ -0,0 +1,347 @@
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::FileHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use tokio::{runtime::Handle, task::block_in_place};
+
+use super::{api_client, files, remote_path, upload};
+use crate::{
+    api::ApiClient,
+    cli::error::{CliError, CliResult},
+    config::{CLI_CONFIG, DATA_DIR},
+    server::models::files::VirtualPath,
+};
+
+const HISTORY_FILE: &str = "shell_history.txt";
+
+/// A line typed into the shell. Paths are relative to the working directory
+/// unless they start with '/'.
+#[derive(Parser, Debug)]
+#[command(multicall = true)]
+struct ShellLine {
+    #[command(subcommand)]
+    command: ShellCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ShellCommand {
+    /// List a directory.
+    Ls {
+        path: Option<String>,
+        #[arg(short = 'l', long = "long")]
+        long: bool,
+    },
+    /// Change the working directory, back to the root if no path is given.
+    Cd { path: Option<String> },
+    /// Print the working directory.
+    Pwd,
+    /// Upload a local file or directory.
+    Put {
+        local: PathBuf,
+        /// The directory to upload to instead of the working directory.
+        dir: Option<String>,
+        /// Delete the upload after a while, e.g. 30m, 12h, 7d.
+        #[arg(short = 'e', long = "expires")]
+        expires: Option<String>,
+    },
+    /// Download a file.
+    Get {
+        path: String,
+        /// Where to save it locally, or - for stdout.
+        output: Option<PathBuf>,
+    },
+    /// Move files to the trash, a directory one file at a time.
+    Rm {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Move or rename a file or directory.
+    Mv { from: String, to: String },
+    /// Create a directory and any missing parents.
+    Mkdir { path: String },
+    /// Make a file public or private.
+    Chmod { mode: Visibility, path: String },
+    /// Give another user access to a file or directory.
+    Share {
+        path: String,
+        user_id: u64,
+        #[arg(short = 'r', long = "role", default_value = "viewer", value_parser = ["viewer", "editor", "owner"])]
+        role: String,
+        /// Take the role away instead.
+        #[arg(long = "revoke")]
+        revoke: bool,
+    },
+    /// Leave the shell.
+    #[command(alias = "quit")]
+    Exit,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Visibility {
+    Public,
+    Private,
+}
+
+/// Completes command names and remote paths.
+struct ShellHelper {
+    client: ApiClient,
+    cwd: VirtualPath,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+
+        if start == 0 {
+            let commands = ShellLine::command()
+                .get_subcommands()
+                .map(|c| c.get_name().to_string())
+                .filter(|name| name.starts_with(word))
+                .map(|name| Pair {
+                    display: name.clone(),
+                    replacement: format!("{name} "),
+                })
+                .collect();
+            return Ok((start, commands));
+        }
+
+        let (dir, prefix) = word.rsplit_once('/').unwrap_or(("", word));
+        let listed_dir = if word.contains('/') && dir.is_empty() {
+            VirtualPath::root()
+        } else {
+            resolve(&self.cwd, dir)
+        };
+
+        // Called from inside readline, which already runs in block_in_place
+        let Ok(files) = Handle::current().block_on(
+            self.client
+                .list_directory(&remote_path(&relative(&listed_dir)), None),
+        ) else {
+            return Ok((start, vec![]));
+        };
+
+        let typed_dir = &word[..word.len() - prefix.len()];
+        let mut candidates: Vec<Pair> = files
+            .into_iter()
+            .filter(|file| file.top_level_name.starts_with(prefix))
+            .map(|file| {
+                let name = if file.is_dir {
+                    format!("{}/", file.top_level_name)
+                } else {
+                    file.top_level_name
+                };
+                Pair {
+                    replacement: format!("{typed_dir}{name}"),
+                    display: name,
+                }
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+pub async fn handler() -> CliResult<()> {
+    let mut editor: Editor<ShellHelper, FileHistory> = Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(ShellHelper {
+        client: api_client()?,
+        cwd: VirtualPath::root(),
+    }));
+
+    let history = DATA_DIR.join(HISTORY_FILE);
+    // Missing on the first run
+    let _ = editor.load_history(&history);
+
+    println!("Type help for a list of commands, exit to leave.");
+    loop {
+        let cwd = editor.helper().unwrap().cwd.clone();
+        let prompt = format!("{}:/{}> ", CLI_CONFIG.active_name(), relative(&cwd));
+
+        let line = match block_in_place(|| editor.readline(&prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_error(e)),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        let Some(words) = shlex::split(&line) else {
+            eprintln!("Unclosed quote.");
+            continue;
+        };
+        let command = match ShellLine::try_parse_from(words) {
+            Ok(parsed) => parsed.command,
+            Err(e) => {
+                let _ = e.print();
+                continue;
+            }
+        };
+
+        match run(command, &cwd).await {
+            Ok(Some(new_cwd)) => editor.helper_mut().unwrap().cwd = new_cwd,
+            Ok(None) => {}
+            Err(ShellExit) => break,
+        }
+    }
+
+    if let Err(e) = editor.save_history(&history) {
+        eprintln!("Could not save shell history: {e}");
+    }
+
+    Ok(())
+}
+
+struct ShellExit;
+
+/// Runs one command, returning the new working directory if it changed.
+/// Errors are printed so the shell keeps going.
+async fn run(command: ShellCommand, cwd: &VirtualPath) -> Result<Option<VirtualPath>, ShellExit> {
+    let path = |arg: &str| relative(&resolve(cwd, arg));
+
+    let result = match command {
+        ShellCommand::Exit => return Err(ShellExit),
+        ShellCommand::Pwd => {
+            println!("/{}", relative(cwd));
+            Ok(())
+        }
+        ShellCommand::Cd { path } => {
+            let target = resolve(cwd, path.as_deref().unwrap_or("/"));
+            match cd(&target).await {
+                Ok(()) => return Ok(Some(target)),
+                Err(e) => Err(e),
+            }
+        }
+        ShellCommand::Ls { path: dir, long } => {
+            files::ls(path(dir.as_deref().unwrap_or("")), long, false).await
+        }
+        ShellCommand::Put {
+            local,
+            dir,
+            expires,
+        } => put(local, path(dir.as_deref().unwrap_or("")), expires).await,
+        ShellCommand::Get { path: file, output } => files::get(path(&file), output).await,
+        ShellCommand::Rm { paths } => files::rm(paths.iter().map(|p| path(p)).collect()).await,
+        ShellCommand::Mv { from, to } => {
+            // Like mv, a trailing slash moves it into the directory under the same name
+            let from = resolve(cwd, &from);
+            let to = match from.name() {
+                Some(name) if to.ends_with('/') => relative(&resolve(cwd, &to).join(name).unwrap()),
+                _ => path(&to),
+            };
+            files::mv(relative(&from), to).await
+        }
+        ShellCommand::Mkdir { path: dir } => files::mkdir(path(&dir)).await,
+        ShellCommand::Chmod { mode, path: file } => {
+            files::publish(path(&file), matches!(mode, Visibility::Public)).await
+        }
+        ShellCommand::Share {
+            path: file,
+            user_id,
+            role,
+            revoke,
+        } => files::share(path(&file), user_id, role, revoke).await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {e:?}");
+    }
+    Ok(None)
+}
+
+/// Only lets cd into directories that exist.
+async fn cd(target: &VirtualPath) -> CliResult<()> {
+    api_client()?
+        .list_directory(&remote_path(&relative(target)), None)
+        .await?;
+    Ok(())
+}
+
+async fn put(local: PathBuf, dir: String, expires: Option<String>) -> CliResult<()> {
+    if local.is_dir() {
+        println!(
+            "{}",
+            upload::dir_handler(local, false, dir, expires, 4).await?
+        );
+    } else {
+        let url = upload::handler(local, false, dir, expires, false).await?;
+        println!("File can be found at {url}");
+    }
+    Ok(())
+}
+
+/// Resolves `arg` against the working directory like a unix shell would.
+/// The result is always a directory path, `..` stops at the root.
+fn resolve(cwd: &VirtualPath, arg: &str) -> VirtualPath {
+    let mut path = if arg.starts_with('/') {
+        VirtualPath::root()
+    } else {
+        cwd.clone()
+    };
+
+    for part in arg.split('/') {
+        path = match part {
+            "" | "." => path,
+            ".." => path.parent().unwrap_or_else(VirtualPath::root),
+            name => path.join(name).unwrap_or(path),
+        };
+    }
+
+    path
+}
+
+/// The path without its root/ prefix, what the file commands take.
+fn relative(path: &VirtualPath) -> String {
+    path.path_parts_no_root().join("/")
+}
+
+fn readline_error(e: ReadlineError) -> CliError {
+    CliError::IoError { err: e.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_resolve_against_the_working_directory() {
+        let cwd = resolve(&VirtualPath::root(), "docs/notes");
+        assert_eq!(relative(&cwd), "docs/notes");
+
+        assert_eq!(relative(&resolve(&cwd, "a.txt")), "docs/notes/a.txt");
+        assert_eq!(relative(&resolve(&cwd, "../pics/")), "docs/pics");
+        assert_eq!(relative(&resolve(&cwd, "./../..")), "");
+        assert_eq!(relative(&resolve(&cwd, "../../../..")), "");
+        assert_eq!(relative(&resolve(&cwd, "/music")), "music");
+        assert_eq!(relative(&resolve(&cwd, "")), "docs/notes");
+    }
+}
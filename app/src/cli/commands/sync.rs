@@ -0,0 +1,663 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use chrono::{DateTime, Local, Utc};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{
+    api_client, files, remote_path,
+    upload::{self, IgnoreRules, UploadProgress},
+};
+use crate::{
+    api::{ApiClient, ApiError},
+    cli::error::{CliError, CliResult},
+    config::{CLI_CONFIG, DATA_DIR},
+};
+
+/// What every synced folder looked like after its last sync, so we can tell
+/// which side changed a file since
+const STATE_FILE: &str = "sync.yaml";
+
+/// A folder pair's files as of the last sync, by their path relative to both roots.
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+    files: BTreeMap<String, SyncedFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SyncedFile {
+    hash: String,
+    /// Local files whose size and mtime still match aren't hashed again
+    size: u64,
+    local_modified: i64,
+    /// Only compared when the server doesn't give us a hash
+    remote_modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+struct LocalFile {
+    hash: String,
+    size: u64,
+    modified: i64,
+}
+
+#[derive(Clone)]
+struct RemoteFile {
+    hash: Option<String>,
+    modified_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Action {
+    Upload,
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+    /// Both sides changed, neither version is dropped
+    Conflict,
+    /// Nothing to move, the state is just brought up to date
+    Keep,
+    /// Gone on both sides
+    Forget,
+}
+
+/// What a sync did, or would do on a dry run.
+#[derive(Default)]
+pub struct SyncSummary {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub deleted: usize,
+    pub conflicts: usize,
+    pub failed: Vec<(String, CliError)>,
+}
+
+impl Display for SyncSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Uploaded {}, downloaded {}, deleted {}, {} conflicts, {} failed.",
+            self.uploaded,
+            self.downloaded,
+            self.deleted,
+            self.conflicts,
+            self.failed.len()
+        )?;
+        for (path, e) in &self.failed {
+            write!(f, "\n  {path}: {e:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Brings `local` and the `remote` directory up to date with each other.
+/// Files changed on only one side are copied to the other, deletions are
+/// carried over, and files changed on both sides are kept as conflict copies.
+pub async fn handler(local: PathBuf, remote: String, dry_run: bool) -> CliResult<SyncSummary> {
+    let client = api_client()?;
+    let remote = remote.trim_matches('/').to_string();
+
+    tokio::fs::create_dir_all(&local).await?;
+    let local = std::fs::canonicalize(&local)?;
+    let key = format!(
+        "{} {} {}",
+        CLI_CONFIG.active_name(),
+        remote_path(&remote),
+        local.to_string_lossy()
+    );
+
+    let mut states = read_states();
+    let mut state = states.remove(&key).unwrap_or_default();
+    let mut summary = SyncSummary::default();
+
+    let (local_files, unscanned, ignore) = scan_local(&local, &state, &mut summary).await;
+    let remote_files = scan_remote(&client, &remote).await?;
+    let actions = plan_all(&local_files, &remote_files, &state, &unscanned, |path| {
+        ignore.is_ignored(path)
+    });
+
+    for (path, action) in actions {
+        let local_file = local_files.get(&path);
+        let remote_file = remote_files.get(&path);
+        match action {
+            Action::Keep => {
+                if let (Some(local_file), Some(remote_file)) = (local_file, remote_file) {
+                    let synced = synced(local_file, Some(remote_file.modified_at));
+                    state.files.insert(path, synced);
+                }
+                continue;
+            }
+            Action::Forget => {
+                state.files.remove(&path);
+                continue;
+            }
+            _ => {}
+        }
+
+        println!("{}", describe(&path, action));
+        if dry_run {
+            count(&mut summary, action);
+            continue;
+        }
+
+        let local_path = local.join(&path);
+        let result = match action {
+            Action::Upload => put(&remote, &path, &local_path)
+                .await
+                .map(|()| vec![(path.clone(), Some(synced(local_file.unwrap(), None)))]),
+            Action::Download => {
+                let remote_file = remote_file.unwrap();
+                fetch(&remote, &path, &local_path, remote_file)
+                    .await
+                    .map(|downloaded| {
+                        let synced = synced(&downloaded, Some(remote_file.modified_at));
+                        vec![(path.clone(), Some(synced))]
+                    })
+            }
+            Action::DeleteLocal => tokio::fs::remove_file(&local_path)
+                .await
+                .map(|()| vec![(path.clone(), None)])
+                .map_err(CliError::from),
+            Action::DeleteRemote => client
+                .delete_file(&remote_path(&join(&remote, &path)))
+                .await
+                .map(|()| vec![(path.clone(), None)])
+                .map_err(CliError::from),
+            Action::Conflict => {
+                resolve_conflict(
+                    &remote,
+                    &path,
+                    &local,
+                    local_file.unwrap(),
+                    remote_file.unwrap(),
+                )
+                .await
+            }
+            Action::Keep | Action::Forget => unreachable!(),
+        };
+
+        match result {
+            Ok(synced_files) => {
+                count(&mut summary, action);
+                for (path, synced) in synced_files {
+                    match synced {
+                        Some(synced) => state.files.insert(path, synced),
+                        None => state.files.remove(&path),
+                    };
+                }
+            }
+            // Its old state stays, so the next sync tries again
+            Err(e) => summary.failed.push((path, e)),
+        }
+    }
+
+    if !dry_run {
+        states.insert(key, state);
+        save_states(&states);
+    }
+
+    Ok(summary)
+}
+
+/// Plans every file seen on either side or at the last sync. Files that couldn't be
+/// scanned, or are under a directory that couldn't, are left out entirely, so
+/// they keep their old state rather than looking deleted. So are `ignored` ones,
+/// which the local scan never sees wherever else they exist.
+fn plan_all(
+    local_files: &BTreeMap<String, LocalFile>,
+    remote_files: &BTreeMap<String, RemoteFile>,
+    state: &SyncState,
+    unscanned: &BTreeSet<String>,
+    ignored: impl Fn(&str) -> bool,
+) -> Vec<(String, Action)> {
+    let paths: BTreeSet<&String> = local_files
+        .keys()
+        .chain(remote_files.keys())
+        .chain(state.files.keys())
+        .collect();
+
+    paths
+        .into_iter()
+        .filter(|path| {
+            !unscanned.iter().any(|failed| {
+                failed.is_empty()
+                    || path.as_str() == failed
+                    || path.starts_with(&format!("{failed}/"))
+            }) && !ignored(path)
+        })
+        .map(|path| {
+            let action = plan(
+                local_files.get(path),
+                remote_files.get(path),
+                state.files.get(path),
+            );
+            (path.clone(), action)
+        })
+        .collect()
+}
+
+/// Decides what to do with a file from how it looks on each side and at the last sync.
+fn plan(
+    local: Option<&LocalFile>,
+    remote: Option<&RemoteFile>,
+    base: Option<&SyncedFile>,
+) -> Action {
+    let local_changed = |base: &SyncedFile| local.is_some_and(|l| l.hash != base.hash);
+    let remote_changed = |base: &SyncedFile| {
+        remote.is_some_and(|r| match &r.hash {
+            Some(hash) => !hash.eq_ignore_ascii_case(&base.hash),
+            None => Some(r.modified_at) != base.remote_modified,
+        })
+    };
+
+    match (local, remote, base) {
+        (None, None, _) => Action::Forget,
+        (Some(_), None, None) => Action::Upload,
+        (None, Some(_), None) => Action::Download,
+        (Some(_), None, Some(base)) if local_changed(base) => Action::Upload,
+        (Some(_), None, Some(_)) => Action::DeleteLocal,
+        (None, Some(_), Some(base)) if remote_changed(base) => Action::Download,
+        (None, Some(_), Some(_)) => Action::DeleteRemote,
+        (Some(l), Some(r), base) => {
+            if r.hash
+                .as_ref()
+                .is_some_and(|h| h.eq_ignore_ascii_case(&l.hash))
+            {
+                return Action::Keep;
+            }
+            let Some(base) = base else {
+                return Action::Conflict;
+            };
+            match (local_changed(base), remote_changed(base)) {
+                (false, false) => Action::Keep,
+                (true, false) => Action::Upload,
+                (false, true) => Action::Download,
+                (true, true) => Action::Conflict,
+            }
+        }
+    }
+}
+
+/// Hashes what isn't ignored under `dir`, reusing the last sync's hash of unchanged files.
+/// Also returns the keys of whatever couldn't be scanned, an empty key meaning all of `dir`,
+/// and the ignore rules it followed.
+async fn scan_local(
+    dir: &Path,
+    state: &SyncState,
+    summary: &mut SyncSummary,
+) -> (BTreeMap<String, LocalFile>, BTreeSet<String>, IgnoreRules) {
+    let (paths, _, failed) = upload::collect_files(dir);
+    let ignore = IgnoreRules::new(dir, &paths);
+
+    let mut unscanned = BTreeSet::new();
+    for (path, e) in failed {
+        let key = key(path.strip_prefix(dir).unwrap_or(Path::new("")));
+        unscanned.insert(key.clone());
+        summary.failed.push((key, e));
+    }
+
+    let mut files = BTreeMap::new();
+    for path in paths {
+        let key = key(&path);
+        match local_file(&dir.join(&path), state.files.get(&key)).await {
+            Ok(file) => {
+                files.insert(key, file);
+            }
+            Err(e) => {
+                unscanned.insert(key.clone());
+                summary.failed.push((key, e));
+            }
+        }
+    }
+
+    (files, unscanned, ignore)
+}
+
+/// A path relative to the synced folder as it's kept in the state, `/` separated.
+fn key(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+async fn local_file(path: &Path, base: Option<&SyncedFile>) -> CliResult<LocalFile> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+
+    let hash = match base {
+        Some(base) if base.size == size && base.local_modified == modified => base.hash.clone(),
+        _ => upload::hash_file(path, size, &UploadProgress::default()).await?,
+    };
+
+    Ok(LocalFile {
+        hash,
+        size,
+        modified,
+    })
+}
+
+/// Every file under the remote directory. One that doesn't exist yet is empty.
+async fn scan_remote(client: &ApiClient, remote: &str) -> CliResult<BTreeMap<String, RemoteFile>> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![String::new()];
+
+    while let Some(dir) = dirs.pop() {
+        let listing = match client
+            .list_directory(&remote_path(&join(remote, &dir)), None)
+            .await
+        {
+            Ok(listing) => listing,
+            Err(ApiError::Http { status, .. })
+                if status == StatusCode::NOT_FOUND && dir.is_empty() =>
+            {
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for file in listing {
+            let path = join(&dir, &file.top_level_name);
+            if file.is_dir {
+                dirs.push(path);
+            } else {
+                files.insert(
+                    path,
+                    RemoteFile {
+                        hash: file.media_hash,
+                        modified_at: file.modified_at,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+async fn put(remote: &str, path: &str, local_path: &Path) -> CliResult<()> {
+    let upload_path = PathBuf::from(remote_path(&join(remote, path)));
+    upload::upload_file(&upload_path, local_path, None, &UploadProgress::default()).await?;
+    Ok(())
+}
+
+async fn fetch(
+    remote: &str,
+    path: &str,
+    local_path: &Path,
+    remote_file: &RemoteFile,
+) -> CliResult<LocalFile> {
+    if let Some(parent) = local_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    files::get(join(remote, path), Some(local_path.to_path_buf())).await?;
+
+    // Hashed again only if the server didn't tell us
+    let base = remote_file.hash.as_ref().map(|hash| {
+        let metadata = std::fs::metadata(local_path).ok();
+        SyncedFile {
+            hash: hash.clone(),
+            size: metadata.as_ref().map(|m| m.len()).unwrap_or_default(),
+            local_modified: metadata
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
+            remote_modified: None,
+        }
+    });
+    local_file(local_path, base.as_ref()).await
+}
+
+/// Moves the local version aside under a conflict name and uploads it,
+/// then downloads the remote version in its place.
+async fn resolve_conflict(
+    remote: &str,
+    path: &str,
+    local: &Path,
+    local_file: &LocalFile,
+    remote_file: &RemoteFile,
+) -> CliResult<Vec<(String, Option<SyncedFile>)>> {
+    let copy = conflict_name(path, Local::now());
+    tokio::fs::rename(local.join(path), local.join(&copy)).await?;
+    put(remote, &copy, &local.join(&copy)).await?;
+    let downloaded = fetch(remote, path, &local.join(path), remote_file).await?;
+
+    Ok(vec![
+        (copy, Some(synced(local_file, None))),
+        (
+            path.to_string(),
+            Some(synced(&downloaded, Some(remote_file.modified_at))),
+        ),
+    ])
+}
+
+/// `notes/plan.md` becomes `notes/plan (conflict 2026-01-02 030405).md`.
+fn conflict_name(path: &str, now: DateTime<Local>) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{dir}/"), name),
+        None => (String::new(), path),
+    };
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+    format!(
+        "{dir}{stem} (conflict {}){ext}",
+        now.format("%Y-%m-%d %H%M%S")
+    )
+}
+
+fn describe(path: &str, action: Action) -> String {
+    match action {
+        Action::Upload => format!("upload    {path}"),
+        Action::Download => format!("download  {path}"),
+        Action::DeleteLocal => format!("delete    {path} (local)"),
+        Action::DeleteRemote => format!("delete    {path} (remote)"),
+        Action::Conflict => format!("conflict  {path}, the local version is kept as a copy"),
+        Action::Keep | Action::Forget => String::new(),
+    }
+}
+
+fn count(summary: &mut SyncSummary, action: Action) {
+    match action {
+        Action::Upload => summary.uploaded += 1,
+        Action::Download => summary.downloaded += 1,
+        Action::DeleteLocal | Action::DeleteRemote => summary.deleted += 1,
+        Action::Conflict => summary.conflicts += 1,
+        Action::Keep | Action::Forget => {}
+    }
+}
+
+/// `remote_modified` is unknown right after an upload, the hash is enough then.
+fn synced(local_file: &LocalFile, remote_modified: Option<DateTime<Utc>>) -> SyncedFile {
+    SyncedFile {
+        hash: local_file.hash.clone(),
+        size: local_file.size,
+        local_modified: local_file.modified,
+        remote_modified,
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+fn read_states() -> BTreeMap<String, SyncState> {
+    std::fs::read_to_string(DATA_DIR.join(STATE_FILE))
+        .ok()
+        .and_then(|s| serde_yaml::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_states(states: &BTreeMap<String, SyncState>) {
+    let result = serde_yaml::to_string(states)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(DATA_DIR.join(STATE_FILE), s).map_err(|e| e.to_string()));
+
+    // The next sync sees every change as new and sorts it out from the hashes
+    if let Err(e) = result {
+        warn!("Failed to save sync state: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn local(hash: &str) -> LocalFile {
+        LocalFile {
+            hash: hash.into(),
+            size: 1,
+            modified: 0,
+        }
+    }
+
+    fn remote(hash: &str) -> RemoteFile {
+        RemoteFile {
+            hash: Some(hash.into()),
+            modified_at: DateTime::default(),
+        }
+    }
+
+    fn base(hash: &str) -> SyncedFile {
+        SyncedFile {
+            hash: hash.into(),
+            size: 1,
+            local_modified: 0,
+            remote_modified: None,
+        }
+    }
+
+    #[test]
+    fn changes_go_to_the_side_that_did_not_make_them() {
+        let a = Some(base("A"));
+        let a = a.as_ref();
+        assert_eq!(plan(Some(&local("A")), Some(&remote("a")), a), Action::Keep);
+        assert_eq!(
+            plan(Some(&local("B")), Some(&remote("A")), a),
+            Action::Upload
+        );
+        assert_eq!(
+            plan(Some(&local("A")), Some(&remote("B")), a),
+            Action::Download
+        );
+        assert_eq!(
+            plan(Some(&local("B")), Some(&remote("C")), a),
+            Action::Conflict
+        );
+        assert_eq!(plan(Some(&local("B")), Some(&remote("B")), a), Action::Keep);
+
+        assert_eq!(plan(Some(&local("A")), None, a), Action::DeleteLocal);
+        assert_eq!(plan(None, Some(&remote("A")), a), Action::DeleteRemote);
+        // An edit wins over a delete on the other side
+        assert_eq!(plan(Some(&local("B")), None, a), Action::Upload);
+        assert_eq!(plan(None, Some(&remote("B")), a), Action::Download);
+        assert_eq!(plan(None, None, a), Action::Forget);
+    }
+
+    #[test]
+    fn files_that_fail_to_scan_are_left_alone() {
+        let state = SyncState {
+            files: [
+                ("a.txt".to_string(), base("A")),
+                ("docs/b.txt".to_string(), base("B")),
+            ]
+            .into(),
+        };
+        let remote_files: BTreeMap<String, RemoteFile> = [
+            ("a.txt".to_string(), remote("A")),
+            ("docs/b.txt".to_string(), remote("B")),
+        ]
+        .into();
+
+        // Neither shows up locally, but only because reading them failed
+        let unscanned: BTreeSet<String> = ["a.txt".to_string(), "docs".to_string()].into();
+        assert!(
+            plan_all(&BTreeMap::new(), &remote_files, &state, &unscanned, |_| {
+                false
+            })
+            .is_empty()
+        );
+
+        let actions = plan_all(
+            &BTreeMap::new(),
+            &remote_files,
+            &state,
+            &BTreeSet::new(),
+            |_| false,
+        );
+        assert!(actions
+            .iter()
+            .all(|(_, action)| *action == Action::DeleteRemote));
+    }
+
+    #[test]
+    fn ignored_files_are_left_alone_on_both_sides() {
+        let state = SyncState {
+            files: [("debug.log".to_string(), base("A"))].into(),
+        };
+        let remote_files: BTreeMap<String, RemoteFile> = [
+            ("debug.log".to_string(), remote("A")),
+            ("logs/new.log".to_string(), remote("B")),
+        ]
+        .into();
+
+        // Never scanned locally, which mustn't look like a deletion or a new remote file
+        let actions = plan_all(
+            &BTreeMap::new(),
+            &remote_files,
+            &state,
+            &BTreeSet::new(),
+            |path| path.ends_with(".log"),
+        );
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn first_syncs_copy_everything_and_keep_both_versions() {
+        assert_eq!(plan(Some(&local("A")), None, None), Action::Upload);
+        assert_eq!(plan(None, Some(&remote("A")), None), Action::Download);
+        assert_eq!(
+            plan(Some(&local("A")), Some(&remote("A")), None),
+            Action::Keep
+        );
+        assert_eq!(
+            plan(Some(&local("A")), Some(&remote("B")), None),
+            Action::Conflict
+        );
+    }
+
+    #[test]
+    fn conflict_copies_keep_their_directory_and_extension() {
+        let now = Local.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(
+            conflict_name("notes/plan.md", now),
+            "notes/plan (conflict 2026-01-02 030405).md"
+        );
+        assert_eq!(
+            conflict_name("Makefile", now),
+            "Makefile (conflict 2026-01-02 030405)"
+        );
+        assert_eq!(
+            conflict_name(".env", now),
+            ".env (conflict 2026-01-02 030405)"
+        );
+    }
+}
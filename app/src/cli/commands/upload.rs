@@ -1,66 +1,622 @@
+use super::{api_client, files::public_link, http_client, server_url};
 use crate::{
     cli::error::{CliError, CliResult},
-    config::CLI_CONFIG,
+    config::DATA_DIR,
+    server::models::files::{HashChallenge, HashUploadResponse, UploadResult},
+    server::web::handlers::files::TUS_VERSION,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use futures_util::{stream::StreamExt, Stream};
-use indicatif::{ProgressBar, ProgressStyle};
+use ignore::{gitignore::Gitignore, Match, WalkBuilder};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::{
     multipart::{Form, Part},
-    Body, Client, Url,
+    Body, Client, StatusCode, Url,
 };
-use std::path::Path;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fmt::Display;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::Duration,
+    time::UNIX_EPOCH,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, SeekFrom};
 use tokio_util::{bytes::Bytes, io::ReaderStream};
-use tracing::{error, trace};
+use tracing::{trace, warn};
+
+/// How many times a failed upload request is retried before giving up.
+/// The upload can still be resumed by running the command again.
+const MAX_RETRIES: u32 = 5;
+
+/// Unfinished uploads, so an interrupted upload of the same file picks up where it left off
+const RESUME_FILE: &str = "uploads.yaml";
+
+/// Held while the resumable uploads file is read and written back,
+/// so concurrent uploads don't drop each other's entries
+static RESUME_LOCK: Mutex<()> = Mutex::new(());
+
+/// gitignore-style patterns of files to leave out of directory uploads
+const IGNORE_FILE: &str = ".ocloudignore";
+
+/// Where upload progress is drawn. Uploads of a directory share one display,
+/// with a bar per file in flight.
+#[derive(Clone, Default)]
+pub struct UploadProgress {
+    multi: Option<MultiProgress>,
+}
+
+impl UploadProgress {
+    fn bar(&self, len: u64, template: &str, label: &Path) -> ProgressBar {
+        let pb = ProgressBar::new(len);
+        pb.set_style(ProgressStyle::default_bar().template(template).unwrap());
+        match &self.multi {
+            Some(multi) => {
+                pb.set_prefix(label.to_string_lossy().to_string());
+                multi.add(pb)
+            }
+            None => pb,
+        }
+    }
 
-pub async fn handler(path: PathBuf, preserve: bool, dir: String) -> CliResult<String> {
-    if let Err(e) = Url::parse(&CLI_CONFIG.server_url) {
-        error!("Error: cloud url is invalid or does not exist. Use the set-url command to set a cloud url.");
-        return Err(e.into());
+    /// Single uploads leave their bar behind, the ones of a directory make room for the next.
+    fn done(&self, pb: &ProgressBar) {
+        match self.multi {
+            Some(_) => pb.finish_and_clear(),
+            None => pb.finish(),
+        }
     }
 
+    fn abandon(&self, pb: &ProgressBar) {
+        match self.multi {
+            Some(_) => pb.finish_and_clear(),
+            None => pb.abandon(),
+        }
+    }
+}
+
+/// What happened to the files of a directory upload.
+pub struct UploadSummary {
+    pub url: String,
+    pub uploaded: usize,
+    /// Left out by ignore files
+    pub skipped: usize,
+    pub failed: Vec<(PathBuf, CliError)>,
+}
+
+impl Display for UploadSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Uploaded {} files, skipped {}, {} failed.",
+            self.uploaded,
+            self.skipped,
+            self.failed.len()
+        )?;
+        for (path, e) in &self.failed {
+            writeln!(f, "  {}: {e:?}", path.to_string_lossy())?;
+        }
+        write!(f, "Files can be found at {}", self.url)
+    }
+}
+
+/// Uploads one file, returning its url, or its public link after making it public.
+pub async fn handler(
+    path: PathBuf,
+    preserve: bool,
+    dir: String,
+    expires: Option<String>,
+    public: bool,
+) -> CliResult<String> {
+    server_url()?;
+
     let upload_dir = PathBuf::from(format!("root/{}", dir.trim_matches('/')));
 
     let upload_path = upload_dir.join({
         if preserve {
             path.clone()
         } else {
-            PathBuf::from(path.file_name().unwrap())
+            PathBuf::from(file_name(&path)?)
         }
     });
 
     trace!("Uploading file to: {}", upload_path.to_string_lossy());
 
-    upload_file(&upload_path, &path).await
+    let url = upload_file(
+        &upload_path,
+        &path,
+        expires.as_deref(),
+        &UploadProgress::default(),
+    )
+    .await?;
+    link(&upload_path, url, public).await
+}
+
+/// Uploads everything `reader` gives as `name` in `dir`, for input whose size
+/// isn't known up front. Resumable uploads need the size, so this is a plain
+/// multipart upload streamed as it's read.
+pub async fn stream_handler(
+    reader: impl AsyncRead + Send + 'static,
+    name: String,
+    dir: String,
+    expires: Option<String>,
+    public: bool,
+) -> CliResult<String> {
+    let server_url = server_url()?;
+    let upload_dir = PathBuf::from(format!("root/{}", dir.trim_matches('/')));
+    let upload_path = upload_dir.join(&name);
+
+    trace!("Streaming upload to: {}", upload_path.to_string_lossy());
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")
+            .unwrap(),
+    );
+    let counter = pb.clone();
+    let stream = ReaderStream::new(reader).inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            counter.inc(chunk.len() as u64);
+        }
+    });
+    let form = Form::new().part(
+        name.clone(),
+        Part::stream(Body::wrap_stream(stream)).file_name(name),
+    );
+
+    let endpoint = format!(
+        "{server_url}/files/{}/",
+        upload_dir.to_string_lossy().trim_end_matches('/')
+    );
+    let mut request = http_client().post(endpoint).multipart(form);
+    if let Some(expires) = &expires {
+        request = request.query(&[("expires", expires)]);
+    }
+    let res = request.send().await?;
+    if !res.status().is_success() {
+        pb.abandon();
+        return Err(CliError::FailStatusCode {
+            status_code: res.status(),
+        });
+    }
+
+    let result: UploadResult = res.json().await?;
+    if let Some(failure) = result.errors.into_iter().next() {
+        pb.abandon();
+        return Err(CliError::UploadFailed {
+            name: failure.name,
+            err: failure.error,
+        });
+    }
+    pb.finish();
+
+    let url = format!("{server_url}/files/{}", upload_path.to_string_lossy());
+    link(&upload_path, url, public).await
+}
+
+/// The uploaded file's url, or its public link after making it public.
+async fn link(upload_path: &Path, url: String, public: bool) -> CliResult<String> {
+    if !public {
+        return Ok(url);
+    }
+
+    let file = api_client()?
+        .change_file_visibility(&upload_path.to_string_lossy(), true)
+        .await?;
+    public_link(&file)
+}
+
+/// Uploads everything under `path` that isn't ignored, mirroring the tree
+/// under `dir`, with up to `jobs` files going at once.
+pub async fn dir_handler(
+    path: PathBuf,
+    preserve: bool,
+    dir: String,
+    expires: Option<String>,
+    jobs: usize,
+) -> CliResult<UploadSummary> {
+    let server_url = server_url()?;
+
+    let mut upload_dir = PathBuf::from(format!("root/{}", dir.trim_matches('/')));
+    if preserve {
+        upload_dir.push(&path);
+    } else if let Some(name) = std::fs::canonicalize(&path)?.file_name() {
+        // `.` and the like have no name of their own
+        upload_dir.push(name);
+    }
+
+    let (files, skipped, mut failed) = collect_files(&path);
+
+    let multi = MultiProgress::new();
+    let total = multi.add(ProgressBar::new(files.len() as u64));
+    total.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} Uploading [{bar:40.cyan/blue}] {pos}/{len} files ({elapsed_precise})")
+            .unwrap(),
+    );
+    let progress = UploadProgress { multi: Some(multi) };
+
+    let results: Vec<_> = futures_util::stream::iter(files)
+        .map(|relative| {
+            let upload_path = upload_dir.join(&relative);
+            let file_path = path.join(&relative);
+            let (expires, progress, total) = (expires.as_deref(), &progress, &total);
+            async move {
+                let result = upload_file(&upload_path, &file_path, expires, progress).await;
+                total.inc(1);
+                (relative, result)
+            }
+        })
+        .buffer_unordered(jobs.max(1))
+        .collect()
+        .await;
+    total.finish();
+
+    let mut uploaded = 0;
+    for (relative, result) in results {
+        match result {
+            Ok(_) => uploaded += 1,
+            Err(e) => failed.push((relative, e)),
+        }
+    }
+
+    Ok(UploadSummary {
+        url: format!("{server_url}/files/{}/", upload_dir.to_string_lossy()),
+        uploaded,
+        skipped,
+        failed,
+    })
+}
+
+/// The files under `dir`, relative to it, that aren't matched by a `.ocloudignore`
+/// or `.gitignore`. Also returns how many were ignored and the ones that couldn't be read.
+pub(super) fn collect_files(dir: &Path) -> (Vec<PathBuf>, usize, Vec<(PathBuf, CliError)>) {
+    let mut files = Vec::new();
+    let mut failed = Vec::new();
+    let walk = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .git_ignore(true)
+        .require_git(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .build();
+    for entry in walk {
+        match entry {
+            Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+                files.push(relative.to_path_buf());
+            }
+            Ok(_) => {}
+            Err(e) => failed.push((
+                error_path(&e).unwrap_or(dir).to_path_buf(),
+                CliError::IoError { err: e.to_string() },
+            )),
+        }
+    }
+
+    // Everything the ignore files left out
+    let all = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .build()
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .count();
+
+    let skipped = all.saturating_sub(files.len());
+    (files, skipped, failed)
+}
+
+/// The `.ocloudignore` and `.gitignore` rules under a directory, for checking
+/// paths that aren't on disk there, like files that only exist remotely.
+pub(super) struct IgnoreRules {
+    root: PathBuf,
+    /// By the directory they're in relative to `root`, `.ocloudignore` first
+    dirs: BTreeMap<PathBuf, Vec<Gitignore>>,
+}
+
+impl IgnoreRules {
+    /// Reads the ignore files among `files`, as returned by [`collect_files`] for `root`.
+    pub(super) fn new(root: &Path, files: &[PathBuf]) -> Self {
+        let mut dirs: BTreeMap<PathBuf, Vec<Gitignore>> = BTreeMap::new();
+        for file in files {
+            let precedence = match file.file_name().and_then(OsStr::to_str) {
+                Some(IGNORE_FILE) => 0,
+                Some(".gitignore") => 1,
+                _ => continue,
+            };
+            // Unreadable lines were already reported by the walk
+            let (rules, _) = Gitignore::new(root.join(file));
+            let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+            let rules_in_dir = dirs.entry(dir).or_default();
+            rules_in_dir.insert(precedence.min(rules_in_dir.len()), rules);
+        }
+
+        IgnoreRules {
+            root: root.to_path_buf(),
+            dirs,
+        }
+    }
+
+    /// Whether `path`, `/` separated and relative to the root, would be left out of
+    /// [`collect_files`], either itself or because a directory it's in is.
+    pub(super) fn is_ignored(&self, path: &str) -> bool {
+        let parts: Vec<&str> = path.split('/').collect();
+        (1..=parts.len()).any(|n| self.matches(&parts[..n].join("/"), n < parts.len()))
+    }
+
+    /// The closest ignore file with a pattern for `path` decides.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let full = self.root.join(path);
+        for dir in Path::new(path).ancestors().skip(1) {
+            for rules in self.dirs.get(dir).into_iter().flatten() {
+                match rules.matched(&full, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        false
+    }
+}
+
+/// The file or directory a walk error is about, if it says.
+fn error_path(e: &ignore::Error) -> Option<&Path> {
+    match e {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithLineNumber { err, .. } | ignore::Error::WithDepth { err, .. } => {
+            error_path(err)
+        }
+        _ => None,
+    }
+}
+
+/// The last part of `path`, which is what the file is called once uploaded.
+fn file_name(path: &Path) -> CliResult<&OsStr> {
+    path.file_name().ok_or_else(|| CliError::NoFileName {
+        path: path.to_string_lossy().to_string(),
+    })
 }
 
 // TODO! this should be in api wrapper
-pub async fn upload_file(upload_path: &Path, file_path: &Path) -> CliResult<String> {
+/// Uploads through the server's resumable (tus) upload endpoint.
+/// Dropped connections are retried from wherever the server got to,
+/// and uploads interrupted entirely continue on the next run.
+/// Files the server already has aren't sent at all.
+/// `expires` is passed along as is, the server decides whether it makes sense.
+pub async fn upload_file(
+    upload_path: &Path,
+    file_path: &Path,
+    expires: Option<&str>,
+    progress: &UploadProgress,
+) -> CliResult<String> {
     trace!(
         "Uploading {:?}...",
         file_path.file_name().unwrap_or_default()
     );
 
-    let client = Client::new();
+    let client = http_client();
+    let server_url = server_url()?;
+
+    match upload_by_hash(&client, upload_path, file_path, expires, progress).await {
+        Ok(true) => {
+            return Ok(format!(
+                "{server_url}/files/{}",
+                upload_path.to_string_lossy()
+            ))
+        }
+        Ok(false) => {}
+        // Not worth failing over, the file can still be uploaded normally
+        Err(e) => trace!("Upload by hash failed, uploading the whole file: {e:?}"),
+    }
+
+    let fname: String = file_name(file_path)?.to_string_lossy().to_string();
+    let upload_dir = format!("{}/", upload_path.parent().unwrap().to_string_lossy());
+
+    let metadata = std::fs::metadata(file_path)?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    // Same file, same contents (as far as we can tell), same destination
+    let resume_key = format!(
+        "{server_url} {} {size} {modified} {}",
+        std::fs::canonicalize(file_path)?.to_string_lossy(),
+        upload_path.to_string_lossy()
+    );
+
+    let mut resumed = None;
+    if let Some(location) = read_resumable().get(&resume_key) {
+        match upload_offset(&client, location).await {
+            Ok(offset) => {
+                trace!("Resuming upload at {location} from byte {offset}");
+                resumed = Some((location.clone(), offset));
+            }
+            Err(e) => warn!("Could not resume previous upload, starting over: {e:?}"),
+        }
+    }
 
-    let fname: String = file_path.file_name().unwrap().to_string_lossy().to_string();
+    let (location, mut offset) = match resumed {
+        Some(r) => r,
+        None => {
+            let location = create_upload(&client, &upload_dir, &fname, size, expires).await?;
+            update_resumable(|uploads| {
+                uploads.insert(resume_key.clone(), location.clone());
+            });
+            (location, 0)
+        }
+    };
 
-    let multipart_form = Form::new().part(
-        fname,
-        Part::stream(Body::wrap_stream(upload_stream(file_path).await?)),
+    let pb = progress.bar(
+        size,
+        "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {prefix}",
+        upload_path,
     );
 
-    let endpoint: String = format!(
-        "{}/files/{}/",
-        CLI_CONFIG.server_url.clone(),
-        upload_path.parent().unwrap().to_string_lossy()
+    let mut retries = 0;
+    while offset < size {
+        pb.set_position(offset);
+
+        match append_to_upload(&client, &location, file_path, offset, &pb).await {
+            Ok(new_offset) => offset = new_offset,
+            // The server is telling us something, retrying won't change it
+            Err(e @ CliError::FailStatusCode { .. }) => {
+                progress.abandon(&pb);
+                return Err(e);
+            }
+            Err(e) => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    progress.abandon(&pb);
+                    return Err(e);
+                }
+                warn!("Upload interrupted ({e:?}), retrying...");
+                tokio::time::sleep(Duration::from_secs(retries as u64)).await;
+                offset = upload_offset(&client, &location).await?;
+            }
+        }
+    }
+
+    progress.done(&pb);
+
+    update_resumable(|uploads| {
+        uploads.remove(&resume_key);
+    });
+
+    Ok(format!(
+        "{server_url}/files/{}",
+        upload_path.to_string_lossy()
+    ))
+}
+
+/// Asks the server to create the file from media it already has.
+/// Returns false if the file has to be uploaded.
+async fn upload_by_hash(
+    client: &Client,
+    upload_path: &Path,
+    file_path: &Path,
+    expires: Option<&str>,
+    progress: &UploadProgress,
+) -> CliResult<bool> {
+    let endpoint = format!("{}/files/by-hash", server_url()?);
+    let size = std::fs::metadata(file_path)?.len();
+
+    let mut request = json!({
+        "path": upload_path.to_string_lossy(),
+        "hash": hash_file(file_path, size, progress).await?,
+        "size": size,
+        "expires": expires,
+    });
+
+    let res = client.post(&endpoint).json(&request).send().await?;
+    if !res.status().is_success() {
+        return Ok(false);
+    }
+
+    let challenge = match res.json::<HashUploadResponse>().await? {
+        HashUploadResponse::Linked { .. } => return Ok(true),
+        HashUploadResponse::Challenge { challenge } => challenge,
+    };
+
+    request["challenge_id"] = json!(challenge.id);
+    request["answer"] = json!(answer_challenge(file_path, &challenge).await?);
+
+    // Anything but success means the server doesn't have it
+    let res = client.post(&endpoint).json(&request).send().await?;
+    Ok(res.status().is_success())
+}
+
+pub(super) async fn hash_file(
+    path: &Path,
+    size: u64,
+    progress: &UploadProgress,
+) -> CliResult<String> {
+    let pb = progress.bar(
+        size,
+        "{spinner:.green} Hashing [{bar:40.cyan/blue}] {bytes}/{total_bytes} {prefix}",
+        path,
     );
 
-    trace!("Uploading to url {endpoint}");
+    let mut hasher = Sha256::new();
+    let mut reader_stream = ReaderStream::new(tokio::fs::File::open(path).await?);
+    while let Some(chunk) = reader_stream.next().await {
+        let chunk = chunk?;
+        pb.inc(chunk.len() as u64);
+        hasher.update(&chunk);
+    }
+    pb.finish_and_clear();
+
+    Ok(format!("{:X}", hasher.finalize()))
+}
+
+async fn answer_challenge(path: &Path, challenge: &HashChallenge) -> CliResult<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(challenge.offset)).await?;
+
+    let mut range = vec![0; challenge.length as usize];
+    file.read_exact(&mut range).await?;
+
+    Ok(challenge.answer(&range))
+}
+
+async fn create_upload(
+    client: &Client,
+    dir: &str,
+    fname: &str,
+    size: u64,
+    expires: Option<&str>,
+) -> CliResult<String> {
+    let endpoint = format!("{}/uploads", server_url()?);
+    trace!("Creating upload at url {endpoint}");
+
+    let mut metadata = format!(
+        "filename {},path {}",
+        BASE64.encode(fname),
+        BASE64.encode(dir)
+    );
+    if let Some(expires) = expires {
+        metadata.push_str(&format!(",expires {}", BASE64.encode(expires)));
+    }
 
     let res = client
         .post(&endpoint)
-        .multipart(multipart_form)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header("Upload-Length", size)
+        .header("Upload-Metadata", metadata)
+        .send()
+        .await?;
+
+    if res.status() != StatusCode::CREATED {
+        return Err(CliError::FailStatusCode {
+            status_code: res.status(),
+        });
+    }
+
+    let location = res
+        .headers()
+        .get("location")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(CliError::FailStatusCode {
+            status_code: res.status(),
+        })?;
+
+    // Can be relative to the server
+    Ok(Url::parse(server_url()?)?.join(location)?.to_string())
+}
+
+async fn upload_offset(client: &Client, location: &str) -> CliResult<u64> {
+    let res = client
+        .head(location)
+        .header("Tus-Resumable", TUS_VERSION)
         .send()
         .await?;
 
@@ -70,32 +626,139 @@ pub async fn upload_file(upload_path: &Path, file_path: &Path) -> CliResult<Stri
         });
     }
 
-    let media_endpoint = res.text().await?.to_string();
+    Ok(offset_header(&res))
+}
 
-    Ok(format!(
-        "{}/files/{}",
-        CLI_CONFIG.server_url, media_endpoint
-    ))
+/// Sends the rest of the file starting at `offset`, returns the offset the server got to.
+async fn append_to_upload(
+    client: &Client,
+    location: &str,
+    file_path: &Path,
+    offset: u64,
+    pb: &ProgressBar,
+) -> CliResult<u64> {
+    let res = client
+        .patch(location)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header("Upload-Offset", offset)
+        .header("Content-Type", "application/offset+octet-stream")
+        .body(Body::wrap_stream(
+            upload_stream(file_path, offset, pb.clone()).await?,
+        ))
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(CliError::FailStatusCode {
+            status_code: res.status(),
+        });
+    }
+
+    Ok(offset_header(&res))
+}
+
+fn offset_header(res: &reqwest::Response) -> u64 {
+    res.headers()
+        .get("upload-offset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
 }
 
 async fn upload_stream(
     path: &Path,
+    offset: u64,
+    pb: ProgressBar,
 ) -> CliResult<impl Stream<Item = std::result::Result<Bytes, std::io::Error>>> {
-    let file = tokio::fs::File::open(path).await?;
-    let size = file.metadata().await?.len();
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
 
     let mut reader_stream = ReaderStream::new(file);
     Ok(async_stream::stream! {
-        let pb = ProgressBar::new(size);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap());
         while let Some(chunk) = reader_stream.next().await {
             if let Ok(ref chunk) = chunk {
                 pb.inc(chunk.len() as u64);
             }
             yield chunk;
         }
-        pb.finish();
     })
 }
+
+fn read_resumable() -> HashMap<String, String> {
+    std::fs::read_to_string(DATA_DIR.join(RESUME_FILE))
+        .ok()
+        .and_then(|s| serde_yaml::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn update_resumable(f: impl FnOnce(&mut HashMap<String, String>)) {
+    let _guard = RESUME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut uploads = read_resumable();
+    f(&mut uploads);
+    save_resumable(&uploads);
+}
+
+fn save_resumable(uploads: &HashMap<String, String>) {
+    let result = serde_yaml::to_string(uploads)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(DATA_DIR.join(RESUME_FILE), s).map_err(|e| e.to_string()));
+
+    // Not fatal, the upload just can't be resumed after a restart
+    if let Err(e) = result {
+        warn!("Failed to save resumable uploads: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_need_a_file_name() {
+        assert_eq!(file_name(Path::new("dir/a.txt")).unwrap(), "a.txt");
+        assert!(matches!(
+            file_name(Path::new("..")),
+            Err(CliError::NoFileName { .. })
+        ));
+    }
+
+    #[test]
+    fn ignored_files_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("ocloud_walk_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("src/target")).unwrap();
+        for (name, contents) in [
+            (IGNORE_FILE, "*.log\n"),
+            ("src/.gitignore", "target/\n"),
+            ("a.txt", ""),
+            ("debug.log", ""),
+            ("src/main.rs", ""),
+            ("src/target/out", ""),
+        ] {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let (mut files, skipped, failed) = collect_files(&dir);
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                PathBuf::from(IGNORE_FILE),
+                PathBuf::from("a.txt"),
+                PathBuf::from("src/.gitignore"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+        assert_eq!(skipped, 2);
+        assert!(failed.is_empty());
+
+        // The same rules hold for files that aren't there
+        let rules = IgnoreRules::new(&dir, &files);
+        assert!(rules.is_ignored("other.log"));
+        assert!(rules.is_ignored("src/target/new"));
+        assert!(rules.is_ignored("src/deep/er.log"));
+        assert!(!rules.is_ignored("target/new"));
+        assert!(!rules.is_ignored("src/lib.rs"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
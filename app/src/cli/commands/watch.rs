@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify_debouncer_full::{
+    new_debouncer,
+    notify::{
+        event::{ModifyKind, RemoveKind, RenameMode},
+        EventKind, RecursiveMode,
+    },
+    DebounceEventResult, DebouncedEvent,
+};
+use reqwest::StatusCode;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use super::{
+    api_client, copy_to_clipboard,
+    files::{files_under, public_link},
+    remote_path,
+    upload::{self, UploadProgress},
+};
+use crate::{
+    api::{ApiClient, ApiError},
+    cli::error::{CliError, CliResult},
+};
+
+/// How long a file has to be left alone before it's uploaded, so files
+/// still being written go up once they're done
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Change {
+    Written,
+    Removed { dir: bool },
+}
+
+/// Uploads files under `path` to `dir` whenever they're created or changed,
+/// until interrupted. Files that are already there aren't uploaded.
+pub async fn handler(
+    path: PathBuf,
+    dir: String,
+    delete: bool,
+    public: bool,
+    copy: bool,
+) -> CliResult<()> {
+    let root = std::fs::canonicalize(&path)?;
+    let dir = dir.trim_matches('/').to_string();
+    let client = api_client()?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| {
+        let _ = tx.send(result);
+    })
+    .map_err(watch_error)?;
+    debouncer
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(watch_error)?;
+
+    println!(
+        "Watching {} for changes, uploading to {}. Ctrl-C to stop.",
+        root.to_string_lossy(),
+        remote_path(&dir)
+    );
+
+    let mut copy = copy;
+    loop {
+        let events = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            events = rx.recv() => match events {
+                Some(Ok(events)) => events,
+                Some(Err(errors)) => {
+                    for e in errors {
+                        warn!("Watch error: {e}");
+                    }
+                    continue;
+                }
+                None => break,
+            },
+        };
+
+        for (path, change) in changes(&events) {
+            let Ok(relative) = path.strip_prefix(&root) else {
+                continue;
+            };
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let remote = if dir.is_empty() {
+                relative.clone()
+            } else {
+                format!("{dir}/{relative}")
+            };
+
+            match change {
+                Change::Written => {
+                    for (local, remote) in written_files(&path, &remote) {
+                        match put(&client, &local, &remote, public).await {
+                            Ok(url) => {
+                                println!("{remote} -> {url}");
+                                if copy {
+                                    if let Err(e) = copy_to_clipboard(&url) {
+                                        // Same result every time, so only say it once
+                                        warn!("Not copying links, the clipboard isn't available: {e:?}");
+                                        copy = false;
+                                    }
+                                }
+                            }
+                            Err(e) => eprintln!("Failed to upload {remote}: {e:?}"),
+                        }
+                    }
+                }
+                Change::Removed { dir: is_dir } if delete => {
+                    // The trash only takes files, so a directory goes one file at a time
+                    let targets = if is_dir {
+                        match files_under(&client, &remote).await {
+                            Ok(files) => files,
+                            Err(e) => {
+                                eprintln!("Failed to list {remote}: {e:?}");
+                                continue;
+                            }
+                        }
+                    } else {
+                        vec![remote]
+                    };
+                    for remote in targets {
+                        match client.delete_file(&remote_path(&remote)).await {
+                            Ok(()) => println!("Moved {remote} to the trash."),
+                            // Never uploaded, e.g. created and deleted before we got to it
+                            Err(ApiError::Http { status, .. })
+                                if status == StatusCode::NOT_FOUND => {}
+                            Err(e) => eprintln!("Failed to delete {remote}: {e:?}"),
+                        }
+                    }
+                }
+                Change::Removed { .. } => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// What happened to each path in a batch of events, by its state now.
+/// Renames are a removal of the old path and a write of the new one.
+fn changes(events: &[DebouncedEvent]) -> BTreeMap<PathBuf, Change> {
+    let mut changes = BTreeMap::new();
+    for event in events {
+        let paths = &event.event.paths;
+        match event.event.kind {
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                for path in paths {
+                    changes.insert(path.clone(), Change::Written);
+                }
+            }
+            EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => {
+                // A directory's contents have events of their own
+                for path in paths.iter().filter(|path| !path.is_dir()) {
+                    changes.insert(path.clone(), Change::Written);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                changes.insert(
+                    paths[0].clone(),
+                    Change::Removed {
+                        dir: paths[1].is_dir(),
+                    },
+                );
+                changes.insert(paths[1].clone(), Change::Written);
+            }
+            EventKind::Remove(kind) => {
+                for path in paths {
+                    let dir = kind == RemoveKind::Folder;
+                    changes.insert(path.clone(), Change::Removed { dir });
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                for path in paths {
+                    changes.insert(path.clone(), Change::Removed { dir: false });
+                }
+            }
+            // Metadata changes and reads don't change what's uploaded
+            _ => {}
+        }
+    }
+
+    // Whatever came last, what's on disk now decides
+    changes.retain(|path, change| match change {
+        Change::Written => path.exists(),
+        Change::Removed { .. } => !path.exists(),
+    });
+
+    // Anything in a directory that was added or removed as a whole goes with it
+    let whole_dirs: Vec<(PathBuf, bool)> = changes
+        .iter()
+        .filter_map(|(path, change)| match change {
+            Change::Written if path.is_dir() => Some((path.clone(), true)),
+            Change::Removed { dir: true } => Some((path.clone(), false)),
+            _ => None,
+        })
+        .collect();
+    changes.retain(|path, change| {
+        let written = *change == Change::Written;
+        !whole_dirs.iter().any(|(dir, dir_written)| {
+            *dir_written == written && path != dir && path.starts_with(dir)
+        })
+    });
+    changes
+}
+
+/// A written file, or everything in a directory that appeared all at once.
+fn written_files(path: &Path, remote: &str) -> Vec<(PathBuf, String)> {
+    if path.is_file() {
+        return vec![(path.to_path_buf(), remote.to_string())];
+    }
+
+    let (files, _, _) = upload::collect_files(path);
+    files
+        .into_iter()
+        .map(|file| {
+            let name = file
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            (path.join(&file), format!("{remote}/{name}"))
+        })
+        .collect()
+}
+
+/// Uploads through the same path as `ocloud upload`, returning the link to print.
+async fn put(client: &ApiClient, local: &Path, remote: &str, public: bool) -> CliResult<String> {
+    let upload_path = PathBuf::from(remote_path(remote));
+    let url = upload::upload_file(&upload_path, local, None, &UploadProgress::default()).await?;
+    if !public {
+        return Ok(url);
+    }
+
+    let file = client
+        .change_file_visibility(&remote_path(remote), true)
+        .await?;
+    public_link(&file)
+}
+
+fn watch_error(e: notify_debouncer_full::notify::Error) -> CliError {
+    CliError::IoError { err: e.to_string() }
+}
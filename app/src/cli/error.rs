@@ -1,4 +1,4 @@
-use crate::server;
+use crate::{api::ApiError, config, server};
 use reqwest::StatusCode;
 
 pub type CliResult<T> = std::result::Result<T, CliError>;
@@ -7,10 +7,18 @@ pub type CliResult<T> = std::result::Result<T, CliError>;
 pub enum CliError {
     ServerError { err: server::error::ServerError },
     NoFileFound,
+    NoFileName { path: String },
     IoError { err: String },
     ReqwestError { err: reqwest::Error },
     FailStatusCode { status_code: StatusCode },
     UrlParseError { issue: String },
+    ApiError { err: ApiError },
+    ConfigError { err: config::Error },
+    NotLoggedIn,
+    NoProfile { name: String },
+    ProfileExists { name: String },
+    ClipboardError { err: arboard::Error },
+    UploadFailed { name: String, err: String },
 }
 
 impl From<std::io::Error> for CliError {
@@ -40,3 +48,21 @@ impl From<server::error::ServerError> for CliError {
         Self::ServerError { err: value }
     }
 }
+
+impl From<ApiError> for CliError {
+    fn from(value: ApiError) -> Self {
+        Self::ApiError { err: value }
+    }
+}
+
+impl From<config::Error> for CliError {
+    fn from(value: config::Error) -> Self {
+        Self::ConfigError { err: value }
+    }
+}
+
+impl From<arboard::Error> for CliError {
+    fn from(value: arboard::Error) -> Self {
+        Self::ClipboardError { err: value }
+    }
+}
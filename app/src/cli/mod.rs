@@ -1,12 +1,11 @@
-use std::process::exit;
+use std::{path::Path, process::exit};
 
 use crate::config::DATA_DIR;
-use crate::config::{self, YamlConfig, CLI_CONFIG, CONFIG_DIR};
+use crate::config::{self, cli::PROFILE_OVERRIDE, YamlConfig, CLI_CONFIG, CONFIG_DIR};
 use clap::Parser;
 use error::CliResult;
 use subcommands::SubCommand;
-use tracing::error;
-mod api;
+use tracing::{error, warn};
 mod commands;
 pub mod error;
 mod subcommands;
@@ -16,27 +15,125 @@ mod subcommands;
 struct Cli {
     #[command(subcommand)]
     command: SubCommand,
+    /// The server profile to use instead of the current one.
+    #[arg(long = "profile", env = "OCLOUD_PROFILE", global = true)]
+    profile: Option<String>,
 }
 
 pub async fn run() -> CliResult<()> {
+    #[cfg(target_os = "linux")]
+    if std::env::var_os(commands::HOLD_CLIPBOARD).is_some() {
+        return commands::hold_clipboard();
+    }
+
     let cli = Cli::parse();
 
+    if let Some(profile) = cli.profile {
+        let _ = PROFILE_OVERRIDE.set(profile);
+    }
     config::init();
 
-    // let mut clipboard: Clipboard = Clipboard::new()?;
-
     match cli.command {
         SubCommand::Upload {
             path,
+            name,
             preserve,
             dir,
+            expires,
+            jobs,
+            public,
+            copy,
+            no_copy,
         } => {
-            let s = commands::upload::handler(path, preserve, dir).await?;
-            println!("File can be found at {s}");
+            let copy = (copy || CLI_CONFIG.copy_links) && !no_copy;
+            if path == Path::new("-") {
+                // clap makes sure there's a name
+                let name = name.unwrap_or_default();
+                let url = commands::upload::stream_handler(
+                    tokio::io::stdin(),
+                    name,
+                    dir,
+                    expires,
+                    public,
+                )
+                .await?;
+                print_link(&url, public, copy);
+            } else if path.is_dir() {
+                if public {
+                    error!("--public only works when uploading a single file.");
+                    exit(1);
+                }
+
+                let summary =
+                    commands::upload::dir_handler(path, preserve, dir, expires, jobs).await?;
+                println!("{summary}");
+                if copy {
+                    copy_link(&summary.url);
+                }
+                if !summary.failed.is_empty() {
+                    exit(1);
+                }
+            } else {
+                let url = commands::upload::handler(path, preserve, dir, expires, public).await?;
+                print_link(&url, public, copy);
+            }
+        }
+        SubCommand::Paste {
+            name,
+            dir,
+            expires,
+            public,
+            copy,
+            no_copy,
+        } => {
+            let copy = (copy || CLI_CONFIG.copy_links) && !no_copy;
+            let url = commands::paste::handler(name, dir, expires, public).await?;
+            print_link(&url, public, copy);
+        }
+        SubCommand::Login { username } => commands::auth::login(username).await?,
+        SubCommand::Register => commands::auth::register().await?,
+        SubCommand::Logout => commands::auth::logout().await?,
+        SubCommand::Whoami => commands::auth::whoami().await?,
+        SubCommand::Ls { path, long, json } => commands::files::ls(path, long, json).await?,
+        SubCommand::Get { path, output } => commands::files::get(path, output).await?,
+        SubCommand::Rm { paths } => commands::files::rm(paths).await?,
+        SubCommand::Mv { from, to } => commands::files::mv(from, to).await?,
+        SubCommand::Mkdir { path } => commands::files::mkdir(path).await?,
+        SubCommand::Publish { path } => commands::files::publish(path, true).await?,
+        SubCommand::Unpublish { path } => commands::files::publish(path, false).await?,
+        SubCommand::Share {
+            path,
+            user_id,
+            role,
+            revoke,
+        } => commands::files::share(path, user_id, role, revoke).await?,
+        SubCommand::Watch {
+            path,
+            dir,
+            delete,
+            public,
+            copy,
+        } => commands::watch::handler(path, dir, delete, public, copy).await?,
+        SubCommand::Shell => commands::shell::handler().await?,
+        SubCommand::Sync {
+            local,
+            remote,
+            dry_run,
+        } => {
+            let summary = commands::sync::handler(local, remote, dry_run).await?;
+            println!("{summary}");
+            if dry_run {
+                println!("Dry run, nothing was changed.");
+            }
+            if !summary.failed.is_empty() {
+                exit(1);
+            }
         }
         SubCommand::SetUrl { url } => {
             let mut config_new = CLI_CONFIG.clone();
-            config_new.server_url = url.to_string().trim_matches('/').into();
+            let name = config_new.active_name().to_string();
+            config_new.profiles.entry(name).or_default().server_url =
+                url.to_string().trim_matches('/').into();
 
             if let Err(e) = config_new.save() {
                 error!("Failed to save config changes: {e:?}");
@@ -45,9 +142,19 @@ pub async fn run() -> CliResult<()> {
 
             println!("Done.");
         }
+        SubCommand::SetCopy { enabled } => {
+            let mut config_new = CLI_CONFIG.clone();
+            config_new.copy_links = enabled;
+            config_new.save()?;
+
+            println!("Done.");
+        }
         SubCommand::Server { command } => {
             commands::server::handler(command).await?;
         }
+        SubCommand::Profile { command } => {
+            commands::profile::handler(command).await?;
+        }
         SubCommand::Paths => {
             println!("Config files: {}", CONFIG_DIR.to_string_lossy());
             println!("Media and other data: {}", DATA_DIR.to_string_lossy());
@@ -56,3 +163,23 @@ pub async fn run() -> CliResult<()> {
 
     Ok(())
 }
+
+fn print_link(url: &str, public: bool, copy: bool) {
+    if public {
+        println!("Anyone can open it at {url}");
+    } else {
+        println!("File can be found at {url}");
+    }
+    if copy {
+        copy_link(url);
+    }
+}
+
+/// The upload already worked, so not being able to copy its link isn't an error.
+/// On Linux a background process holds the link until something else is copied.
+fn copy_link(url: &str) {
+    match commands::copy_to_clipboard(url) {
+        Ok(()) => println!("Copied to the clipboard."),
+        Err(e) => warn!("Couldn't copy the link: {e:?}"),
+    }
+}
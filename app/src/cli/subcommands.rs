@@ -1,13 +1,18 @@
 use std::path::PathBuf;
 
-use clap::Subcommand;
+use clap::{builder::BoolishValueParser, ArgAction, Subcommand};
 use url::Url;
 
 #[derive(Subcommand, Debug)]
 pub enum SubCommand {
-    /// Upload files to oCloud.
+    /// Upload files to oCloud. Directories are uploaded with everything in them,
+    /// leaving out what their .ocloudignore and .gitignore files match.
+    /// Use - as the path to upload what's piped in, named with --name.
     Upload {
         path: PathBuf,
+        /// The name to give what's uploaded from stdin.
+        #[arg(long = "name", required_if_eq("path", "-"))]
+        name: Option<String>,
         /// Preserve the directory structure relative to the cwd when uploading.
         /// Ex: ocloud upload -p ./books/fiction/AM.pdf will be uploaded to
         /// endpoint.com/media/root/books/fiction/AM.pdf
@@ -17,9 +22,135 @@ pub enum SubCommand {
         /// The target directory to upload the file to.
         #[arg(short = 'd', long = "dir", default_value = "")]
         dir: String,
+        /// Delete the file after a while, e.g. 30m, 12h, 7d,
+        /// or at a time like 2030-01-01T00:00:00Z.
+        #[arg(short = 'e', long = "expires")]
+        expires: Option<String>,
+        /// How many files of a directory to upload at once.
+        #[arg(short = 'j', long = "jobs", default_value = "4")]
+        jobs: usize,
+        /// Make the file public and print its public link instead.
+        #[arg(long = "public")]
+        public: bool,
+        /// Copy the link to the clipboard, the default after set-copy on.
+        #[arg(short = 'c', long = "copy", overrides_with = "no_copy")]
+        copy: bool,
+        /// Don't copy the link, even with set-copy on.
+        #[arg(long = "no-copy", overrides_with = "copy")]
+        no_copy: bool,
     },
-    /// Set the base url of the server to use.
+    /// Upload what's on the clipboard, text as a .txt file and images as a .png.
+    Paste {
+        /// The name to give the file instead of one with the time in it.
+        #[arg(long = "name")]
+        name: Option<String>,
+        /// The target directory to upload the file to.
+        #[arg(short = 'd', long = "dir", default_value = "")]
+        dir: String,
+        /// Delete the file after a while, e.g. 30m, 12h, 7d.
+        #[arg(short = 'e', long = "expires")]
+        expires: Option<String>,
+        /// Make the file public and print its public link instead.
+        #[arg(long = "public")]
+        public: bool,
+        /// Copy the link to the clipboard, the default after set-copy on.
+        #[arg(short = 'c', long = "copy", overrides_with = "no_copy")]
+        copy: bool,
+        /// Don't copy the link, even with set-copy on.
+        #[arg(long = "no-copy", overrides_with = "copy")]
+        no_copy: bool,
+    },
+    /// Log in to the server, the session is kept for later commands.
+    Login { username: Option<String> },
+    /// Create an account on the server and log in with it.
+    Register,
+    /// End the current session.
+    Logout,
+    /// Print who you're logged in as.
+    Whoami,
+    /// List a directory, relative to your root directory.
+    Ls {
+        #[arg(default_value = "")]
+        path: String,
+        /// Show whether each file is public, when it was modified and when it expires.
+        #[arg(short = 'l', long = "long")]
+        long: bool,
+        /// Print the files as JSON.
+        #[arg(long = "json", conflicts_with = "long")]
+        json: bool,
+    },
+    /// Download a file.
+    #[command(alias = "download")]
+    Get {
+        path: String,
+        /// Where to save it, defaults to its name in the cwd. Use - for stdout.
+        #[arg(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+    },
+    /// Move files to the trash. Directories go one file at a time, and stay behind empty.
+    Rm {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Move or rename a file or directory.
+    Mv { from: String, to: String },
+    /// Create a directory, along with any parents it's missing.
+    Mkdir { path: String },
+    /// Make a file public and print a link to it.
+    Publish { path: String },
+    /// Make a file private again.
+    Unpublish { path: String },
+    /// Give another user access to a file.
+    Share {
+        path: String,
+        /// The id of the user to share with.
+        user_id: u64,
+        #[arg(short = 'r', long = "role", default_value = "viewer", value_parser = ["viewer", "editor", "owner"])]
+        role: String,
+        /// Take the role away instead.
+        #[arg(long = "revoke")]
+        revoke: bool,
+    },
+    /// Upload files in a directory as they're created or changed, until stopped.
+    Watch {
+        path: PathBuf,
+        /// The target directory to upload them to.
+        #[arg(short = 'd', long = "dir", default_value = "")]
+        dir: String,
+        /// Move files to the trash when they're deleted locally.
+        #[arg(long = "delete")]
+        delete: bool,
+        /// Make each upload public and print its public link.
+        #[arg(long = "public")]
+        public: bool,
+        /// Copy each link to the clipboard.
+        #[arg(short = 'c', long = "copy")]
+        copy: bool,
+    },
+    /// Browse your files interactively, with cd, ls, put, get and friends.
+    Shell,
+    /// Sync a local folder with a remote directory both ways.
+    /// Files changed on both sides since the last sync are kept as conflict copies.
+    Sync {
+        local: PathBuf,
+        #[arg(default_value = "")]
+        remote: String,
+        /// Print what would change without changing anything.
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Set the base url of the server to use, for the current profile.
     SetUrl { url: Url },
+    /// Whether uploads copy their link to the clipboard without --copy.
+    SetCopy {
+        #[arg(action = ArgAction::Set, value_parser = BoolishValueParser::new())]
+        enabled: bool,
+    },
+    /// Manage the servers the CLI can talk to, each with its own login.
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
     /// Manage or run the oCloud server.
     Server {
         #[command(subcommand)]
@@ -42,4 +173,43 @@ pub enum ServerCommand {
     },
     /// Clears all data in the server, including uploaded files, etc.
     Wipe,
+    /// Checks the database against the stored files. Reports orphaned blobs,
+    /// media rows with missing blobs, the files pointing at them and stale temp files.
+    Fsck {
+        /// Repair what was found. Files whose contents are missing are deleted.
+        #[arg(long = "fix")]
+        fix: bool,
+    },
+    /// Makes a user an admin, who can see server-wide things like scrub results.
+    MakeAdmin { username: String },
+    /// Sets how many bytes a user can store.
+    SetQuota {
+        username: String,
+        /// Leave out to go back to the server's default_quota.
+        bytes: Option<u64>,
+    },
+    /// Finds storage that nothing uses anymore, like orphaned blobs and stale temp files.
+    Gc {
+        /// Delete what was found.
+        #[arg(long = "fix")]
+        fix: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommand {
+    /// Adds a profile for a server.
+    Add {
+        name: String,
+        url: Url,
+        /// Switch to it straight away.
+        #[arg(long = "use")]
+        r#use: bool,
+    },
+    /// Lists the profiles, marking the one in use.
+    List,
+    /// Makes a profile the current one.
+    Use { name: String },
+    /// Removes a profile along with its session.
+    Remove { name: String },
 }
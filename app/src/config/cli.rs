@@ -1,13 +1,101 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
 use serde::{Deserialize, Serialize};
 
 use super::YamlConfig;
 
+/// Name of the profile older configs are moved into
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Set from --profile or OCLOUD_PROFILE, overrides the current profile for one run
+pub static PROFILE_OVERRIDE: OnceLock<String> = OnceLock::new();
+
 #[derive(Deserialize, Serialize, Clone)]
+#[serde(from = "StoredCliConfig")]
 pub struct CliConfig {
+    /// The profile used unless another is picked
+    pub current: String,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Copy links to the clipboard after uploading without being asked to
+    #[serde(default)]
+    pub copy_links: bool,
+}
+
+/// A server and who we're logged in as there.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Profile {
     pub server_url: String,
+    /// Sent as the bearer token with every request, set by login
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+}
+
+impl CliConfig {
+    pub fn active_name(&self) -> &str {
+        PROFILE_OVERRIDE.get().unwrap_or(&self.current)
+    }
+
+    pub fn active(&self) -> Option<&Profile> {
+        self.profiles.get(self.active_name())
+    }
+}
+
+/// What's in cli.yaml, either with profiles or from before there were any.
+#[derive(Deserialize)]
+struct StoredCliConfig {
+    current: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    server_url: Option<String>,
+    session: Option<String>,
+    #[serde(default)]
+    copy_links: bool,
+}
+
+impl From<StoredCliConfig> for CliConfig {
+    fn from(stored: StoredCliConfig) -> Self {
+        let mut profiles = stored.profiles;
+        if let Some(server_url) = stored.server_url {
+            profiles
+                .entry(DEFAULT_PROFILE.to_string())
+                .or_insert(Profile {
+                    server_url,
+                    session: stored.session,
+                });
+        }
+
+        Self {
+            current: stored
+                .current
+                .unwrap_or_else(|| DEFAULT_PROFILE.to_string()),
+            profiles,
+            copy_links: stored.copy_links,
+        }
+    }
 }
 
 impl YamlConfig for CliConfig {
     const CONFIG_NAME: &'static str = "cli";
     const DEFAULT_YAML: &'static str = include_str!("../../configuration/cli.yaml");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_configs_become_the_default_profile() {
+        let config: CliConfig =
+            serde_yaml::from_str("server_url: https://cloud.example.com\nsession: abc\n").unwrap();
+        assert_eq!(config.current, DEFAULT_PROFILE);
+        let profile = &config.profiles[DEFAULT_PROFILE];
+        assert_eq!(profile.server_url, "https://cloud.example.com");
+        assert_eq!(profile.session.as_deref(), Some("abc"));
+        assert!(!config.copy_links);
+
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        assert!(!yaml.starts_with("server_url"));
+        let reread: CliConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(reread.profiles.len(), 1);
+    }
+}
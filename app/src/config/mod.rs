@@ -5,7 +5,7 @@ use inquire::Confirm;
 use lazy_static::lazy_static;
 pub use settings::Settings;
 
-mod cli;
+pub mod cli;
 pub mod settings;
 mod util;
 
@@ -8,6 +8,16 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub directories: DirectorySettings,
+    #[serde(default)]
+    pub storage: StorageSettings,
+    #[serde(default)]
+    pub scrubber: ScrubberSettings,
+    #[serde(default)]
+    pub trash: TrashSettings,
+    #[serde(default)]
+    pub versions: VersionSettings,
+    #[serde(default)]
+    pub extract: ExtractSettings,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -15,6 +25,17 @@ pub struct BaseConfig {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub directories: DirectorySettings,
+    // Older configs don't have it, default to local storage
+    #[serde(default)]
+    pub storage: StorageSettings,
+    #[serde(default)]
+    pub scrubber: ScrubberSettings,
+    #[serde(default)]
+    pub trash: TrashSettings,
+    #[serde(default)]
+    pub versions: VersionSettings,
+    #[serde(default)]
+    pub extract: ExtractSettings,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -67,6 +88,10 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub environment: Environment,
     pub max_filesize: Option<usize>,
+    /// Bytes each user can store unless they have a quota of their own.
+    /// None for no limit.
+    #[serde(default)]
+    pub default_quota: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -85,6 +110,110 @@ pub struct DirectorySettings {
     pub files_dir: PathBuf,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    // Only needed for the s3 backend
+    pub s3: Option<S3Settings>,
+}
+
+/// The background task that re-hashes stored media to catch corruption.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ScrubberSettings {
+    pub enabled: bool,
+    /// How fast blobs are read while scrubbing, 0 for no limit
+    pub bytes_per_second: u64,
+    /// How long each media goes between checks
+    pub recheck_after_hours: u64,
+}
+
+/// Where deleted files wait before they're gone for good.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TrashSettings {
+    /// How long files stay in the trash, 0 to keep them until it's emptied
+    pub retention_days: u64,
+}
+
+/// How much of each file's history is kept. Older versions are pruned
+/// if either limit says so, the current version never is.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct VersionSettings {
+    /// How many versions of a file to keep, 0 for no limit
+    pub keep_versions: u64,
+    /// How long old versions are kept, 0 for no limit
+    pub keep_days: u64,
+}
+
+/// Limits on archives unpacked with `?extract=true`, so a small upload
+/// can't expand into something huge.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ExtractSettings {
+    /// How many files and directories an archive can have, 0 for no limit
+    pub max_entries: u64,
+    /// How many bytes the files in an archive can add up to, 0 for no limit
+    pub max_expanded_bytes: u64,
+}
+
+impl Default for ExtractSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_expanded_bytes: 4 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for VersionSettings {
+    fn default() -> Self {
+        Self {
+            keep_versions: 10,
+            keep_days: 0,
+        }
+    }
+}
+
+impl Default for TrashSettings {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+impl Default for ScrubberSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bytes_per_second: 8 * 1024 * 1024,
+            recheck_after_hours: 7 * 24,
+        }
+    }
+}
+
+/// Where uploaded files are kept.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// In `directories.files_dir`
+    #[default]
+    #[serde(alias = "local")]
+    Local,
+    /// In a bucket of an S3 compatible service
+    #[serde(alias = "s3")]
+    S3,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct S3Settings {
+    /// e.g. `https://s3.us-east-1.amazonaws.com` or `http://localhost:9000` for MinIO
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum Environment {
     #[serde(alias = "development", alias = "dev")]
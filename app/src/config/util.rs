@@ -1,6 +1,7 @@
 use super::{Error, Result, CONFIG_DIR};
 use serde::{de::DeserializeOwned, Serialize};
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 
 pub fn read_yaml<T>(rel_path: impl AsRef<Path> + ToString) -> Result<T>
@@ -18,5 +19,24 @@ where
     let path = CONFIG_DIR.join(rel_path);
 
     let content = serde_yaml::to_string(&config).map_err(|_| Error::SerializeError)?;
-    fs::write(path, content).map_err(Error::from)
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Configs can hold the session token, only the user gets to read them
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path)?;
+
+    // The mode only applies to new files, older ones may still be readable by others
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())?;
+
+    Ok(())
 }
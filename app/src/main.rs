@@ -1,12 +1,8 @@
 use std::env::set_var;
 
-use cli::error::CliResult;
+use ocloud::cli::{self, error::CliResult};
 use tracing_subscriber::EnvFilter;
 
-mod cli;
-mod config;
-mod server;
-
 #[tokio::main]
 async fn main() -> CliResult<()> {
     set_var("RUST_LOG", "none,ocloud=trace");
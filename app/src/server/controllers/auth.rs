@@ -4,7 +4,7 @@ use uuid::Uuid;
 
 use crate::server::{
     error::{ServerError, ServerResult},
-    models::auth::*,
+    models::{auth::*, files::VirtualPath},
 };
 
 #[derive(Clone)]
@@ -206,6 +206,154 @@ impl AuthController {
         Ok(())
     }
 
+    /// Create an API token. Returns the token itself, which is never stored
+    pub async fn create_api_token(
+        &self,
+        user_id: i64,
+        request: CreateTokenRequest,
+    ) -> ServerResult<(String, ApiToken)> {
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err(ServerError::ValidationError {
+                message: "Tokens need a name".to_string(),
+            });
+        }
+        if request.scopes.is_empty() {
+            return Err(ServerError::ValidationError {
+                message: "Tokens need at least one scope".to_string(),
+            });
+        }
+        if request.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(ServerError::ValidationError {
+                message: "expires_at is in the past".to_string(),
+            });
+        }
+
+        let path_prefix = request
+            .path_prefix
+            .map(|prefix| {
+                VirtualPath::try_from_string(&prefix)
+                    .map(|path| path.as_dir().to_string_with_trailing())
+                    .map_err(|e| ServerError::ValidationError {
+                        message: e.to_string(),
+                    })
+            })
+            .transpose()?;
+
+        let mut scopes: Vec<String> = request
+            .scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect();
+        scopes.sort();
+        scopes.dedup();
+
+        let token = api_token::generate();
+        let api_token = sqlx::query_as!(
+            ApiToken,
+            r#"
+            INSERT INTO api_tokens (user_id, name, token_hash, scopes, path_prefix, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id, name) DO NOTHING
+            RETURNING id, user_id, name, token_hash, scopes, path_prefix, expires_at, created_at, last_used
+            "#,
+            user_id,
+            name,
+            api_token::hash(&token),
+            &scopes,
+            path_prefix,
+            request.expires_at.map(|at| at.naive_utc())
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to create token: {e}"),
+        })?
+        .ok_or_else(|| ServerError::ValidationError {
+            message: format!("A token named {name} already exists"),
+        })?;
+
+        Ok((token, api_token))
+    }
+
+    /// A user's API tokens, newest first
+    pub async fn list_api_tokens(&self, user_id: i64) -> ServerResult<Vec<ApiToken>> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"
+            SELECT id, user_id, name, token_hash, scopes, path_prefix, expires_at, created_at, last_used
+            FROM api_tokens WHERE user_id = $1
+            ORDER BY created_at DESC, id DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to list tokens: {e}"),
+        })
+    }
+
+    /// Revoke one of a user's API tokens
+    pub async fn revoke_api_token(&self, user_id: i64, token_id: i64) -> ServerResult<()> {
+        let result = sqlx::query!(
+            "DELETE FROM api_tokens WHERE id = $1 AND user_id = $2",
+            token_id,
+            user_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to revoke token: {e}"),
+        })?;
+
+        if result.rows_affected() == 0 {
+            return Err(ServerError::PathDoesntExist);
+        }
+
+        Ok(())
+    }
+
+    /// Validate an API token and return its user
+    pub async fn validate_api_token(&self, token: &str) -> ServerResult<(User, ApiToken)> {
+        let api_token = sqlx::query_as!(
+            ApiToken,
+            r#"
+            UPDATE api_tokens
+            SET last_used = CURRENT_TIMESTAMP
+            WHERE token_hash = $1
+            AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            RETURNING id, user_id, name, token_hash, scopes, path_prefix, expires_at, created_at, last_used
+            "#,
+            api_token::hash(token)
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to find token: {e}"),
+        })?
+        .ok_or_else(|| ServerError::AuthenticationError {
+            message: "Invalid or expired token".to_string(),
+        })?;
+
+        let user = sqlx::query_as!(
+            User,
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, last_login 
+             FROM users WHERE id = $1 AND is_active = true",
+            api_token.user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to find user: {e}"),
+        })?
+        .ok_or_else(|| ServerError::AuthenticationError {
+            message: "User not found".to_string(),
+        })?;
+
+        Ok((user, api_token))
+    }
+
     /// Build auth context for a user (load all their permissions)
     /// TODO! seperate into multiple queuries?
     pub async fn build_auth_context(&self, user_id: i64) -> ServerResult<AuthContext> {
@@ -313,6 +461,65 @@ impl AuthController {
         Ok(())
     }
 
+    /// Makes the user an admin. Only done from the server's CLI,
+    /// since there's no one to grant it before the first admin exists.
+    pub async fn make_admin(&self, username: &str) -> ServerResult<()> {
+        let user = sqlx::query!("SELECT id FROM users WHERE username = $1", username)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| ServerError::DatabaseError {
+                message: format!("Failed to find user: {e}"),
+            })?
+            .ok_or_else(|| ServerError::ValidationError {
+                message: format!("No user named {username}"),
+            })?;
+
+        let resource = self.get_or_create_resource(SYSTEM_RESOURCE, None).await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO user_resource_relationships (user_id, resource_id, relationship, granted_at)
+            SELECT $1, $2, 'owner', CURRENT_TIMESTAMP
+            WHERE NOT EXISTS (
+                SELECT 1 FROM user_resource_relationships
+                WHERE user_id = $1 AND resource_id = $2 AND relationship = 'owner'
+            )
+            "#,
+            user.id,
+            resource.id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to make admin: {e}"),
+        })?;
+
+        Ok(())
+    }
+
+    /// Sets how many bytes the user can store, None to go back to the server default.
+    pub async fn set_quota(&self, username: &str, quota_bytes: Option<u64>) -> ServerResult<()> {
+        let updated = sqlx::query!(
+            "UPDATE users SET quota_bytes = $2, updated_at = CURRENT_TIMESTAMP WHERE username = $1",
+            username,
+            quota_bytes.map(|q| q.min(i64::MAX as u64) as i64)
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| ServerError::DatabaseError {
+            message: format!("Failed to set quota: {e}"),
+        })?
+        .rows_affected();
+
+        if updated == 0 {
+            return Err(ServerError::ValidationError {
+                message: format!("No user named {username}"),
+            });
+        }
+
+        Ok(())
+    }
+
     /// Revoke permission from a user on a resource
     pub async fn revoke_permission(
         &self,
@@ -336,7 +543,7 @@ impl AuthController {
         let resource = sqlx::query_as!(
             Resource,
             "SELECT id, resource_type, resource_id, created_at FROM resources 
-             WHERE resource_type = $1 AND resource_id = $2",
+             WHERE resource_type = $1 AND resource_id IS NOT DISTINCT FROM $2",
             request.resource_type,
             request.resource_id.map(|id| id as i64)
         )
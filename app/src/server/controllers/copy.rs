@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+
+use sqlx::{query, Postgres, Transaction};
+use tracing::{error, trace};
+
+use crate::server::{
+    controllers::files::{grant_owner, FileControllerInner},
+    error::{ServerError, ServerResult},
+    models::files::{ConflictPolicy, SFile, VirtualPath},
+};
+
+impl FileControllerInner {
+    /// Copies the user's file or directory tree at `from` to `to`, which names the copy.
+    /// The copies point at the same media, so nothing is copied on disk.
+    /// Missing parent directories of `to` are created. Returns the top of the copy.
+    pub async fn copy(
+        &self,
+        from: &VirtualPath,
+        to: &VirtualPath,
+        on_conflict: ConflictPolicy,
+        user_id: i64,
+    ) -> ServerResult<SFile> {
+        if from.is_root() {
+            return Err(ServerError::BadOperation {
+                details: "Cannot copy the root directory".into(),
+            });
+        }
+
+        let mut tx = self.db_pool().begin().await?;
+
+        let source = self.path_info_transacted(from, user_id, &mut tx).await?;
+        let from = if source.is_dir {
+            from.as_dir()
+        } else {
+            from.as_file()
+        };
+        let mut to = if source.is_dir {
+            to.as_dir()
+        } else {
+            to.as_file()
+        };
+
+        if to.is_root() {
+            return Err(ServerError::BadOperation {
+                details: "Cannot copy over the root directory".into(),
+            });
+        }
+        if to.child_of(&from) {
+            return Err(ServerError::BadOperation {
+                details: "Cannot copy directory into itself or its descendants".into(),
+            });
+        }
+
+        self.make_all_dirs(&to.as_file(), user_id, Some(&mut tx))
+            .await?;
+
+        if self.exists_tx(&to, user_id, &mut tx).await? {
+            match on_conflict {
+                ConflictPolicy::Fail => return Err(ServerError::PathAlreadyExists),
+                ConflictPolicy::Rename => to = self.free_path_tx(&to, user_id, &mut tx).await?,
+                ConflictPolicy::Overwrite => {}
+            }
+        }
+
+        // Breadth first, so the top of the copy comes out first
+        let mut queue = VecDeque::from([(source.id as i64, to)]);
+        let mut top = None;
+        let mut overwritten = Vec::new();
+
+        while let Some((source_id, dest)) = queue.pop_front() {
+            let source = query!(
+                "SELECT media_id, is_dir, expires_at FROM sfiles WHERE id = $1",
+                source_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            let existed = self.exists_tx(&dest, user_id, &mut tx).await?;
+
+            let copy = match (source.is_dir, source.media_id) {
+                (true, _) => self.copy_dir_tx(&dest, existed, user_id, &mut tx).await?,
+                (false, Some(media_id)) => {
+                    // Files in the way get a new version
+                    let file = self
+                        .link_media_tx(&dest, media_id, user_id, source.expires_at, &mut tx)
+                        .await?;
+                    if existed {
+                        overwritten.push(file.id as i64);
+                    }
+                    file
+                }
+                (false, None) => return Err(ServerError::NoMediaFound),
+            };
+
+            if source.is_dir {
+                let children = query!(
+                    "SELECT e.filename, e.child_sfile_id, s.is_dir
+                    FROM sfile_entries e
+                    JOIN sfiles s ON s.id = e.child_sfile_id
+                    WHERE e.parent_sfile_id = $1 AND e.user_id = $2
+                    ORDER BY e.filename",
+                    source_id,
+                    user_id
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+
+                for child in children {
+                    let child_dest = if child.is_dir {
+                        dest.join(&child.filename)?
+                    } else {
+                        dest.join_file(&child.filename)?
+                    };
+                    queue.push_back((child.child_sfile_id, child_dest));
+                }
+            }
+
+            top.get_or_insert(copy);
+        }
+
+        tx.commit().await?;
+
+        for sfile_id in overwritten {
+            if let Err(e) = self.prune_versions(Some(sfile_id)).await {
+                error!("Failed to prune old versions of file {sfile_id}: {e}");
+            }
+        }
+
+        let top = top.expect("the source is always copied");
+        trace!("Copied {from} to {}", top.full_path);
+
+        Ok(top)
+    }
+
+    /// Makes the directory at `dest`, or merges into it if it's there already.
+    async fn copy_dir_tx(
+        &self,
+        dest: &VirtualPath,
+        existed: bool,
+        user_id: i64,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<SFile> {
+        if existed {
+            let existing = self.path_info_transacted(dest, user_id, tx).await?;
+            if !existing.is_dir {
+                return Err(ServerError::PathAlreadyExists);
+            }
+            return Ok(existing);
+        }
+
+        let dir = self.make_dir(dest, user_id, Some(tx)).await?;
+        grant_owner(dir.id as i64, user_id, tx).await?;
+        Ok(dir)
+    }
+
+    async fn exists_tx(
+        &self,
+        vpath: &VirtualPath,
+        user_id: i64,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<bool> {
+        match self.resolve_path_to_sfile_id_tx(vpath, user_id, tx).await {
+            Ok(_) => Ok(true),
+            Err(ServerError::PathDoesntExist) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The first of `name (1)`, `name (2)`... that's free, keeping file extensions.
+    async fn free_path_tx(
+        &self,
+        vpath: &VirtualPath,
+        user_id: i64,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<VirtualPath> {
+        let parent = vpath.parent().unwrap_or_else(VirtualPath::root);
+        let name = vpath.name().unwrap_or_default();
+
+        for n in 1.. {
+            let candidate = if vpath.is_dir() {
+                parent.join(format!("{name} ({n})"))?
+            } else {
+                let numbered = match (vpath.file_stem(), vpath.extension()) {
+                    (Some(stem), Some(ext)) => format!("{stem} ({n}).{ext}"),
+                    _ => format!("{name} ({n})"),
+                };
+                parent.join_file(numbered)?
+            };
+            if !self.exists_tx(&candidate, user_id, tx).await? {
+                return Ok(candidate);
+            }
+        }
+        unreachable!()
+    }
+}
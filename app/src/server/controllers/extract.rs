@@ -0,0 +1,263 @@
+// Archives unpacked into a directory as they're uploaded.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use async_compression::tokio::bufread::GzipDecoder;
+use async_zip::base::read::seek::ZipFileReader;
+use chrono::NaiveDateTime;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use uuid::Uuid;
+
+use crate::{
+    config::SETTINGS,
+    server::{
+        controllers::files::FileControllerInner,
+        error::{ServerError, ServerResult},
+        models::files::{FileUploadInfo, StagedUpload, StorageUsage, VirtualPath},
+        validation::resolve_relative_path,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn from_name(name: &str) -> ServerResult<Self> {
+        let name = name.to_lowercase();
+        if name.ends_with(".zip") {
+            Ok(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(Self::Tar)
+        } else {
+            Err(ServerError::ValidationError {
+                message: "only .zip, .tar and .tar.gz files can be extracted".to_string(),
+            })
+        }
+    }
+}
+
+/// Everything taken out of an archive so far, checked against the limits as it goes.
+struct Staging<'a> {
+    dir: &'a VirtualPath,
+    usage: &'a StorageUsage,
+    user_id: i64,
+    expires_at: Option<NaiveDateTime>,
+    entries: u64,
+    expanded_bytes: u64,
+    staged: StagedUpload,
+    temp_paths: Vec<PathBuf>,
+}
+
+impl FileControllerInner {
+    /// Unpacks the zip, tar or tar.gz file at `archive` into temporary files, to be
+    /// stored under `dir` with `finish_uploads`. The kind of archive comes from
+    /// `archive_name`. The unpacked files have to fit in `usage` as well as the limits.
+    pub async fn stage_archive(
+        &self,
+        archive: &Path,
+        archive_name: &str,
+        dir: &VirtualPath,
+        user_id: i64,
+        expires_at: Option<NaiveDateTime>,
+        usage: &StorageUsage,
+    ) -> ServerResult<StagedUpload> {
+        let kind = ArchiveKind::from_name(archive_name)?;
+        let mut staging = Staging {
+            dir,
+            usage,
+            user_id,
+            expires_at,
+            entries: 0,
+            expanded_bytes: 0,
+            staged: StagedUpload {
+                dirs: vec![dir.clone()],
+                files: Vec::new(),
+            },
+            temp_paths: Vec::new(),
+        };
+
+        let file = BufReader::new(File::open(archive).await?);
+        let result = match kind {
+            ArchiveKind::Zip => read_zip(file, &mut staging).await,
+            ArchiveKind::Tar => read_tar(file, &mut staging).await,
+            ArchiveKind::TarGz => read_tar(GzipDecoder::new(file), &mut staging).await,
+        };
+
+        if let Err(e) = result {
+            for temp_path in &staging.temp_paths {
+                let _ = fs::remove_file(temp_path).await;
+            }
+            return Err(e);
+        }
+
+        Ok(staging.staged)
+    }
+}
+
+async fn read_zip(file: BufReader<File>, staging: &mut Staging<'_>) -> ServerResult<()> {
+    let mut zip = ZipFileReader::with_tokio(file)
+        .await
+        .map_err(invalid_archive)?;
+
+    for index in 0..zip.file().entries().len() {
+        staging.count_entry()?;
+
+        let entry = &zip.file().entries()[index];
+        let name = entry
+            .filename()
+            .as_str()
+            .map_err(invalid_archive)?
+            .to_string();
+        if entry.dir().map_err(invalid_archive)? {
+            staging.add_dir(&name)?;
+            continue;
+        }
+
+        let reader = zip
+            .reader_without_entry(index)
+            .await
+            .map_err(invalid_archive)?;
+        staging.add_file(&name, reader.compat()).await?;
+    }
+
+    Ok(())
+}
+
+async fn read_tar<R: AsyncRead + Unpin>(reader: R, staging: &mut Staging<'_>) -> ServerResult<()> {
+    let mut tar = tokio_tar::Archive::new(reader);
+    let mut entries = tar.entries().map_err(invalid_archive)?;
+
+    while let Some(entry) = entries.next().await {
+        let entry = entry.map_err(invalid_archive)?;
+        staging.count_entry()?;
+
+        let name = entry
+            .path()
+            .map_err(invalid_archive)?
+            .to_str()
+            .ok_or_else(|| invalid_archive("entry names have to be UTF-8"))?
+            .to_string();
+        let kind = entry.header().entry_type();
+        if kind.is_dir() {
+            staging.add_dir(&name)?;
+        } else if kind.is_file() {
+            staging.add_file(&name, entry).await?;
+        }
+        // Links and the like are left out
+    }
+
+    Ok(())
+}
+
+fn invalid_archive(e: impl Display) -> ServerError {
+    ServerError::ValidationError {
+        message: format!("invalid archive: {e}"),
+    }
+}
+
+impl Staging<'_> {
+    fn count_entry(&mut self) -> ServerResult<()> {
+        self.entries += 1;
+        let max = SETTINGS.extract.max_entries;
+        if max > 0 && self.entries > max {
+            return Err(ServerError::ValidationError {
+                message: format!("archive has more than {max} entries"),
+            });
+        }
+        Ok(())
+    }
+
+    fn add_dir(&mut self, name: &str) -> ServerResult<()> {
+        if let Some((parent, name)) = resolve_relative_path(self.dir, name)? {
+            self.staged.dirs.push(parent.join(name)?);
+        }
+        Ok(())
+    }
+
+    /// Writes the file out to a temporary file, hashing it on the way.
+    async fn add_file<R: AsyncRead + Unpin>(
+        &mut self,
+        name: &str,
+        mut reader: R,
+    ) -> ServerResult<()> {
+        let Some((dir, name)) = resolve_relative_path(self.dir, name)? else {
+            return Err(ServerError::ValidationError {
+                message: "archive has a file without a name".to_string(),
+            });
+        };
+
+        let temp_path = SETTINGS
+            .directories
+            .files_dir
+            .join(format!("tmp_extract_{}", Uuid::new_v4()));
+        let mut file = File::create(&temp_path).await?;
+        self.temp_paths.push(temp_path.clone());
+
+        let max = SETTINGS.extract.max_expanded_bytes;
+        let mut hasher = Sha256::new();
+        let mut size: u64 = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buf).await.map_err(invalid_archive)?;
+            if read == 0 {
+                break;
+            }
+
+            size += read as u64;
+            self.expanded_bytes += read as u64;
+            if max > 0 && self.expanded_bytes > max {
+                return Err(ServerError::ValidationError {
+                    message: format!("archive expands to more than {max} bytes"),
+                });
+            }
+            self.usage.check(self.expanded_bytes)?;
+
+            file.write_all(&buf[..read]).await?;
+            hasher.update(&buf[..read]);
+        }
+        file.flush().await?;
+
+        self.staged.files.push(FileUploadInfo {
+            file_name: name,
+            temp_path,
+            file_size: size as i64,
+            file_hash: format!("{:X}", hasher.finalize()),
+            vpath: dir,
+            user_id: self.user_id,
+            expires_at: self.expires_at,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_kind_from_name() {
+        assert_eq!(ArchiveKind::from_name("a.zip").unwrap(), ArchiveKind::Zip);
+        assert_eq!(ArchiveKind::from_name("a.TAR").unwrap(), ArchiveKind::Tar);
+        assert_eq!(
+            ArchiveKind::from_name("a.tar.gz").unwrap(),
+            ArchiveKind::TarGz
+        );
+        assert_eq!(ArchiveKind::from_name("a.tgz").unwrap(), ArchiveKind::TarGz);
+        assert!(ArchiveKind::from_name("a.rar").is_err());
+    }
+}
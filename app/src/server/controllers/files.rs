@@ -1,16 +1,24 @@
 // controller.rs
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use bytes::Bytes;
+use chrono::{NaiveDateTime, Utc};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
 use key_mutex::tokio::KeyMutex;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
 use sqlx::query;
 use sqlx::query_as;
-use sqlx::{
-    postgres::{PgArguments, PgQueryResult},
-    query::Query,
-    PgPool, Postgres, Transaction,
-};
+use sqlx::{PgPool, Postgres, Transaction};
 use tokio::fs;
-use tracing::trace;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{error, trace};
+use uuid::Uuid;
 
 use crate::{
     config::SETTINGS,
@@ -18,12 +26,15 @@ use crate::{
         controllers::websocket::WebSocketController,
         error::{ServerError, ServerResult},
         models::files::SFileRow,
+        storage::Blobs,
     },
 };
 
 use crate::server::models::auth::RelationshipType;
-use crate::server::models::files::{FileUploadInfo, Media, SFile, VirtualPath};
-
+use crate::server::models::files::{
+    FileDeletedEvent, FileUploadInfo, HashChallenge, HashUploadResponse, Media, SFile,
+    StagedUpload, Upload, VirtualPath,
+};
 
 /// File permission operations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -59,10 +70,40 @@ pub enum SFileCreateInfo<'a> {
 
 pub type FileController = Arc<FileControllerInner>;
 
+/// How long a client has to answer a hash challenge.
+const HASH_CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+/// How many unanswered hash challenges a user can have at once.
+const HASH_CHALLENGES_PER_USER: usize = 16;
+/// The most bytes of a file a hash challenge asks for.
+const HASH_CHALLENGE_LENGTH: u64 = 64 * 1024;
+/// How often the reaper looks for expired files.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Advisory lock namespace for creating a user's directories, keyed by user id.
+const MKDIR_LOCK_CLASS: i32 = 1;
+
+/// A hash challenge waiting for an answer.
+struct PendingChallenge {
+    challenge: HashChallenge,
+    user_id: i64,
+    /// Where the file goes, the answer has to be for the same path
+    vpath: VirtualPath,
+    file_hash: String,
+    file_size: u64,
+    expires_at: Option<NaiveDateTime>,
+    created_at: Instant,
+}
+
 #[derive(Clone)]
 pub struct FileControllerInner {
     db_pool: PgPool,
     pub active_uploads: Arc<KeyMutex<String, ()>>,
+    /// Held while a resumable upload is being written to, keyed by the upload id
+    resumable_uploads: Arc<KeyMutex<Uuid, ()>>,
+    /// Unanswered upload by hash challenges, keyed by challenge id
+    hash_challenges: Arc<DashMap<Uuid, PendingChallenge>>,
+    /// Where the contents of uploaded files are kept
+    blobs: Blobs,
     ws: Option<WebSocketController>,
 }
 
@@ -71,26 +112,123 @@ impl FileControllerInner {
         &self.db_pool
     }
 
-    pub async fn new_no_ws(db_pool: PgPool) -> Self {
+    pub fn blobs(&self) -> &Blobs {
+        &self.blobs
+    }
+
+    pub async fn new_no_ws(db_pool: PgPool, blobs: Blobs) -> Self {
         Self {
             db_pool,
             active_uploads: Arc::new(KeyMutex::new()),
+            resumable_uploads: Arc::new(KeyMutex::new()),
+            hash_challenges: Arc::new(DashMap::new()),
+            blobs,
             ws: None,
         }
     }
 
-    pub async fn new(db_pool: PgPool, ws: WebSocketController) -> Self {
+    pub async fn new(db_pool: PgPool, ws: WebSocketController, blobs: Blobs) -> Self {
         Self {
             db_pool,
             active_uploads: Arc::new(KeyMutex::new()),
+            resumable_uploads: Arc::new(KeyMutex::new()),
+            hash_challenges: Arc::new(DashMap::new()),
+            blobs,
             ws: Some(ws),
         }
     }
 }
 
 impl FileControllerInner {
-    pub async fn finish_upload(&self, mut info: FileUploadInfo) -> ServerResult<SFile> {
-        let mut tx = self.db_pool.begin().await?;
+    /// Stores an uploaded file. The caller holds the upload lock of its hash.
+    pub async fn finish_upload(&self, info: FileUploadInfo) -> ServerResult<SFile> {
+        let mut put_keys = Vec::new();
+        let result = async {
+            let mut tx = self.db_pool.begin().await?;
+            let f = self.finish_upload_tx(info, &mut put_keys, &mut tx).await?;
+
+            // finally commit transaction... phew
+            tx.commit().await?;
+            Ok(f)
+        }
+        .await;
+        let f = match result {
+            Ok(f) => f,
+            Err(e) => {
+                self.discard_blobs(&put_keys).await;
+                return Err(e);
+            }
+        };
+
+        // It may have replaced an older version of the file
+        if let Err(e) = self.prune_versions(Some(f.id as i64)).await {
+            error!("Failed to prune old versions of {}: {e}", f.full_path);
+        }
+
+        // notify ws clients of file creation and upload completion
+        if let Some(ref _ws) = self.ws {
+            // TODO!
+        }
+
+        Ok(f)
+    }
+
+    /// Stores everything staged in one transaction, so either all of it is or none of it is.
+    /// Takes the upload locks of the files' hashes itself.
+    pub async fn finish_uploads(
+        &self,
+        user_id: i64,
+        staged: StagedUpload,
+    ) -> ServerResult<Vec<SFile>> {
+        let mut hashes: Vec<String> = staged.files.iter().map(|f| f.file_hash.clone()).collect();
+        // Always locked in the same order, so two of these can't deadlock
+        hashes.sort();
+        hashes.dedup();
+        let mut guards = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            guards.push(self.active_uploads.lock(hash).await);
+        }
+
+        let mut put_keys = Vec::new();
+        let result = async {
+            let mut tx = self.db_pool.begin().await?;
+            let mut stored = Vec::new();
+            for dir in &staged.dirs {
+                stored.extend(self.make_all_dirs(dir, user_id, Some(&mut tx)).await?);
+            }
+            for info in staged.files {
+                stored.push(self.finish_upload_tx(info, &mut put_keys, &mut tx).await?);
+            }
+            tx.commit().await?;
+            Ok(stored)
+        }
+        .await;
+        let stored = match result {
+            Ok(stored) => stored,
+            Err(e) => {
+                self.discard_blobs(&put_keys).await;
+                return Err(e);
+            }
+        };
+        drop(guards);
+
+        for f in stored.iter().filter(|f| !f.is_dir) {
+            if let Err(e) = self.prune_versions(Some(f.id as i64)).await {
+                error!("Failed to prune old versions of {}: {e}", f.full_path);
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Stores one file as part of `tx`. The keys of blobs it puts in the store are added
+    /// to `put_keys`, nothing points at them if the transaction doesn't commit.
+    async fn finish_upload_tx(
+        &self,
+        mut info: FileUploadInfo,
+        put_keys: &mut Vec<String>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<SFile> {
         info.vpath.push_file(info.file_name)?;
         // stage 1: insert into media table if it doesnt exist
         let existing: Option<Media> = query_as!(
@@ -100,7 +238,7 @@ impl FileControllerInner {
             LIMIT 1",
             info.file_hash
         )
-        .fetch_optional(&mut *tx)
+        .fetch_optional(&mut **tx)
         .await?;
 
         let is_duplicate = existing.is_some();
@@ -116,11 +254,10 @@ impl FileControllerInner {
                     )
                     VALUES ($1, $2)
                     RETURNING *",
-                    // TODO! expiring times maybe??
                     info.file_size,
                     info.file_hash
                 )
-                .fetch_one(&mut *tx)
+                .fetch_one(&mut **tx)
                 .await?
             }
         };
@@ -139,53 +276,75 @@ impl FileControllerInner {
             );
         } else {
             // No duplicate
-            // Rename the file to its proper name
-            let true_path: PathBuf = media.true_path().await;
-
-            fs::create_dir_all(true_path.parent().unwrap()).await?;
+            // Hand the file off to the blob store
+            let key = media.blob_key();
+            self.blobs.put(&key, &info.temp_path).await?;
+            put_keys.push(key.clone());
 
-            fs::rename(&info.temp_path, &true_path)
-                .await
-                .map_err(|e| ServerError::IOError {
-                    message: e.to_string(),
-                })?;
-
-            trace!("Finalized upload: {}", true_path.to_string_lossy());
+            trace!("Finalized upload: {key}");
         }
 
         // stage 3: insert the symbolic file into its table after creating all dirs
-        self.make_all_dirs(&info.vpath, info.user_id, Some(&mut tx))
-            .await?;
-        let f = self
-            .make_file(&info.vpath, media.id, info.user_id, Some(&mut tx))
-            .await?;
+        self.link_media_tx(&info.vpath, media.id, info.user_id, info.expires_at, tx)
+            .await
+    }
 
-        // Create resource and grant owner permission
-        let resource_id = query!(
-            r"INSERT INTO resources (resource_type, resource_id) 
-            VALUES ($1, $2) 
-            RETURNING id",
-            "sfile",
-            f.id as i64
-        )
-        .fetch_one(&mut *tx)
-        .await?;
+    /// Deletes the blobs of uploads whose transaction rolled back. The caller
+    /// still holds their upload locks, so no other media can have claimed them.
+    async fn discard_blobs(&self, keys: &[String]) {
+        for key in keys {
+            match self.blobs.delete(key).await {
+                Ok(()) => trace!("Deleted blob {key} of a failed upload"),
+                Err(e) => error!("Failed to delete blob {key} of a failed upload: {e}"),
+            }
+        }
+    }
 
-        query!(
-            r"INSERT INTO user_resource_relationships (user_id, resource_id, relationship, granted_by) 
-            VALUES ($1, $2, $3, $1)",
-            info.user_id,
-            resource_id.id,
-            RelationshipType::Owner as RelationshipType
-        ).execute(&mut *tx).await?;
+    /// Creates a file at `vpath` pointing to existing media, along with any missing
+    /// parent directories, and makes the user its owner.
+    /// If the user already has a file there, the media becomes its new version instead.
+    pub(super) async fn link_media_tx(
+        &self,
+        vpath: &VirtualPath,
+        media_id: i64,
+        user_id: i64,
+        expires_at: Option<NaiveDateTime>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<SFile> {
+        match self.resolve_path_to_sfile_id_tx(vpath, user_id, tx).await {
+            Ok(sfile_id) => {
+                return self
+                    .new_version_tx(sfile_id, vpath, media_id, user_id, expires_at, tx)
+                    .await
+            }
+            Err(ServerError::PathDoesntExist) => {}
+            Err(e) => return Err(e),
+        }
 
-        // finally commit transaction... phew
-        tx.commit().await?;
+        self.make_all_dirs(vpath, user_id, Some(tx)).await?;
+        let mut f = self.make_file(vpath, media_id, user_id, Some(tx)).await?;
 
-        // notify ws clients of file creation and upload completion
-        if let Some(ref ws) = self.ws {
-            // TODO!
+        if expires_at.is_some() {
+            query!(
+                "UPDATE sfiles SET expires_at = $2 WHERE id = $1",
+                f.id as i64,
+                expires_at
+            )
+            .execute(&mut **tx)
+            .await?;
+            f.expires_at = expires_at.map(|t| t.and_utc());
         }
+        refresh_media_expiry(media_id, tx).await?;
+        query!(
+            "INSERT INTO sfile_versions (sfile_id, media_id, uploaded_by) VALUES ($1, $2, $3)",
+            f.id as i64,
+            media_id,
+            user_id
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        grant_owner(f.id as i64, user_id, tx).await?;
 
         Ok(f)
     }
@@ -249,7 +408,7 @@ impl FileControllerInner {
         Ok(current_sfile_id)
     }
 
-    async fn resolve_path_to_sfile_id_tx(
+    pub(super) async fn resolve_path_to_sfile_id_tx(
         &self,
         vpath: &VirtualPath,
         target_user_id: i64,
@@ -320,8 +479,8 @@ impl FileControllerInner {
             .map(|rows| rows.into_iter().map(SFile::from_row_incomplete).collect())
     }
 
-    // Deletes the symbolic file, aka the 'pointer' to the media.
-    // Also will delete the media if there are no more references to it
+    // Moves the symbolic file, aka the 'pointer' to the media, to the user's trash.
+    // Its media stays until the trash is purged, see the trash controller.
     pub async fn delete_sfile(&self, vpath: &VirtualPath, user_id: i64) -> ServerResult<()> {
         vpath.err_if_dir()?;
 
@@ -335,14 +494,14 @@ impl FileControllerInner {
 
         let sfile_id = self.resolve_path_to_sfile_id(vpath, user_id).await?;
 
-        // stage 1: Get the media_id before deletion
-        let media_id = query!(r"SELECT media_id FROM sfiles WHERE id = $1", sfile_id)
+        // Directories have no media, and the trash only holds files
+        query!(r"SELECT media_id FROM sfiles WHERE id = $1", sfile_id)
             .fetch_optional(&mut *tx)
             .await?
             .and_then(|row| row.media_id)
             .ok_or(ServerError::NoMediaFound)?;
 
-        // stage 2: Delete the directory entry (removes the (filename, parent) -> sfile mapping)
+        // Frees up the path, the file is only reachable from the trash now
         query!(
             r"DELETE FROM sfile_entries WHERE child_sfile_id = $1",
             sfile_id
@@ -350,44 +509,101 @@ impl FileControllerInner {
         .execute(&mut *tx)
         .await?;
 
-        // stage 3: Delete the sfile itself
-        query!(r"DELETE FROM sfiles WHERE id = $1", sfile_id)
-            .execute(&mut *tx)
-            .await?;
+        query!(
+            r"UPDATE sfiles SET trashed_at = $2, trashed_path = $3 WHERE id = $1",
+            sfile_id,
+            Utc::now().naive_utc(),
+            vpath.to_string()
+        )
+        .execute(&mut *tx)
+        .await?;
 
-        // stage 4: Check if any other sfiles still reference this media
-        let has_remaining_refs = query!(
-            r"SELECT 1 as _exists FROM sfiles WHERE media_id = $1 LIMIT 1",
-            media_id
+        tx.commit().await?;
+
+        if let Some(ref _ws) = self.ws {
+            // TODO!
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the file for good, along with its versions, and their media and blobs
+    /// if nothing else points at them.
+    pub(super) async fn delete_sfile_tx(
+        &self,
+        sfile_id: i64,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<()> {
+        // stage 1: Get the media_id before deletion
+        let media_id = query!(r"SELECT media_id FROM sfiles WHERE id = $1", sfile_id)
+            .fetch_optional(&mut **tx)
+            .await?
+            .and_then(|row| row.media_id)
+            .ok_or(ServerError::NoMediaFound)?;
+        let mut media_ids: Vec<i64> = query!(
+            "SELECT DISTINCT media_id FROM sfile_versions WHERE sfile_id = $1",
+            sfile_id
         )
-        .fetch_optional(&mut *tx)
+        .fetch_all(&mut **tx)
         .await?
-        .is_some();
-
-        // stage 5: If no remaining references, delete the media and file from disk
-        if !has_remaining_refs {
-            let deleted_media = query_as!(
-                Media,
-                r"DELETE FROM media WHERE id = $1 RETURNING *",
-                media_id
-            )
-            .fetch_one(&mut *tx)
-            .await?;
+        .into_iter()
+        .map(|row| row.media_id)
+        .filter(|&id| id != media_id)
+        .collect();
+        media_ids.push(media_id);
 
-            deleted_media.delete_from_disk().await?;
-        }
+        // stage 2: Delete the directory entry (removes the (filename, parent) -> sfile mapping)
+        query!(
+            r"DELETE FROM sfile_entries WHERE child_sfile_id = $1",
+            sfile_id
+        )
+        .execute(&mut **tx)
+        .await?;
 
-        // If anything fails (delete db entries or delete on disk) then
-        // the transaction doesn't go through
-        tx.commit().await?;
+        // stage 3: Delete the sfile itself, its versions go with it
+        query!(r"DELETE FROM sfiles WHERE id = $1", sfile_id)
+            .execute(&mut **tx)
+            .await?;
 
-        if let Some(ref ws) = self.ws {
-            // TODO!
+        // stage 4: Delete the media and files from disk that nothing else references
+        for media_id in media_ids {
+            if !self.delete_media_if_unreferenced_tx(media_id, tx).await? {
+                refresh_media_expiry(media_id, tx).await?;
+            }
         }
 
         Ok(())
     }
 
+    /// Deletes the media and its blob, unless a file or a version of one still points at it.
+    /// Returns whether it was deleted.
+    pub(super) async fn delete_media_if_unreferenced_tx(
+        &self,
+        media_id: i64,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<bool> {
+        let deleted_media = query_as!(
+            Media,
+            r"DELETE FROM media WHERE id = $1
+            AND NOT EXISTS (SELECT 1 FROM sfiles WHERE media_id = $1)
+            AND NOT EXISTS (SELECT 1 FROM sfile_versions WHERE media_id = $1)
+            RETURNING *",
+            media_id
+        )
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        let Some(deleted_media) = deleted_media else {
+            return Ok(false);
+        };
+
+        // Cached HLS segments don't have to exist
+        let _ = fs::remove_dir_all(deleted_media.stream_cache_dir()).await;
+        self.blobs.delete(&deleted_media.blob_key()).await?;
+
+        Ok(true)
+    }
+
     async fn _create_file(
         &self,
         info: SFileCreateInfo<'_>,
@@ -403,9 +619,51 @@ impl FileControllerInner {
         };
 
         let mut default_transaction = None;
-        let tx =
-            transaction.unwrap_or(default_transaction.get_or_insert(self.db_pool.begin().await?));
+        let tx = match transaction {
+            Some(transaction) => transaction,
+            None => default_transaction.insert(self.db_pool.begin().await?),
+        };
+
+        if path.is_root() {
+            return Err(ServerError::BadOperation {
+                details: "Cannot create another root directory.".into(),
+            });
+        }
+
+        // create the sfile
+        let sfile_row = query_as!(
+            SFileRow,
+            r"INSERT INTO sfiles (media_id, is_dir, user_id, is_public)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *",
+            media_id,
+            is_dir,
+            user_id,
+            false // Default to private
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+
+        self.insert_entry_tx(path, sfile_row.id, user_id, tx)
+            .await?;
+
+        if let Some(t) = default_transaction {
+            t.commit().await?;
+        }
+
+        let sfile = SFile::from_row(sfile_row, path)?;
 
+        Ok(sfile)
+    }
+
+    /// Puts an existing sfile at `path`, whose parent directory has to exist.
+    pub(super) async fn insert_entry_tx(
+        &self,
+        path: &VirtualPath,
+        sfile_id: i64,
+        user_id: i64,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<()> {
         let (parent_sfile_id, filename) = if path.is_root() {
             return Err(ServerError::BadOperation {
                 details: "Cannot create another root directory.".into(),
@@ -435,27 +693,13 @@ impl FileControllerInner {
             (parent_sfile_id, filename)
         };
 
-        // create the sfile
-        let sfile_row = query_as!(
-            SFileRow,
-            r"INSERT INTO sfiles (media_id, is_dir, user_id, is_public)
-            VALUES ($1, $2, $3, $4)
-            RETURNING *",
-            media_id,
-            is_dir,
-            user_id,
-            false // Default to private
-        )
-        .fetch_one(&mut **tx)
-        .await?;
-
         // create directory entry
         query!(
             r"INSERT INTO sfile_entries (parent_sfile_id, filename, child_sfile_id, user_id)
             VALUES ($1, $2, $3, $4)",
             parent_sfile_id,
             filename,
-            sfile_row.id,
+            sfile_id,
             user_id
         )
         .execute(&mut **tx)
@@ -472,13 +716,7 @@ impl FileControllerInner {
             }
         })?;
 
-        if let Some(t) = default_transaction {
-            t.commit().await?;
-        }
-
-        let sfile = SFile::from_row(sfile_row, path)?;
-
-        Ok(sfile)
+        Ok(())
     }
 
     pub async fn make_file(
@@ -520,8 +758,8 @@ impl FileControllerInner {
     pub async fn list_dir(
         &self,
         vpath: &VirtualPath,
-        user_id: Option<i64>, // None for anonymous users browsing public dirs
-        target_id: i64,       // The user whose files we want to list
+        _user_id: Option<i64>, // None for anonymous users browsing public dirs
+        target_id: i64,        // The user whose files we want to list
     ) -> ServerResult<Option<Vec<SFile>>> {
         vpath.err_if_file()?;
 
@@ -532,7 +770,7 @@ impl FileControllerInner {
 
         // Get files belonging to the target user in this directory
         let results = query!(
-            r"SELECT 
+            r#"SELECT 
                 sf.id,
                 sf.media_id, 
                 sf.is_dir,
@@ -540,12 +778,15 @@ impl FileControllerInner {
                 sf.modified_at,
                 sf.is_public,
                 se.filename,
-                sf.user_id
+                sf.user_id,
+                sf.expires_at,
+                m.file_hash AS "media_hash?"
             FROM sfile_entries se
             JOIN sfiles sf ON se.child_sfile_id = sf.id
+            LEFT JOIN media m ON m.id = sf.media_id
             WHERE se.parent_sfile_id = $1 
             AND se.user_id = $2
-            ORDER BY se.filename",
+            ORDER BY se.filename"#,
             dir_sfile_id,
             target_user_id
         )
@@ -569,12 +810,93 @@ impl FileControllerInner {
                 top_level_name: row.filename,
                 is_public: row.is_public,
                 user_id: row.user_id,
+                expires_at: row.expires_at.map(|t| t.and_utc()),
+                media_hash: row.media_hash,
             })
             .collect();
 
         Ok(Some(sfiles))
     }
 
+    /// Everything under the target user's directory at `vpath`, parents before their
+    /// children, each file with its media.
+    pub async fn walk_dir(
+        &self,
+        vpath: &VirtualPath,
+        target_id: i64,
+    ) -> ServerResult<Vec<(SFile, Option<Media>)>> {
+        vpath.err_if_file()?;
+
+        let dir_sfile_id = self.resolve_path_to_sfile_id(vpath, target_id).await?;
+
+        let results = query!(
+            r#"WITH RECURSIVE tree AS (
+                SELECT e.child_sfile_id AS id, e.filename AS path
+                FROM sfile_entries e
+                WHERE e.parent_sfile_id = $1 AND e.user_id = $2
+                UNION ALL
+                SELECT e.child_sfile_id, t.path || '/' || e.filename
+                FROM tree t
+                JOIN sfile_entries e ON e.parent_sfile_id = t.id
+                WHERE e.user_id = $2
+            )
+            SELECT
+                sf.id,
+                sf.media_id,
+                sf.is_dir,
+                sf.created_at,
+                sf.modified_at,
+                sf.is_public,
+                sf.user_id,
+                sf.expires_at,
+                t.path AS "path!"
+            FROM tree t
+            JOIN sfiles sf ON sf.id = t.id
+            ORDER BY t.path"#,
+            dir_sfile_id,
+            target_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let media_ids: Vec<i64> = results.iter().filter_map(|row| row.media_id).collect();
+        let media: HashMap<i64, Media> =
+            query_as!(Media, "SELECT * FROM media WHERE id = ANY($1)", &media_ids)
+                .fetch_all(&self.db_pool)
+                .await?
+                .into_iter()
+                .map(|m| (m.id, m))
+                .collect();
+
+        let base_path = vpath.to_string();
+        let entries = results
+            .into_iter()
+            .map(|row| {
+                let media = row.media_id.and_then(|id| media.get(&id).cloned());
+                let sfile = SFile {
+                    id: row.id as u64,
+                    media_id: row.media_id.map(|id| id as u64),
+                    is_dir: row.is_dir,
+                    full_path: if vpath.is_root() {
+                        format!("root/{}", row.path)
+                    } else {
+                        format!("{}/{}", base_path, row.path)
+                    },
+                    created_at: row.created_at.and_utc(),
+                    modified_at: row.modified_at.and_utc(),
+                    top_level_name: row.path.rsplit('/').next().unwrap_or_default().to_string(),
+                    is_public: row.is_public,
+                    user_id: row.user_id,
+                    expires_at: row.expires_at.map(|t| t.and_utc()),
+                    media_hash: media.as_ref().map(|m| m.file_hash.clone()),
+                };
+                (sfile, media)
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
     /// Create all directories. If vpath is a directory, it will create that too, otherwise it will
     /// just create up to the deepest parent.
     /// Could do it in one query,
@@ -593,24 +915,30 @@ impl FileControllerInner {
         let mut vec = Vec::with_capacity(parts.len());
 
         let mut default_transaction = None;
-        let transaction =
-            transaction.unwrap_or(default_transaction.get_or_insert(self.db_pool.begin().await?));
+        let transaction = match transaction {
+            Some(transaction) => transaction,
+            None => default_transaction.insert(self.db_pool.begin().await?),
+        };
 
+        let mut locked = false;
         for part in parts {
             curr.push_dir(part).expect("should never happen, again");
-            // check if directory already exists
-            let exists = self
-                .path_info_transacted(&curr, user_id, transaction)
-                .await
-                .map(|sfile| sfile.is_dir);
+            let mut exists = self.dir_exists(&curr, user_id, transaction).await?;
 
-            let exists = match exists {
-                Ok(bool) => bool,
-                Err(e) => match e {
-                    ServerError::PathDoesntExist => false,
-                    _ => return Err(e),
-                },
-            };
+            // Uploads into the same new directory race to create it. Whoever is
+            // second waits for the first to commit, then sees the directory.
+            if !exists && !locked {
+                // Ids past i32 wrap around, sharing a lock is only a little slower
+                query!(
+                    "SELECT pg_advisory_xact_lock($1, $2)",
+                    MKDIR_LOCK_CLASS,
+                    user_id as i32
+                )
+                .fetch_one(&mut **transaction)
+                .await?;
+                locked = true;
+                exists = self.dir_exists(&curr, user_id, transaction).await?;
+            }
 
             if exists {
                 trace!("Directory already exists, skipping: {curr:?}");
@@ -629,6 +957,19 @@ impl FileControllerInner {
         Ok(vec)
     }
 
+    async fn dir_exists(
+        &self,
+        vpath: &VirtualPath,
+        user_id: i64,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<bool> {
+        match self.path_info_transacted(vpath, user_id, transaction).await {
+            Ok(sfile) => Ok(sfile.is_dir),
+            Err(ServerError::PathDoesntExist) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Returns the file after the move.
     /// Works with directories and single files.
     pub async fn mv(
@@ -679,7 +1020,7 @@ impl FileControllerInner {
         let sfile = SFile::from_row(result, &to)?;
 
         // Notify WebSocket clients of file move
-        if let Some(ref ws) = self.ws {
+        if let Some(ref _ws) = self.ws {
             // TODO!
         }
 
@@ -741,18 +1082,6 @@ impl FileControllerInner {
             .map(|opt| opt.map(|rec| rec.media_id.unwrap()))
     }
 
-    async fn execute_maybe_transacted<'a>(
-        &self,
-        q: Query<'a, Postgres, PgArguments>,
-        transaction: Option<&mut Transaction<'_, Postgres>>,
-    ) -> core::result::Result<PgQueryResult, sqlx::Error> {
-        if let Some(t) = transaction {
-            q.execute(&mut **t).await
-        } else {
-            q.execute(&self.db_pool).await
-        }
-    }
-
     /// Set the visibility status of a file or directory, returns the updated file
     pub async fn set_file_visibility(
         &self,
@@ -796,7 +1125,6 @@ impl FileControllerInner {
         filename: &str,
         is_public: bool,
     ) -> ServerResult<()> {
-
         query!(
             "UPDATE sfiles SET is_public = $1 WHERE id = (
                 SELECT s.id FROM sfiles s 
@@ -818,7 +1146,6 @@ impl FileControllerInner {
         dirname: &str,
         is_public: bool,
     ) -> ServerResult<()> {
-
         query!(
             "UPDATE sfiles SET is_public = $1 WHERE id = (
                 SELECT s.id FROM sfiles s 
@@ -842,7 +1169,9 @@ impl FileControllerInner {
         relationship: RelationshipType,
         granter_user_id: i64,
     ) -> ServerResult<()> {
-        let sfile_id = self.resolve_path_to_sfile_id(vpath, granter_user_id).await?;
+        let sfile_id = self
+            .resolve_path_to_sfile_id(vpath, granter_user_id)
+            .await?;
 
         // Check if granter has permission to change permissions on this file
         // First check if user is the owner (direct ownership via sfiles.user_id)
@@ -871,7 +1200,8 @@ impl FileControllerInner {
                 sfile_id
             )
             .fetch_optional(&self.db_pool)
-            .await? {
+            .await?
+            {
                 resource.id
             } else {
                 // Create new resource
@@ -926,7 +1256,9 @@ impl FileControllerInner {
         relationship: RelationshipType,
         granter_user_id: i64,
     ) -> ServerResult<()> {
-        let sfile_id = self.resolve_path_to_sfile_id(vpath, granter_user_id).await?;
+        let sfile_id = self
+            .resolve_path_to_sfile_id(vpath, granter_user_id)
+            .await?;
 
         // Check if granter has permission to change permissions on this file
         let is_owner = query!("SELECT user_id FROM sfiles WHERE id = $1", sfile_id)
@@ -960,7 +1292,10 @@ impl FileControllerInner {
             target_user_id as i64,
             resource.id,
             relationship as RelationshipType
-        ).execute(&self.db_pool).await?.rows_affected();
+        )
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected();
 
         if rows_affected == 0 {
             return Err(ServerError::ValidationError {
@@ -971,3 +1306,534 @@ impl FileControllerInner {
         Ok(())
     }
 }
+
+// Resumable (tus) uploads
+impl FileControllerInner {
+    /// Starts a resumable upload of `file_name` into the directory `dir`.
+    /// Fails early if a directory is in the way, so nothing gets uploaded for nothing.
+    pub async fn create_upload(
+        &self,
+        dir: &VirtualPath,
+        file_name: String,
+        upload_length: i64,
+        user_id: i64,
+        expires_at: Option<NaiveDateTime>,
+    ) -> ServerResult<Upload> {
+        dir.err_if_file()?;
+
+        let mut target = dir.clone();
+        target.push_file(file_name.clone())?;
+        self.err_if_dir_exists(&target, user_id).await?;
+        self.check_quota(user_id, upload_length as u64).await?;
+
+        let upload = query_as!(
+            Upload,
+            "INSERT INTO uploads (id, user_id, vpath, file_name, upload_length, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *",
+            Uuid::new_v4(),
+            user_id,
+            dir.to_string_with_trailing(),
+            file_name,
+            upload_length,
+            expires_at
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        if let Err(e) = fs::File::create(upload.temp_path()).await {
+            query!("DELETE FROM uploads WHERE id = $1", upload.id)
+                .execute(&self.db_pool)
+                .await?;
+            return Err(e.into());
+        }
+
+        trace!("Created resumable upload {}", upload.id);
+
+        Ok(upload)
+    }
+
+    /// An unfinished upload, if it exists and belongs to the user.
+    pub async fn get_upload(&self, id: Uuid, user_id: i64) -> ServerResult<Upload> {
+        query_as!(
+            Upload,
+            "SELECT * FROM uploads WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?
+        .ok_or(ServerError::PathDoesntExist)
+    }
+
+    /// Appends the body to the upload, which has to currently be at `offset`.
+    /// Whatever arrives before the body errors out is kept, so the client can
+    /// resume from there. Once all bytes are in, the file is checked in like
+    /// a regular upload and the upload stops existing.
+    ///
+    /// Returns the new offset.
+    pub async fn append_to_upload<S, E>(
+        &self,
+        id: Uuid,
+        user_id: i64,
+        offset: u64,
+        mut body: S,
+    ) -> ServerResult<u64>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let _guard = self.resumable_uploads.lock(id).await;
+
+        // Fetched under the lock, a request that was waiting may find it finished
+        let upload = self.get_upload(id, user_id).await?;
+        let upload_length = upload.upload_length as u64;
+
+        let mut current = upload.offset().await?;
+        if current != offset {
+            return Err(ServerError::UploadOffsetMismatch { expected: current });
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(upload.temp_path())
+            .await?;
+
+        let mut result = Ok(());
+        while let Some(chunk) = body.next().await {
+            let chunk = match chunk {
+                Ok(c) => c,
+                Err(e) => {
+                    result = Err(ServerError::AxumError {
+                        message: format!("Upload body error: {e}"),
+                    });
+                    break;
+                }
+            };
+
+            if current + chunk.len() as u64 > upload_length {
+                result = Err(ServerError::ValidationError {
+                    message: format!("Upload is only {upload_length} bytes long"),
+                });
+                break;
+            }
+
+            if let Err(e) = file.write_all(&chunk).await {
+                result = Err(e.into());
+                break;
+            }
+            current += chunk.len() as u64;
+        }
+
+        // Keep whatever made it, even if the request failed halfway
+        file.flush().await?;
+        drop(file);
+        result?;
+
+        if current == upload_length {
+            self.finish_resumable_upload(upload).await?;
+        }
+
+        Ok(current)
+    }
+
+    /// Cancels an upload, throwing away everything received so far.
+    pub async fn terminate_upload(&self, id: Uuid, user_id: i64) -> ServerResult<()> {
+        let _guard = self.resumable_uploads.lock(id).await;
+
+        let upload = self.get_upload(id, user_id).await?;
+        self.remove_upload(&upload).await;
+
+        trace!("Terminated resumable upload {id}");
+
+        Ok(())
+    }
+
+    async fn finish_resumable_upload(&self, upload: Upload) -> ServerResult<SFile> {
+        let temp_path = upload.temp_path();
+
+        let mut hasher = Sha256::new();
+        let mut written = 0;
+        let mut file = fs::File::open(&temp_path).await?;
+        let mut buf = vec![0; 1024 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            written += n as u64;
+        }
+        drop(file);
+
+        // Other uploads may have used up the quota since this one was created
+        if let Err(e) = self.check_quota(upload.user_id, written).await {
+            self.remove_upload(&upload).await;
+            return Err(e);
+        }
+
+        let file_hash = format!("{:X}", hasher.finalize());
+
+        let info = FileUploadInfo {
+            file_name: upload.file_name.clone(),
+            temp_path: temp_path.clone(),
+            file_size: upload.upload_length,
+            file_hash: file_hash.clone(),
+            vpath: VirtualPath::from(upload.vpath.as_str()),
+            user_id: upload.user_id,
+            expires_at: upload.expires_at,
+        };
+
+        let mutex = self.active_uploads.lock(file_hash).await;
+        let result = self.finish_upload(info).await;
+        drop(mutex);
+
+        // Either way there is nothing left to resume
+        if let Err(e) = &result {
+            error!("Resumable upload {} failed to finish: {e:?}", upload.id);
+        }
+        self.remove_upload(&upload).await;
+
+        result
+    }
+
+    /// Deletes the upload row and its temp file, if it's still there.
+    async fn remove_upload(&self, upload: &Upload) {
+        let _ = fs::remove_file(upload.temp_path()).await;
+        if let Err(e) = query!("DELETE FROM uploads WHERE id = $1", upload.id)
+            .execute(&self.db_pool)
+            .await
+        {
+            error!("Failed to delete upload {}: {e}", upload.id);
+        }
+    }
+}
+
+// Uploads by hash
+impl FileControllerInner {
+    /// Creates the user's file at `vpath` from existing media with the same hash and size,
+    /// so the bytes don't have to be sent again.
+    ///
+    /// Media the user already has a file for is linked right away. For anything else the
+    /// user gets a challenge only someone with the file can answer, whether or not such
+    /// media exists, so this can't be used to find out what other users uploaded.
+    pub async fn upload_by_hash(
+        &self,
+        vpath: &VirtualPath,
+        file_hash: &str,
+        file_size: u64,
+        user_id: i64,
+        expires_at: Option<NaiveDateTime>,
+    ) -> ServerResult<HashUploadResponse> {
+        vpath.err_if_dir()?;
+        let file_hash = normalize_hash(file_hash)?;
+        self.err_if_dir_exists(vpath, user_id).await?;
+
+        let owned = query_as!(
+            Media,
+            "SELECT m.* FROM media m
+            WHERE m.file_hash = $1
+            AND m.file_size = $2
+            AND EXISTS (SELECT 1 FROM sfiles s WHERE s.media_id = m.id AND s.user_id = $3)
+            LIMIT 1",
+            file_hash,
+            file_size as i64,
+            user_id
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        if let Some(media) = owned {
+            let file = self.link_media(vpath, &media, user_id, expires_at).await?;
+            return Ok(HashUploadResponse::Linked { file });
+        }
+
+        // Linking media the user already has doesn't take up any more space
+        self.check_quota(user_id, file_size).await?;
+
+        // Asking again for the same path replaces the earlier challenge
+        self.hash_challenges.retain(|_, c| {
+            c.created_at.elapsed() < HASH_CHALLENGE_TTL
+                && !(c.user_id == user_id && c.vpath == *vpath)
+        });
+
+        // And past the limit, the user's oldest challenges make room
+        let mut pending: Vec<(Uuid, Instant)> = self
+            .hash_challenges
+            .iter()
+            .filter(|c| c.user_id == user_id)
+            .map(|c| (*c.key(), c.created_at))
+            .collect();
+        if pending.len() >= HASH_CHALLENGES_PER_USER {
+            pending.sort_by_key(|(_, created_at)| *created_at);
+            for (id, _) in &pending[..=pending.len() - HASH_CHALLENGES_PER_USER] {
+                self.hash_challenges.remove(id);
+            }
+        }
+
+        let length = file_size.min(HASH_CHALLENGE_LENGTH);
+        let challenge = HashChallenge {
+            id: Uuid::new_v4(),
+            nonce: Uuid::new_v4().simple().to_string(),
+            offset: OsRng.next_u64() % (file_size - length + 1),
+            length,
+        };
+
+        self.hash_challenges.insert(
+            challenge.id,
+            PendingChallenge {
+                challenge: challenge.clone(),
+                user_id,
+                vpath: vpath.clone(),
+                file_hash,
+                file_size,
+                expires_at,
+                created_at: Instant::now(),
+            },
+        );
+
+        Ok(HashUploadResponse::Challenge { challenge })
+    }
+
+    /// Links the file once the user answers the challenge from [`Self::upload_by_hash`].
+    /// A wrong answer and missing media look the same, both are [`ServerError::NoMediaFound`].
+    /// Challenges can only be answered once.
+    pub async fn answer_hash_challenge(
+        &self,
+        vpath: &VirtualPath,
+        challenge_id: Uuid,
+        answer: &str,
+        user_id: i64,
+    ) -> ServerResult<SFile> {
+        vpath.err_if_dir()?;
+
+        let pending = self
+            .hash_challenges
+            .remove(&challenge_id)
+            .map(|(_, c)| c)
+            .filter(|c| {
+                c.user_id == user_id
+                    && c.vpath == *vpath
+                    && c.created_at.elapsed() < HASH_CHALLENGE_TTL
+            })
+            .ok_or(ServerError::NoMediaFound)?;
+
+        let media = query_as!(
+            Media,
+            "SELECT * FROM media
+            WHERE file_hash = $1 AND file_size = $2
+            LIMIT 1",
+            pending.file_hash,
+            pending.file_size as i64
+        )
+        .fetch_optional(&self.db_pool)
+        .await?
+        .ok_or(ServerError::NoMediaFound)?;
+
+        let challenge = &pending.challenge;
+        let mut hasher = challenge.hasher();
+        let mut stream = self
+            .blobs
+            .get_range(&media.blob_key(), challenge.offset, challenge.length)
+            .await?;
+        while let Some(chunk) = stream.next().await {
+            hasher.update(&chunk?);
+        }
+
+        if !format!("{:X}", hasher.finalize()).eq_ignore_ascii_case(answer) {
+            return Err(ServerError::NoMediaFound);
+        }
+
+        self.link_media(vpath, &media, user_id, pending.expires_at)
+            .await
+    }
+
+    async fn link_media(
+        &self,
+        vpath: &VirtualPath,
+        media: &Media,
+        user_id: i64,
+        expires_at: Option<NaiveDateTime>,
+    ) -> ServerResult<SFile> {
+        // Same lock as uploads of this hash
+        let _guard = self.active_uploads.lock(media.file_hash.clone()).await;
+        self.err_if_dir_exists(vpath, user_id).await?;
+
+        let mut tx = self.db_pool.begin().await?;
+        let f = self
+            .link_media_tx(vpath, media.id, user_id, expires_at, &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        trace!("Linked {vpath} to existing media {}", media.id);
+        if let Err(e) = self.prune_versions(Some(f.id as i64)).await {
+            error!("Failed to prune old versions of {vpath}: {e}");
+        }
+
+        Ok(f)
+    }
+
+    /// Files can be uploaded over, they get a new version, but directories can't.
+    async fn err_if_dir_exists(&self, vpath: &VirtualPath, user_id: i64) -> ServerResult<()> {
+        let sfile_id = match self.resolve_path_to_sfile_id(vpath, user_id).await {
+            Ok(id) => id,
+            Err(ServerError::PathDoesntExist) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let is_dir = query!("SELECT is_dir FROM sfiles WHERE id = $1", sfile_id)
+            .fetch_one(&self.db_pool)
+            .await?
+            .is_dir;
+        if is_dir {
+            return Err(ServerError::PathAlreadyExists);
+        }
+        Ok(())
+    }
+}
+
+// Expiring files
+impl FileControllerInner {
+    /// Reaps expired files, purges old trash and prunes old versions forever.
+    /// Started by `server::run`.
+    pub async fn run_reaper(self: Arc<Self>) {
+        loop {
+            match self.reap_expired().await {
+                Ok(reaped) if !reaped.is_empty() => trace!("Reaped {} expired files", reaped.len()),
+                Ok(_) => {}
+                Err(e) => error!("Failed to reap expired files: {e}"),
+            }
+            match self.purge_old_trash().await {
+                Ok(0) => {}
+                Ok(purged) => trace!("Purged {purged} files from the trash"),
+                Err(e) => error!("Failed to purge old trash: {e}"),
+            }
+            match self.prune_versions(None).await {
+                Ok(0) => {}
+                Ok(pruned) => trace!("Pruned {pruned} old file versions"),
+                Err(e) => error!("Failed to prune old file versions: {e}"),
+            }
+            tokio::time::sleep(REAP_INTERVAL).await;
+        }
+    }
+
+    /// Deletes every file past its expiry, along with media nothing points at anymore,
+    /// and tells websocket clients about each one.
+    pub async fn reap_expired(&self) -> ServerResult<Vec<FileDeletedEvent>> {
+        let now = Utc::now().naive_utc();
+
+        let expired = query!(
+            r#"WITH RECURSIVE paths AS (
+                SELECT e.child_sfile_id AS id, e.filename AS path, e.parent_sfile_id AS parent
+                FROM sfile_entries e
+                JOIN sfiles s ON s.id = e.child_sfile_id
+                WHERE s.expires_at <= $1
+                UNION ALL
+                SELECT p.id, pe.filename || '/' || p.path, pe.parent_sfile_id
+                FROM paths p
+                JOIN sfile_entries pe ON pe.child_sfile_id = p.parent
+                WHERE p.parent <> 0
+            )
+            SELECT p.id AS "id!", p.path AS "path!", m.file_hash
+            FROM paths p
+            JOIN sfiles s ON s.id = p.id
+            JOIN media m ON m.id = s.media_id
+            WHERE p.parent = 0
+            ORDER BY p.path"#,
+            now
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let mut reaped = Vec::new();
+        for file in expired {
+            // Same lock as uploads of this hash, so they don't link to media being deleted
+            let _guard = self.active_uploads.lock(file.file_hash).await;
+            let mut tx = self.db_pool.begin().await?;
+
+            // It may have been deleted, or given a new expiry, in the meantime
+            let still_expired = query!(
+                "SELECT 1 as _exists FROM sfiles WHERE id = $1 AND expires_at <= $2 FOR UPDATE",
+                file.id,
+                now
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .is_some();
+            if !still_expired {
+                continue;
+            }
+
+            self.delete_sfile_tx(file.id, &mut tx).await?;
+            tx.commit().await?;
+            trace!("Reaped expired file {}", file.path);
+
+            let event = FileDeletedEvent {
+                path: file.path,
+                file_id: file.id as u64,
+            };
+            if let Some(ref ws) = self.ws {
+                ws.broadcast(event.clone()).await;
+            }
+            reaped.push(event);
+        }
+
+        Ok(reaped)
+    }
+}
+
+/// Keeps `media.expiring_time` in line with the files pointing at the media:
+/// the latest expiry among them, or none if any of them never expire.
+pub(super) async fn refresh_media_expiry(
+    media_id: i64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> ServerResult<()> {
+    query!(
+        "UPDATE media SET expiring_time = (
+            SELECT CASE WHEN bool_or(expires_at IS NULL) THEN NULL ELSE MAX(expires_at) END
+            FROM sfiles WHERE media_id = $1
+        )
+        WHERE id = $1",
+        media_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Creates the file's resource and makes the user its owner.
+pub(super) async fn grant_owner(
+    sfile_id: i64,
+    user_id: i64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> ServerResult<()> {
+    let resource_id = query!(
+        r"INSERT INTO resources (resource_type, resource_id) 
+        VALUES ($1, $2) 
+        RETURNING id",
+        "sfile",
+        sfile_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    query!(
+        r"INSERT INTO user_resource_relationships (user_id, resource_id, relationship, granted_by) 
+        VALUES ($1, $2, $3, $1)",
+        user_id,
+        resource_id.id,
+        RelationshipType::Owner as RelationshipType
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Hashes are stored as uppercase hex.
+fn normalize_hash(hash: &str) -> ServerResult<String> {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ServerError::ValidationError {
+            message: "Hash must be a hex encoded SHA-256".to_string(),
+        });
+    }
+    Ok(hash.to_ascii_uppercase())
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use sqlx::{query, query_as};
+use tokio::fs;
+use tracing::trace;
+use uuid::Uuid;
+
+use crate::{
+    config::SETTINGS,
+    server::{
+        controllers::files::FileControllerInner,
+        error::ServerResult,
+        models::files::{FsckReport, Media},
+    },
+};
+
+/// Temp files untouched for this long are assumed to be from failed uploads.
+/// Resumable uploads keep theirs for as long as the upload exists.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl FileControllerInner {
+    /// Checks the database against the blob store and the data directories.
+    /// Doesn't change anything, see [`Self::repair`].
+    pub async fn fsck(&self) -> ServerResult<FsckReport> {
+        let mut report = FsckReport::default();
+
+        let all_media = query_as!(Media, "SELECT * FROM media")
+            .fetch_all(self.db_pool())
+            .await?;
+        let blobs: HashSet<String> = self.blobs().list().await?.into_iter().collect();
+
+        let media_keys: HashSet<String> = all_media.iter().map(Media::blob_key).collect();
+        report.orphaned_blobs = blobs.difference(&media_keys).cloned().collect();
+        report.orphaned_blobs.sort();
+
+        let referenced: HashSet<i64> = query!(
+            "SELECT media_id AS \"media_id!\" FROM sfiles WHERE media_id IS NOT NULL
+            UNION SELECT media_id FROM sfile_versions"
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .map(|row| row.media_id)
+        .collect();
+
+        for media in all_media {
+            if !blobs.contains(&media.blob_key()) {
+                report.dangling_media.push(media);
+            } else if !referenced.contains(&media.id) {
+                report.unreferenced_media.push(media);
+            }
+        }
+
+        let dangling_ids: Vec<i64> = report.dangling_media.iter().map(|m| m.id).collect();
+        report.broken_sfiles = query!(
+            r#"WITH RECURSIVE paths AS (
+                SELECT e.child_sfile_id AS id, e.filename AS path, e.parent_sfile_id AS parent
+                FROM sfile_entries e
+                JOIN sfiles s ON s.id = e.child_sfile_id
+                WHERE s.media_id = ANY($1)
+                UNION ALL
+                SELECT p.id, pe.filename || '/' || p.path, pe.parent_sfile_id
+                FROM paths p
+                JOIN sfile_entries pe ON pe.child_sfile_id = p.parent
+                WHERE p.parent <> 0
+            )
+            SELECT id AS "id!", path AS "path!" FROM paths WHERE parent = 0 ORDER BY path"#,
+            &dangling_ids
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.path))
+        .collect();
+
+        let active_uploads: HashSet<Uuid> = query!("SELECT id FROM uploads")
+            .fetch_all(self.db_pool())
+            .await?
+            .into_iter()
+            .map(|row| row.id)
+            .collect();
+
+        for dir in [
+            SETTINGS.directories.files_dir.clone(),
+            SETTINGS.directories.data_dir.join("streams"),
+        ] {
+            report
+                .stale_temp_files
+                .extend(stale_temp_files(&dir, &active_uploads).await?);
+        }
+        report.stale_temp_files.sort();
+
+        Ok(report)
+    }
+
+    /// Fixes what [`Self::fsck`] found. Dangling media is removed along with
+    /// the files pointing at it, since their contents are gone anyway.
+    /// Garbage is checked again before it's removed, in case it was claimed
+    /// since the report was made.
+    pub async fn repair(&self, report: &FsckReport) -> ServerResult<()> {
+        for key in &report.orphaned_blobs {
+            let file_hash = key.replace('/', "");
+            let _guard = self.active_uploads.lock(file_hash.clone()).await;
+
+            let claimed = query!(
+                "SELECT 1 as _exists FROM media WHERE file_hash = $1 LIMIT 1",
+                file_hash
+            )
+            .fetch_optional(self.db_pool())
+            .await?
+            .is_some();
+
+            if !claimed {
+                self.blobs().delete(key).await?;
+                trace!("Deleted orphaned blob {key}");
+            }
+        }
+
+        for media in &report.dangling_media {
+            let mut tx = self.db_pool().begin().await?;
+
+            // Permissions on the files pointing at it aren't tied to them by a foreign key
+            query!(
+                "DELETE FROM user_resource_relationships WHERE resource_id IN (
+                    SELECT r.id FROM resources r
+                    JOIN sfiles s ON s.id = r.resource_id
+                    WHERE r.resource_type = 'sfile' AND s.media_id = $1
+                )",
+                media.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            query!(
+                "DELETE FROM resources WHERE resource_type = 'sfile'
+                AND resource_id IN (SELECT id FROM sfiles WHERE media_id = $1)",
+                media.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            // The files themselves go too, the foreign keys cascade
+            query!("DELETE FROM media WHERE id = $1", media.id)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+            let _ = fs::remove_dir_all(media.stream_cache_dir()).await;
+            trace!("Deleted dangling media {}", media.id);
+        }
+
+        for media in &report.unreferenced_media {
+            let _guard = self.active_uploads.lock(media.file_hash.clone()).await;
+            let mut tx = self.db_pool().begin().await?;
+
+            if self
+                .delete_media_if_unreferenced_tx(media.id, &mut tx)
+                .await?
+            {
+                trace!("Deleted unreferenced media {}", media.id);
+            }
+
+            tx.commit().await?;
+        }
+
+        for path in &report.stale_temp_files {
+            if fs::metadata(path).await?.is_dir() {
+                fs::remove_dir_all(path).await?;
+            } else {
+                fs::remove_file(path).await?;
+            }
+            trace!("Deleted stale temp file {}", path.to_string_lossy());
+        }
+
+        Ok(())
+    }
+}
+
+/// Temp files and directories (`tmp_*`) directly in `dir` that haven't been
+/// touched in a while and don't belong to a resumable upload.
+async fn stale_temp_files(
+    dir: &Path,
+    active_uploads: &HashSet<Uuid>,
+) -> ServerResult<Vec<PathBuf>> {
+    let mut stale = Vec::new();
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stale),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(rest) = name.strip_prefix("tmp_") else {
+            continue;
+        };
+
+        let is_active_upload = rest
+            .strip_prefix("upload_")
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .is_some_and(|id| active_uploads.contains(&id));
+        if is_active_upload {
+            continue;
+        }
+
+        let modified = entry.metadata().await?.modified()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age >= STALE_TEMP_AGE {
+            stale.push(entry.path());
+        }
+    }
+
+    Ok(stale)
+}
@@ -1,5 +1,13 @@
 pub mod auth;
+pub mod copy;
+pub mod extract;
 pub mod files;
+pub mod fsck;
+pub mod quota;
+pub mod scrub;
+pub mod stream;
+pub mod trash;
+pub mod versions;
 pub mod websocket;
 
 #[cfg(test)]
@@ -0,0 +1,53 @@
+use sqlx::query;
+
+use crate::{
+    config::SETTINGS,
+    server::{
+        controllers::files::FileControllerInner, error::ServerResult, models::files::StorageUsage,
+    },
+};
+
+impl FileControllerInner {
+    /// How much the user stores and how much they may.
+    /// Each media is counted once per user, however many of their files or old
+    /// versions of them point at it, and its size is split evenly between everyone who has it.
+    pub async fn usage(&self, user_id: i64) -> ServerResult<StorageUsage> {
+        let row = query!(
+            r#"WITH held AS (
+                SELECT media_id, user_id FROM sfiles WHERE media_id IS NOT NULL
+                UNION
+                SELECT v.media_id, s.user_id
+                FROM sfile_versions v
+                JOIN sfiles s ON s.id = v.sfile_id
+            ),
+            holders AS (
+                SELECT media_id, COUNT(DISTINCT user_id) AS users
+                FROM held
+                WHERE media_id IN (SELECT media_id FROM held WHERE user_id = $1)
+                GROUP BY media_id
+            )
+            SELECT
+                COALESCE(SUM(m.file_size / h.users), 0)::BIGINT AS "used_bytes!",
+                (SELECT quota_bytes FROM users WHERE id = $1) AS quota_bytes
+            FROM holders h
+            JOIN media m ON m.id = h.media_id"#,
+            user_id
+        )
+        .fetch_one(self.db_pool())
+        .await?;
+
+        Ok(StorageUsage {
+            used_bytes: row.used_bytes.max(0) as u64,
+            quota_bytes: row
+                .quota_bytes
+                .map(|q| q.max(0) as u64)
+                .or(SETTINGS.application.default_quota),
+        })
+    }
+
+    /// Fails if `incoming` more bytes don't fit in the user's quota.
+    /// Uploads are checked at their full size, before deduplication.
+    pub async fn check_quota(&self, user_id: i64, incoming: u64) -> ServerResult<()> {
+        self.usage(user_id).await?.check(incoming)
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, PgPool};
+use tracing::{error, trace, warn};
+
+use crate::{
+    config::settings::ScrubberSettings,
+    server::{
+        error::ServerResult,
+        models::files::{Media, ScrubFailure, ScrubReport},
+        storage::Blobs,
+    },
+};
+
+/// How long the scrubber waits before looking again when nothing is due.
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub type ScrubController = Arc<ScrubControllerInner>;
+
+/// Re-hashes stored media in the background, so blobs that silently changed
+/// on disk get noticed. The media that went the longest without a check goes first.
+pub struct ScrubControllerInner {
+    db_pool: PgPool,
+    blobs: Blobs,
+    settings: ScrubberSettings,
+}
+
+impl ScrubControllerInner {
+    pub fn new(db_pool: PgPool, blobs: Blobs, settings: ScrubberSettings) -> Self {
+        Self {
+            db_pool,
+            blobs,
+            settings,
+        }
+    }
+
+    /// Scrubs forever. Started by `server::run` if the scrubber is enabled.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            match self.scrub_next().await {
+                Ok(true) => {}
+                Ok(false) => tokio::time::sleep(IDLE_INTERVAL).await,
+                Err(e) => {
+                    error!("Scrubber failed: {e}");
+                    tokio::time::sleep(IDLE_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Checks the media most overdue for a check.
+    /// Returns false if none are due.
+    pub async fn scrub_next(&self) -> ServerResult<bool> {
+        let due_before = Utc::now().naive_utc()
+            - chrono::Duration::hours(self.settings.recheck_after_hours as i64);
+
+        let media = query_as!(
+            Media,
+            "SELECT m.*
+            FROM media m
+            LEFT JOIN media_scrubs s ON s.media_id = m.id
+            WHERE s.checked_at IS NULL OR s.checked_at < $1
+            ORDER BY s.checked_at ASC NULLS FIRST, m.id
+            LIMIT 1",
+            due_before
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        let Some(media) = media else {
+            return Ok(false);
+        };
+
+        let (verified, actual_hash, error) = match self.hash(&media).await {
+            Ok(hash) if hash.eq_ignore_ascii_case(&media.file_hash) => (true, None, None),
+            Ok(hash) => {
+                warn!(
+                    "Media {} is corrupt: expected hash {}, got {hash}",
+                    media.id, media.file_hash
+                );
+                (false, Some(hash), None)
+            }
+            Err(e) => {
+                warn!("Failed to scrub media {}: {e}", media.id);
+                (false, None, Some(e.to_string()))
+            }
+        };
+        trace!("Scrubbed media {}", media.id);
+
+        // The media may have been deleted while it was being hashed
+        query!(
+            "INSERT INTO media_scrubs (media_id, checked_at, verified_at, actual_hash, error)
+            SELECT $1, CURRENT_TIMESTAMP, CASE WHEN $2 THEN CURRENT_TIMESTAMP END, $3, $4
+            WHERE EXISTS (SELECT 1 FROM media WHERE id = $1)
+            ON CONFLICT (media_id) DO UPDATE SET
+                checked_at = EXCLUDED.checked_at,
+                verified_at = COALESCE(EXCLUDED.verified_at, media_scrubs.verified_at),
+                actual_hash = EXCLUDED.actual_hash,
+                error = EXCLUDED.error",
+            media.id,
+            verified,
+            actual_hash,
+            error
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Hashes the blob, no faster than the configured rate.
+    async fn hash(&self, media: &Media) -> ServerResult<String> {
+        let mut stream = self.blobs.get(&media.blob_key()).await?;
+        let mut hasher = Sha256::new();
+        let started = Instant::now();
+        let mut read = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            read += chunk.len() as u64;
+
+            if self.settings.bytes_per_second > 0 {
+                let should_take =
+                    Duration::from_secs_f64(read as f64 / self.settings.bytes_per_second as f64);
+                if let Some(ahead) = should_take.checked_sub(started.elapsed()) {
+                    tokio::time::sleep(ahead).await;
+                }
+            }
+        }
+
+        Ok(format!("{:X}", hasher.finalize()))
+    }
+
+    pub async fn report(&self) -> ServerResult<ScrubReport> {
+        let counts = query!(
+            r#"SELECT
+                (SELECT COUNT(*) FROM media) AS "total_media!",
+                COUNT(*) AS "checked_media!",
+                MIN(checked_at) AS oldest_check
+            FROM media_scrubs"#
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        let failures = query!(
+            "SELECT s.media_id, m.file_hash, s.actual_hash, s.error, s.checked_at, s.verified_at
+            FROM media_scrubs s
+            JOIN media m ON m.id = s.media_id
+            WHERE s.actual_hash IS NOT NULL OR s.error IS NOT NULL
+            ORDER BY s.checked_at DESC"
+        )
+        .fetch_all(&self.db_pool)
+        .await?
+        .into_iter()
+        .map(|row| ScrubFailure {
+            media_id: row.media_id,
+            file_hash: row.file_hash,
+            actual_hash: row.actual_hash,
+            error: row.error,
+            checked_at: row.checked_at.and_utc(),
+            verified_at: row.verified_at.map(|t| t.and_utc()),
+        })
+        .collect();
+
+        Ok(ScrubReport {
+            total_media: counts.total_media,
+            checked_media: counts.checked_media,
+            oldest_check: counts.oldest_check.map(|t| t.and_utc()),
+            failures,
+        })
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use key_mutex::tokio::KeyMutex;
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::trace;
+
+use crate::server::{
+    error::{ServerError, ServerResult},
+    models::files::Media,
+    remux,
+    storage::Blobs,
+};
+
+pub type StreamController = Arc<StreamControllerInner>;
+
+/// Serves uploaded videos as HLS, remuxing them on first request
+/// and caching the segments under the data directory.
+pub struct StreamControllerInner {
+    /// Held while a media file is being remuxed so it only happens once,
+    /// keyed by the file hash
+    active_remuxes: KeyMutex<String, ()>,
+    blobs: Blobs,
+}
+
+impl StreamControllerInner {
+    pub fn new(blobs: Blobs) -> Self {
+        Self {
+            active_remuxes: KeyMutex::new(),
+            blobs,
+        }
+    }
+
+    /// Returns the directory holding the HLS output for the media,
+    /// remuxing it first if it isn't cached yet.
+    pub async fn prepare(&self, media: &Media) -> ServerResult<PathBuf> {
+        let cache_dir = media.stream_cache_dir();
+        if is_cached(&cache_dir).await {
+            return Ok(cache_dir);
+        }
+
+        let _guard = self.active_remuxes.lock(media.file_hash.clone()).await;
+
+        // Another request may have finished it while we were waiting
+        if is_cached(&cache_dir).await {
+            return Ok(cache_dir);
+        }
+
+        // Remux into a temporary directory and move it in place when done,
+        // so a half written cache is never served
+        let temp_dir =
+            cache_dir.with_file_name(format!("tmp_{}_{}", media.file_hash, nanoid::nanoid!()));
+        fs::create_dir_all(&temp_dir).await?;
+
+        trace!("Remuxing media {} for streaming", media.id);
+        let result = self.remux(media, &temp_dir).await;
+
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&temp_dir).await;
+            return Err(e);
+        }
+
+        // Leftovers from an interrupted remux
+        let _ = fs::remove_dir_all(&cache_dir).await;
+        fs::rename(&temp_dir, &cache_dir).await?;
+
+        Ok(cache_dir)
+    }
+
+    async fn remux(&self, media: &Media, out_dir: &Path) -> ServerResult<()> {
+        // The remuxer needs a file it can seek around in,
+        // so blobs that aren't on the local filesystem are downloaded first
+        let key = media.blob_key();
+        let (src, downloaded) = match self.blobs.local_path(&key) {
+            Some(path) => (path, false),
+            None => {
+                let path = out_dir.join("source");
+                let mut file = fs::File::create(&path).await?;
+                let mut stream = self.blobs.get(&key).await?;
+                while let Some(chunk) = stream.next().await {
+                    file.write_all(&chunk?).await?;
+                }
+                file.flush().await?;
+                (path, true)
+            }
+        };
+
+        let out_dir = out_dir.to_path_buf();
+        let result = tokio::task::spawn_blocking({
+            let src = src.clone();
+            move || remux::remux_to_hls(&src, &out_dir)
+        })
+        .await
+        .map_err(|e| ServerError::InternalError {
+            message: format!("Remux task failed: {e}"),
+        });
+
+        if downloaded {
+            let _ = fs::remove_file(&src).await;
+        }
+
+        result?
+    }
+
+    /// The playlist for the media. `query` is appended to every URI in it,
+    /// so requests for the segments carry the same query as the playlist request.
+    pub async fn playlist(&self, media: &Media, query: Option<&str>) -> ServerResult<String> {
+        let cache_dir = self.prepare(media).await?;
+        let playlist = fs::read_to_string(cache_dir.join(remux::PLAYLIST_FILE)).await?;
+
+        Ok(match query {
+            Some(query) => with_query(&playlist, query),
+            None => playlist,
+        })
+    }
+
+    /// Path to the init segment or a media segment, by file name.
+    pub async fn segment_path(&self, media: &Media, name: &str) -> ServerResult<PathBuf> {
+        if !is_segment_name(name) {
+            return Err(ServerError::PathDoesntExist);
+        }
+
+        let path = self.prepare(media).await?.join(name);
+        if !fs::try_exists(&path).await? {
+            return Err(ServerError::PathDoesntExist);
+        }
+
+        Ok(path)
+    }
+}
+
+async fn is_cached(cache_dir: &Path) -> bool {
+    fs::try_exists(cache_dir.join(remux::PLAYLIST_FILE))
+        .await
+        .unwrap_or(false)
+}
+
+/// Only names the remuxer writes are allowed, nothing that could walk out of the cache.
+fn is_segment_name(name: &str) -> bool {
+    if name == remux::INIT_SEGMENT_FILE {
+        return true;
+    }
+
+    name.strip_suffix(remux::SEGMENT_EXTENSION)
+        .and_then(|n| n.strip_suffix('.'))
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn with_query(playlist: &str, query: &str) -> String {
+    playlist
+        .lines()
+        .map(|line| {
+            if let Some(uri) = line
+                .strip_prefix("#EXT-X-MAP:URI=\"")
+                .and_then(|l| l.strip_suffix('"'))
+            {
+                format!("#EXT-X-MAP:URI=\"{uri}?{query}\"\n")
+            } else if line.is_empty() || line.starts_with('#') {
+                format!("{line}\n")
+            } else {
+                format!("{line}?{query}\n")
+            }
+        })
+        .collect()
+}
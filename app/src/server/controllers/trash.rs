@@ -0,0 +1,170 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as};
+use tracing::trace;
+
+use crate::{
+    config::SETTINGS,
+    server::{
+        controllers::files::FileControllerInner,
+        error::{ServerError, ServerResult},
+        models::files::{SFile, SFileRow, TrashedFile, VirtualPath},
+    },
+};
+
+impl FileControllerInner {
+    /// The user's trash, most recently deleted first.
+    pub async fn list_trash(&self, user_id: i64) -> ServerResult<Vec<TrashedFile>> {
+        let retention = retention();
+
+        let files = query!(
+            r#"SELECT s.id, s.trashed_at AS "trashed_at!", s.trashed_path AS "trashed_path!",
+                COALESCE(m.file_size, 0) AS "file_size!"
+            FROM sfiles s
+            LEFT JOIN media m ON m.id = s.media_id
+            WHERE s.user_id = $1 AND s.trashed_at IS NOT NULL
+            ORDER BY s.trashed_at DESC, s.id DESC"#,
+            user_id
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .map(|row| TrashedFile {
+            id: row.id as u64,
+            original_path: row.trashed_path,
+            trashed_at: row.trashed_at.and_utc(),
+            purge_at: retention.map(|r| (row.trashed_at + r).and_utc()),
+            file_size: row.file_size,
+        })
+        .collect();
+
+        Ok(files)
+    }
+
+    /// Puts a file from the trash back where it was, or at `to` if given.
+    /// Missing parent directories are created again.
+    pub async fn restore_from_trash(
+        &self,
+        id: i64,
+        to: Option<VirtualPath>,
+        user_id: i64,
+    ) -> ServerResult<SFile> {
+        let mut tx = self.db_pool().begin().await?;
+
+        let trashed_path = query!(
+            r#"SELECT trashed_path AS "trashed_path!" FROM sfiles
+            WHERE id = $1 AND user_id = $2 AND trashed_at IS NOT NULL
+            FOR UPDATE"#,
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(ServerError::PathDoesntExist)?
+        .trashed_path;
+
+        let path = to.unwrap_or_else(|| VirtualPath::from(trashed_path.as_str()));
+        path.err_if_dir()?;
+
+        self.make_all_dirs(&path, user_id, Some(&mut tx)).await?;
+        self.insert_entry_tx(&path, id, user_id, &mut tx).await?;
+
+        let row = query_as!(
+            SFileRow,
+            "UPDATE sfiles SET trashed_at = NULL, trashed_path = NULL WHERE id = $1 RETURNING *",
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        trace!("Restored {path} from the trash");
+
+        SFile::from_row(row, &path)
+    }
+
+    /// Deletes files in the user's trash for good, or just the one with `id`.
+    /// Returns how many were deleted.
+    pub async fn purge_trash(&self, user_id: i64, id: Option<i64>) -> ServerResult<u64> {
+        let ids: Vec<i64> = query!(
+            "SELECT id FROM sfiles
+            WHERE user_id = $1 AND trashed_at IS NOT NULL AND ($2::BIGINT IS NULL OR id = $2)",
+            user_id,
+            id
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        if id.is_some() && ids.is_empty() {
+            return Err(ServerError::PathDoesntExist);
+        }
+
+        self.purge(&ids).await
+    }
+
+    /// Deletes everything that has been in the trash longer than the retention period.
+    pub async fn purge_old_trash(&self) -> ServerResult<u64> {
+        let Some(retention) = retention() else {
+            return Ok(0);
+        };
+
+        let ids: Vec<i64> = query!(
+            "SELECT id FROM sfiles WHERE trashed_at <= $1",
+            Utc::now().naive_utc() - retention
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        self.purge(&ids).await
+    }
+
+    async fn purge(&self, ids: &[i64]) -> ServerResult<u64> {
+        let mut purged = 0;
+        for &id in ids {
+            let media = query!(
+                "SELECT m.file_hash FROM sfiles s JOIN media m ON m.id = s.media_id WHERE s.id = $1",
+                id
+            )
+            .fetch_optional(self.db_pool())
+            .await?;
+            let Some(media) = media else {
+                continue;
+            };
+
+            // Same lock as uploads of this hash, so they don't link to media being deleted
+            let _guard = self.active_uploads.lock(media.file_hash).await;
+            let mut tx = self.db_pool().begin().await?;
+
+            // It may have been restored or purged in the meantime
+            let still_trashed = query!(
+                "SELECT 1 as _exists FROM sfiles WHERE id = $1 AND trashed_at IS NOT NULL FOR UPDATE",
+                id
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .is_some();
+            if !still_trashed {
+                continue;
+            }
+
+            self.delete_sfile_tx(id, &mut tx).await?;
+            tx.commit().await?;
+            purged += 1;
+        }
+
+        Ok(purged)
+    }
+}
+
+/// None if the trash is kept until it's emptied.
+fn retention() -> Option<Duration> {
+    match SETTINGS.trash.retention_days {
+        0 => None,
+        days => Some(Duration::days(days as i64)),
+    }
+}
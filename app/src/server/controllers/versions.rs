@@ -0,0 +1,204 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::{query, query_as, Postgres, Transaction};
+use tracing::trace;
+
+use crate::{
+    config::SETTINGS,
+    server::{
+        controllers::files::{refresh_media_expiry, FileControllerInner},
+        error::{ServerError, ServerResult},
+        models::files::{FileVersion, Media, SFile, SFileRow, VirtualPath},
+    },
+};
+
+impl FileControllerInner {
+    /// Every version of the user's file at `vpath`, newest first.
+    pub async fn list_versions(
+        &self,
+        vpath: &VirtualPath,
+        user_id: i64,
+    ) -> ServerResult<Vec<FileVersion>> {
+        vpath.err_if_dir()?;
+        let sfile = self.get_sfile(vpath, user_id).await?;
+
+        let versions = query!(
+            "SELECT v.id, v.uploaded_by, v.created_at, m.file_size, m.file_hash
+            FROM sfile_versions v
+            JOIN media m ON m.id = v.media_id
+            WHERE v.sfile_id = $1
+            ORDER BY v.id DESC",
+            sfile.id as i64
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| FileVersion {
+            id: row.id as u64,
+            file_size: row.file_size,
+            file_hash: row.file_hash,
+            uploaded_by: row.uploaded_by,
+            created_at: row.created_at.and_utc(),
+            is_current: i == 0,
+        })
+        .collect();
+
+        Ok(versions)
+    }
+
+    /// The media of one of the file's versions.
+    pub async fn version_media(&self, sfile_id: i64, version_id: i64) -> ServerResult<Media> {
+        query_as!(
+            Media,
+            "SELECT m.* FROM sfile_versions v
+            JOIN media m ON m.id = v.media_id
+            WHERE v.id = $1 AND v.sfile_id = $2",
+            version_id,
+            sfile_id
+        )
+        .fetch_optional(self.db_pool())
+        .await?
+        .ok_or(ServerError::NoMediaFound)
+    }
+
+    /// Gives the user's file at `vpath` the contents it had at `version_id`.
+    /// This is a new version itself, so the versions in between stay around.
+    pub async fn rollback(
+        &self,
+        vpath: &VirtualPath,
+        version_id: i64,
+        user_id: i64,
+    ) -> ServerResult<SFile> {
+        vpath.err_if_dir()?;
+        let sfile = self.get_sfile(vpath, user_id).await?;
+        let media = self.version_media(sfile.id as i64, version_id).await?;
+
+        // Same lock as uploads of this hash, so pruning doesn't delete it underneath us
+        let guard = self.active_uploads.lock(media.file_hash.clone()).await;
+        let mut tx = self.db_pool().begin().await?;
+        let file = self
+            .new_version_tx(sfile.id as i64, vpath, media.id, user_id, None, &mut tx)
+            .await?;
+        tx.commit().await?;
+        drop(guard);
+
+        trace!("Rolled {vpath} back to version {version_id}");
+        self.prune_versions(Some(sfile.id as i64)).await?;
+
+        Ok(file)
+    }
+
+    /// Points an existing file at `media_id`, recording it as a new version
+    /// unless the file already has that media.
+    pub(super) async fn new_version_tx(
+        &self,
+        sfile_id: i64,
+        vpath: &VirtualPath,
+        media_id: i64,
+        user_id: i64,
+        expires_at: Option<NaiveDateTime>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> ServerResult<SFile> {
+        let old = query_as!(
+            SFileRow,
+            "SELECT * FROM sfiles WHERE id = $1 FOR UPDATE",
+            sfile_id
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        if old.is_dir {
+            return Err(ServerError::PathAlreadyExists);
+        }
+
+        let row = query_as!(
+            SFileRow,
+            "UPDATE sfiles SET media_id = $2, modified_at = $3, expires_at = COALESCE($4, expires_at)
+            WHERE id = $1
+            RETURNING *",
+            sfile_id,
+            media_id,
+            Utc::now().naive_utc(),
+            expires_at
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+
+        if old.media_id != Some(media_id) {
+            query!(
+                "INSERT INTO sfile_versions (sfile_id, media_id, uploaded_by) VALUES ($1, $2, $3)",
+                sfile_id,
+                media_id,
+                user_id
+            )
+            .execute(&mut **tx)
+            .await?;
+            if let Some(old_media_id) = old.media_id {
+                refresh_media_expiry(old_media_id, tx).await?;
+            }
+        }
+        refresh_media_expiry(media_id, tx).await?;
+
+        SFile::from_row(row, vpath)
+    }
+
+    /// Deletes old versions past what the settings keep, of one file or of all of them,
+    /// along with media nothing points at anymore. Returns how many were deleted.
+    pub async fn prune_versions(&self, sfile_id: Option<i64>) -> ServerResult<u64> {
+        let settings = &SETTINGS.versions;
+        if settings.keep_versions == 0 && settings.keep_days == 0 {
+            return Ok(0);
+        }
+        let cutoff = (settings.keep_days > 0)
+            .then(|| Utc::now().naive_utc() - Duration::days(settings.keep_days as i64));
+
+        // The newest version is the current one and always stays
+        let mut media_ids: Vec<i64> = query!(
+            r"WITH ranked AS (
+                SELECT id, created_at,
+                    ROW_NUMBER() OVER (PARTITION BY sfile_id ORDER BY id DESC) AS n
+                FROM sfile_versions
+                WHERE $1::BIGINT IS NULL OR sfile_id = $1
+            )
+            DELETE FROM sfile_versions v
+            USING ranked r
+            WHERE v.id = r.id AND r.n > 1
+            AND (($2 > 0 AND r.n > $2) OR r.created_at < $3)
+            RETURNING v.media_id",
+            sfile_id,
+            settings.keep_versions as i64,
+            cutoff
+        )
+        .fetch_all(self.db_pool())
+        .await?
+        .into_iter()
+        .map(|row| row.media_id)
+        .collect();
+
+        let pruned = media_ids.len() as u64;
+        media_ids.sort_unstable();
+        media_ids.dedup();
+
+        for media_id in media_ids {
+            // Checked before locking, media still in use may be locked by whoever called us
+            let unreferenced = query!(
+                "SELECT file_hash FROM media WHERE id = $1
+                AND NOT EXISTS (SELECT 1 FROM sfiles WHERE media_id = $1)
+                AND NOT EXISTS (SELECT 1 FROM sfile_versions WHERE media_id = $1)",
+                media_id
+            )
+            .fetch_optional(self.db_pool())
+            .await?;
+            let Some(media) = unreferenced else {
+                continue;
+            };
+
+            let _guard = self.active_uploads.lock(media.file_hash).await;
+            let mut tx = self.db_pool().begin().await?;
+            self.delete_media_if_unreferenced_tx(media_id, &mut tx)
+                .await?;
+            tx.commit().await?;
+        }
+
+        Ok(pruned)
+    }
+}
@@ -39,6 +39,7 @@ pub trait WsOutEvent: Serialize + Clone + 'static {
 }
 
 #[enum_dispatch]
+#[allow(async_fn_in_trait)]
 pub trait WsIncomingEvent {
     async fn handle(self, state: &ServerState, connection_id: Uuid) -> ServerResult<()>;
 }
@@ -41,6 +41,16 @@ pub enum ServerError {
     AuthorizationError { message: String },
     #[error("Database error: {message}")]
     DatabaseError { message: String },
+    #[error("Unsupported media: {details}")]
+    UnsupportedMedia { details: String },
+    #[error("Upload offset mismatch: expected {expected}")]
+    UploadOffsetMismatch { expected: u64 },
+    #[error("Payload too large: {details}")]
+    PayloadTooLarge { details: String },
+    #[error("Storage quota of {quota} bytes exceeded")]
+    QuotaExceeded { quota: u64 },
+    #[error("Storage backend error: {message}")]
+    StorageError { message: String },
 }
 
 #[derive(Serialize)]
@@ -95,6 +105,34 @@ impl ServerError {
                     details: None,
                 },
             ),
+            ServerError::UnsupportedMedia { details } => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ErrorResponse {
+                    error: "Unsupported media".to_string(),
+                    details: Some(details.clone()),
+                },
+            ),
+            ServerError::UploadOffsetMismatch { expected } => (
+                StatusCode::CONFLICT,
+                ErrorResponse {
+                    error: "Upload offset mismatch".to_string(),
+                    details: Some(format!("Expected offset {expected}")),
+                },
+            ),
+            ServerError::PayloadTooLarge { details } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorResponse {
+                    error: "Payload too large".to_string(),
+                    details: Some(details.clone()),
+                },
+            ),
+            ServerError::QuotaExceeded { quota } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorResponse {
+                    error: "Storage quota exceeded".to_string(),
+                    details: Some(format!("The quota is {quota} bytes")),
+                },
+            ),
             ServerError::RateLimitExceeded => (
                 StatusCode::TOO_MANY_REQUESTS,
                 ErrorResponse {
@@ -106,6 +144,7 @@ impl ServerError {
             | ServerError::DatabaseQueryError { .. }
             | ServerError::DatabaseError { .. }
             | ServerError::IOError { .. }
+            | ServerError::StorageError { .. }
             | ServerError::InternalError { .. } => {
                 error!("Internal server error: {}", self);
                 (
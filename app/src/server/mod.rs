@@ -2,6 +2,8 @@ pub mod controllers;
 pub mod db_utils;
 pub mod error;
 pub mod models;
+pub mod remux;
+pub mod storage;
 pub mod validation;
 pub mod web;
 use axum::{
@@ -13,6 +15,8 @@ use axum::{
 use controllers::{
     auth::AuthController,
     files::{FileController, FileControllerInner},
+    scrub::{ScrubController, ScrubControllerInner},
+    stream::{StreamController, StreamControllerInner},
     websocket::{WebSocketController, WebSocketControllerInner},
 };
 use dashmap::DashMap;
@@ -27,6 +31,8 @@ pub struct ServerState {
     pub file_controller: FileController,
     pub ws_controller: WebSocketController,
     pub auth_controller: AuthController,
+    pub stream_controller: StreamController,
+    pub scrub_controller: ScrubController,
     /// Maps user session IDs to WebSocket connection IDs for progress updates
     /// Only authenticated users have sessions - anonymous users get direct broadcasts
     pub session_to_ws: Arc<DashMap<Uuid, Uuid>>,
@@ -81,13 +87,24 @@ pub async fn create_server(db_pool: PgPool) -> (Router, ServerState) {
 
     let auth_controller = AuthController::new(db_pool.clone());
 
-    let file_controller =
-        Arc::new(FileControllerInner::new(db_pool, Arc::clone(&ws_controller)).await);
+    let blobs = storage::from_settings().expect("Failed to set up file storage.");
+
+    let scrub_controller = Arc::new(ScrubControllerInner::new(
+        db_pool.clone(),
+        blobs.clone(),
+        SETTINGS.scrubber.clone(),
+    ));
+
+    let file_controller = Arc::new(
+        FileControllerInner::new(db_pool, Arc::clone(&ws_controller), blobs.clone()).await,
+    );
 
     let server_state = ServerState {
         file_controller: file_controller.clone(),
         ws_controller: ws_controller.clone(),
         auth_controller: auth_controller.clone(),
+        stream_controller: Arc::new(StreamControllerInner::new(blobs)),
+        scrub_controller,
         session_to_ws: Arc::new(DashMap::new()),
     };
 
@@ -114,7 +131,15 @@ pub async fn run(host: &str, port: u16, pg_connect_opts: PgConnectOptions) -> Se
         .expect("Failed to run migrations.");
     trace!("Ran database migrations.");
 
-    let (routes, _server_state) = create_server(db_pool).await;
+    let (routes, server_state) = create_server(db_pool).await;
+
+    if SETTINGS.scrubber.enabled {
+        tokio::spawn(server_state.scrub_controller.clone().run());
+        trace!("Started the integrity scrubber.");
+    }
+
+    tokio::spawn(server_state.file_controller.clone().run_reaper());
+    trace!("Started the expired file reaper.");
 
     trace!("Binding to {host}:{port}...");
     let listener = TcpListener::bind(format!("{host}:{port}")).await?;
@@ -137,6 +162,20 @@ async fn main_response_mapper(res: Response) -> Response {
     error_response.unwrap_or(res)
 }
 
+pub async fn auth_controller() -> ServerResult<AuthController> {
+    init().await?;
+    let db_url = SETTINGS.database.connection_string();
+
+    let db_pool = PgPool::connect(&db_url).await?;
+
+    sqlx::migrate!("./migrations")
+        .run(&db_pool)
+        .await
+        .expect("Failed to run migrations.");
+
+    Ok(AuthController::new(db_pool))
+}
+
 pub async fn file_controller() -> ServerResult<FileController> {
     init().await?;
     let db_url = SETTINGS.database.connection_string();
@@ -148,7 +187,9 @@ pub async fn file_controller() -> ServerResult<FileController> {
         .await
         .expect("Failed to run migrations.");
 
-    Ok(Arc::new(FileControllerInner::new_no_ws(db_pool).await))
+    Ok(Arc::new(
+        FileControllerInner::new_no_ws(db_pool, storage::from_settings()?).await,
+    ))
 }
 
 /// Only useful for nuking lol
@@ -158,5 +199,7 @@ pub async fn file_controller_no_migrate() -> ServerResult<FileController> {
 
     let db_pool = PgPool::connect(&db_url).await?;
 
-    Ok(Arc::new(FileControllerInner::new_no_ws(db_pool).await))
+    Ok(Arc::new(
+        FileControllerInner::new_no_ws(db_pool, storage::from_settings()?).await,
+    ))
 }
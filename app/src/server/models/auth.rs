@@ -1,9 +1,14 @@
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
-use std::collections::HashSet;
+use std::{collections::HashSet, path::Path, str::FromStr};
 use uuid::Uuid;
 
+use crate::server::{
+    error::{ServerError, ServerResult},
+    models::files::VirtualPath,
+};
+
 #[derive(Debug, Clone, FromRow)]
 pub struct User {
     pub id: i64,
@@ -46,7 +51,7 @@ pub enum RelationshipType {
     Editor,
     Viewer,
     // TODO! this isn't really implemented or done anything with anywhere
-    None
+    None,
 }
 
 impl RelationshipType {
@@ -70,7 +75,7 @@ impl RelationshipType {
             // Viewer permissions
             (RelationshipType::Viewer, Permission::Read) => true,
             (RelationshipType::Viewer, _) => false,
-            (RelationshipType::None, _) => false
+            (RelationshipType::None, _) => false,
         }
     }
 
@@ -102,6 +107,58 @@ pub enum Permission {
     ChangePermissions,
 }
 
+impl Permission {
+    /// The scope an API token needs for this permission
+    pub fn scope(&self) -> TokenScope {
+        match self {
+            Permission::Read => TokenScope::Read,
+            Permission::Write => TokenScope::Write,
+            Permission::Delete => TokenScope::Delete,
+            Permission::Share | Permission::ChangePermissions => TokenScope::Share,
+        }
+    }
+}
+
+/// What an API token may be used for. A token can never do more than its user can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Read,
+    Write,
+    Delete,
+    Share,
+    Admin,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+            TokenScope::Delete => "delete",
+            TokenScope::Share => "share",
+            TokenScope::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenScope::Read),
+            "write" => Ok(TokenScope::Write),
+            "delete" => Ok(TokenScope::Delete),
+            "share" => Ok(TokenScope::Share),
+            "admin" => Ok(TokenScope::Admin),
+            _ => Err(ServerError::ValidationError {
+                message: format!("Unknown token scope: {s}"),
+            }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum PermissionAction {
@@ -165,6 +222,26 @@ impl UserSession {
     }
 }
 
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub token_hash: String, // hex SHA-256 of the token
+    pub scopes: Vec<String>,
+    pub path_prefix: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+impl ApiToken {
+    /// Scopes that somehow aren't valid anymore are dropped
+    pub fn scopes(&self) -> HashSet<TokenScope> {
+        self.scopes.iter().filter_map(|s| s.parse().ok()).collect()
+    }
+}
+
 // DTOs for API requests/responses
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -220,6 +297,51 @@ impl From<&User> for UserInfo {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    /// A directory like 'root/backups/', the token can't touch files outside it
+    pub path_prefix: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A token as it's listed, without the token itself
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiTokenInfo {
+    pub id: u64,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub path_prefix: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl From<ApiToken> for ApiTokenInfo {
+    fn from(token: ApiToken) -> Self {
+        let mut scopes: Vec<TokenScope> = token.scopes().into_iter().collect();
+        scopes.sort_by_key(|scope| *scope as u8);
+        Self {
+            id: token.id as u64,
+            name: token.name,
+            scopes,
+            path_prefix: token.path_prefix,
+            expires_at: token.expires_at.map(|dt| dt.and_utc()),
+            created_at: token.created_at.and_utc(),
+            last_used: token.last_used.map(|dt| dt.and_utc()),
+        }
+    }
+}
+
+/// The only time the token itself is sent back
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiTokenInfo,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GrantPermissionRequest {
     pub target_user_id: u64,
@@ -246,12 +368,18 @@ pub struct PermissionInfo {
     pub expires_at: Option<DateTime<Utc>>,
 }
 
+/// The resource admins are owners of.
+pub const SYSTEM_RESOURCE: &str = "system";
+
 // ReBAC context for checking permissions
 #[derive(Debug, Clone)]
 pub struct AuthContext {
     pub user_id: i64,
     pub username: String,
     pub permissions: HashSet<(String, Option<i64>, RelationshipType)>, // (resource_type, resource_id, relationship)
+    // What an API token is limited to, None when logged in with a session
+    pub scopes: Option<HashSet<TokenScope>>,
+    pub path_prefix: Option<VirtualPath>,
 }
 
 impl AuthContext {
@@ -260,6 +388,52 @@ impl AuthContext {
             user_id,
             username,
             permissions: HashSet::new(),
+            scopes: None,
+            path_prefix: None,
+        }
+    }
+
+    /// Limits the context to what the token was created for.
+    pub fn restrict_to(&mut self, token: &ApiToken) {
+        self.scopes = Some(token.scopes());
+        self.path_prefix = token.path_prefix.as_deref().map(VirtualPath::from);
+    }
+
+    pub fn is_token(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    /// Sessions have every scope.
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    pub fn require_scope(&self, scope: TokenScope) -> ServerResult<()> {
+        if self.allows(scope) {
+            Ok(())
+        } else {
+            Err(ServerError::AuthorizationError {
+                message: format!("This token doesn't have the {} scope", scope.as_str()),
+            })
+        }
+    }
+
+    /// Whether the path is in the directory the token is limited to, if any.
+    pub fn can_access(&self, path: &VirtualPath) -> bool {
+        self.path_prefix.as_ref().is_none_or(|prefix| {
+            AsRef::<Path>::as_ref(path).starts_with(AsRef::<Path>::as_ref(prefix))
+        })
+    }
+
+    pub fn require_path(&self, path: &VirtualPath) -> ServerResult<()> {
+        if self.can_access(path) {
+            Ok(())
+        } else {
+            Err(ServerError::AuthorizationError {
+                message: format!("This token can't access {path}"),
+            })
         }
     }
 
@@ -269,6 +443,10 @@ impl AuthContext {
         resource_id: Option<i64>,
         required_permission: Permission,
     ) -> bool {
+        if !self.allows(required_permission.scope()) {
+            return false;
+        }
+
         // Check if user has any relationship that grants the required permission
         for (res_type, res_id, relationship) in &self.permissions {
             if res_type == resource_type
@@ -282,6 +460,16 @@ impl AuthContext {
         false
     }
 
+    /// Admins own the system resource.
+    pub fn is_admin(&self) -> bool {
+        self.allows(TokenScope::Admin)
+            && self.permissions.contains(&(
+                SYSTEM_RESOURCE.to_string(),
+                None,
+                RelationshipType::Owner,
+            ))
+    }
+
     pub fn add_permission(
         &mut self,
         resource_type: String,
@@ -293,6 +481,30 @@ impl AuthContext {
     }
 }
 
+/// API tokens start with this, so they can't be mistaken for session ids
+pub const API_TOKEN_PREFIX: &str = "oc_";
+
+// API token utils
+pub mod api_token {
+    use rand_core::{OsRng, RngCore};
+    use sha2::{Digest, Sha256};
+
+    use super::API_TOKEN_PREFIX;
+
+    /// A new random token, 256 bits after the prefix
+    pub fn generate() -> String {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        format!("{API_TOKEN_PREFIX}{}", hex::encode(bytes))
+    }
+
+    /// Tokens are random enough that a fast hash is fine, unlike passwords,
+    /// and it lets them be looked up by their hash
+    pub fn hash(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+}
+
 // Password utils
 pub mod password {
     use crate::server::error::{ServerError, ServerResult};
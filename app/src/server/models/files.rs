@@ -11,23 +11,23 @@ use crate::{
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::de::Error as err;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::prelude::FromRow;
 use std::{
     fmt,
     path::{Path, PathBuf},
 };
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
 // A row from the database.
-#[derive(FromRow, Debug)]
+#[derive(FromRow, Debug, Clone)]
 pub struct Media {
     pub id: i64,
     pub uploaded_time: NaiveDateTime,
     // TODO! Will be used to implement caching later.
     pub accessed_time: NaiveDateTime,
-    // uhh.. TODO!
+    // When the last file pointing at this media expires,
+    // None if any of them never do.
     pub expiring_time: Option<NaiveDateTime>,
     // Size of the file in bytes.
     pub file_size: i64,
@@ -38,43 +38,102 @@ pub struct Media {
 }
 
 impl Media {
-    /// Returns the path that the file should be at
-    /// on the host machine's filesystem.
-    /// Is not guarenteed a file exists at this path.
-    /// Storage path follows this format:
-    /// `save_dir/[first 2 chars of hash]/[next 2]/[rest of hash]`
-    pub async fn true_path(&self) -> PathBuf {
-        let save_dir = &SETTINGS.directories.files_dir;
-
-        let hash = self.file_hash.clone();
-        let first_dir = &hash[0..2];
-        let second_dir = &hash[2..4];
-        let fname = &hash[4..];
-
-        save_dir.join(Path::new(&format!("{first_dir}/{second_dir}/{fname}")))
-    }
-
-    // Get a ReaderStream from the file, or an Err if it doesn't exist.
-    pub async fn reader_stream(&self) -> ServerResult<ReaderStream<File>> {
-        let file = File::open(&self.true_path().await)
-            .await
-            .map_err(|e| ServerError::IOError {
-                message: e.to_string(),
-            })?;
-
-        Ok(ReaderStream::new(file))
-    }
-
-    // Attempts to delete the underlying file from the disk.
-    pub async fn delete_from_disk(&self) -> ServerResult<()> {
-        tokio::fs::remove_file(self.true_path().await.as_path())
-            .await
-            .map_err(|e| ServerError::IOError {
-                message: e.to_string(),
-            })
+    /// The key the file is kept under in the blob store.
+    /// Keys follow this format:
+    /// `[first 2 chars of hash]/[next 2]/[rest of hash]`
+    pub fn blob_key(&self) -> String {
+        Self::blob_key_for(&self.file_hash)
+    }
+
+    /// The key of the blob holding contents with this hash, see [`Self::blob_key`].
+    pub fn blob_key_for(file_hash: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            &file_hash[0..2],
+            &file_hash[2..4],
+            &file_hash[4..]
+        )
+    }
+
+    /// Where the HLS playlist and segments for this media are cached.
+    /// Keyed by hash, so deduplicated uploads share one cache.
+    pub fn stream_cache_dir(&self) -> PathBuf {
+        SETTINGS
+            .directories
+            .data_dir
+            .join("streams")
+            .join(&self.file_hash)
+    }
+}
+
+// A row from the uploads table, a resumable upload that hasn't finished yet.
+#[derive(FromRow, Debug)]
+pub struct Upload {
+    pub id: Uuid,
+    // Owner of the upload, only they can continue it
+    pub user_id: i64,
+    // Directory the file goes in once the upload is done
+    pub vpath: String,
+    pub file_name: String,
+    // Total size of the file in bytes, declared when the upload was created.
+    pub upload_length: i64,
+    pub created_at: NaiveDateTime,
+    // When the file expires once it's created, if ever
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl Upload {
+    /// Where the bytes received so far are kept until the upload is done.
+    pub fn temp_path(&self) -> PathBuf {
+        SETTINGS
+            .directories
+            .files_dir
+            .join(format!("tmp_upload_{}", self.id))
+    }
+
+    /// How many bytes have been received, which is just the size of the temp file.
+    pub async fn offset(&self) -> ServerResult<u64> {
+        Ok(tokio::fs::metadata(self.temp_path()).await?.len())
     }
 }
 
+/// Asks a client to prove it has the file it claims to, before the server
+/// links it to existing media with the same hash.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HashChallenge {
+    pub id: Uuid,
+    pub nonce: String,
+    // The range of the file to hash
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl HashChallenge {
+    /// A hasher already fed the nonce, the file range goes in next.
+    pub fn hasher(&self) -> Sha256 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.nonce.as_bytes());
+        hasher
+    }
+
+    /// The answer to the challenge: SHA-256 of the nonce followed by the requested range.
+    pub fn answer(&self, range: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(range);
+        format!("{:X}", hasher.finalize())
+    }
+}
+
+/// Response to an upload by hash.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum HashUploadResponse {
+    /// The file was created from existing media, nothing needs uploading.
+    Linked { file: SFile },
+    /// The client has to answer this before the file can be linked.
+    Challenge { challenge: HashChallenge },
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SFile {
     pub id: u64,
@@ -89,6 +148,12 @@ pub struct SFile {
     // Whether file/folder is publicly accessible
     pub is_public: bool,
     pub user_id: Option<i64>,
+    // When the file gets deleted, if ever
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    // Hash of the contents, only filled in when listing directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_hash: Option<String>,
 }
 
 // A row from the sfiles table (new schema - no paths stored)
@@ -104,6 +169,10 @@ pub struct SFileRow {
     pub user_id: Option<i64>,
     // Whether file/folder is publicly accessible
     pub is_public: bool,
+    pub expires_at: Option<NaiveDateTime>,
+    // Set while the file is in the trash, along with where it was
+    pub trashed_at: Option<NaiveDateTime>,
+    pub trashed_path: Option<String>,
 }
 
 // A row from the sfile_entries table
@@ -142,6 +211,8 @@ impl SFile {
             top_level_name: full_path.name().unwrap(),
             is_public: row.is_public,
             user_id: row.user_id,
+            expires_at: row.expires_at.map(|t| t.and_utc()),
+            media_hash: None,
         })
     }
 
@@ -157,11 +228,13 @@ impl SFile {
             top_level_name: "".into(),
             is_public: row.is_public,
             user_id: row.user_id,
+            expires_at: row.expires_at.map(|t| t.and_utc()),
+            media_hash: None,
         }
     }
 }
 
-fn check_is_dir(path: &PathBuf) -> bool {
+fn check_is_dir(path: &Path) -> bool {
     path.to_string_lossy().ends_with('/')
 }
 
@@ -523,6 +596,164 @@ pub struct FileUploadInfo {
     pub file_hash: String,
     pub vpath: VirtualPath,
     pub user_id: i64,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Uploaded files waiting in temporary files, and directories to create with them.
+/// Stored all at once by `finish_uploads`.
+#[derive(Default)]
+pub struct StagedUpload {
+    pub dirs: Vec<VirtualPath>,
+    pub files: Vec<FileUploadInfo>,
+}
+
+impl StagedUpload {
+    pub fn bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.file_size as u64).sum()
+    }
+
+    pub fn temp_paths(&self) -> Vec<PathBuf> {
+        self.files.iter().map(|f| f.temp_path.clone()).collect()
+    }
+
+    pub fn extend(&mut self, other: StagedUpload) {
+        self.dirs.extend(other.dirs);
+        self.files.extend(other.files);
+    }
+}
+
+/// What a `POST /files/[dir]` stored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UploadResult {
+    /// Every file and directory created
+    pub files: Vec<SFile>,
+    /// The uploaded files that couldn't be stored
+    pub errors: Vec<UploadFailure>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadFailure {
+    /// The multipart field name of the file
+    pub name: String,
+    pub error: String,
+}
+
+/// What `server fsck` found wrong with the stored files.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Keys of blobs that no media row points to
+    pub orphaned_blobs: Vec<String>,
+    /// Media rows whose blob is missing from the store
+    pub dangling_media: Vec<Media>,
+    /// Ids and full paths of files pointing at dangling media
+    pub broken_sfiles: Vec<(i64, String)>,
+    /// Media rows that no file points to
+    pub unreferenced_media: Vec<Media>,
+    /// Temp files and directories left behind by failed uploads and remuxes
+    pub stale_temp_files: Vec<PathBuf>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_blobs.is_empty()
+            && self.dangling_media.is_empty()
+            && self.broken_sfiles.is_empty()
+            && self.unreferenced_media.is_empty()
+            && self.stale_temp_files.is_empty()
+    }
+
+    /// Only the problems that are just wasting space, which can be
+    /// cleaned up without any files disappearing.
+    pub fn garbage_only(self) -> Self {
+        Self {
+            dangling_media: Vec::new(),
+            broken_sfiles: Vec::new(),
+            ..self
+        }
+    }
+}
+
+/// A deleted file waiting in its owner's trash, from `GET /trash`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrashedFile {
+    pub id: u64,
+    // Where the file was when it was deleted, and where it's restored to
+    pub original_path: String,
+    pub trashed_at: DateTime<Utc>,
+    // When it's purged on its own, None if it stays until the trash is emptied
+    pub purge_at: Option<DateTime<Utc>>,
+    pub file_size: i64,
+}
+
+/// What a copy does when something is already at the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Give up with a 409
+    #[default]
+    Fail,
+    /// Merge into directories, files that are in the way get a new version
+    Overwrite,
+    /// Copy to the first free `name (n)` instead
+    Rename,
+}
+
+/// One version of a file, from `GET /versions/*path`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileVersion {
+    pub id: u64,
+    pub file_size: i64,
+    pub file_hash: String,
+    // None if the uploader's account has been deleted
+    pub uploaded_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    // Whether this is what the file has right now
+    pub is_current: bool,
+}
+
+/// How much a user stores, from `GET /auth/me/usage`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageUsage {
+    /// Media shared by several users is split evenly between them
+    pub used_bytes: u64,
+    /// None if there is no limit
+    pub quota_bytes: Option<u64>,
+}
+
+impl StorageUsage {
+    /// Fails with [`ServerError::QuotaExceeded`] if `incoming` more bytes don't fit.
+    pub fn check(&self, incoming: u64) -> ServerResult<()> {
+        match self.quota_bytes {
+            Some(quota) if self.used_bytes.saturating_add(incoming) > quota => {
+                Err(ServerError::QuotaExceeded { quota })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How the scrubber is doing, from `GET /admin/scrub`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrubReport {
+    pub total_media: i64,
+    /// Media checked at least once
+    pub checked_media: i64,
+    /// When the least recently checked media was checked
+    pub oldest_check: Option<DateTime<Utc>>,
+    /// Media that didn't match its hash, or couldn't be read, on its last check
+    pub failures: Vec<ScrubFailure>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrubFailure {
+    pub media_id: i64,
+    pub file_hash: String,
+    /// What the blob hashed to instead, if it could be read
+    pub actual_hash: Option<String>,
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+    /// Last time it did match, if ever
+    pub verified_at: Option<DateTime<Utc>>,
 }
 
 // Websocket (outgoing) events
@@ -569,7 +800,7 @@ pub struct CancelUploadEvent {
 }
 
 impl WsIncomingEvent for CancelUploadEvent {
-    async fn handle(self, state: &ServerState, connection_id: Uuid) -> ServerResult<()> {
+    async fn handle(self, _state: &ServerState, _connection_id: Uuid) -> ServerResult<()> {
         todo!()
     }
 }
@@ -0,0 +1,307 @@
+// Fragmented MP4 (CMAF-ish) writer.
+// Produces the init segment (ftyp + moov with mvex) and media segments (moof + mdat)
+// that an HLS playlist points at through EXT-X-MAP.
+
+use super::{Sample, Track, TrackKind};
+
+/// Writes a box, the closure writes the payload.
+/// The size is patched in after the payload is written.
+pub(crate) fn write_box(buf: &mut Vec<u8>, fourcc: &[u8; 4], f: impl FnOnce(&mut Vec<u8>)) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0, 0, 0, 0]);
+    buf.extend_from_slice(fourcc);
+    f(buf);
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Same as write_box, but writes the version and flags of a "full box" first.
+pub(crate) fn write_full_box(
+    buf: &mut Vec<u8>,
+    fourcc: &[u8; 4],
+    version: u8,
+    flags: u32,
+    f: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(buf, fourcc, |buf| {
+        buf.extend_from_slice(&((version as u32) << 24 | (flags & 0x00FF_FFFF)).to_be_bytes());
+        f(buf);
+    });
+}
+
+pub(crate) fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_be_bytes());
+}
+
+pub(crate) fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_be_bytes());
+}
+
+pub(crate) fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_be_bytes());
+}
+
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+// sample_flags for the trun box
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// Builds the initialization segment for the given tracks.
+/// Track ids are 1-based, in the same order as `tracks`.
+pub fn init_segment(tracks: &[Track]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_box(&mut buf, b"ftyp", |buf| {
+        buf.extend_from_slice(b"iso6");
+        put_u32(buf, 0);
+        for brand in [b"iso6", b"iso5", b"mp41", b"cmfc"] {
+            buf.extend_from_slice(brand);
+        }
+    });
+
+    write_box(&mut buf, b"moov", |buf| {
+        write_full_box(buf, b"mvhd", 0, 0, |buf| {
+            put_u32(buf, 0); // creation time
+            put_u32(buf, 0); // modification time
+            put_u32(buf, 1000); // timescale
+            put_u32(buf, 0); // duration, unknown for fragmented files
+            put_u32(buf, 0x0001_0000); // rate
+            put_u16(buf, 0x0100); // volume
+            buf.extend_from_slice(&[0; 10]);
+            UNITY_MATRIX.iter().for_each(|v| put_u32(buf, *v));
+            buf.extend_from_slice(&[0; 24]);
+            put_u32(buf, tracks.len() as u32 + 1); // next track id
+        });
+
+        for (i, track) in tracks.iter().enumerate() {
+            write_trak(buf, i as u32 + 1, track);
+        }
+
+        write_box(buf, b"mvex", |buf| {
+            for i in 0..tracks.len() {
+                write_full_box(buf, b"trex", 0, 0, |buf| {
+                    put_u32(buf, i as u32 + 1); // track id
+                    put_u32(buf, 1); // default sample description index
+                    put_u32(buf, 0); // default sample duration
+                    put_u32(buf, 0); // default sample size
+                    put_u32(buf, 0); // default sample flags
+                });
+            }
+        });
+    });
+
+    buf
+}
+
+fn write_trak(buf: &mut Vec<u8>, track_id: u32, track: &Track) {
+    let is_video = track.kind == TrackKind::Video;
+
+    write_box(buf, b"trak", |buf| {
+        // flags: enabled | in movie
+        write_full_box(buf, b"tkhd", 0, 0x3, |buf| {
+            put_u32(buf, 0); // creation time
+            put_u32(buf, 0); // modification time
+            put_u32(buf, track_id);
+            put_u32(buf, 0);
+            put_u32(buf, 0); // duration
+            buf.extend_from_slice(&[0; 8]);
+            put_u16(buf, 0); // layer
+            put_u16(buf, 0); // alternate group
+            put_u16(buf, if is_video { 0 } else { 0x0100 }); // volume
+            put_u16(buf, 0);
+            UNITY_MATRIX.iter().for_each(|v| put_u32(buf, *v));
+            // 16.16 fixed point
+            put_u32(buf, track.width << 16);
+            put_u32(buf, track.height << 16);
+        });
+
+        write_box(buf, b"mdia", |buf| {
+            write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                put_u32(buf, 0); // creation time
+                put_u32(buf, 0); // modification time
+                put_u32(buf, track.timescale);
+                put_u32(buf, 0); // duration
+                put_u16(buf, 0x55C4); // language, 'und'
+                put_u16(buf, 0);
+            });
+
+            write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                put_u32(buf, 0);
+                buf.extend_from_slice(if is_video { b"vide" } else { b"soun" });
+                buf.extend_from_slice(&[0; 12]);
+                buf.extend_from_slice(if is_video {
+                    b"VideoHandler\0"
+                } else {
+                    b"SoundHandler\0"
+                });
+            });
+
+            write_box(buf, b"minf", |buf| {
+                if is_video {
+                    write_full_box(buf, b"vmhd", 0, 1, |buf| {
+                        buf.extend_from_slice(&[0; 8]);
+                    });
+                } else {
+                    write_full_box(buf, b"smhd", 0, 0, |buf| {
+                        put_u32(buf, 0);
+                    });
+                }
+
+                write_box(buf, b"dinf", |buf| {
+                    write_full_box(buf, b"dref", 0, 0, |buf| {
+                        put_u32(buf, 1);
+                        // flags 1: media data is in the same file
+                        write_full_box(buf, b"url ", 0, 1, |_| {});
+                    });
+                });
+
+                write_box(buf, b"stbl", |buf| {
+                    buf.extend_from_slice(&track.stsd);
+                    write_full_box(buf, b"stts", 0, 0, |buf| put_u32(buf, 0));
+                    write_full_box(buf, b"stsc", 0, 0, |buf| put_u32(buf, 0));
+                    write_full_box(buf, b"stsz", 0, 0, |buf| {
+                        put_u32(buf, 0);
+                        put_u32(buf, 0);
+                    });
+                    write_full_box(buf, b"stco", 0, 0, |buf| put_u32(buf, 0));
+                });
+            });
+        });
+    });
+}
+
+/// Builds one media segment (moof + mdat) out of the samples of each track.
+/// `fragments[i]` holds the samples of the track with id `i + 1`.
+pub fn media_segment(sequence_number: u32, fragments: &[Vec<Sample>]) -> Vec<u8> {
+    // The data offsets in the trun boxes depend on the size of the moof,
+    // which doesn't depend on the offsets themselves. So build it once to
+    // measure it, then build it again with the right offsets.
+    let zero_offsets = vec![0; fragments.len()];
+    let moof_len = moof(sequence_number, fragments, &zero_offsets).len();
+
+    let mut offsets = Vec::with_capacity(fragments.len());
+    // moof, then the mdat header
+    let mut offset = moof_len + 8;
+    for samples in fragments {
+        offsets.push(offset as i32);
+        offset += samples.iter().map(|s| s.data.len()).sum::<usize>();
+    }
+
+    let mut buf = moof(sequence_number, fragments, &offsets);
+
+    write_box(&mut buf, b"mdat", |buf| {
+        for sample in fragments.iter().flatten() {
+            buf.extend_from_slice(&sample.data);
+        }
+    });
+
+    buf
+}
+
+fn moof(sequence_number: u32, fragments: &[Vec<Sample>], data_offsets: &[i32]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_box(&mut buf, b"moof", |buf| {
+        write_full_box(buf, b"mfhd", 0, 0, |buf| put_u32(buf, sequence_number));
+
+        for (i, samples) in fragments.iter().enumerate() {
+            if samples.is_empty() {
+                continue;
+            }
+
+            write_box(buf, b"traf", |buf| {
+                // flags: default-base-is-moof
+                write_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| put_u32(buf, i as u32 + 1));
+
+                write_full_box(buf, b"tfdt", 1, 0, |buf| {
+                    put_u64(buf, samples[0].dts.max(0) as u64);
+                });
+
+                // flags: data offset, sample duration, size, flags and composition time offset
+                // version 1 so composition time offsets are signed
+                write_full_box(buf, b"trun", 1, 0x0F01, |buf| {
+                    put_u32(buf, samples.len() as u32);
+                    buf.extend_from_slice(&data_offsets[i].to_be_bytes());
+                    for sample in samples {
+                        put_u32(buf, sample.duration);
+                        put_u32(buf, sample.data.len() as u32);
+                        put_u32(
+                            buf,
+                            if sample.is_sync {
+                                SYNC_SAMPLE_FLAGS
+                            } else {
+                                NON_SYNC_SAMPLE_FLAGS
+                            },
+                        );
+                        buf.extend_from_slice(&sample.cts_offset.to_be_bytes());
+                    }
+                });
+            });
+        }
+    });
+
+    buf
+}
+
+/// Builds a stsd box holding a single visual sample entry,
+/// ex. `vp09` with its `vpcC` configuration box.
+pub(crate) fn visual_stsd(
+    fourcc: &[u8; 4],
+    width: u16,
+    height: u16,
+    config: impl FnOnce(&mut Vec<u8>),
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_full_box(&mut buf, b"stsd", 0, 0, |buf| {
+        put_u32(buf, 1);
+        write_box(buf, fourcc, |buf| {
+            buf.extend_from_slice(&[0; 6]);
+            put_u16(buf, 1); // data reference index
+            buf.extend_from_slice(&[0; 16]);
+            put_u16(buf, width);
+            put_u16(buf, height);
+            put_u32(buf, 0x0048_0000); // 72 dpi
+            put_u32(buf, 0x0048_0000);
+            put_u32(buf, 0);
+            put_u16(buf, 1); // frame count
+            buf.extend_from_slice(&[0; 32]); // compressor name
+            put_u16(buf, 0x0018); // depth
+            put_u16(buf, 0xFFFF);
+            config(buf);
+        });
+    });
+    buf
+}
+
+/// Builds a stsd box holding a single audio sample entry, ex. `Opus` with its `dOps` box.
+pub(crate) fn audio_stsd(
+    fourcc: &[u8; 4],
+    channels: u16,
+    sample_rate: u32,
+    config: impl FnOnce(&mut Vec<u8>),
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_full_box(&mut buf, b"stsd", 0, 0, |buf| {
+        put_u32(buf, 1);
+        write_box(buf, fourcc, |buf| {
+            buf.extend_from_slice(&[0; 6]);
+            put_u16(buf, 1); // data reference index
+            buf.extend_from_slice(&[0; 8]);
+            put_u16(buf, channels);
+            put_u16(buf, 16); // sample size
+            put_u32(buf, 0);
+            // 16.16 fixed point, rates that don't fit are left as 0
+            put_u32(
+                buf,
+                if sample_rate <= u16::MAX as u32 {
+                    sample_rate << 16
+                } else {
+                    0
+                },
+            );
+            config(buf);
+        });
+    });
+    buf
+}
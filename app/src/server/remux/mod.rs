@@ -0,0 +1,564 @@
+// A small remuxer that turns uploaded MP4 and WebM files into HLS.
+// Nothing is transcoded: samples are copied as-is into fragmented MP4 segments,
+// so the codecs have to be ones the client can already play.
+
+pub mod fmp4;
+pub mod mp4;
+pub mod webm;
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use crate::server::error::{ServerError, ServerResult};
+
+/// Segments are cut at the first keyframe after this many seconds.
+pub const TARGET_SEGMENT_DURATION: f64 = 6.0;
+
+pub const PLAYLIST_FILE: &str = "index.m3u8";
+pub const INIT_SEGMENT_FILE: &str = "init.mp4";
+pub const SEGMENT_EXTENSION: &str = "m4s";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub kind: TrackKind,
+    pub timescale: u32,
+    // in pixels, zero for audio
+    pub width: u32,
+    pub height: u32,
+    /// The complete stsd box (sample descriptions) for the init segment.
+    pub stsd: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Index into the demuxer's tracks
+    pub track: usize,
+    /// Decode timestamp, in the track's timescale
+    pub dts: i64,
+    pub cts_offset: i32,
+    pub duration: u32,
+    pub is_sync: bool,
+    pub data: Vec<u8>,
+}
+
+pub trait Demuxer {
+    fn tracks(&self) -> &[Track];
+
+    /// The next sample in (roughly) decode order across all tracks.
+    fn next_sample(&mut self) -> ServerResult<Option<Sample>>;
+}
+
+/// Opens a demuxer for the file at `path`, based on its first bytes rather than its name.
+pub fn open(path: &Path) -> ServerResult<Box<dyn Demuxer>> {
+    let mut magic = [0u8; 8];
+    let read = File::open(path)?.read(&mut magic)?;
+
+    if read >= 4 && magic[0..4] == webm::EBML_MAGIC {
+        return Ok(Box::new(webm::WebmDemuxer::open(path)?));
+    }
+
+    if read == 8
+        && matches!(
+            &magic[4..8],
+            b"ftyp" | b"moov" | b"mdat" | b"free" | b"wide"
+        )
+    {
+        return Ok(Box::new(mp4::Mp4Demuxer::open(path)?));
+    }
+
+    Err(ServerError::UnsupportedMedia {
+        details: "Only MP4 and WebM files can be streamed".to_string(),
+    })
+}
+
+/// Remuxes the file at `src` into `out_dir`, writing the playlist,
+/// the init segment and the media segments.
+pub fn remux_to_hls(src: &Path, out_dir: &Path) -> ServerResult<()> {
+    let mut demuxer = open(src)?;
+    let tracks = demuxer.tracks().to_vec();
+
+    if tracks.is_empty() {
+        return Err(ServerError::UnsupportedMedia {
+            details: "No playable audio or video tracks".to_string(),
+        });
+    }
+
+    fs::write(out_dir.join(INIT_SEGMENT_FILE), fmp4::init_segment(&tracks))?;
+
+    // Segments are cut on the video track if there is one
+    let primary = tracks
+        .iter()
+        .position(|t| t.kind == TrackKind::Video)
+        .unwrap_or(0);
+    let primary_timescale = tracks[primary].timescale as f64;
+    let timescales: Vec<f64> = tracks.iter().map(|t| t.timescale as f64).collect();
+
+    let mut fragments: Vec<Vec<Sample>> = vec![Vec::new(); tracks.len()];
+    let mut durations: Vec<f64> = Vec::new();
+    let mut segment_start: Option<f64> = None;
+    let mut primary_end = 0.0;
+
+    while let Some(sample) = demuxer.next_sample()? {
+        if sample.track == primary {
+            let time = sample.dts as f64 / primary_timescale;
+            match segment_start {
+                None => segment_start = Some(time),
+                Some(start)
+                    if sample.is_sync
+                        && time - start >= TARGET_SEGMENT_DURATION
+                        && !fragments[primary].is_empty() =>
+                {
+                    // Demuxers don't guarantee perfect interleaving, samples of
+                    // the other tracks that belong after the cut go in the next segment
+                    let carried: Vec<Vec<Sample>> = fragments
+                        .iter_mut()
+                        .enumerate()
+                        .map(|(i, samples)| {
+                            let cut =
+                                samples.partition_point(|s| (s.dts as f64 / timescales[i]) < time);
+                            samples.split_off(cut)
+                        })
+                        .collect();
+
+                    write_segment(out_dir, durations.len(), &fragments)?;
+                    durations.push(time - start);
+                    segment_start = Some(time);
+                    fragments = carried;
+                }
+                _ => {}
+            }
+            primary_end = (sample.dts + sample.duration as i64) as f64 / primary_timescale;
+        }
+        fragments[sample.track].push(sample);
+    }
+
+    if fragments.iter().any(|f| !f.is_empty()) {
+        write_segment(out_dir, durations.len(), &fragments)?;
+        durations.push(primary_end - segment_start.unwrap_or(0.0));
+    }
+
+    if durations.is_empty() {
+        return Err(ServerError::UnsupportedMedia {
+            details: "The file has no samples".to_string(),
+        });
+    }
+
+    // Written last, so its existence means the rest is there
+    fs::write(out_dir.join(PLAYLIST_FILE), playlist(&durations))?;
+
+    Ok(())
+}
+
+fn write_segment(out_dir: &Path, index: usize, fragments: &[Vec<Sample>]) -> ServerResult<()> {
+    // sequence numbers start at 1
+    let segment = fmp4::media_segment(index as u32 + 1, fragments);
+    fs::write(out_dir.join(segment_file_name(index)), segment)?;
+    Ok(())
+}
+
+pub fn segment_file_name(index: usize) -> String {
+    format!("{index}.{SEGMENT_EXTENSION}")
+}
+
+/// A VOD media playlist, with URIs relative to the playlist.
+pub fn playlist(durations: &[f64]) -> String {
+    let target = durations.iter().cloned().fold(0.0, f64::max).ceil() as u64;
+
+    let mut playlist = format!(
+        "#EXTM3U\n\
+        #EXT-X-VERSION:7\n\
+        #EXT-X-TARGETDURATION:{}\n\
+        #EXT-X-MEDIA-SEQUENCE:0\n\
+        #EXT-X-PLAYLIST-TYPE:VOD\n\
+        #EXT-X-INDEPENDENT-SEGMENTS\n\
+        #EXT-X-MAP:URI=\"{INIT_SEGMENT_FILE}\"\n",
+        target.max(1)
+    );
+
+    for (i, duration) in durations.iter().enumerate() {
+        playlist.push_str(&format!(
+            "#EXTINF:{duration:.3},\n{}\n",
+            segment_file_name(i)
+        ));
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+
+    playlist
+}
+
+/// Converts a timestamp between timescales, rounding to the nearest unit.
+pub(crate) fn rescale(value: i64, from: u64, to: u64) -> i64 {
+    let from = from as i128;
+    let scaled = value as i128 * to as i128;
+    let rounded = if scaled >= 0 {
+        (scaled + from / 2) / from
+    } else {
+        (scaled - from / 2) / from
+    };
+    rounded as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fmp4::{put_u16, put_u32, visual_stsd, write_box, write_full_box};
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ocloud_remux_{}", nanoid::nanoid!()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // video samples are 2 seconds long, with keyframes at 0s and 6s
+    fn video_samples() -> Vec<Vec<u8>> {
+        (0..6).map(|i| vec![0x10 + i; 10]).collect()
+    }
+
+    // audio samples are 1 second long
+    fn audio_samples() -> Vec<Vec<u8>> {
+        (0..12).map(|i| vec![0x40 + i; 4]).collect()
+    }
+
+    fn trak(
+        handler: &[u8; 4],
+        timescale: u32,
+        delta: u32,
+        samples: &[Vec<u8>],
+        sync: Option<&[u32]>,
+        chunk_offset: u32,
+        stsd: &[u8],
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"trak", |buf| {
+            write_full_box(buf, b"tkhd", 0, 3, |buf| {
+                buf.extend_from_slice(&[0; 72]);
+                put_u32(buf, 64 << 16);
+                put_u32(buf, 48 << 16);
+            });
+            write_box(buf, b"mdia", |buf| {
+                write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                    put_u32(buf, 0);
+                    put_u32(buf, 0);
+                    put_u32(buf, timescale);
+                    put_u32(buf, 0);
+                    put_u16(buf, 0x55C4);
+                    put_u16(buf, 0);
+                });
+                write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                    put_u32(buf, 0);
+                    buf.extend_from_slice(handler);
+                    buf.extend_from_slice(&[0; 13]);
+                });
+                write_box(buf, b"minf", |buf| {
+                    write_box(buf, b"stbl", |buf| {
+                        buf.extend_from_slice(stsd);
+                        write_full_box(buf, b"stts", 0, 0, |buf| {
+                            put_u32(buf, 1);
+                            put_u32(buf, samples.len() as u32);
+                            put_u32(buf, delta);
+                        });
+                        if let Some(sync) = sync {
+                            write_full_box(buf, b"stss", 0, 0, |buf| {
+                                put_u32(buf, sync.len() as u32);
+                                sync.iter().for_each(|s| put_u32(buf, *s));
+                            });
+                        }
+                        write_full_box(buf, b"stsz", 0, 0, |buf| {
+                            put_u32(buf, 0);
+                            put_u32(buf, samples.len() as u32);
+                            samples.iter().for_each(|s| put_u32(buf, s.len() as u32));
+                        });
+                        write_full_box(buf, b"stsc", 0, 0, |buf| {
+                            put_u32(buf, 1);
+                            put_u32(buf, 1);
+                            put_u32(buf, samples.len() as u32);
+                            put_u32(buf, 1);
+                        });
+                        write_full_box(buf, b"stco", 0, 0, |buf| {
+                            put_u32(buf, 1);
+                            put_u32(buf, chunk_offset);
+                        });
+                    });
+                });
+            });
+        });
+        buf
+    }
+
+    /// A (non fragmented) MP4 with the moov at the end, like most encoders write it
+    fn test_mp4() -> Vec<u8> {
+        let video = video_samples();
+        let audio = audio_samples();
+
+        let mut file = Vec::new();
+        write_box(&mut file, b"ftyp", |buf| {
+            buf.extend_from_slice(b"isom");
+            put_u32(buf, 0);
+            buf.extend_from_slice(b"isom");
+        });
+
+        let video_offset = file.len() as u32 + 8;
+        let audio_offset = video_offset + video.iter().map(|s| s.len() as u32).sum::<u32>();
+        write_box(&mut file, b"mdat", |buf| {
+            video.iter().chain(audio.iter()).for_each(|s| buf.extend(s));
+        });
+
+        let video_stsd = visual_stsd(b"avc1", 64, 48, |buf| {
+            write_box(buf, b"avcC", |buf| {
+                buf.extend_from_slice(&[1, 0x42, 0, 0x1E])
+            })
+        });
+        let audio_stsd = fmp4::audio_stsd(b"mp4a", 2, 44100, |_| {});
+
+        write_box(&mut file, b"moov", |buf| {
+            buf.extend(trak(
+                b"vide",
+                90_000,
+                180_000,
+                &video,
+                Some(&[1, 4]),
+                video_offset,
+                &video_stsd,
+            ));
+            buf.extend(trak(
+                b"soun",
+                1000,
+                1000,
+                &audio,
+                None,
+                audio_offset,
+                &audio_stsd,
+            ));
+        });
+
+        file
+    }
+
+    fn ebml(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf: Vec<u8> = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        // 8 byte size vint
+        buf.push(0x01);
+        buf.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+        ebml(id, &value.to_be_bytes())
+    }
+
+    fn simple_block(track: u8, relative: i16, keyframe: bool, data: &[u8]) -> Vec<u8> {
+        let mut block = vec![0x80 | track];
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0 });
+        block.extend_from_slice(data);
+        ebml(0xA3, &block)
+    }
+
+    /// A WebM with unknown-size segment and clusters, like MediaRecorder writes
+    fn test_webm() -> Vec<u8> {
+        let video = video_samples();
+        let audio = audio_samples();
+
+        let mut file = ebml(0x1A45DFA3, &ebml(0x4282, b"webm"));
+        // Segment, unknown size
+        file.extend_from_slice(&[
+            0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ]);
+        file.extend(ebml(0x1549A966, &ebml_uint(0x2AD7B1, 1_000_000)));
+
+        let mut opus_head = b"OpusHead".to_vec();
+        opus_head.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xBB, 0, 0, 0, 0, 0]);
+
+        let video_track = [
+            ebml_uint(0xD7, 1),
+            ebml_uint(0x83, 1),
+            ebml(0x86, b"V_VP9"),
+            ebml(0xE0, &[ebml_uint(0xB0, 64), ebml_uint(0xBA, 48)].concat()),
+        ]
+        .concat();
+        let audio_track = [
+            ebml_uint(0xD7, 2),
+            ebml_uint(0x83, 2),
+            ebml(0x86, b"A_OPUS"),
+            ebml(0x63A2, &opus_head),
+        ]
+        .concat();
+        file.extend(ebml(
+            0x1654AE6B,
+            &[ebml(0xAE, &video_track), ebml(0xAE, &audio_track)].concat(),
+        ));
+
+        for cluster in 0..2 {
+            // Cluster, unknown size
+            file.extend_from_slice(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+            file.extend(ebml_uint(0xE7, cluster as u64 * 6000));
+            for i in 0..6 {
+                if i % 2 == 0 {
+                    let v = cluster * 3 + i / 2;
+                    file.extend(simple_block(1, i as i16 * 1000, i == 0, &video[v]));
+                }
+                file.extend(simple_block(
+                    2,
+                    i as i16 * 1000,
+                    true,
+                    &audio[cluster * 6 + i],
+                ));
+            }
+        }
+
+        file
+    }
+
+    fn mdat_payload(segment: &[u8]) -> &[u8] {
+        let moof_len = u32::from_be_bytes(segment[0..4].try_into().unwrap()) as usize;
+        assert_eq!(&segment[4..8], b"moof");
+        assert_eq!(&segment[moof_len + 4..moof_len + 8], b"mdat");
+        &segment[moof_len + 8..]
+    }
+
+    fn check_output(out_dir: &Path) {
+        let video = video_samples();
+        let audio = audio_samples();
+
+        let playlist = fs::read_to_string(out_dir.join(PLAYLIST_FILE)).unwrap();
+        assert_eq!(playlist, super::playlist(&[6.0, 6.0]));
+        assert!(playlist.contains("#EXTINF:6.000,\n0.m4s\n#EXTINF:6.000,\n1.m4s\n"));
+
+        let init = fs::read(out_dir.join(INIT_SEGMENT_FILE)).unwrap();
+        assert_eq!(&init[4..8], b"ftyp");
+
+        // Each segment has the video samples, then the audio samples of the same 6 seconds
+        for (i, name) in ["0.m4s", "1.m4s"].iter().enumerate() {
+            let segment = fs::read(out_dir.join(name)).unwrap();
+            let expected = [
+                video[i * 3..i * 3 + 3].concat(),
+                audio[i * 6..i * 6 + 6].concat(),
+            ]
+            .concat();
+            assert_eq!(mdat_payload(&segment), expected.as_slice());
+        }
+
+        assert!(!out_dir.join("2.m4s").exists());
+    }
+
+    #[test]
+    fn test_remux_mp4() {
+        let dir = temp_dir();
+        let src = dir.join("video.mp4");
+        fs::write(&src, test_mp4()).unwrap();
+
+        let out_dir = dir.join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        remux_to_hls(&src, &out_dir).unwrap();
+
+        check_output(&out_dir);
+        // The sample description is copied as-is
+        let init = fs::read(out_dir.join(INIT_SEGMENT_FILE)).unwrap();
+        assert!(init.windows(4).any(|w| w == b"avcC"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remux_webm() {
+        let dir = temp_dir();
+        let src = dir.join("video.webm");
+        fs::write(&src, test_webm()).unwrap();
+
+        let out_dir = dir.join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        remux_to_hls(&src, &out_dir).unwrap();
+
+        check_output(&out_dir);
+        let init = fs::read(out_dir.join(INIT_SEGMENT_FILE)).unwrap();
+        assert!(init.windows(4).any(|w| w == b"vpcC"));
+        assert!(init.windows(4).any(|w| w == b"dOps"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_hostile_mp4_sizes() {
+        let dir = temp_dir();
+        let src = dir.join("video.mp4");
+
+        // A sample claiming to be 4 GiB, in a file of a few hundred bytes
+        let mut huge_sample = test_mp4();
+        let stsz = huge_sample.windows(4).position(|w| w == b"stsz").unwrap();
+        huge_sample[stsz + 16..stsz + 20].copy_from_slice(&0xFFFF_FFF0u32.to_be_bytes());
+        fs::write(&src, huge_sample).unwrap();
+        assert!(matches!(
+            remux_to_hls(&src, &dir),
+            Err(ServerError::UnsupportedMedia { .. })
+        ));
+
+        // A 64 bit box size that overflows the position
+        let mut overflowing = Vec::new();
+        write_box(&mut overflowing, b"ftyp", |buf| {
+            buf.extend_from_slice(b"isom")
+        });
+        put_u32(&mut overflowing, 1);
+        overflowing.extend_from_slice(b"free");
+        overflowing.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        fs::write(&src, overflowing).unwrap();
+        assert!(matches!(
+            remux_to_hls(&src, &dir),
+            Err(ServerError::UnsupportedMedia { .. })
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_hostile_webm_timestamps() {
+        let dir = temp_dir();
+        let src = dir.join("video.webm");
+
+        // A cluster timestamp that overflows once scaled to nanoseconds
+        let mut huge_timestamp = test_webm();
+        let timestamp = ebml_uint(0xE7, 0);
+        let at = huge_timestamp
+            .windows(timestamp.len())
+            .position(|w| w == timestamp)
+            .unwrap();
+        let value = at + timestamp.len() - 8;
+        huge_timestamp[value..value + 8].copy_from_slice(&(i64::MAX as u64 / 2).to_be_bytes());
+        fs::write(&src, huge_timestamp).unwrap();
+        assert!(matches!(
+            remux_to_hls(&src, &dir),
+            Err(ServerError::UnsupportedMedia { .. })
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_files() {
+        let dir = temp_dir();
+        let src = dir.join("notes.txt");
+        fs::write(&src, b"definitely not a video").unwrap();
+
+        assert!(matches!(
+            remux_to_hls(&src, &dir),
+            Err(ServerError::UnsupportedMedia { .. })
+        ));
+        assert!(!dir.join(PLAYLIST_FILE).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,436 @@
+// MP4 (ISO BMFF) demuxer.
+// Reads the sample tables out of the moov box, then reads samples
+// straight out of the file in decode order.
+// The stsd box is copied as-is into the init segment,
+// so any codec the client understands works without parsing it here.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{Demuxer, Sample, Track, TrackKind};
+use crate::server::error::{ServerError, ServerResult};
+
+/// Refuse to load absurdly large moov boxes into memory.
+const MAX_MOOV_SIZE: u64 = 256 * 1024 * 1024;
+/// Roughly 46 hours of 60fps video, anything more is a broken (or hostile) file.
+const MAX_SAMPLES: usize = 10_000_000;
+/// No real frame comes close, and each sample is read into memory whole.
+const MAX_SAMPLE_SIZE: u32 = 64 * 1024 * 1024;
+
+struct SampleRef {
+    track: usize,
+    offset: u64,
+    size: u32,
+    dts: i64,
+    cts_offset: i32,
+    duration: u32,
+    is_sync: bool,
+}
+
+pub struct Mp4Demuxer {
+    reader: BufReader<File>,
+    // where the reader currently is, so sequential samples don't need a seek
+    position: u64,
+    tracks: Vec<Track>,
+    samples: Vec<SampleRef>,
+    next: usize,
+}
+
+impl Mp4Demuxer {
+    pub fn open(path: &Path) -> ServerResult<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let file_size = reader.get_ref().metadata()?.len();
+        let moov = find_moov(&mut reader, file_size)?;
+
+        let mut tracks = Vec::new();
+        let mut samples = Vec::new();
+        let mut has_video = false;
+        let mut has_audio = false;
+
+        for (_, trak) in boxes(&moov).filter(|(fourcc, _)| fourcc == b"trak") {
+            let Some((track, track_samples)) = parse_trak(trak, tracks.len(), file_size)? else {
+                continue;
+            };
+
+            // Only the first track of each kind is played
+            let seen = match track.kind {
+                TrackKind::Video => &mut has_video,
+                TrackKind::Audio => &mut has_audio,
+            };
+            if *seen {
+                continue;
+            }
+            *seen = true;
+
+            tracks.push(track);
+            samples.extend(track_samples);
+        }
+
+        if !tracks.is_empty() && samples.is_empty() {
+            return Err(ServerError::UnsupportedMedia {
+                details: "Fragmented MP4 files are not supported".to_string(),
+            });
+        }
+
+        // Interleave the tracks by decode time. The sort is stable,
+        // so samples of the same track stay in order.
+        let timescales: Vec<i128> = tracks.iter().map(|t| t.timescale as i128).collect();
+        samples.sort_by(|a, b| {
+            (a.dts as i128 * timescales[b.track]).cmp(&(b.dts as i128 * timescales[a.track]))
+        });
+
+        Ok(Self {
+            reader,
+            position: 0,
+            tracks,
+            samples,
+            next: 0,
+        })
+    }
+}
+
+impl Demuxer for Mp4Demuxer {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn next_sample(&mut self) -> ServerResult<Option<Sample>> {
+        let Some(sample) = self.samples.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+
+        if sample.offset != self.position {
+            self.reader.seek(SeekFrom::Start(sample.offset))?;
+        }
+
+        let mut data = vec![0; sample.size as usize];
+        self.reader.read_exact(&mut data)?;
+        self.position = sample.offset + sample.size as u64;
+
+        Ok(Some(Sample {
+            track: sample.track,
+            dts: sample.dts,
+            cts_offset: sample.cts_offset,
+            duration: sample.duration,
+            is_sync: sample.is_sync,
+            data,
+        }))
+    }
+}
+
+fn unsupported(details: &str) -> ServerError {
+    ServerError::UnsupportedMedia {
+        details: details.to_string(),
+    }
+}
+
+/// Walks the top level boxes of the file and reads the moov box into memory.
+fn find_moov(reader: &mut BufReader<File>, file_size: u64) -> ServerResult<Vec<u8>> {
+    let mut position: u64 = 0;
+
+    while position + 8 <= file_size {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = file_size - position;
+        }
+
+        if size < header_len {
+            return Err(unsupported("Malformed MP4 box"));
+        }
+        let end = position
+            .checked_add(size)
+            .filter(|end| *end <= file_size)
+            .ok_or_else(|| unsupported("MP4 box runs past the end of the file"))?;
+
+        if &header[4..8] == b"moov" {
+            let len = size - header_len;
+            if len > MAX_MOOV_SIZE {
+                return Err(unsupported("MP4 metadata is too large"));
+            }
+            let mut moov = vec![0; len as usize];
+            reader.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+
+        position = end;
+    }
+
+    Err(unsupported("MP4 file has no moov box"))
+}
+
+/// Iterates over the boxes in `data`, yielding (type, payload).
+/// Stops at the first malformed box.
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut position = 0usize;
+    std::iter::from_fn(move || {
+        let header = data.get(position..position + 8)?;
+        let fourcc: [u8; 4] = header[4..8].try_into().unwrap();
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            size = u64::from_be_bytes(data.get(position + 8..position + 16)?.try_into().unwrap());
+            header_len = 16;
+        } else if size == 0 {
+            size = (data.len() - position) as u64;
+        }
+        if size < header_len as u64
+            || (position as u64)
+                .checked_add(size)
+                .is_none_or(|end| end > data.len() as u64)
+        {
+            return None;
+        }
+
+        let start = position;
+        position += size as usize;
+        Some((fourcc, &data[start + header_len..position]))
+    })
+}
+
+fn child<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(f, _)| f == fourcc)
+        .map(|(_, payload)| payload)
+}
+
+/// Like child, but includes the box header. Needed for boxes that get copied verbatim.
+fn child_with_header(data: &[u8], fourcc: &[u8; 4]) -> Option<Vec<u8>> {
+    child(data, fourcc).map(|payload| {
+        let mut full = Vec::with_capacity(payload.len() + 8);
+        full.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+        full.extend_from_slice(fourcc);
+        full.extend_from_slice(payload);
+        full
+    })
+}
+
+fn u32_at(data: &[u8], offset: usize) -> ServerResult<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| unsupported("Truncated MP4 box"))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> ServerResult<u64> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| unsupported("Truncated MP4 box"))
+}
+
+/// The entries of a full box table like stts, where the entry count
+/// comes right after the version and flags.
+fn table(data: &[u8], entry_size: usize) -> ServerResult<(u32, &[u8])> {
+    let count = u32_at(data, 4)?;
+    let entries = data
+        .get(8..8 + count as usize * entry_size)
+        .ok_or_else(|| unsupported("Truncated MP4 sample table"))?;
+    Ok((count, entries))
+}
+
+/// Returns None for tracks that aren't audio or video (subtitles, timecodes...).
+fn parse_trak(
+    trak: &[u8],
+    index: usize,
+    file_size: u64,
+) -> ServerResult<Option<(Track, Vec<SampleRef>)>> {
+    let missing = || unsupported("MP4 track is missing required boxes");
+
+    let tkhd = child(trak, b"tkhd").ok_or_else(missing)?;
+    let mdia = child(trak, b"mdia").ok_or_else(missing)?;
+    let mdhd = child(mdia, b"mdhd").ok_or_else(missing)?;
+    let hdlr = child(mdia, b"hdlr").ok_or_else(missing)?;
+    let stbl = child(mdia, b"minf")
+        .and_then(|minf| child(minf, b"stbl"))
+        .ok_or_else(missing)?;
+
+    let kind = match hdlr.get(8..12) {
+        Some(b"vide") => TrackKind::Video,
+        Some(b"soun") => TrackKind::Audio,
+        _ => return Ok(None),
+    };
+
+    let timescale = match mdhd.first() {
+        Some(1) => u32_at(mdhd, 20)?,
+        _ => u32_at(mdhd, 12)?,
+    };
+    if timescale == 0 {
+        return Err(unsupported("MP4 track has a timescale of zero"));
+    }
+
+    // The last 8 bytes of tkhd are the width and height, in 16.16 fixed point
+    let (width, height) = match kind {
+        TrackKind::Video if tkhd.len() >= 8 => (
+            u32_at(tkhd, tkhd.len() - 8)? >> 16,
+            u32_at(tkhd, tkhd.len() - 4)? >> 16,
+        ),
+        _ => (0, 0),
+    };
+
+    let track = Track {
+        kind,
+        timescale,
+        width,
+        height,
+        stsd: child_with_header(stbl, b"stsd").ok_or_else(missing)?,
+    };
+
+    let samples = parse_sample_table(stbl, index, file_size)?;
+
+    Ok(Some((track, samples)))
+}
+
+/// Every sample has to be inside the file, so reading one can't allocate more than the file holds.
+fn parse_sample_table(stbl: &[u8], track: usize, file_size: u64) -> ServerResult<Vec<SampleRef>> {
+    let missing = || unsupported("MP4 sample table is missing required boxes");
+
+    // sizes
+    let stsz = child(stbl, b"stsz").ok_or_else(|| {
+        if child(stbl, b"stz2").is_some() {
+            unsupported("Compact sample sizes (stz2) are not supported")
+        } else {
+            missing()
+        }
+    })?;
+    let fixed_size = u32_at(stsz, 4)?;
+    let sample_count = u32_at(stsz, 8)? as usize;
+    if sample_count > MAX_SAMPLES {
+        return Err(unsupported("MP4 track has too many samples"));
+    }
+    let sizes: Vec<u32> = if fixed_size != 0 {
+        vec![fixed_size; sample_count]
+    } else {
+        (0..sample_count)
+            .map(|i| u32_at(stsz, 12 + i * 4))
+            .collect::<ServerResult<_>>()?
+    };
+
+    // decode times
+    let (stts_count, stts) = table(child(stbl, b"stts").ok_or_else(missing)?, 8)?;
+    let mut durations = Vec::with_capacity(sample_count);
+    for i in 0..stts_count as usize {
+        let count = u32_at(stts, i * 8)?;
+        let delta = u32_at(stts, i * 8 + 4)?;
+        for _ in 0..count {
+            if durations.len() == sample_count {
+                break;
+            }
+            durations.push(delta);
+        }
+    }
+    // Some files leave the last sample out of stts
+    let last = durations.last().copied().unwrap_or(0);
+    durations.resize(sample_count, last);
+
+    // composition offsets, version 0 is unsigned but anything that large is broken anyway
+    let mut cts_offsets = vec![0i32; sample_count];
+    if let Some(ctts) = child(stbl, b"ctts") {
+        let (ctts_count, entries) = table(ctts, 8)?;
+        let mut i = 0;
+        for entry in 0..ctts_count as usize {
+            let count = u32_at(entries, entry * 8)?;
+            let offset = u32_at(entries, entry * 8 + 4)? as i32;
+            for _ in 0..count {
+                if i == sample_count {
+                    break;
+                }
+                cts_offsets[i] = offset;
+                i += 1;
+            }
+        }
+    }
+
+    // sync samples, every sample is a sync sample if there's no stss
+    let sync: Option<HashSet<u32>> = match child(stbl, b"stss") {
+        Some(stss) => {
+            let (count, entries) = table(stss, 4)?;
+            Some(
+                (0..count as usize)
+                    .map(|i| u32_at(entries, i * 4))
+                    .collect::<ServerResult<_>>()?,
+            )
+        }
+        None => None,
+    };
+
+    // chunk offsets
+    let chunk_offsets: Vec<u64> = if let Some(stco) = child(stbl, b"stco") {
+        let (count, entries) = table(stco, 4)?;
+        (0..count as usize)
+            .map(|i| u32_at(entries, i * 4).map(u64::from))
+            .collect::<ServerResult<_>>()?
+    } else if let Some(co64) = child(stbl, b"co64") {
+        let (count, entries) = table(co64, 8)?;
+        (0..count as usize)
+            .map(|i| u64_at(entries, i * 8))
+            .collect::<ServerResult<_>>()?
+    } else {
+        return Err(missing());
+    };
+
+    // which samples are in which chunk
+    let (stsc_count, stsc) = table(child(stbl, b"stsc").ok_or_else(missing)?, 12)?;
+    let mut samples = Vec::with_capacity(sample_count);
+    let mut dts: i64 = 0;
+
+    for entry in 0..stsc_count as usize {
+        let first_chunk = u32_at(stsc, entry * 12)? as usize;
+        let samples_per_chunk = u32_at(stsc, entry * 12 + 4)? as usize;
+        let last_chunk = if entry + 1 < stsc_count as usize {
+            u32_at(stsc, (entry + 1) * 12)? as usize
+        } else {
+            chunk_offsets.len() + 1
+        };
+
+        // chunks are 1-based
+        for chunk in first_chunk.max(1)..last_chunk {
+            let Some(&chunk_offset) = chunk_offsets.get(chunk - 1) else {
+                break;
+            };
+            let mut offset = chunk_offset;
+
+            for _ in 0..samples_per_chunk {
+                let i = samples.len();
+                if i == sample_count {
+                    break;
+                }
+                if sizes[i] > MAX_SAMPLE_SIZE {
+                    return Err(unsupported("MP4 sample is too large"));
+                }
+                let end = offset
+                    .checked_add(sizes[i] as u64)
+                    .filter(|end| *end <= file_size)
+                    .ok_or_else(|| unsupported("MP4 sample is past the end of the file"))?;
+
+                samples.push(SampleRef {
+                    track,
+                    offset,
+                    size: sizes[i],
+                    dts,
+                    cts_offset: cts_offsets[i],
+                    duration: durations[i],
+                    // sample numbers are 1-based too
+                    is_sync: sync.as_ref().is_none_or(|s| s.contains(&(i as u32 + 1))),
+                });
+
+                offset = end;
+                dts += durations[i] as i64;
+            }
+        }
+    }
+
+    Ok(samples)
+}
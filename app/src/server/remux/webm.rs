@@ -0,0 +1,692 @@
+// WebM (Matroska) demuxer.
+// Clusters are read front to back without relying on Cues, so files
+// written live (ex. by MediaRecorder, with unknown-size clusters) work too.
+// Supports the WebM codecs that have an ISO BMFF binding: VP8, VP9, AV1 and Opus.
+// Vorbis has none, so Vorbis audio is dropped.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use tracing::warn;
+
+use super::{
+    fmp4::{self, put_u16, put_u32},
+    rescale, Demuxer, Sample, Track, TrackKind,
+};
+use crate::server::error::{ServerError, ServerResult};
+
+pub const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+
+// Element ids, with their length markers kept
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const DEFAULT_DURATION: u32 = 0x23E383;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const REFERENCE_BLOCK: u32 = 0xFB;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+const VIDEO_TIMESCALE: u32 = 90_000;
+// Opus always runs at 48kHz, whatever the input was
+const OPUS_TIMESCALE: u32 = 48_000;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Elements we read into memory, anything larger than this is garbage.
+const MAX_ELEMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+fn unsupported(details: impl Into<String>) -> ServerError {
+    ServerError::UnsupportedMedia {
+        details: details.into(),
+    }
+}
+
+struct WebmTrack {
+    number: u64,
+    // in nanoseconds
+    default_duration: Option<u64>,
+    // The last sample, held back until the next one tells us its duration
+    pending: Option<Sample>,
+    last_duration: u32,
+}
+
+pub struct WebmDemuxer {
+    reader: EbmlReader,
+    tracks: Vec<Track>,
+    webm_tracks: Vec<WebmTrack>,
+    timestamp_scale: u64,
+    cluster_timestamp: i64,
+    ready: VecDeque<Sample>,
+    finished: bool,
+}
+
+impl WebmDemuxer {
+    pub fn open(path: &Path) -> ServerResult<Self> {
+        let mut reader = EbmlReader::open(path)?;
+        let mut timestamp_scale = 1_000_000;
+        let mut parsed_tracks = None;
+
+        // Read up to the first cluster
+        while let Some((id, size)) = reader.header()? {
+            match id {
+                SEGMENT => {}
+                CLUSTER => break,
+                INFO => {
+                    let info = reader.read_payload(size)?;
+                    for (id, value) in elements(&info) {
+                        if id == TIMESTAMP_SCALE {
+                            timestamp_scale = uint(value).max(1);
+                        }
+                    }
+                }
+                TRACKS => {
+                    let tracks = reader.read_payload(size)?;
+                    parsed_tracks = Some(parse_tracks(&tracks)?);
+                }
+                _ => reader.skip(size)?,
+            }
+        }
+
+        let (tracks, webm_tracks) =
+            parsed_tracks.ok_or_else(|| unsupported("WebM file has no tracks"))?;
+
+        Ok(Self {
+            reader,
+            tracks,
+            webm_tracks,
+            timestamp_scale,
+            cluster_timestamp: 0,
+            ready: VecDeque::new(),
+            finished: false,
+        })
+    }
+
+    fn handle_block(
+        &mut self,
+        block: &[u8],
+        keyframe: Option<bool>,
+        block_duration: Option<u64>,
+    ) -> ServerResult<()> {
+        let truncated = || unsupported("Truncated WebM block");
+
+        let (track_number, len) = vint(block, false).ok_or_else(truncated)?;
+        let Some(index) = self
+            .webm_tracks
+            .iter()
+            .position(|t| t.number == track_number)
+        else {
+            // Not a track we play
+            return Ok(());
+        };
+
+        let header = block.get(len..len + 3).ok_or_else(truncated)?;
+        let relative = i16::from_be_bytes([header[0], header[1]]) as i64;
+        let flags = header[2];
+        let frames = split_laced(&block[len + 3..], (flags >> 1) & 0b11)?;
+
+        let track = &self.tracks[index];
+        let is_video = track.kind == TrackKind::Video;
+        let timescale = track.timescale as u64;
+        // SimpleBlocks have a keyframe flag, BlockGroups have a ReferenceBlock for non-keyframes
+        let is_sync = !is_video || keyframe.unwrap_or(flags & 0x80 != 0);
+
+        // Everything here comes straight from the file
+        let out_of_range = || unsupported("WebM timestamp out of range");
+        let scale = self.timestamp_scale;
+        let block_ns = self
+            .cluster_timestamp
+            .checked_add(relative)
+            .and_then(|t| t.checked_mul(i64::try_from(scale).ok()?))
+            .ok_or_else(out_of_range)?;
+
+        // Laced frames only carry one timestamp, the rest are spaced by the default duration
+        let frame_duration = match (self.webm_tracks[index].default_duration, block_duration) {
+            (Some(d), _) => Some(d),
+            (None, Some(d)) => {
+                Some(d.checked_mul(scale).ok_or_else(out_of_range)? / frames.len().max(1) as u64)
+            }
+            (None, None) => None,
+        };
+        let frame_duration = frame_duration
+            .map(i64::try_from)
+            .transpose()
+            .map_err(|_| out_of_range())?;
+
+        for (i, frame) in frames.into_iter().enumerate() {
+            let ns = (i as i64)
+                .checked_mul(frame_duration.unwrap_or(0))
+                .and_then(|offset| block_ns.checked_add(offset))
+                .ok_or_else(out_of_range)?;
+            let sample = Sample {
+                track: index,
+                dts: rescale(ns, NANOS_PER_SECOND, timescale),
+                cts_offset: 0,
+                // only used if this turns out to be the track's last sample
+                duration: frame_duration
+                    .map_or(0, |d| rescale(d, NANOS_PER_SECOND, timescale) as u32),
+                is_sync,
+                data: frame.to_vec(),
+            };
+            self.push_sample(sample);
+        }
+
+        Ok(())
+    }
+
+    fn push_sample(&mut self, sample: Sample) {
+        let track = &mut self.webm_tracks[sample.track];
+
+        if let Some(mut previous) = track.pending.take() {
+            previous.duration = (sample.dts - previous.dts).clamp(0, u32::MAX as i64) as u32;
+            track.last_duration = previous.duration;
+            self.ready.push_back(previous);
+        }
+
+        track.pending = Some(sample);
+    }
+
+    /// Flushes the samples held back at the end of the file.
+    fn finish(&mut self) {
+        self.finished = true;
+
+        let mut last: Vec<Sample> = self
+            .webm_tracks
+            .iter_mut()
+            .filter_map(|track| {
+                let mut sample = track.pending.take()?;
+                if sample.duration == 0 {
+                    sample.duration = track.last_duration;
+                }
+                Some(sample)
+            })
+            .collect();
+
+        let timescales: Vec<i128> = self.tracks.iter().map(|t| t.timescale as i128).collect();
+        last.sort_by(|a, b| {
+            (a.dts as i128 * timescales[b.track]).cmp(&(b.dts as i128 * timescales[a.track]))
+        });
+
+        self.ready.extend(last);
+    }
+}
+
+impl Demuxer for WebmDemuxer {
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn next_sample(&mut self) -> ServerResult<Option<Sample>> {
+        loop {
+            if let Some(sample) = self.ready.pop_front() {
+                return Ok(Some(sample));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+
+            let Some((id, size)) = self.reader.header()? else {
+                self.finish();
+                continue;
+            };
+
+            match id {
+                // Masters that may have an unknown size, their children are read as they come
+                SEGMENT | CLUSTER => {}
+                TIMESTAMP => {
+                    self.cluster_timestamp = i64::try_from(uint(&self.reader.read_payload(size)?))
+                        .map_err(|_| unsupported("WebM timestamp out of range"))?;
+                }
+                SIMPLE_BLOCK => {
+                    let block = self.reader.read_payload(size)?;
+                    self.handle_block(&block, None, None)?;
+                }
+                BLOCK_GROUP => {
+                    let group = self.reader.read_payload(size)?;
+                    let mut block = None;
+                    let mut duration = None;
+                    let mut keyframe = true;
+
+                    for (id, data) in elements(&group) {
+                        match id {
+                            BLOCK => block = Some(data),
+                            BLOCK_DURATION => duration = Some(uint(data)),
+                            REFERENCE_BLOCK => keyframe = false,
+                            _ => {}
+                        }
+                    }
+
+                    if let Some(block) = block {
+                        self.handle_block(block, Some(keyframe), duration)?;
+                    }
+                }
+                _ => self.reader.skip(size)?,
+            }
+        }
+    }
+}
+
+fn parse_tracks(data: &[u8]) -> ServerResult<(Vec<Track>, Vec<WebmTrack>)> {
+    let mut tracks = Vec::new();
+    let mut webm_tracks = Vec::new();
+
+    for (_, entry) in elements(data).filter(|(id, _)| *id == TRACK_ENTRY) {
+        let mut number = 0;
+        let mut track_type = 0;
+        let mut codec_id = String::new();
+        let mut codec_private: &[u8] = &[];
+        let mut default_duration = None;
+        let mut width = 0;
+        let mut height = 0;
+
+        for (id, value) in elements(entry) {
+            match id {
+                TRACK_NUMBER => number = uint(value),
+                TRACK_TYPE => track_type = uint(value),
+                CODEC_ID => {
+                    codec_id = String::from_utf8_lossy(value)
+                        .trim_end_matches('\0')
+                        .to_string()
+                }
+                CODEC_PRIVATE => codec_private = value,
+                DEFAULT_DURATION => default_duration = Some(uint(value)),
+                VIDEO => {
+                    for (id, value) in elements(value) {
+                        match id {
+                            PIXEL_WIDTH => width = uint(value) as u16,
+                            PIXEL_HEIGHT => height = uint(value) as u16,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let kind = match track_type {
+            TRACK_TYPE_VIDEO => TrackKind::Video,
+            TRACK_TYPE_AUDIO => TrackKind::Audio,
+            _ => continue,
+        };
+
+        // Only the first track of each kind is played
+        if tracks.iter().any(|t: &Track| t.kind == kind) {
+            continue;
+        }
+
+        let track = match codec_id.as_str() {
+            "V_VP9" => {
+                let features = vp9_features(codec_private);
+                Track {
+                    kind,
+                    timescale: VIDEO_TIMESCALE,
+                    width: width as u32,
+                    height: height as u32,
+                    stsd: fmp4::visual_stsd(b"vp09", width, height, |buf| {
+                        write_vpcc(buf, features)
+                    }),
+                }
+            }
+            "V_VP8" => Track {
+                kind,
+                timescale: VIDEO_TIMESCALE,
+                width: width as u32,
+                height: height as u32,
+                stsd: fmp4::visual_stsd(b"vp08", width, height, |buf| {
+                    write_vpcc(buf, Vp9Features::default())
+                }),
+            },
+            // The CodecPrivate of AV1 is the av1C box payload
+            "V_AV1" if !codec_private.is_empty() => Track {
+                kind,
+                timescale: VIDEO_TIMESCALE,
+                width: width as u32,
+                height: height as u32,
+                stsd: fmp4::visual_stsd(b"av01", width, height, |buf| {
+                    fmp4::write_box(buf, b"av1C", |buf| buf.extend_from_slice(codec_private))
+                }),
+            },
+            "A_OPUS" => match OpusHead::parse(codec_private) {
+                Some(head) => Track {
+                    kind,
+                    timescale: OPUS_TIMESCALE,
+                    width: 0,
+                    height: 0,
+                    stsd: fmp4::audio_stsd(b"Opus", head.channels as u16, OPUS_TIMESCALE, |buf| {
+                        head.write_dops(buf)
+                    }),
+                },
+                None => {
+                    warn!("Skipping Opus track {number} with an invalid OpusHead");
+                    continue;
+                }
+            },
+            other => {
+                warn!("Skipping WebM track {number} with unsupported codec {other}");
+                continue;
+            }
+        };
+
+        tracks.push(track);
+        webm_tracks.push(WebmTrack {
+            number,
+            default_duration,
+            pending: None,
+            last_duration: 0,
+        });
+    }
+
+    Ok((tracks, webm_tracks))
+}
+
+#[derive(Clone, Copy)]
+struct Vp9Features {
+    profile: u8,
+    level: u8,
+    bit_depth: u8,
+    chroma_subsampling: u8,
+}
+
+impl Default for Vp9Features {
+    fn default() -> Self {
+        Self {
+            profile: 0,
+            level: 31,
+            bit_depth: 8,
+            // 4:2:0, colocated with luma
+            chroma_subsampling: 1,
+        }
+    }
+}
+
+/// The VP9 CodecPrivate is a list of (id, length, value) features, all optional.
+fn vp9_features(data: &[u8]) -> Vp9Features {
+    let mut features = Vp9Features::default();
+    let mut i = 0;
+    while i + 1 < data.len() {
+        let id = data[i];
+        let len = data[i + 1] as usize;
+        let Some(value) = data.get(i + 2..i + 2 + len) else {
+            break;
+        };
+        if let Some(&value) = value.first() {
+            match id {
+                1 => features.profile = value,
+                2 => features.level = value,
+                3 => features.bit_depth = value,
+                4 => features.chroma_subsampling = value,
+                _ => {}
+            }
+        }
+        i += 2 + len;
+    }
+    features
+}
+
+fn write_vpcc(buf: &mut Vec<u8>, features: Vp9Features) {
+    fmp4::write_full_box(buf, b"vpcC", 1, 0, |buf| {
+        buf.push(features.profile);
+        buf.push(features.level);
+        buf.push(features.bit_depth << 4 | (features.chroma_subsampling & 0b111) << 1);
+        // colour primaries, transfer characteristics and matrix coefficients: unspecified
+        buf.extend_from_slice(&[2, 2, 2]);
+        // no codec initialization data
+        put_u16(buf, 0);
+    });
+}
+
+struct OpusHead<'a> {
+    channels: u8,
+    pre_skip: u16,
+    input_sample_rate: u32,
+    output_gain: i16,
+    mapping_family: u8,
+    // stream count, coupled count and the channel mapping
+    mapping_table: &'a [u8],
+}
+
+impl<'a> OpusHead<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 19 || &data[0..8] != b"OpusHead" {
+            return None;
+        }
+        // little endian, unlike everything else in here
+        Some(Self {
+            channels: data[9],
+            pre_skip: u16::from_le_bytes([data[10], data[11]]),
+            input_sample_rate: u32::from_le_bytes(data[12..16].try_into().unwrap()),
+            output_gain: i16::from_le_bytes([data[16], data[17]]),
+            mapping_family: data[18],
+            mapping_table: &data[19..],
+        })
+    }
+
+    fn write_dops(&self, buf: &mut Vec<u8>) {
+        fmp4::write_box(buf, b"dOps", |buf| {
+            buf.push(0);
+            buf.push(self.channels);
+            put_u16(buf, self.pre_skip);
+            put_u32(buf, self.input_sample_rate);
+            buf.extend_from_slice(&self.output_gain.to_be_bytes());
+            buf.push(self.mapping_family);
+            if self.mapping_family != 0 {
+                buf.extend_from_slice(self.mapping_table);
+            }
+        });
+    }
+}
+
+/// Splits the frames of a block according to its lacing mode.
+fn split_laced(data: &[u8], lacing: u8) -> ServerResult<Vec<&[u8]>> {
+    if lacing == 0 {
+        return Ok(vec![data]);
+    }
+
+    let truncated = || unsupported("Malformed WebM lacing");
+
+    let count = *data.first().ok_or_else(truncated)? as usize + 1;
+    let mut position = 1;
+    let mut sizes = Vec::with_capacity(count);
+
+    match lacing {
+        // Xiph: sizes are sums of bytes, ending at the first byte that isn't 255
+        0b01 => {
+            for _ in 0..count - 1 {
+                let mut size = 0;
+                loop {
+                    let byte = *data.get(position).ok_or_else(truncated)?;
+                    position += 1;
+                    size += byte as usize;
+                    if byte != 255 {
+                        break;
+                    }
+                }
+                sizes.push(size);
+            }
+        }
+        // EBML: the first size is a vint, the rest are signed differences from the previous size
+        0b11 => {
+            let (first, len) = vint(&data[position..], false).ok_or_else(truncated)?;
+            position += len;
+            let mut size = first as i64;
+            sizes.push(size as usize);
+            for _ in 1..count - 1 {
+                let (raw, len) = vint(&data[position..], false).ok_or_else(truncated)?;
+                position += len;
+                let bias = (1i64 << (7 * len - 1)) - 1;
+                size += raw as i64 - bias;
+                if size < 0 {
+                    return Err(truncated());
+                }
+                sizes.push(size as usize);
+            }
+        }
+        // Fixed: every frame is the same size
+        _ => {
+            let size = (data.len() - position) / count;
+            sizes.resize(count - 1, size);
+        }
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    for size in sizes {
+        frames.push(data.get(position..position + size).ok_or_else(truncated)?);
+        position += size;
+    }
+    // the last frame is whatever is left
+    frames.push(&data[position..]);
+
+    Ok(frames)
+}
+
+/// Reads a variable length integer, returning (value, length in bytes).
+/// Element ids keep their length marker, sizes don't.
+fn vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    if first == 0 {
+        return None;
+    }
+    let len = first.leading_zeros() as usize + 1;
+    let bytes = data.get(..len)?;
+
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first & 0xFFu8.checked_shr(len as u32).unwrap_or(0)) as u64
+    };
+    for byte in &bytes[1..] {
+        value = value << 8 | *byte as u64;
+    }
+    Some((value, len))
+}
+
+/// Iterates over the child elements in `data`, yielding (id, payload).
+/// Stops at the first malformed element.
+fn elements(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        let (id, id_len) = vint(&data[position..], true)?;
+        let (size, size_len) = vint(&data[position + id_len..], false)?;
+        let start = position + id_len + size_len;
+        let end = if is_unknown_size(size, size_len) {
+            data.len()
+        } else {
+            start.checked_add(size as usize)?
+        };
+        let payload = data.get(start..end)?;
+        position = end;
+        Some((id as u32, payload))
+    })
+}
+
+fn is_unknown_size(size: u64, len: usize) -> bool {
+    size == (1u64 << (7 * len)) - 1
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().take(8).fold(0, |acc, b| acc << 8 | *b as u64)
+}
+
+/// Reads elements from the file one at a time.
+struct EbmlReader {
+    inner: BufReader<File>,
+    position: u64,
+    len: u64,
+}
+
+impl EbmlReader {
+    fn open(path: &Path) -> ServerResult<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = Self {
+            inner: BufReader::new(file),
+            position: 0,
+            len,
+        };
+
+        match reader.header()? {
+            Some((EBML, size)) => reader.skip(size)?,
+            _ => return Err(unsupported("Not an EBML file")),
+        }
+
+        Ok(reader)
+    }
+
+    fn read_vint(&mut self, keep_marker: bool) -> io::Result<Option<(u64, usize)>> {
+        let mut bytes = [0u8; 8];
+        if self.inner.read(&mut bytes[..1])? == 0 {
+            return Ok(None);
+        }
+        let len = bytes[0].leading_zeros() as usize + 1;
+        if len > 8 {
+            return Ok(None);
+        }
+        self.inner.read_exact(&mut bytes[1..len])?;
+        self.position += len as u64;
+        Ok(vint(&bytes[..len], keep_marker))
+    }
+
+    /// Reads the next element header, returning (id, size) with None as the size
+    /// if it's unknown. Returns None at the end of the file.
+    fn header(&mut self) -> ServerResult<Option<(u32, Option<u64>)>> {
+        if self.position >= self.len {
+            return Ok(None);
+        }
+
+        let read = (|| -> io::Result<Option<(u32, Option<u64>)>> {
+            let Some((id, _)) = self.read_vint(true)? else {
+                return Ok(None);
+            };
+            let Some((size, len)) = self.read_vint(false)? else {
+                return Ok(None);
+            };
+            let size = (!is_unknown_size(size, len)).then_some(size);
+            Ok(Some((id as u32, size)))
+        })();
+
+        match read {
+            Ok(header) => Ok(header),
+            // A cut off header at the end of the file is treated as the end
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn skip(&mut self, size: Option<u64>) -> ServerResult<()> {
+        let size = size.ok_or_else(|| unsupported("Unknown-size WebM element can't be skipped"))?;
+        self.inner.seek_relative(size as i64)?;
+        self.position += size;
+        Ok(())
+    }
+
+    fn read_payload(&mut self, size: Option<u64>) -> ServerResult<Vec<u8>> {
+        let size = size.ok_or_else(|| unsupported("Unexpected unknown-size WebM element"))?;
+        if size > MAX_ELEMENT_SIZE {
+            return Err(unsupported("WebM element is too large"));
+        }
+        let mut payload = vec![0; size as usize];
+        self.inner.read_exact(&mut payload)?;
+        self.position += size;
+        Ok(payload)
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+use tracing::trace;
+
+use super::{BlobStore, BlobStream};
+use crate::server::error::ServerResult;
+
+/// Keeps blobs as plain files under a directory.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalStore {
+    async fn put(&self, key: &str, temp_path: &Path) -> ServerResult<()> {
+        let path = self.path(key);
+
+        let result = async {
+            fs::create_dir_all(path.parent().unwrap()).await?;
+            fs::rename(temp_path, &path).await
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = fs::remove_file(temp_path).await;
+        }
+        result?;
+
+        trace!("Stored blob at {}", path.to_string_lossy());
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> ServerResult<BlobStream> {
+        let file = File::open(self.path(key)).await?;
+        Ok(Box::pin(ReaderStream::new(file)))
+    }
+
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> ServerResult<BlobStream> {
+        let mut file = File::open(self.path(key)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok(Box::pin(ReaderStream::new(file.take(len))))
+    }
+
+    async fn delete(&self, key: &str) -> ServerResult<()> {
+        fs::remove_file(self.path(key)).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> ServerResult<Vec<String>> {
+        // Blobs are always three levels deep, anything else in the root
+        // (like temp files) isn't one
+        let mut keys = Vec::new();
+        let mut prefixes = vec![String::new()];
+        for depth in 0..3 {
+            let mut next = Vec::new();
+            for prefix in prefixes {
+                let mut entries = match fs::read_dir(self.root.join(&prefix)).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                while let Some(entry) = entries.next_entry().await? {
+                    let Ok(name) = entry.file_name().into_string() else {
+                        continue;
+                    };
+                    let is_dir = entry.file_type().await?.is_dir();
+                    let key = format!("{prefix}{name}");
+                    match depth {
+                        0 | 1 if is_dir && name.len() == 2 => next.push(format!("{key}/")),
+                        2 if !is_dir => keys.push(key),
+                        _ => {}
+                    }
+                }
+            }
+            prefixes = next;
+        }
+
+        Ok(keys)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
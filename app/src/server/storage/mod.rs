@@ -0,0 +1,69 @@
+//! Where the bytes of uploaded files live. Uploads are first written to a temp file
+//! in `directories.files_dir`, then handed to the configured [`BlobStore`].
+mod local;
+mod s3;
+
+pub use local::LocalStore;
+pub use s3::S3Store;
+
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+
+use crate::{
+    config::{settings::StorageBackend, SETTINGS},
+    server::error::{ServerError, ServerResult},
+};
+
+pub type BlobStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+pub type Blobs = Arc<dyn BlobStore>;
+
+/// Stores blobs by key. Keys are relative paths like `AB/CD/EF01...`.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Moves the file at `temp_path` into the store under `key`.
+    /// The temp file is gone afterwards, whether or not it succeeded.
+    async fn put(&self, key: &str, temp_path: &Path) -> ServerResult<()>;
+
+    /// Reads the whole blob.
+    async fn get(&self, key: &str) -> ServerResult<BlobStream>;
+
+    /// Reads `len` bytes of the blob starting at byte `start`.
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> ServerResult<BlobStream>;
+
+    async fn delete(&self, key: &str) -> ServerResult<()>;
+
+    /// Every key in the store.
+    async fn list(&self) -> ServerResult<Vec<String>>;
+
+    /// A path the blob can be read from directly, if it's on the local filesystem.
+    fn local_path(&self, key: &str) -> Option<PathBuf>;
+}
+
+/// The store picked in the `storage` section of the config.
+pub fn from_settings() -> ServerResult<Blobs> {
+    match SETTINGS.storage.backend {
+        StorageBackend::Local => Ok(Arc::new(LocalStore::new(
+            SETTINGS.directories.files_dir.clone(),
+        ))),
+        StorageBackend::S3 => {
+            let settings =
+                SETTINGS
+                    .storage
+                    .s3
+                    .as_ref()
+                    .ok_or_else(|| ServerError::StorageError {
+                        message: "The s3 backend needs a storage.s3 section in the config"
+                            .to_string(),
+                    })?;
+            Ok(Arc::new(S3Store::new(settings)?))
+        }
+    }
+}
@@ -1,4 +1,7 @@
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+
 use crate::server::error::{ServerError, ServerResult};
+use crate::server::models::files::VirtualPath;
 
 pub fn validate_filename(filename: &str) -> ServerResult<()> {
     if filename.is_empty() {
@@ -57,6 +60,38 @@ pub fn validate_path(path: &str) -> ServerResult<()> {
     Ok(())
 }
 
+/// Where a relative path from a client, like an upload's field name or an archive
+/// entry, ends up under `dir`: its parent directory and its own name.
+/// Anything that would escape `dir` is rejected. None if it's `dir` itself.
+pub fn resolve_relative_path(
+    dir: &VirtualPath,
+    name: &str,
+) -> ServerResult<Option<(VirtualPath, String)>> {
+    validate_path(name)?;
+    if name.starts_with('/') || name.starts_with('\\') {
+        return Err(ServerError::ValidationError {
+            message: format!("'{name}' is an absolute path"),
+        });
+    }
+
+    let parts: Vec<&str> = name
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    let Some((last, parents)) = parts.split_last() else {
+        return Ok(None);
+    };
+
+    let mut parent = dir.clone();
+    for part in parents {
+        validate_filename(part)?;
+        parent = parent.join(part)?;
+    }
+    validate_filename(last)?;
+
+    Ok(Some((parent, last.to_string())))
+}
+
 pub fn sanitize_path_component(component: &str) -> String {
     component
         .chars()
@@ -64,6 +99,50 @@ pub fn sanitize_path_component(component: &str) -> String {
         .collect()
 }
 
+/// When a file should expire, from either an RFC 3339 timestamp or a TTL
+/// like `30m`, `12h` or `7d` (a plain number is seconds). Has to be in the future.
+pub fn parse_expiry(value: &str) -> ServerResult<NaiveDateTime> {
+    let invalid = || ServerError::ValidationError {
+        message: format!(
+            "invalid expiry '{value}', expected a duration like 30m, 12h or 7d, or an RFC 3339 time"
+        ),
+    };
+
+    let value = value.trim();
+    let now = Utc::now();
+    let expires_at = match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => time.with_timezone(&Utc),
+        Err(_) => {
+            let split = value
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(value.len());
+            let (amount, unit) = value.split_at(split);
+            let amount: i64 = amount.parse().map_err(|_| invalid())?;
+            let seconds_per = match unit {
+                "" | "s" => 1,
+                "m" => 60,
+                "h" => 60 * 60,
+                "d" => 24 * 60 * 60,
+                "w" => 7 * 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+            amount
+                .checked_mul(seconds_per)
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|ttl| now.checked_add_signed(ttl))
+                .ok_or_else(invalid)?
+        }
+    };
+
+    if expires_at <= now {
+        return Err(ServerError::ValidationError {
+            message: "expiry must be in the future".to_string(),
+        });
+    }
+
+    Ok(expires_at.naive_utc())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,6 +167,24 @@ mod tests {
         assert!(validate_path(&"a".repeat(4097)).is_err());
     }
 
+    #[test]
+    fn test_resolve_relative_path() {
+        let dir = VirtualPath::from("root/target/");
+
+        let (parent, name) = resolve_relative_path(&dir, "./src/main.rs")
+            .unwrap()
+            .unwrap();
+        assert_eq!(parent.to_string(), "root/target/src");
+        assert_eq!(name, "main.rs");
+        assert!(resolve_relative_path(&dir, "./").unwrap().is_none());
+
+        assert!(resolve_relative_path(&dir, "../escape.txt").is_err());
+        assert!(resolve_relative_path(&dir, "src/../../escape.txt").is_err());
+        assert!(resolve_relative_path(&dir, "/etc/passwd").is_err());
+        assert!(resolve_relative_path(&dir, "..\\escape.txt").is_err());
+        assert!(resolve_relative_path(&dir, "C:\\escape.txt").is_err());
+    }
+
     #[test]
     fn test_sanitize_path_component() {
         assert_eq!(
@@ -100,4 +197,25 @@ mod tests {
             "filewithslashes"
         );
     }
+
+    #[test]
+    fn test_parse_expiry() {
+        let now = Utc::now().naive_utc();
+        let in_an_hour = parse_expiry("1h").unwrap() - now;
+        assert!((in_an_hour.num_seconds() - 3600).abs() <= 1);
+        let in_a_week = parse_expiry("1w").unwrap() - now;
+        assert!((in_a_week.num_days() - 7).abs() <= 1);
+        assert!((parse_expiry("90").unwrap() - now).num_seconds() <= 90);
+
+        assert_eq!(
+            parse_expiry("2999-01-01T00:00:00Z").unwrap(),
+            "2999-01-01T00:00:00".parse::<NaiveDateTime>().unwrap()
+        );
+        assert!(parse_expiry("2000-01-01T00:00:00Z").is_err());
+        assert!(parse_expiry("0s").is_err());
+        assert!(parse_expiry("").is_err());
+        assert!(parse_expiry("5y").is_err());
+        assert!(parse_expiry("-5m").is_err());
+        assert!(parse_expiry("99999999999999999d").is_err());
+    }
 }
@@ -0,0 +1,181 @@
+// Directories downloaded as archives, built while they're sent.
+// The archive is written into a pipe by a background task and the response
+// body reads from the other end, so nothing is buffered on disk.
+
+use std::io;
+
+use async_compression::tokio::write::GzipEncoder;
+use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use axum::{
+    body::Body,
+    http::{header, HeaderValue},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use futures::{AsyncWriteExt as _, StreamExt};
+use serde::Deserialize;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio_tar::{EntryType, Header};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::error;
+
+use crate::server::{models::files::Media, storage::Blobs};
+
+/// How much of the archive can be written ahead of what the client has read.
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// A file or directory in the archive.
+pub struct ArchiveEntry {
+    /// Relative to the directory being archived, without a trailing slash
+    pub path: String,
+    /// None for directories
+    pub media: Option<Media>,
+    pub modified_at: DateTime<Utc>,
+}
+
+/// Streams `entries` as an archive named after `name`.
+pub fn archive_response(
+    blobs: Blobs,
+    format: ArchiveFormat,
+    name: &str,
+    entries: Vec<ArchiveEntry>,
+) -> Response {
+    let (writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+
+    let task = tokio::spawn(async move {
+        let result = match format {
+            ArchiveFormat::Zip => write_zip(&blobs, entries, writer).await,
+            ArchiveFormat::TarGz => write_tar_gz(&blobs, entries, writer).await,
+        };
+        if let Err(e) = &result {
+            error!("Failed to write archive: {e}");
+        }
+        result
+    });
+
+    // A failed archive has to fail the response too, or it would look
+    // like a complete (but shorter) download
+    let outcome = futures::stream::once(async move {
+        match task.await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(e) => Some(Err(io::Error::other(e))),
+        }
+    })
+    .filter_map(futures::future::ready);
+
+    let mut res = Response::new(Body::from_stream(ReaderStream::new(reader).chain(outcome)));
+    res.headers_mut().append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    let disposition = format!(
+        "attachment; filename=\"{}.{}\"",
+        name.replace(['"', '\\'], "_"),
+        format.extension()
+    );
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        res.headers_mut().append(header::CONTENT_DISPOSITION, value);
+    }
+
+    res
+}
+
+async fn write_zip(
+    blobs: &Blobs,
+    entries: Vec<ArchiveEntry>,
+    writer: DuplexStream,
+) -> io::Result<()> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+
+    for entry in entries {
+        let Some(media) = entry.media else {
+            let builder =
+                ZipEntryBuilder::new(format!("{}/", entry.path).into(), Compression::Stored)
+                    .last_modification_date((&entry.modified_at).into());
+            zip.write_entry_whole(builder, &[])
+                .await
+                .map_err(io::Error::other)?;
+            continue;
+        };
+
+        let builder = ZipEntryBuilder::new(entry.path.into(), Compression::Deflate)
+            .last_modification_date((&entry.modified_at).into());
+        let mut entry_writer = zip
+            .write_entry_stream(builder)
+            .await
+            .map_err(io::Error::other)?;
+        let mut contents = blobs
+            .get(&media.blob_key())
+            .await
+            .map_err(io::Error::other)?;
+        while let Some(chunk) = contents.next().await {
+            entry_writer.write_all(&chunk?).await?;
+        }
+        entry_writer.close().await.map_err(io::Error::other)?;
+    }
+
+    let mut writer = zip.close().await.map_err(io::Error::other)?.into_inner();
+    writer.shutdown().await
+}
+
+async fn write_tar_gz(
+    blobs: &Blobs,
+    entries: Vec<ArchiveEntry>,
+    writer: DuplexStream,
+) -> io::Result<()> {
+    let mut tar = tokio_tar::Builder::new(GzipEncoder::new(writer));
+
+    for entry in entries {
+        let mut header = Header::new_gnu();
+        header.set_mtime(entry.modified_at.timestamp().max(0) as u64);
+
+        match entry.media {
+            None => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                tar.append_data(&mut header, format!("{}/", entry.path), tokio::io::empty())
+                    .await?;
+            }
+            Some(media) => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(0o644);
+                header.set_size(media.file_size as u64);
+                let contents = blobs
+                    .get(&media.blob_key())
+                    .await
+                    .map_err(io::Error::other)?;
+                tar.append_data(&mut header, entry.path, StreamReader::new(contents))
+                    .await?;
+            }
+        }
+    }
+
+    let mut gzip = tar.into_inner().await?;
+    gzip.shutdown().await
+}
@@ -0,0 +1,34 @@
+use axum::{extract::State, routing::get, Extension, Json, Router};
+
+use crate::server::{
+    controllers::scrub::ScrubController,
+    error::{ServerError, ServerResult},
+    models::{auth::AuthContext, files::ScrubReport},
+    web::middleware::require_auth,
+};
+
+pub fn routes(scrub_controller: ScrubController) -> Router {
+    Router::new()
+        .route("/admin/scrub", get(scrub_report))
+        .layer(axum::middleware::from_fn(require_auth))
+        .with_state(scrub_controller)
+}
+
+fn require_admin(auth_context: &AuthContext) -> ServerResult<()> {
+    if auth_context.is_admin() {
+        Ok(())
+    } else {
+        Err(ServerError::AuthorizationError {
+            message: "Admins only".to_string(),
+        })
+    }
+}
+
+/// What the integrity scrubber has checked so far, and what failed.
+async fn scrub_report(
+    Extension(auth_context): Extension<AuthContext>,
+    State(scrubber): State<ScrubController>,
+) -> ServerResult<Json<ScrubReport>> {
+    require_admin(&auth_context)?;
+    Ok(Json(scrubber.report().await?))
+}
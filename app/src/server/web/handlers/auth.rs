@@ -1,17 +1,21 @@
 use axum::{
+    extract::{Path, State},
+    http::StatusCode,
     response::Json as ResponseJson,
-    routing::{get, post},
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
 use serde_json::{json, Value};
 use uuid::Uuid;
 
 use crate::server::{
-    controllers::auth::AuthController, error::ServerError, models::auth::*,
+    controllers::{auth::AuthController, files::FileController},
+    error::ServerError,
+    models::{auth::*, files::StorageUsage},
     web::middleware::require_auth,
 };
 
-pub fn routes(auth_controller: AuthController) -> Router {
+pub fn routes(_auth_controller: AuthController, files: FileController) -> Router {
     let public_routes = Router::new()
         .route("/auth/register", post(register_handler))
         .route("/auth/login", post(login_handler));
@@ -19,6 +23,12 @@ pub fn routes(auth_controller: AuthController) -> Router {
     let protected_routes = Router::new()
         .route("/auth/logout", post(logout_handler))
         .route("/auth/me", get(me_handler))
+        .route("/auth/me/usage", get(usage_handler).with_state(files))
+        .route(
+            "/auth/tokens",
+            get(list_tokens_handler).post(create_token_handler),
+        )
+        .route("/auth/tokens/:id", delete(revoke_token_handler))
         .route("/auth/permissions/grant", post(grant_permission_handler))
         .route("/auth/permissions/revoke", post(revoke_permission_handler))
         .route(
@@ -62,8 +72,13 @@ async fn login_handler(
 
 async fn logout_handler(
     Extension(auth_controller): Extension<AuthController>,
-    Extension(session_id): Extension<Uuid>,
+    session_id: Option<Extension<Uuid>>,
 ) -> Result<ResponseJson<Value>, ServerError> {
+    let Some(Extension(session_id)) = session_id else {
+        return Err(ServerError::ValidationError {
+            message: "API tokens can't log out, revoke them instead".to_string(),
+        });
+    };
     auth_controller.delete_session(session_id).await?;
 
     Ok(ResponseJson(json!({
@@ -81,6 +96,69 @@ async fn me_handler(
     })))
 }
 
+async fn usage_handler(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+) -> Result<ResponseJson<StorageUsage>, ServerError> {
+    Ok(ResponseJson(files.usage(auth_context.user_id).await?))
+}
+
+/// Tokens can't be used to make more tokens, or to revoke them.
+fn require_session(auth_context: &AuthContext) -> Result<(), ServerError> {
+    if auth_context.is_token() {
+        return Err(ServerError::AuthorizationError {
+            message: "Log in with a password to manage API tokens".to_string(),
+        });
+    }
+    Ok(())
+}
+
+async fn create_token_handler(
+    Extension(auth_controller): Extension<AuthController>,
+    Extension(auth_context): Extension<AuthContext>,
+    Json(request): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, ResponseJson<CreatedToken>), ServerError> {
+    require_session(&auth_context)?;
+    let (token, api_token) = auth_controller
+        .create_api_token(auth_context.user_id, request)
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        ResponseJson(CreatedToken {
+            token,
+            info: api_token.into(),
+        }),
+    ))
+}
+
+async fn list_tokens_handler(
+    Extension(auth_controller): Extension<AuthController>,
+    Extension(auth_context): Extension<AuthContext>,
+) -> Result<ResponseJson<Vec<ApiTokenInfo>>, ServerError> {
+    require_session(&auth_context)?;
+    let tokens = auth_controller
+        .list_api_tokens(auth_context.user_id)
+        .await?;
+
+    Ok(ResponseJson(tokens.into_iter().map(Into::into).collect()))
+}
+
+async fn revoke_token_handler(
+    Extension(auth_controller): Extension<AuthController>,
+    Extension(auth_context): Extension<AuthContext>,
+    Path(id): Path<u64>,
+) -> Result<ResponseJson<Value>, ServerError> {
+    require_session(&auth_context)?;
+    auth_controller
+        .revoke_api_token(auth_context.user_id, id as i64)
+        .await?;
+
+    Ok(ResponseJson(json!({
+        "message": "Token revoked successfully"
+    })))
+}
+
 async fn grant_permission_handler(
     Extension(auth_controller): Extension<AuthController>,
     Extension(auth_context): Extension<AuthContext>,
@@ -1,31 +1,44 @@
 use crate::{config::SETTINGS, server::models::files::SFile};
 use axum::{
     body::Body,
-    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
-    http::{header, HeaderValue},
-    response::Response,
-    routing::{delete, get, put},
+    extract::{multipart::Field, DefaultBodyLimit, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{delete, get, head, post, put},
     Extension, Json, Router,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
+use chrono::NaiveDateTime;
+use futures::StreamExt;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
-use std::{
-    io::Write,
-    path::PathBuf,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::{collections::HashMap, io::Write, path::PathBuf};
 use tokio::fs;
 use tokio::{fs::File, io::AsyncWriteExt};
-use tracing::error;
 
 use crate::server::error::{ServerError, ServerResult};
+use crate::server::validation::{parse_expiry, resolve_relative_path};
+use crate::server::web::archive::{archive_response, ArchiveEntry, ArchiveFormat};
 use crate::server::web::middleware::{optional_auth, require_auth};
+use crate::server::web::range::{self, ByteRange, RangeRequest};
 use crate::server::{
     controllers::files::FileController,
     models::auth::{AuthContext, Permission, RelationshipType},
-    models::files::{FileUploadInfo, Media, VirtualPath},
+    models::files::{
+        ConflictPolicy, FileUploadInfo, HashUploadResponse, Media, StagedUpload, StorageUsage,
+        UploadFailure, UploadResult, VirtualPath,
+    },
+    storage::Blobs,
 };
 use sqlx::query;
+use uuid::Uuid;
+
+/// The only version of the tus protocol spoken here.
+pub const TUS_VERSION: &str = "1.0.0";
+/// tus extensions supported on top of the core protocol.
+const TUS_EXTENSIONS: &str = "creation,termination";
 
 pub fn routes(controller: FileController) -> Router {
     // Public routes (no authentication required - handlers check if files are public)
@@ -45,12 +58,30 @@ pub fn routes(controller: FileController) -> Router {
                 },
             ),
         )
-        .route("/files", put(move_files).patch(set_permissions_and_visibility))
+        .route(
+            "/files",
+            put(move_files).patch(set_permissions_and_visibility),
+        )
+        .route("/files/by-hash", post(upload_by_hash))
+        .route("/files/copy", post(copy_files))
         .layer(axum::middleware::from_fn(require_auth));
 
+    // Resumable uploads (tus), see https://tus.io/protocols/resumable-upload
+    let upload_routes = Router::new()
+        .route("/uploads", post(create_upload))
+        .route(
+            "/uploads/:id",
+            head(upload_offset)
+                .patch(append_to_upload)
+                .delete(terminate_upload),
+        )
+        .layer(axum::middleware::from_fn(require_auth))
+        .layer(axum::middleware::from_fn(tus_resumable));
+
     Router::new()
         .merge(public_routes)
         .merge(protected_routes)
+        .merge(upload_routes)
         .with_state(controller)
 }
 
@@ -60,6 +91,15 @@ pub struct MoveInfo {
     pub to: VirtualPath,
 }
 
+#[derive(Deserialize)]
+pub struct CopyInfo {
+    pub from: VirtualPath,
+    // The path of the copy itself, not the directory it goes in
+    pub to: VirtualPath,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+}
+
 #[derive(Deserialize)]
 pub struct VisibilityInfo {
     pub path: VirtualPath,
@@ -80,9 +120,38 @@ pub struct FilePermissionRequest {
     pub permissions: Option<PermissionOperation>, // Optional - permissions object, exclude to not change permissions
 }
 
+#[derive(Deserialize)]
+pub struct HashUploadInfo {
+    pub path: VirtualPath,
+    // SHA-256 of the file, hex encoded
+    pub hash: String,
+    pub size: u64,
+    // Both set when answering a challenge
+    pub challenge_id: Option<Uuid>,
+    pub answer: Option<String>,
+    // TTL or time the file expires at, see `validation::parse_expiry`
+    pub expires: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    // TTL or time the file expires at, see `validation::parse_expiry`
+    pub expires: Option<String>,
+    // Unpack a zip, tar or tar.gz upload into the directory instead of storing it
+    #[serde(default)]
+    pub extract: bool,
+    // Store every file in the request or none of them, instead of each on its own
+    #[serde(default)]
+    pub atomic: bool,
+}
+
 #[derive(Deserialize)]
 pub struct UserQuery {
     pub u: Option<i64>, // Optional user ID to access other user's files
+    // A version id from `GET /versions/*path`, to download older contents of a file
+    pub version: Option<i64>,
+    // Download a directory as an archive instead of listing it
+    pub archive: Option<ArchiveFormat>,
 }
 
 pub async fn move_files(
@@ -90,6 +159,9 @@ pub async fn move_files(
     State(files): State<FileController>,
     Json(move_info): Json<MoveInfo>,
 ) -> ServerResult<Json<SFile>> {
+    auth_context.require_path(&move_info.from)?;
+    auth_context.require_path(&move_info.to)?;
+
     // Check if user has permission to move the source file
     let sfile = files
         .get_sfile(&move_info.from, auth_context.user_id)
@@ -121,223 +193,633 @@ pub async fn move_files(
         .map(Json)
 }
 
+/// Copies a file or directory tree without copying any contents on disk.
+pub async fn copy_files(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Json(copy_info): Json<CopyInfo>,
+) -> ServerResult<(StatusCode, Json<SFile>)> {
+    auth_context.require_path(&copy_info.from)?;
+    auth_context.require_path(&copy_info.to)?;
+    let copy = files
+        .copy(
+            &copy_info.from,
+            &copy_info.to,
+            copy_info.on_conflict,
+            auth_context.user_id,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(copy)))
+}
+
 pub async fn upload_or_mk_dirs(
     Extension(auth_context): Extension<AuthContext>,
     State(files): State<FileController>,
     Path(mut path): Path<VirtualPath>,
+    Query(upload_query): Query<UploadQuery>,
+    headers: HeaderMap,
     multipart: Option<Multipart>,
-) -> ServerResult<Json<Vec<SFile>>> {
+) -> ServerResult<Json<UploadResult>> {
     path.err_if_file()?;
+    if path.to_string_with_trailing().is_empty() {
+        path = VirtualPath::root();
+    }
+    auth_context.require_path(&path)?;
+    let expires_at = upload_query
+        .expires
+        .as_deref()
+        .map(parse_expiry)
+        .transpose()?;
     // If it was multipart
     if let Some(mut multipart) = multipart {
-        if let Some(mut field) =
-            multipart
-                .next_field()
-                .await
-                .map_err(|e| ServerError::AxumError {
-                    message: format!("Multipart error: {}", e.body_text()),
-                })?
-        {
-            let save_dir = &SETTINGS.directories.files_dir;
+        // The body is a bit bigger than the files, but close enough to turn
+        // uploads that can't fit away before they're sent
+        let mut usage = files.usage(auth_context.user_id).await?;
+        if let Some(length) = header_number(&headers, "content-length")? {
+            usage.check(length)?;
+        }
 
+        // Every file is written out before any of them are stored
+        let mut staged: Vec<(String, ServerResult<StagedUpload>)> = Vec::new();
+        loop {
+            let field = match multipart.next_field().await {
+                Ok(Some(field)) => field,
+                Ok(None) => break,
+                Err(e) => {
+                    discard_staged(&staged).await;
+                    return Err(ServerError::AxumError {
+                        message: format!("Multipart error: {}", e.body_text()),
+                    });
+                }
+            };
             // Name should be the name of the file, including the extension.
-            let name: String = field.name().expect("File has no name??").to_string();
-            // trace!("Got file: {name}");
-            // trace!("for path: {}", path.to_string());
+            // It can start with directories to put the file in.
+            let name = field.name().unwrap_or_default().to_string();
 
-            if path.to_string_with_trailing().is_empty() {
-                path = VirtualPath::root();
+            let result = stage_field(
+                &files,
+                field,
+                &name,
+                &path,
+                auth_context.user_id,
+                expires_at,
+                upload_query.extract,
+                &usage,
+            )
+            .await;
+            match result {
+                Err(e) if upload_query.atomic => {
+                    discard_staged(&staged).await;
+                    return Err(e);
+                }
+                result => {
+                    if let Ok(upload) = &result {
+                        usage.used_bytes += upload.bytes();
+                    }
+                    staged.push((name, result));
+                }
             }
+        }
+
+        if !staged.is_empty() {
+            return store_staged(&files, auth_context.user_id, staged, upload_query.atomic)
+                .await
+                .map(Json);
+        }
+    }
+
+    // either no content in multipart or not multipart. thats okay, just make the directory.
+    files
+        .make_all_dirs(&path, auth_context.user_id, None)
+        .await
+        .map(|files| {
+            Json(UploadResult {
+                files,
+                errors: Vec::new(),
+            })
+        })
+}
+
+/// Writes a multipart field out to a temporary file. Archives being extracted
+/// are unpacked straight away, and only what's in them is kept.
+#[allow(clippy::too_many_arguments)]
+async fn stage_field(
+    files: &FileController,
+    mut field: Field<'_>,
+    name: &str,
+    dir: &VirtualPath,
+    user_id: i64,
+    expires_at: Option<NaiveDateTime>,
+    extract: bool,
+    usage: &StorageUsage,
+) -> ServerResult<StagedUpload> {
+    let (dir, file_name) =
+        resolve_relative_path(dir, name)?.ok_or_else(|| ServerError::ValidationError {
+            message: "uploaded files need a name".to_string(),
+        })?;
+
+    let temp_path: PathBuf = SETTINGS
+        .directories
+        .files_dir
+        .join(format!("tmp_{}_{file_name}", Uuid::new_v4()));
+    let mut file = File::create(&temp_path)
+        .await
+        .map_err(|e| ServerError::IOError {
+            message: e.to_string(),
+        })?;
+    let written = write_field(&mut field, &mut file, usage).await;
+    // Ensure the file handle is dropped before doing anything
+    // ahem windows
+    drop(file);
+    let (file_size, file_hash) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+    };
+
+    if extract {
+        let staged = files
+            .stage_archive(&temp_path, &file_name, &dir, user_id, expires_at, usage)
+            .await;
+        let _ = fs::remove_file(&temp_path).await;
+        return staged;
+    }
+
+    Ok(StagedUpload {
+        dirs: Vec::new(),
+        files: vec![FileUploadInfo {
+            file_name,
+            temp_path,
+            file_size,
+            file_hash,
+            vpath: dir,
+            user_id,
+            expires_at,
+        }],
+    })
+}
+
+/// Copies the field into `file`, returning its size and hash.
+async fn write_field(
+    field: &mut Field<'_>,
+    file: &mut File,
+    usage: &StorageUsage,
+) -> ServerResult<(i64, String)> {
+    let mut hasher = Sha256::new();
+    // i64 type because postgres doesnt support unsigned gg
+    let mut file_size: i64 = 0;
+
+    while let Some(chunk) = field.chunk().await.map_err(|e| ServerError::AxumError {
+        message: format!("Chunk error: {}", e.body_text()),
+    })? {
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| ServerError::IOError {
+                message: e.to_string(),
+            })?;
+        file_size += chunk.len() as i64;
+        hasher.write_all(&chunk).expect("Failed to hash shit");
+
+        // Without a Content-Length this is the first we hear of the size
+        usage.check(file_size as u64)?;
+    }
+
+    file.flush().await.expect("Bluh flushing file failed");
+
+    Ok((file_size, format!("{:X}", hasher.finalize())))
+}
 
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
-
-            let mut hasher = Sha256::new();
-
-            let temp_path: PathBuf = save_dir.join(format!("./tmp_{now}_{name}"));
-
-            let mut file: File =
-                File::create(&temp_path)
-                    .await
-                    .map_err(|e| ServerError::IOError {
-                        message: e.to_string(),
-                    })?;
-            // i64 type because postgres doesnt support unsigned gg
-
-            let mut file_size: i64 = 0;
-            const PROGRESS_THRESHOLD: u64 = 1024 * 1024; // 1MB
-            let mut last_progress_report: u64 = 0;
-
-            while let Some(chunk) = field.chunk().await.map_err(|e| ServerError::AxumError {
-                message: format!("Chunk error: {}", e.body_text()),
-            })? {
-                file.write_all(&chunk)
-                    .await
-                    .map_err(|e| ServerError::IOError {
-                        message: e.to_string(),
-                    })?;
-                file_size += chunk.len() as i64;
-                hasher.write_all(&chunk).expect("Failed to hash shit");
-
-                // Send progress updates every 1MB or so
-                if file_size as u64 - last_progress_report >= PROGRESS_THRESHOLD {
-                    // Note: We don't have total size available with multipart uploads in axum
-                    // So we'll just report current bytes uploaded without percentage
-                    last_progress_report = file_size as u64;
+/// Stores the staged files, all in one go if `atomic`, otherwise each field on its own.
+/// If none of them could be stored the request fails like a single upload would.
+async fn store_staged(
+    files: &FileController,
+    user_id: i64,
+    staged: Vec<(String, ServerResult<StagedUpload>)>,
+    atomic: bool,
+) -> ServerResult<UploadResult> {
+    if atomic {
+        let mut all = StagedUpload::default();
+        for upload in staged.into_iter().filter_map(|(_, upload)| upload.ok()) {
+            all.extend(upload);
+        }
+        let temp_paths = all.temp_paths();
+        return match files.finish_uploads(user_id, all).await {
+            Ok(files) => Ok(UploadResult {
+                files,
+                errors: Vec::new(),
+            }),
+            Err(e) => {
+                remove_temp_files(&temp_paths).await;
+                Err(e)
+            }
+        };
+    }
+
+    let mut result = UploadResult::default();
+    let mut first_error = None;
+    let mut any_stored = false;
+    for (name, upload) in staged {
+        let stored = match upload {
+            Ok(upload) => {
+                let temp_paths = upload.temp_paths();
+                let stored = files.finish_uploads(user_id, upload).await;
+                if stored.is_err() {
+                    remove_temp_files(&temp_paths).await;
                 }
+                stored
+            }
+            Err(e) => Err(e),
+        };
+
+        match stored {
+            Ok(stored) => {
+                any_stored = true;
+                result.files.extend(stored);
+            }
+            Err(e) => {
+                result.errors.push(UploadFailure {
+                    name,
+                    error: e.to_string(),
+                });
+                first_error.get_or_insert(e);
             }
+        }
+    }
 
-            file.flush().await.expect("Bluh flushing file failed");
+    match first_error {
+        Some(e) if !any_stored => Err(e),
+        _ => Ok(result),
+    }
+}
 
-            let hash = hasher.finalize();
-            let file_hash: String = format!("{hash:X}");
+async fn discard_staged(staged: &[(String, ServerResult<StagedUpload>)]) {
+    for (_, upload) in staged {
+        if let Ok(upload) = upload {
+            remove_temp_files(&upload.temp_paths()).await;
+        }
+    }
+}
 
-            let info = FileUploadInfo {
-                file_name: name,
-                temp_path: temp_path.clone(),
-                file_size,
-                file_hash: file_hash.clone(),
-                vpath: path,
-                user_id: auth_context.user_id,
-            };
+async fn remove_temp_files(temp_paths: &[PathBuf]) {
+    for temp_path in temp_paths {
+        // doesnt really have to be checked
+        let _ = fs::remove_file(temp_path).await;
+    }
+}
 
-            // Ensure the file handle is dropped before doing anything
-            // ahem windows
-            drop(file);
+/// Creates a file from media already on the server, by hash. Returns 201 with the
+/// file if it was linked, or 200 with a challenge to answer in a second request.
+pub async fn upload_by_hash(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Json(info): Json<HashUploadInfo>,
+) -> ServerResult<(StatusCode, Json<HashUploadResponse>)> {
+    auth_context.require_path(&info.path)?;
+    let response = match (info.challenge_id, info.answer) {
+        (Some(challenge_id), Some(answer)) => HashUploadResponse::Linked {
+            file: files
+                .answer_hash_challenge(&info.path, challenge_id, &answer, auth_context.user_id)
+                .await?,
+        },
+        _ => {
+            let expires_at = info.expires.as_deref().map(parse_expiry).transpose()?;
+            files
+                .upload_by_hash(
+                    &info.path,
+                    &info.hash,
+                    info.size,
+                    auth_context.user_id,
+                    expires_at,
+                )
+                .await?
+        }
+    };
 
-            // HEHEHEHAW fix race condition
-            // just in case if two people upload the same file at the exact same time down to the millisecond...??
-            let mutex = files.active_uploads.lock(file_hash.clone()).await;
+    let status = match response {
+        HashUploadResponse::Linked { .. } => StatusCode::CREATED,
+        HashUploadResponse::Challenge { .. } => StatusCode::OK,
+    };
 
-            // Check-in file to database
-            let sfile = match files.finish_upload(info).await {
-                Err(e) => {
-                    // doesnt really have to be checked
-                    let _ = fs::remove_file(&temp_path).await;
-                    error!(
-                        "(Tried) removed {} due to error: {e:?}",
-                        temp_path.to_string_lossy()
-                    );
-                    Err(e)
-                }
-                Ok(c) => {
-                    // Notify upload completion via WebSocket if available
-                    // This is handled in the FileController's finish_upload method
-                    Ok(c)
-                }
-            };
+    Ok((status, Json(response)))
+}
 
-            drop(mutex);
+/// Rejects requests for a tus version other than [`TUS_VERSION`],
+/// and tags every response with the version in use.
+async fn tus_resumable(request: Request, next: Next) -> Response {
+    let supported = request
+        .headers()
+        .get("tus-resumable")
+        .is_some_and(|v| v == TUS_VERSION);
 
-            return sfile.map(|s| Json(vec![s]));
+    let mut res = if supported {
+        next.run(request).await
+    } else {
+        let mut res = StatusCode::PRECONDITION_FAILED.into_response();
+        res.headers_mut()
+            .insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+        res
+    };
+
+    res.headers_mut()
+        .insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+    res
+}
+
+/// Answers `OPTIONS /uploads` so clients can discover what the server supports
+/// before logging in. Sits outside the CORS layer, which would otherwise take
+/// every OPTIONS request for a preflight; real preflights still go through.
+pub async fn tus_discovery(request: Request, next: Next) -> Response {
+    if request.method() == Method::OPTIONS
+        && request.uri().path() == "/uploads"
+        && !request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        let mut res = tus_options();
+        res.headers_mut()
+            .insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+        return res;
+    }
+
+    next.run(request).await
+}
+
+fn tus_options() -> Response {
+    let mut res = StatusCode::NO_CONTENT.into_response();
+    res.headers_mut()
+        .insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+    res.headers_mut()
+        .insert("tus-extension", HeaderValue::from_static(TUS_EXTENSIONS));
+    if let Some(max) = SETTINGS.application.max_filesize {
+        res.headers_mut()
+            .insert("tus-max-size", HeaderValue::from(max));
+    }
+    res
+}
+
+/// Creates an upload from the Upload-Length and Upload-Metadata headers.
+/// The metadata needs a `filename`, and can have a `path` with the directory
+/// to put the file in (defaults to root) and when the file `expires`.
+pub async fn create_upload(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    headers: HeaderMap,
+) -> ServerResult<Response> {
+    let upload_length: u64 =
+        header_number(&headers, "upload-length")?.ok_or_else(|| ServerError::ValidationError {
+            message: "Upload-Length header is required".to_string(),
+        })?;
+    // Lengths are stored as BIGINT
+    if upload_length > i64::MAX as u64 {
+        return Err(ServerError::ValidationError {
+            message: "Upload-Length is too large".to_string(),
+        });
+    }
+
+    if let Some(max) = SETTINGS.application.max_filesize {
+        if upload_length > max as u64 {
+            return Err(ServerError::PayloadTooLarge {
+                details: format!("Uploads can be at most {max} bytes"),
+            });
         }
     }
 
-    // either no content in multipart or not multipart. thats okay, just make the directory.
-    files
-        .make_all_dirs(&path, auth_context.user_id, None)
-        .await
-        .map(Json)
+    let mut metadata = parse_upload_metadata(
+        headers
+            .get("upload-metadata")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default(),
+    )?;
+
+    let file_name = metadata
+        .remove("filename")
+        .filter(|n| !n.is_empty() && n != "." && n != ".." && !n.contains(['/', '\0']))
+        .ok_or_else(|| ServerError::ValidationError {
+            message: "Upload-Metadata needs a valid filename".to_string(),
+        })?;
+
+    let dir = match metadata.remove("path") {
+        Some(path) => VirtualPath::try_from_string(&path)
+            .map_err(|e| ServerError::ValidationError {
+                message: e.to_string(),
+            })?
+            .as_dir(),
+        None => VirtualPath::root(),
+    };
+    auth_context.require_path(&dir)?;
+
+    let expires_at = metadata
+        .remove("expires")
+        .as_deref()
+        .map(parse_expiry)
+        .transpose()?;
+
+    let upload = files
+        .create_upload(
+            &dir,
+            file_name,
+            upload_length as i64,
+            auth_context.user_id,
+            expires_at,
+        )
+        .await?;
+
+    let mut res = StatusCode::CREATED.into_response();
+    res.headers_mut().insert(
+        header::LOCATION,
+        header_value(&format!("/uploads/{}", upload.id))?,
+    );
+    Ok(res)
+}
+
+pub async fn upload_offset(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(id): Path<Uuid>,
+) -> ServerResult<Response> {
+    let upload = files.get_upload(id, auth_context.user_id).await?;
+
+    let mut res = StatusCode::OK.into_response();
+    res.headers_mut()
+        .insert("upload-offset", HeaderValue::from(upload.offset().await?));
+    res.headers_mut()
+        .insert("upload-length", HeaderValue::from(upload.upload_length));
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(res)
+}
+
+/// Appends the request body to the upload at the given Upload-Offset.
+/// The upload turns into a regular file as soon as the last byte arrives.
+pub async fn append_to_upload(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Body,
+) -> ServerResult<Response> {
+    if headers
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|v| v != "application/offset+octet-stream")
+    {
+        return Err(ServerError::UnsupportedMedia {
+            details: "Content-Type must be application/offset+octet-stream".to_string(),
+        });
+    }
+
+    let offset: u64 =
+        header_number(&headers, "upload-offset")?.ok_or_else(|| ServerError::ValidationError {
+            message: "Upload-Offset header is required".to_string(),
+        })?;
+
+    let offset = files
+        .append_to_upload(id, auth_context.user_id, offset, body.into_data_stream())
+        .await?;
+
+    let mut res = StatusCode::NO_CONTENT.into_response();
+    res.headers_mut()
+        .insert("upload-offset", HeaderValue::from(offset));
+    Ok(res)
+}
+
+pub async fn terminate_upload(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(id): Path<Uuid>,
+) -> ServerResult<StatusCode> {
+    files.terminate_upload(id, auth_context.user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn header_number(headers: &HeaderMap, name: &str) -> ServerResult<Option<u64>> {
+    headers
+        .get(name)
+        .map(|v| {
+            v.to_str().ok().and_then(|v| v.parse().ok()).ok_or_else(|| {
+                ServerError::ValidationError {
+                    message: format!("{name} must be a non-negative integer"),
+                }
+            })
+        })
+        .transpose()
+}
+
+/// Parses an Upload-Metadata header: comma separated `key base64(value)` pairs,
+/// where the value can be left out.
+fn parse_upload_metadata(header: &str) -> ServerResult<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = BASE64
+            .decode(value.trim())
+            .ok()
+            .and_then(|v| String::from_utf8(v).ok())
+            .ok_or_else(|| ServerError::ValidationError {
+                message: format!("Upload-Metadata value for {key} is not valid base64"),
+            })?;
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+/// Picks whose files a read request is for: the user from the `u` query parameter,
+/// or the authenticated user if there is none.
+pub fn resolve_target_user(
+    auth_context: Option<&AuthContext>,
+    user_query: &UserQuery,
+) -> ServerResult<i64> {
+    match user_query.u {
+        // Accessing someone else's files, permission checks happen per file
+        Some(requested_user_id) => Ok(requested_user_id),
+        // No specific user requested, use authenticated user's files or reject if not authenticated
+        None => match auth_context {
+            Some(ctx) => Ok(ctx.user_id),
+            None => Err(ServerError::AuthenticationError {
+                message: "Authentication required when no target user specified".to_string(),
+            }),
+        },
+    }
+}
+
+/// Checks that a file can be read: it's public, the user owns it,
+/// or the user has been granted read access through ReBAC.
+pub async fn authorize_file_read(
+    files: &FileController,
+    auth_context: Option<&AuthContext>,
+    sfile: &SFile,
+) -> ServerResult<()> {
+    // Check if file is public - if so, allow access regardless of authentication
+    if sfile.is_public {
+        return Ok(());
+    }
+
+    // File is private, require authentication and permission checking
+    let auth_context = auth_context.ok_or_else(|| ServerError::AuthenticationError {
+        message: "Authentication required to access private files".to_string(),
+    })?;
+
+    // First check if user is the owner (direct ownership via sfiles.user_id)
+    let is_owner = query!("SELECT user_id FROM sfiles WHERE id = $1", sfile.id as i64)
+        .fetch_optional(files.db_pool())
+        .await?
+        .map(|row| row.user_id == Some(auth_context.user_id))
+        .unwrap_or(false);
+
+    // If not owner, check ReBAC permissions
+    if !is_owner && !auth_context.has_permission("sfile", Some(sfile.id as i64), Permission::Read) {
+        return Err(ServerError::AuthorizationError {
+            message: "You don't have permission to access this file".to_string(),
+        });
+    }
+
+    Ok(())
 }
 
 pub async fn get_file_or_list_dir(
     auth_context: Option<Extension<AuthContext>>,
+    headers: HeaderMap,
     Path(path): Path<VirtualPath>,
     Query(user_query): Query<UserQuery>,
     State(files): State<FileController>,
 ) -> ServerResult<Response> {
+    if let Some(Extension(ctx)) = &auth_context {
+        ctx.require_path(&path)?;
+    }
+
     // Determine target user ID based on query parameter or authenticated user
-    let target_user_id = match user_query.u {
-        Some(requested_user_id) => {
-            // User wants to access someone else's files
-            match auth_context.as_ref() {
-                Some(Extension(ctx)) => {
-                    // For now, allow access if authenticated (permission checks can be added later)
-                    requested_user_id
-                }
-                None => {
-                    // Anonymous user trying to access specific user's files - only allow for public files
-                    requested_user_id
-                }
-            }
-        }
-        None => {
-            // No specific user requested, use authenticated user's files or reject if not authenticated
-            match auth_context.as_ref() {
-                Some(Extension(ctx)) => ctx.user_id,
-                None => {
-                    return Err(ServerError::AuthenticationError {
-                        message: "Authentication required when no target user specified"
-                            .to_string(),
-                    });
-                }
-            }
-        }
-    };
+    let target_user_id =
+        resolve_target_user(auth_context.as_ref().map(|Extension(ctx)| ctx), &user_query)?;
 
     if !path.is_dir() {
         let sfile = files.get_sfile(&path, target_user_id).await?;
 
-        // Check if file is public - if so, allow access regardless of authentication
-        if !sfile.is_public {
-            // File is private, require authentication and permission checking
-            let auth_context = match auth_context {
-                Some(Extension(ctx)) => ctx,
-                None => {
-                    return Err(ServerError::AuthenticationError {
-                        message: "Authentication required to access private files".to_string(),
-                    })
-                }
-            };
-
-            // First check if user is the owner (direct ownership via sfiles.user_id)
-            let is_owner = query!("SELECT user_id FROM sfiles WHERE id = $1", sfile.id as i64)
-                .fetch_optional(files.db_pool())
-                .await?
-                .map(|row| row.user_id == Some(auth_context.user_id))
-                .unwrap_or(false);
-
-            // If not owner, check ReBAC permissions
-            if !is_owner
-                && !auth_context.has_permission("sfile", Some(sfile.id as i64), Permission::Read)
-            {
-                return Err(ServerError::AuthorizationError {
-                    message: "You don't have permission to access this file".to_string(),
-                });
-            }
-        }
-
-        let media: Media = files.get_media(&path, target_user_id).await?;
+        authorize_file_read(
+            &files,
+            auth_context.as_ref().map(|Extension(ctx)| ctx),
+            &sfile,
+        )
+        .await?;
 
-        let stream = media.reader_stream().await?;
-        let body = Body::from_stream(stream);
-        let mut res = Response::new(body);
+        let media: Media = match user_query.version {
+            Some(version) => files.version_media(sfile.id as i64, version).await?,
+            None => files.get_media(&path, target_user_id).await?,
+        };
 
         // error should be propogated from the storage.get_media call,
         // since there it has a directory or not check.
         let file_name = path.file_name().expect("Should not have gotten here.");
 
-        let mime_type = mime_guess::from_path(&file_name)
-            .first_raw()
-            .unwrap_or("application/octet-stream");
-        res.headers_mut()
-            .append(header::CONTENT_TYPE, HeaderValue::from_static(mime_type));
-
-        res.headers_mut().append(
-            header::CONTENT_DISPOSITION,
-            HeaderValue::from_str(&format!("inline; filename=\"{file_name}\"")).map_err(|_e| {
-                ServerError::InternalError {
-                    message: "Parse error".to_string(),
-                }
-            })?,
-        );
-
-        res.headers_mut()
-            .append(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        let range = headers
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| range::parse_range_header(v, media.file_size as u64))
+            .unwrap_or(RangeRequest::Full);
 
-        Ok(res)
+        media_response(files.blobs().clone(), media, &file_name, range).await
     } else {
         // For directory listing, check if user has read permission for the directory
         let sfile = files.get_sfile(&path, target_user_id).await?;
@@ -357,7 +839,7 @@ pub async fn get_file_or_list_dir(
             // Check if directory is public - if so, allow access regardless of authentication
             if !sfile.is_public {
                 // Directory is private, require authentication and permission checking
-                let auth_context = match auth_context {
+                let auth_context = match auth_context.as_ref() {
                     Some(Extension(ctx)) => ctx,
                     None => {
                         return Err(ServerError::AuthenticationError {
@@ -388,6 +870,18 @@ pub async fn get_file_or_list_dir(
                 }
             }
         }
+
+        if let Some(format) = user_query.archive {
+            return archive_dir(
+                &files,
+                auth_context.as_ref().map(|Extension(ctx)| ctx),
+                &path,
+                target_user_id,
+                format,
+            )
+            .await;
+        }
+
         let list = files
             .list_dir(&path, user_id, target_user_id)
             .await?
@@ -396,11 +890,184 @@ pub async fn get_file_or_list_dir(
     }
 }
 
+/// Streams the directory at `path` as an archive of everything in it the requester may read.
+/// Directories they can't read are left out along with everything under them.
+async fn archive_dir(
+    files: &FileController,
+    auth_context: Option<&AuthContext>,
+    path: &VirtualPath,
+    target_user_id: i64,
+    format: ArchiveFormat,
+) -> ServerResult<Response> {
+    let base = format!("{path}/");
+    let mut hidden: Vec<String> = Vec::new();
+    let mut entries = Vec::new();
+
+    for (sfile, media) in files.walk_dir(path, target_user_id).await? {
+        if hidden.iter().any(|dir| sfile.full_path.starts_with(dir)) {
+            continue;
+        }
+        if authorize_file_read(files, auth_context, &sfile)
+            .await
+            .is_err()
+        {
+            if sfile.is_dir {
+                hidden.push(format!("{}/", sfile.full_path));
+            }
+            continue;
+        }
+
+        let relative = sfile
+            .full_path
+            .strip_prefix(&base)
+            .unwrap_or(&sfile.top_level_name)
+            .to_string();
+        entries.push(ArchiveEntry {
+            path: relative,
+            media,
+            modified_at: sfile.modified_at,
+        });
+    }
+
+    let name = path.name().unwrap_or_else(|| "root".to_string());
+    Ok(archive_response(
+        files.blobs().clone(),
+        format,
+        &name,
+        entries,
+    ))
+}
+
+/// Builds the response for downloading a file, honoring the Range header.
+/// A single range gets a plain 206, multiple ranges get a multipart/byteranges body,
+/// and an unsatisfiable Range header gets a 416.
+async fn media_response(
+    blobs: Blobs,
+    media: Media,
+    file_name: &str,
+    range: RangeRequest,
+) -> ServerResult<Response> {
+    let file_size = media.file_size as u64;
+    let key = media.blob_key();
+
+    let mime_type = mime_guess::from_path(file_name)
+        .first_raw()
+        .unwrap_or("application/octet-stream");
+
+    let mut res = match range {
+        RangeRequest::Full => {
+            let stream = blobs.get(&key).await?;
+            let mut res = Response::new(Body::from_stream(stream));
+            res.headers_mut()
+                .append(header::CONTENT_TYPE, HeaderValue::from_static(mime_type));
+            res.headers_mut()
+                .append(header::CONTENT_LENGTH, HeaderValue::from(file_size));
+            res
+        }
+        RangeRequest::Unsatisfiable => {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            res.headers_mut().append(
+                header::CONTENT_RANGE,
+                header_value(&format!("bytes */{file_size}"))?,
+            );
+            res.headers_mut()
+                .append(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            return Ok(res);
+        }
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            let stream = blobs.get_range(&key, range.start, range.len()).await?;
+            let mut res = Response::new(Body::from_stream(stream));
+            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+            res.headers_mut()
+                .append(header::CONTENT_TYPE, HeaderValue::from_static(mime_type));
+            res.headers_mut()
+                .append(header::CONTENT_LENGTH, HeaderValue::from(range.len()));
+            res.headers_mut().append(
+                header::CONTENT_RANGE,
+                header_value(&range.content_range(file_size))?,
+            );
+            res
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = format!("ocloud_{}", nanoid::nanoid!());
+
+            // Each part is: the part headers, the bytes, then a CRLF.
+            let parts: Vec<(ByteRange, Bytes)> = ranges
+                .into_iter()
+                .map(|range| {
+                    let part_header = format!(
+                        "--{boundary}\r\n\
+                        Content-Type: {mime_type}\r\n\
+                        Content-Range: {}\r\n\r\n",
+                        range.content_range(file_size)
+                    );
+                    (range, Bytes::from(part_header))
+                })
+                .collect();
+            let closing = Bytes::from(format!("--{boundary}--\r\n"));
+
+            let content_length = parts
+                .iter()
+                .map(|(range, part_header)| part_header.len() as u64 + range.len() + 2)
+                .sum::<u64>()
+                + closing.len() as u64;
+
+            let stream = async_stream::stream! {
+                for (range, part_header) in parts {
+                    yield Ok(part_header);
+                    let mut reader = match blobs.get_range(&key, range.start, range.len()).await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    };
+                    while let Some(chunk) = reader.next().await {
+                        yield chunk.map_err(ServerError::from);
+                    }
+                    yield Ok(Bytes::from_static(b"\r\n"));
+                }
+                yield Ok(closing);
+            };
+
+            let mut res = Response::new(Body::from_stream(stream));
+            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+            res.headers_mut().append(
+                header::CONTENT_TYPE,
+                header_value(&format!("multipart/byteranges; boundary={boundary}"))?,
+            );
+            res.headers_mut()
+                .append(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+            res
+        }
+    };
+
+    res.headers_mut().append(
+        header::CONTENT_DISPOSITION,
+        header_value(&format!("inline; filename=\"{file_name}\""))?,
+    );
+
+    res.headers_mut()
+        .append(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    Ok(res)
+}
+
+fn header_value(value: &str) -> ServerResult<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|_e| ServerError::InternalError {
+        message: "Parse error".to_string(),
+    })
+}
+
 pub async fn delete_file(
     Extension(auth_context): Extension<AuthContext>,
     State(files): State<FileController>,
     Path(path): Path<VirtualPath>,
 ) -> ServerResult<()> {
+    auth_context.require_path(&path)?;
+
     // Check if user has delete permission for this file
     let sfile = files.get_sfile(&path, auth_context.user_id).await?;
 
@@ -430,10 +1097,10 @@ pub async fn set_permissions_and_visibility(
     State(files): State<FileController>,
     Json(request): Json<FilePermissionRequest>,
 ) -> ServerResult<Json<SFile>> {
+    auth_context.require_path(&request.path)?;
+
     // Get the file first to verify it exists and check permissions
-    let sfile = files
-        .get_sfile(&request.path, auth_context.user_id)
-        .await?;
+    let sfile = files.get_sfile(&request.path, auth_context.user_id).await?;
 
     // First check if user is the owner (direct ownership via sfiles.user_id)
     let is_owner = query!("SELECT user_id FROM sfiles WHERE id = $1", sfile.id as i64)
@@ -493,9 +1160,7 @@ pub async fn set_permissions_and_visibility(
             }
         }
         // Refetch the file to get updated information
-        updated_sfile = files
-            .get_sfile(&request.path, auth_context.user_id)
-            .await?;
+        updated_sfile = files.get_sfile(&request.path, auth_context.user_id).await?;
     }
 
     Ok(Json(updated_sfile))
@@ -513,11 +1178,7 @@ pub async fn change_file_visibility(
         public: Some(visibility_info.public),
         permissions: None,
     };
-    
-    set_permissions_and_visibility(
-        Extension(auth_context),
-        State(files),
-        Json(unified_request),
-    )
-    .await
+
+    set_permissions_and_visibility(Extension(auth_context), State(files), Json(unified_request))
+        .await
 }
@@ -1,4 +1,7 @@
+pub mod admin;
 pub mod auth;
 pub mod files;
 pub mod stream;
+pub mod trash;
+pub mod versions;
 pub mod ws;
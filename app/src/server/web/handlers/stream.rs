@@ -1 +1,103 @@
-
+use axum::{
+    body::Body,
+    extract::{Path, Query, RawQuery, State},
+    http::{header, HeaderValue},
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+
+use super::files::{authorize_file_read, resolve_target_user, UserQuery};
+use crate::server::{
+    controllers::{files::FileController, stream::StreamController},
+    error::{ServerError, ServerResult},
+    models::{auth::AuthContext, files::VirtualPath},
+    remux,
+    web::middleware::optional_auth,
+};
+
+#[derive(Clone)]
+pub struct StreamState {
+    pub file_controller: FileController,
+    pub stream_controller: StreamController,
+}
+
+pub fn routes(file_controller: FileController, stream_controller: StreamController) -> Router {
+    let state = StreamState {
+        file_controller,
+        stream_controller,
+    };
+
+    // Public like GET /files, the handler checks if the file is public
+    Router::new()
+        .route("/stream/*path", get(stream))
+        .layer(axum::middleware::from_fn(optional_auth))
+        .with_state(state)
+}
+
+/// Serves a video as HLS. The last path component picks what to serve:
+/// `root/videos/cat.mp4/index.m3u8` is the playlist, and the playlist
+/// points at `init.mp4` and `0.m4s`, `1.m4s`... next to it.
+pub async fn stream(
+    auth_context: Option<Extension<AuthContext>>,
+    Path(path): Path<String>,
+    Query(user_query): Query<UserQuery>,
+    RawQuery(raw_query): RawQuery,
+    State(state): State<StreamState>,
+) -> ServerResult<Response> {
+    let (file_path, resource) = path.rsplit_once('/').ok_or(ServerError::PathDoesntExist)?;
+    let file_path =
+        VirtualPath::try_from_string(file_path).map_err(|e| ServerError::ValidationError {
+            message: e.to_string(),
+        })?;
+    file_path.err_if_dir()?;
+
+    let auth_context = auth_context.as_ref().map(|Extension(ctx)| ctx);
+    if let Some(ctx) = auth_context {
+        ctx.require_path(&file_path)?;
+    }
+    let target_user_id = resolve_target_user(auth_context, &user_query)?;
+
+    let files = &state.file_controller;
+    let sfile = files.get_sfile(&file_path, target_user_id).await?;
+    authorize_file_read(files, auth_context, &sfile).await?;
+
+    let media = files.get_media(&file_path, target_user_id).await?;
+
+    if resource == remux::PLAYLIST_FILE {
+        let playlist = state
+            .stream_controller
+            .playlist(&media, raw_query.as_deref())
+            .await?;
+
+        let mut res = Response::new(Body::from(playlist));
+        res.headers_mut().append(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/vnd.apple.mpegurl"),
+        );
+        return Ok(res);
+    }
+
+    let segment_path = state
+        .stream_controller
+        .segment_path(&media, resource)
+        .await?;
+
+    let file = File::open(&segment_path).await?;
+    let len = file.metadata().await?.len();
+
+    let mut res = Response::new(Body::from_stream(ReaderStream::new(file)));
+    res.headers_mut()
+        .append(header::CONTENT_TYPE, HeaderValue::from_static("video/mp4"));
+    res.headers_mut()
+        .append(header::CONTENT_LENGTH, HeaderValue::from(len));
+    // Segments never change for a given file
+    res.headers_mut().append(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=31536000, immutable"),
+    );
+
+    Ok(res)
+}
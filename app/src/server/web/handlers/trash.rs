@@ -0,0 +1,74 @@
+use axum::{
+    extract::{Path, State},
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::server::{
+    controllers::files::FileController,
+    error::ServerResult,
+    models::{
+        auth::AuthContext,
+        files::{SFile, TrashedFile, VirtualPath},
+    },
+    web::middleware::require_auth,
+};
+
+pub fn routes(controller: FileController) -> Router {
+    Router::new()
+        .route("/trash", get(list_trash).delete(empty_trash))
+        .route("/trash/:id", delete(purge_file))
+        .route("/trash/:id/restore", post(restore_file))
+        .layer(axum::middleware::from_fn(require_auth))
+        .with_state(controller)
+}
+
+#[derive(Deserialize, Default)]
+pub struct RestoreInfo {
+    // Somewhere else to put the file, if its old path is taken
+    pub to: Option<VirtualPath>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PurgeResult {
+    pub purged: u64,
+}
+
+async fn list_trash(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+) -> ServerResult<Json<Vec<TrashedFile>>> {
+    Ok(Json(files.list_trash(auth_context.user_id).await?))
+}
+
+/// Puts the file back where it was, or wherever `to` says.
+async fn restore_file(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(id): Path<i64>,
+    info: Option<Json<RestoreInfo>>,
+) -> ServerResult<Json<SFile>> {
+    let Json(info) = info.unwrap_or_default();
+    files
+        .restore_from_trash(id, info.to, auth_context.user_id)
+        .await
+        .map(Json)
+}
+
+async fn purge_file(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(id): Path<i64>,
+) -> ServerResult<Json<PurgeResult>> {
+    let purged = files.purge_trash(auth_context.user_id, Some(id)).await?;
+    Ok(Json(PurgeResult { purged }))
+}
+
+async fn empty_trash(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+) -> ServerResult<Json<PurgeResult>> {
+    let purged = files.purge_trash(auth_context.user_id, None).await?;
+    Ok(Json(PurgeResult { purged }))
+}
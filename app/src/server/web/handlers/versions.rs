@@ -0,0 +1,55 @@
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::Deserialize;
+
+use crate::server::{
+    controllers::files::FileController,
+    error::ServerResult,
+    models::{
+        auth::AuthContext,
+        files::{FileVersion, SFile, VirtualPath},
+    },
+    web::middleware::require_auth,
+};
+
+pub fn routes(controller: FileController) -> Router {
+    Router::new()
+        .route("/versions/*path", get(list_versions).post(rollback))
+        .layer(axum::middleware::from_fn(require_auth))
+        .with_state(controller)
+}
+
+#[derive(Deserialize)]
+pub struct RollbackInfo {
+    // The id of the version to go back to, from `GET /versions/*path`
+    pub version: i64,
+}
+
+async fn list_versions(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(path): Path<VirtualPath>,
+) -> ServerResult<Json<Vec<FileVersion>>> {
+    auth_context.require_path(&path)?;
+    files
+        .list_versions(&path, auth_context.user_id)
+        .await
+        .map(Json)
+}
+
+/// Gives the file the contents of an older version, as a new version.
+async fn rollback(
+    Extension(auth_context): Extension<AuthContext>,
+    State(files): State<FileController>,
+    Path(path): Path<VirtualPath>,
+    Json(info): Json<RollbackInfo>,
+) -> ServerResult<Json<SFile>> {
+    auth_context.require_path(&path)?;
+    files
+        .rollback(&path, info.version, auth_context.user_id)
+        .await
+        .map(Json)
+}
@@ -1,5 +1,5 @@
 use axum::extract::Request;
-use axum::http::{HeaderValue, StatusCode};
+use axum::http::{HeaderValue, Method, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use std::collections::HashSet;
@@ -11,7 +11,11 @@ use std::time::Instant;
 use tracing::info_span;
 use uuid::Uuid;
 
-use crate::server::{controllers::auth::AuthController, error::ServerError};
+use crate::server::{
+    controllers::auth::AuthController,
+    error::{ServerError, ServerResult},
+    models::auth::{AuthContext, TokenScope, API_TOKEN_PREFIX},
+};
 
 pub async fn trace_request(mut request: Request, next: Next) -> Response {
     let start = Instant::now();
@@ -100,121 +104,34 @@ impl RequireAuth {
             return Ok(next.run(request).await);
         }
 
-        // Get auth controller from extensions
-        let auth_controller = match request.extensions().get::<AuthController>() {
-            Some(controller) => controller.clone(),
-            None => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Auth controller not available",
-                )
-                    .into_response());
-            }
-        };
-
-        // Extract session ID from Authorization header
-        let session_id = match extract_session_id(&request) {
-            Some(id) => id,
-            None => {
-                return Err((
-                    StatusCode::UNAUTHORIZED,
-                    "Missing or invalid authorization header",
-                )
-                    .into_response());
-            }
-        };
-
-        // Validate session
-        let (user, _session) = match auth_controller.validate_session(session_id).await {
-            Ok(result) => result,
-            Err(ServerError::AuthenticationError { .. }) => {
-                return Err(
-                    (StatusCode::UNAUTHORIZED, "Invalid or expired session").into_response()
-                );
-            }
-            Err(e) => {
-                tracing::error!("Auth validation error: {}", e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Authentication service error",
-                )
-                    .into_response());
-            }
-        };
-
-        // Build auth context
-        let auth_context = match auth_controller.build_auth_context(user.id).await {
-            Ok(context) => context,
-            Err(e) => {
-                tracing::error!("Failed to build auth context: {}", e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to load user permissions",
-                )
-                    .into_response());
-            }
-        };
-
-        // Add auth context and session ID to request extensions
-        request.extensions_mut().insert(auth_context);
-        request.extensions_mut().insert(session_id);
+        authenticate(&mut request).await?;
 
-        Ok(next.run(request).await)
+        Ok::<_, Response>(next.run(request).await)
     }
 }
 
-/// Extract session ID from Authorization header
-/// Expected format: "Bearer <session_id>"
-fn extract_session_id(request: &Request) -> Option<Uuid> {
-    let auth_header = request.headers().get("authorization")?.to_str().ok()?;
-
-    if !auth_header.starts_with("Bearer ") {
-        return None;
-    }
+/// What the Authorization header holds
+enum Credentials {
+    Session(Uuid),
+    ApiToken(String),
+}
 
+/// Extract credentials from Authorization header
+/// Expected format: "Bearer <session_id>" or "Bearer oc_<token>"
+fn extract_credentials(request: &Request) -> Option<Credentials> {
+    let auth_header = request.headers().get("authorization")?.to_str().ok()?;
     let token = auth_header.strip_prefix("Bearer ")?;
-    Uuid::parse_str(token).ok()
-}
 
-/// Session-based authentication middleware function
-/// Middleware that optionally extracts auth context without requiring it
-pub async fn optional_auth(mut request: Request, next: Next) -> Response {
-    // Get auth controller from extensions
-    if let Some(auth_controller) = request.extensions().get::<AuthController>() {
-        let auth_controller = auth_controller.clone();
-
-        // Try to extract authorization header
-        if let Some(auth_header) = request.headers().get("authorization") {
-            if let Ok(auth_str) = auth_header.to_str() {
-                if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                    if let Ok(session_uuid) = token.parse::<Uuid>() {
-                        // Try to validate session and build auth context
-                        if let Ok((user, _session)) =
-                            auth_controller.validate_session(session_uuid).await
-                        {
-                            if let Ok(auth_context) =
-                                auth_controller.build_auth_context(user.id).await
-                            {
-                                // Add auth context to request extensions
-                                request.extensions_mut().insert(auth_context);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    if token.starts_with(API_TOKEN_PREFIX) {
+        Some(Credentials::ApiToken(token.to_string()))
+    } else {
+        Uuid::parse_str(token).ok().map(Credentials::Session)
     }
-
-    next.run(request).await
 }
 
-pub async fn require_auth(mut request: Request, next: Next) -> Result<Response, impl IntoResponse> {
-    let path = request.uri().path();
-
-    // Skip authentication for excluded paths
-    if path == "/auth/register" || path == "/auth/login" {
-        return Ok(next.run(request).await);
-    }
+/// Validates the request's credentials, and adds the auth context (and the
+/// session ID for sessions) to its extensions
+async fn authenticate(request: &mut Request) -> Result<(), Response> {
     // Get auth controller from extensions
     let auth_controller = match request.extensions().get::<AuthController>() {
         Some(controller) => controller.clone(),
@@ -227,9 +144,8 @@ pub async fn require_auth(mut request: Request, next: Next) -> Result<Response,
         }
     };
 
-    // Extract session ID from Authorization header
-    let session_id = match extract_session_id(&request) {
-        Some(id) => id,
+    let credentials = match extract_credentials(request) {
+        Some(credentials) => credentials,
         None => {
             return Err((
                 StatusCode::UNAUTHORIZED,
@@ -239,11 +155,25 @@ pub async fn require_auth(mut request: Request, next: Next) -> Result<Response,
         }
     };
 
-    // Validate session
-    let (user, _session) = match auth_controller.validate_session(session_id).await {
+    // Validate session or token
+    let validated = match &credentials {
+        Credentials::Session(session_id) => auth_controller
+            .validate_session(*session_id)
+            .await
+            .map(|(user, _session)| (user, None)),
+        Credentials::ApiToken(token) => auth_controller
+            .validate_api_token(token)
+            .await
+            .map(|(user, api_token)| (user, Some(api_token))),
+    };
+    let (user, api_token) = match validated {
         Ok(result) => result,
         Err(ServerError::AuthenticationError { .. }) => {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid or expired session").into_response());
+            let message = match credentials {
+                Credentials::Session(_) => "Invalid or expired session",
+                Credentials::ApiToken(_) => "Invalid or expired token",
+            };
+            return Err((StatusCode::UNAUTHORIZED, message).into_response());
         }
         Err(e) => {
             tracing::error!("Auth validation error: {}", e);
@@ -256,7 +186,7 @@ pub async fn require_auth(mut request: Request, next: Next) -> Result<Response,
     };
 
     // Build auth context
-    let auth_context = match auth_controller.build_auth_context(user.id).await {
+    let mut auth_context = match auth_controller.build_auth_context(user.id).await {
         Ok(context) => context,
         Err(e) => {
             tracing::error!("Failed to build auth context: {}", e);
@@ -268,9 +198,79 @@ pub async fn require_auth(mut request: Request, next: Next) -> Result<Response,
         }
     };
 
+    if let Some(api_token) = &api_token {
+        auth_context.restrict_to(api_token);
+        check_token_route(&auth_context, request.method(), request.uri().path())
+            .map_err(IntoResponse::into_response)?;
+    }
+
     // Add auth context and session ID to request extensions
     request.extensions_mut().insert(auth_context);
-    request.extensions_mut().insert(session_id);
+    if let Credentials::Session(session_id) = credentials {
+        request.extensions_mut().insert(session_id);
+    }
+
+    Ok(())
+}
+
+/// Routes that work on paths, which handlers check against a token's path prefix
+const PATH_ROUTES: [&str; 5] = ["/files", "/uploads", "/stream/", "/versions/", "/auth/me"];
+
+/// Checks that a token's scopes cover the request. Owners skip ReBAC checks for
+/// their own files, so this is what keeps e.g. a read token from deleting them.
+fn check_token_route(auth_context: &AuthContext, method: &Method, path: &str) -> ServerResult<()> {
+    let scope = if path.starts_with("/admin") {
+        TokenScope::Admin
+    } else if (path.starts_with("/auth/permissions/") && method != Method::GET)
+        || (path == "/files" && method == Method::PATCH)
+    {
+        TokenScope::Share
+    } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        TokenScope::Read
+    } else if method == Method::DELETE {
+        TokenScope::Delete
+    } else {
+        TokenScope::Write
+    };
+    auth_context.require_scope(scope)?;
+
+    if auth_context.path_prefix.is_some()
+        && !PATH_ROUTES.iter().any(|route| path.starts_with(route))
+    {
+        return Err(ServerError::AuthorizationError {
+            message: "Tokens limited to a path can only be used for files".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Session-based authentication middleware function
+/// Middleware that optionally extracts auth context without requiring it
+pub async fn optional_auth(mut request: Request, next: Next) -> Response {
+    if request.extensions().get::<AuthController>().is_some()
+        && extract_credentials(&request).is_some()
+    {
+        match authenticate(&mut request).await {
+            // A token without the scope for this is turned away
+            Err(res) if res.status() == StatusCode::FORBIDDEN => return res,
+            // Otherwise carry on as if no credentials were sent
+            _ => {}
+        }
+    }
+
+    next.run(request).await
+}
+
+pub async fn require_auth(mut request: Request, next: Next) -> Result<Response, impl IntoResponse> {
+    let path = request.uri().path();
+
+    // Skip authentication for excluded paths
+    if path == "/auth/register" || path == "/auth/login" {
+        return Ok(next.run(request).await);
+    }
+
+    authenticate(&mut request).await?;
 
-    Ok(next.run(request).await)
+    Ok::<_, Response>(next.run(request).await)
 }
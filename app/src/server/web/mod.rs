@@ -1,3 +1,5 @@
+pub mod archive;
 pub mod handlers;
 pub mod middleware;
+pub mod range;
 pub mod routes;
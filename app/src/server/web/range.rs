@@ -0,0 +1,216 @@
+// HTTP Range header parsing (RFC 9110, section 14).
+// Only the "bytes" unit is supported, anything else is ignored
+// and the full representation is served instead.
+
+/// More ranges than this in one request are treated as abuse,
+/// and the whole file is served instead.
+const MAX_RANGES: usize = 32;
+
+/// An inclusive byte range, already clamped to the size of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    // Can't actually happen for ranges that came out of the parser
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    /// Value for the Content-Range header, ex. `bytes 0-499/1234`
+    pub fn content_range(&self, file_size: u64) -> String {
+        format!("bytes {}-{}/{file_size}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No (valid) Range header, serve the whole file.
+    Full,
+    /// One or more satisfiable ranges, sorted and coalesced.
+    Partial(Vec<ByteRange>),
+    /// The header was valid but none of the ranges overlap the file.
+    Unsatisfiable,
+}
+
+/// Parses the value of a Range header against a file of `file_size` bytes.
+/// Syntactically invalid headers are ignored (as the RFC says), which results in
+/// `RangeRequest::Full`.
+pub fn parse_range_header(header: &str, file_size: u64) -> RangeRequest {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(s) => s,
+        None => return RangeRequest::Full,
+    };
+
+    let mut ranges = Vec::new();
+    let mut spec_count = 0;
+
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        spec_count += 1;
+        if spec_count > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+
+        let (first, last) = match spec.split_once('-') {
+            Some(parts) => parts,
+            None => return RangeRequest::Full,
+        };
+
+        let range = if first.is_empty() {
+            // suffix range: the last N bytes
+            let suffix_len: u64 = match last.parse() {
+                Ok(n) => n,
+                Err(_) => return RangeRequest::Full,
+            };
+            if suffix_len == 0 || file_size == 0 {
+                continue;
+            }
+            ByteRange {
+                start: file_size.saturating_sub(suffix_len),
+                end: file_size - 1,
+            }
+        } else {
+            let start: u64 = match first.parse() {
+                Ok(n) => n,
+                Err(_) => return RangeRequest::Full,
+            };
+            let end: Option<u64> = if last.is_empty() {
+                None
+            } else {
+                match last.parse() {
+                    Ok(n) => Some(n),
+                    Err(_) => return RangeRequest::Full,
+                }
+            };
+            if end.is_some_and(|end| end < start) {
+                return RangeRequest::Full;
+            }
+            if start >= file_size {
+                continue;
+            }
+            ByteRange {
+                start,
+                end: end.map_or(file_size - 1, |end| end.min(file_size - 1)),
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    if spec_count == 0 {
+        return RangeRequest::Full;
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+/// Sorts the ranges and merges any that overlap or are adjacent,
+/// so a client can't make us send the same bytes over and over.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(prev) if range.start <= prev.end.saturating_add(1) => {
+                prev.end = prev.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn test_single_ranges() {
+        assert_eq!(
+            parse_range_header("bytes=0-499", 1000),
+            RangeRequest::Partial(vec![r(0, 499)])
+        );
+        assert_eq!(
+            parse_range_header("bytes=500-", 1000),
+            RangeRequest::Partial(vec![r(500, 999)])
+        );
+        assert_eq!(
+            parse_range_header("bytes=-100", 1000),
+            RangeRequest::Partial(vec![r(900, 999)])
+        );
+        // end past the file gets clamped
+        assert_eq!(
+            parse_range_header("bytes=900-5000", 1000),
+            RangeRequest::Partial(vec![r(900, 999)])
+        );
+        // suffix longer than the file is the whole file
+        assert_eq!(
+            parse_range_header("bytes=-5000", 1000),
+            RangeRequest::Partial(vec![r(0, 999)])
+        );
+        assert_eq!(r(0, 499).len(), 500);
+        assert_eq!(r(0, 499).content_range(1000), "bytes 0-499/1000");
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        assert_eq!(
+            parse_range_header("bytes=500-599, 0-99", 1000),
+            RangeRequest::Partial(vec![r(0, 99), r(500, 599)])
+        );
+        // overlapping and adjacent ranges are merged
+        assert_eq!(
+            parse_range_header("bytes=0-99,50-199,200-299,-10", 1000),
+            RangeRequest::Partial(vec![r(0, 299), r(990, 999)])
+        );
+        // unsatisfiable ranges are dropped if any others are satisfiable
+        assert_eq!(
+            parse_range_header("bytes=0-9,2000-3000", 1000),
+            RangeRequest::Partial(vec![r(0, 9)])
+        );
+    }
+
+    #[test]
+    fn test_unsatisfiable_ranges() {
+        assert_eq!(
+            parse_range_header("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range_header("bytes=-0", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range_header("bytes=0-10", 0),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn test_invalid_ranges_are_ignored() {
+        assert_eq!(parse_range_header("items=0-10", 1000), RangeRequest::Full);
+        assert_eq!(parse_range_header("bytes=", 1000), RangeRequest::Full);
+        assert_eq!(parse_range_header("bytes=abc", 1000), RangeRequest::Full);
+        assert_eq!(parse_range_header("bytes=10-5", 1000), RangeRequest::Full);
+        assert_eq!(parse_range_header("bytes=1-x", 1000), RangeRequest::Full);
+
+        let too_many = format!("bytes={}", vec!["0-1"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse_range_header(&too_many, 1000), RangeRequest::Full);
+    }
+}
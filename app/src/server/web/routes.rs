@@ -1,9 +1,9 @@
-use axum::http::{self, HeaderValue, Method};
+use axum::http::{self, HeaderName, HeaderValue, Method};
 use axum::routing::get;
 use axum::Router;
 use tower_http::cors::CorsLayer;
 
-use super::handlers::{auth, files, ws};
+use super::handlers::{admin, auth, files, stream, trash, versions, ws};
 use crate::server::{
     controllers::{files::FileController, websocket::WebSocketController},
     ServerState,
@@ -16,17 +16,44 @@ pub async fn routes(
 ) -> Router {
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_methods([
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PATCH,
+            Method::DELETE,
+        ])
         .allow_headers([
             http::header::CONTENT_TYPE,
             http::header::AUTHORIZATION,
             http::header::ACCEPT,
+            HeaderName::from_static("tus-resumable"),
+            HeaderName::from_static("upload-length"),
+            HeaderName::from_static("upload-offset"),
+            HeaderName::from_static("upload-metadata"),
+        ])
+        // So browser tus clients can read them
+        .expose_headers([
+            http::header::LOCATION,
+            HeaderName::from_static("tus-resumable"),
+            HeaderName::from_static("upload-length"),
+            HeaderName::from_static("upload-offset"),
         ])
         .allow_credentials(true);
 
     let mut router = Router::new()
         .nest("/", files::routes(controller.clone()))
-        .nest("/", auth::routes(server_state.auth_controller.clone()))
+        .nest(
+            "/",
+            auth::routes(server_state.auth_controller.clone(), controller.clone()),
+        )
+        .nest(
+            "/",
+            stream::routes(controller.clone(), server_state.stream_controller.clone()),
+        )
+        .nest("/", trash::routes(controller.clone()))
+        .nest("/", versions::routes(controller.clone()))
+        .nest("/", admin::routes(server_state.scrub_controller.clone()))
         .route("/ping", get(ping))
         .route("/health", get(health_check))
         .layer(axum::Extension(server_state.auth_controller.clone()));
@@ -36,7 +63,9 @@ pub async fn routes(
         router = router.nest("/", ws::routes(ws_ctrl, controller.clone(), server_state));
     }
 
-    router.layer(cors)
+    router
+        .layer(cors)
+        .layer(axum::middleware::from_fn(files::tus_discovery))
     // Rate limiting temporarily disabled
     // .layer(middleware::rate_limiting_layer())
 }
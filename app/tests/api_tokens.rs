@@ -0,0 +1,274 @@
+mod common;
+
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use common::{authenticate_random, cleanup_test_database, create_test_db, has_status};
+use ocloud::api::ApiClient;
+use ocloud::server::controllers::auth::AuthController;
+use ocloud::server::models::auth::{CreateTokenRequest, TokenScope};
+
+fn token_request(name: &str, scopes: &[TokenScope]) -> CreateTokenRequest {
+    CreateTokenRequest {
+        name: name.to_string(),
+        scopes: scopes.to_vec(),
+        path_prefix: None,
+        expires_at: None,
+    }
+}
+
+/// A client that authenticates with `token` instead of a session
+async fn token_client(db_pool: &sqlx::PgPool, token: &str) -> ApiClient {
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    client.set_session(token.to_string());
+    client
+}
+
+#[tokio::test]
+async fn tokens_work_until_revoked() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let created = client
+        .create_api_token(&token_request(
+            "backups",
+            &[TokenScope::Read, TokenScope::Write],
+        ))
+        .await
+        .expect("Failed to create token");
+    assert!(created.token.starts_with("oc_"));
+    assert_eq!(
+        created.info.scopes,
+        vec![TokenScope::Read, TokenScope::Write]
+    );
+    assert!(created.info.last_used.is_none());
+
+    // Names are unique per user
+    assert!(has_status(
+        client
+            .create_api_token(&token_request("backups", &[TokenScope::Read]))
+            .await,
+        StatusCode::BAD_REQUEST
+    ));
+
+    let token = token_client(&db_pool, &created.token).await;
+    token
+        .upload_file("root/", "a.txt", b"hello".to_vec())
+        .await
+        .expect("Failed to upload with token");
+    assert_eq!(
+        token.get_file("root/a.txt", None).await.unwrap(),
+        b"hello".to_vec()
+    );
+
+    // Only the hash is kept, so the token itself is never listed
+    let tokens = client
+        .list_api_tokens()
+        .await
+        .expect("Failed to list tokens");
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].name, "backups");
+    assert!(tokens[0].last_used.is_some());
+
+    // Tokens can't manage tokens or log out
+    assert!(has_status(
+        token.list_api_tokens().await,
+        StatusCode::FORBIDDEN
+    ));
+    assert!(has_status(
+        token
+            .create_api_token(&token_request("more", &[TokenScope::Read]))
+            .await,
+        StatusCode::FORBIDDEN
+    ));
+    let mut logout = token_client(&db_pool, &created.token).await;
+    assert!(has_status(logout.logout().await, StatusCode::BAD_REQUEST));
+
+    client.revoke_api_token(created.info.id).await.unwrap();
+    assert!(has_status(
+        token.get_file("root/a.txt", None).await,
+        StatusCode::UNAUTHORIZED
+    ));
+    assert!(has_status(
+        client.revoke_api_token(created.info.id).await,
+        StatusCode::NOT_FOUND
+    ));
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn tokens_only_do_what_their_scopes_allow() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    let user = authenticate_random(&mut client).await;
+    client
+        .upload_file("root/", "a.txt", b"hello".to_vec())
+        .await
+        .unwrap();
+
+    let read_only = client
+        .create_api_token(&token_request("read", &[TokenScope::Read]))
+        .await
+        .unwrap();
+    let token = token_client(&db_pool, &read_only.token).await;
+
+    assert!(token.get_file("root/a.txt", None).await.is_ok());
+    assert!(token.list_directory("root/", None).await.is_ok());
+    assert!(has_status(
+        token.upload_file("root/", "b.txt", b"nope".to_vec()).await,
+        StatusCode::FORBIDDEN
+    ));
+    assert!(has_status(
+        token.delete_file("root/a.txt").await,
+        StatusCode::FORBIDDEN
+    ));
+    assert!(has_status(
+        token.change_file_visibility("root/a.txt", true).await,
+        StatusCode::FORBIDDEN
+    ));
+
+    // Scopes never add to what the user can do
+    let not_admin = client
+        .create_api_token(&token_request("admin", &[TokenScope::Admin]))
+        .await
+        .unwrap();
+    assert!(has_status(
+        token_client(&db_pool, &not_admin.token)
+            .await
+            .scrub_report()
+            .await,
+        StatusCode::FORBIDDEN
+    ));
+
+    AuthController::new(db_pool.clone())
+        .make_admin(user["username"].as_str().unwrap())
+        .await
+        .expect("Failed to make admin");
+    assert!(token_client(&db_pool, &not_admin.token)
+        .await
+        .scrub_report()
+        .await
+        .is_ok());
+    assert!(has_status(
+        token.scrub_report().await,
+        StatusCode::FORBIDDEN
+    ));
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn tokens_stay_inside_their_path_prefix() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+    client
+        .upload_file("root/docs/", "a.txt", b"private".to_vec())
+        .await
+        .unwrap();
+
+    let created = client
+        .create_api_token(&CreateTokenRequest {
+            path_prefix: Some("root/backups".to_string()),
+            ..token_request(
+                "backups",
+                &[TokenScope::Read, TokenScope::Write, TokenScope::Delete],
+            )
+        })
+        .await
+        .unwrap();
+    assert_eq!(created.info.path_prefix.as_deref(), Some("root/backups/"));
+    let token = token_client(&db_pool, &created.token).await;
+
+    token
+        .upload_file("root/backups/2024/", "db.sql", b"dump".to_vec())
+        .await
+        .expect("Failed to upload inside the prefix");
+    assert!(token.list_directory("root/backups/", None).await.is_ok());
+    assert!(token
+        .get_file("root/backups/2024/db.sql", None)
+        .await
+        .is_ok());
+
+    assert!(has_status(
+        token.get_file("root/docs/a.txt", None).await,
+        StatusCode::FORBIDDEN
+    ));
+    assert!(has_status(
+        token.list_directory("root/", None).await,
+        StatusCode::FORBIDDEN
+    ));
+    // Not fooled by a directory that only starts with the same name
+    assert!(has_status(
+        token
+            .upload_file("root/backups2/", "x.txt", b"x".to_vec())
+            .await,
+        StatusCode::FORBIDDEN
+    ));
+    assert!(has_status(
+        token
+            .move_file("root/backups/2024/db.sql", "root/docs/db.sql")
+            .await,
+        StatusCode::FORBIDDEN
+    ));
+    assert!(has_status(
+        token.delete_file("root/docs/a.txt").await,
+        StatusCode::FORBIDDEN
+    ));
+    // The trash isn't under any one path
+    assert!(has_status(token.list_trash().await, StatusCode::FORBIDDEN));
+
+    token
+        .delete_file("root/backups/2024/db.sql")
+        .await
+        .expect("Failed to delete inside the prefix");
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    assert!(has_status(
+        client
+            .create_api_token(&CreateTokenRequest {
+                expires_at: Some(Utc::now() - Duration::minutes(1)),
+                ..token_request("old", &[TokenScope::Read])
+            })
+            .await,
+        StatusCode::BAD_REQUEST
+    ));
+    assert!(has_status(
+        client.create_api_token(&token_request("none", &[])).await,
+        StatusCode::BAD_REQUEST
+    ));
+
+    let created = client
+        .create_api_token(&CreateTokenRequest {
+            expires_at: Some(Utc::now() + Duration::days(30)),
+            ..token_request("ci", &[TokenScope::Read])
+        })
+        .await
+        .unwrap();
+    let token = token_client(&db_pool, &created.token).await;
+    assert!(token.list_directory("root/", None).await.is_ok());
+
+    sqlx::query(
+        "UPDATE api_tokens SET expires_at = (NOW() AT TIME ZONE 'UTC') - INTERVAL '1 minute'",
+    )
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    assert!(has_status(
+        token.list_directory("root/", None).await,
+        StatusCode::UNAUTHORIZED
+    ));
+    // Still listed, so it can be seen to have expired
+    assert_eq!(client.list_api_tokens().await.unwrap().len(), 1);
+
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use async_compression::tokio::bufread::GzipDecoder;
+use async_zip::base::read::mem::ZipFileReader;
+use common::{authenticate_random, cleanup_test_database, create_test_db, unique};
+use futures::StreamExt;
+use ocloud::api::ApiClient;
+use ocloud::server::web::archive::ArchiveFormat;
+use tokio::io::AsyncReadExt;
+
+/// Every entry of a zip, directories with a trailing '/' and no contents
+async fn unzip(archive: Vec<u8>) -> BTreeMap<String, Vec<u8>> {
+    let reader = ZipFileReader::new(archive).await.expect("Invalid zip");
+    let mut entries = BTreeMap::new();
+    for index in 0..reader.file().entries().len() {
+        let name = reader.file().entries()[index]
+            .filename()
+            .as_str()
+            .unwrap()
+            .to_string();
+        let mut contents = Vec::new();
+        reader
+            .reader_with_entry(index)
+            .await
+            .unwrap()
+            .read_to_end_checked(&mut contents)
+            .await
+            .expect("Zip entry doesn't match its checksum");
+        entries.insert(name, contents);
+    }
+    entries
+}
+
+/// Every entry of a tar.gz, directories with a trailing '/' and no contents
+async fn untar(archive: Vec<u8>) -> BTreeMap<String, Vec<u8>> {
+    let mut tar = tokio_tar::Archive::new(GzipDecoder::new(&archive[..]));
+    let mut stream = tar.entries().expect("Invalid tar.gz");
+    let mut entries = BTreeMap::new();
+    while let Some(entry) = stream.next().await {
+        let mut entry = entry.unwrap();
+        let mut name = entry.path().unwrap().to_string_lossy().into_owned();
+        if entry.header().entry_type().is_dir() && !name.ends_with('/') {
+            name.push('/');
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).await.unwrap();
+        entries.insert(name, contents);
+    }
+    entries
+}
+
+#[tokio::test]
+async fn directories_download_as_archives() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let a = unique("a");
+    let b = unique("b");
+    client
+        .upload_file("root/project/", "a.txt", a.clone())
+        .await
+        .unwrap();
+    client
+        .upload_file("root/project/src/", "b.rs", b.clone())
+        .await
+        .unwrap();
+
+    let expected = BTreeMap::from([
+        ("a.txt".to_string(), a.clone()),
+        ("src/".to_string(), Vec::new()),
+        ("src/b.rs".to_string(), b.clone()),
+    ]);
+
+    let zip = client
+        .download_archive("root/project/", ArchiveFormat::Zip, None)
+        .await
+        .expect("Failed to download zip");
+    assert_eq!(unzip(zip).await, expected);
+
+    let tar = client
+        .download_archive("root/project/", ArchiveFormat::TarGz, None)
+        .await
+        .expect("Failed to download tar.gz");
+    assert_eq!(untar(tar).await, expected);
+
+    // The whole tree from the root
+    let zip = client
+        .download_archive("root/", ArchiveFormat::Zip, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        unzip(zip).await.keys().collect::<Vec<_>>(),
+        [
+            "project/",
+            "project/a.txt",
+            "project/src/",
+            "project/src/b.rs"
+        ]
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn archives_only_have_what_the_requester_can_read() {
+    let db_pool = create_test_db().await;
+    let mut alice = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut alice).await;
+    let mut bob = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut bob).await;
+
+    let shown = unique("shown");
+    let hidden = unique("hidden");
+    let alice_id = alice
+        .upload_file("root/shared/", "shown.txt", shown.clone())
+        .await
+        .unwrap()[0]
+        .user_id;
+    alice
+        .upload_file("root/shared/", "hidden.txt", hidden.clone())
+        .await
+        .unwrap();
+    alice
+        .upload_file("root/shared/private/", "public.txt", shown.clone())
+        .await
+        .unwrap();
+
+    for path in [
+        "root/shared/",
+        "root/shared/shown.txt",
+        "root/shared/private/public.txt",
+    ] {
+        alice.change_file_visibility(path, true).await.unwrap();
+    }
+
+    let zip = bob
+        .download_archive("root/shared/", ArchiveFormat::Zip, alice_id)
+        .await
+        .expect("Failed to download a public directory");
+    // A public file in a private directory stays hidden
+    assert_eq!(
+        unzip(zip).await,
+        BTreeMap::from([("shown.txt".to_string(), shown.clone())])
+    );
+
+    // The owner gets everything
+    let tar = alice
+        .download_archive("root/shared/", ArchiveFormat::TarGz, None)
+        .await
+        .unwrap();
+    assert_eq!(untar(tar).await.len(), 4);
+
+    cleanup_test_database(db_pool).await;
+}
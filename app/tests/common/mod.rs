@@ -1,7 +1,14 @@
-use ocloud::api::ApiClient;
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use axum::http::StatusCode;
+use ocloud::api::{ApiClient, ApiError};
 use ocloud::config::SETTINGS;
 use ocloud::server::models::auth::{LoginRequest, RegisterRequest};
+use ocloud::server::models::files::Media;
 use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 
@@ -25,7 +32,6 @@ static INIT: Lazy<()> = Lazy::new(|| {
 
     // Setup tracing
     let default_filter_level = "info".to_string();
-    let subscriber_name = "test".to_string();
 
     if std::env::var("TEST_LOG").is_ok() {
         let subscriber = tracing_subscriber::FmtSubscriber::builder()
@@ -182,15 +188,55 @@ async fn register_and_login(
 
 /// Create multiple authenticated users for testing permissions
 /// Returns a vector of (client, user_info, user_id) tuples
-pub async fn create_multiple_users(db_pool: &PgPool, count: usize) -> Vec<(ApiClient, serde_json::Value, u64)> {
+pub async fn create_multiple_users(
+    db_pool: &PgPool,
+    count: usize,
+) -> Vec<(ApiClient, serde_json::Value, u64)> {
     let mut users = Vec::new();
-    
+
     for _ in 0..count {
         let mut client = ApiClient::new_local(db_pool.clone()).await;
         let user_info = authenticate_random(&mut client).await;
         let user_id = user_info["id"].as_u64().expect("User should have valid ID");
         users.push((client, user_info, user_id));
     }
-    
+
     users
 }
+
+/// Whether the request failed with `expected`
+pub fn has_status<T>(result: Result<T, ApiError>, expected: StatusCode) -> bool {
+    matches!(result, Err(ApiError::Http { status, .. }) if status == expected)
+}
+
+/// The hash the server keeps for `content`
+pub fn sha256(content: &[u8]) -> String {
+    format!("{:X}", Sha256::digest(content))
+}
+
+/// The key of the blob `content` is stored under
+pub fn blob_key(content: &[u8]) -> String {
+    Media::blob_key_for(&sha256(content))
+}
+
+/// Where the local store keeps `content`
+pub fn blob_path(content: &[u8]) -> PathBuf {
+    SETTINGS.directories.files_dir.join(blob_key(content))
+}
+
+/// `len` bytes of a repeating pattern
+pub fn test_content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 13 % 251) as u8).collect()
+}
+
+/// Contents no other test uploads, since tests share the files directory
+pub fn unique(label: &str) -> Vec<u8> {
+    format!("{label} {}", Uuid::new_v4()).into_bytes()
+}
+
+/// Exactly `len` bytes that no other test uploads
+pub fn unique_content(len: usize) -> Vec<u8> {
+    let mut content = Uuid::new_v4().simple().to_string().into_bytes();
+    content.resize(len, b'.');
+    content
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, cleanup_test_database, create_test_db, unique};
+use ocloud::api::{ApiClient, ApiError};
+use ocloud::server::models::files::ConflictPolicy;
+
+async fn media_count(db_pool: &sqlx::PgPool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM media")
+        .fetch_one(db_pool)
+        .await
+        .unwrap()
+}
+
+async fn owns(db_pool: &sqlx::PgPool, user_id: i64, sfile_id: u64) -> bool {
+    sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT 1 FROM resources r
+            JOIN user_resource_relationships u ON u.resource_id = r.id
+            WHERE r.resource_type = 'sfile' AND r.resource_id = $2
+            AND u.user_id = $1 AND u.relationship = 'owner'
+        )",
+    )
+    .bind(user_id)
+    .bind(sfile_id as i64)
+    .fetch_one(db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn directory_trees_are_copied() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let a = unique("a");
+    let b = unique("b");
+    client
+        .upload_file("root/src/", "a.txt", a.clone())
+        .await
+        .unwrap();
+    client
+        .upload_file("root/src/sub/", "b.txt", b.clone())
+        .await
+        .unwrap();
+    let media_before = media_count(&db_pool).await;
+    let usage_before = client.usage().await.unwrap().used_bytes;
+
+    let copy = client
+        .copy_file("root/src", "root/backups/src", ConflictPolicy::Fail)
+        .await
+        .expect("Failed to copy directory");
+    assert!(copy.is_dir);
+    assert_eq!(copy.full_path, "root/backups/src");
+
+    assert_eq!(
+        client
+            .get_file("root/backups/src/a.txt", None)
+            .await
+            .unwrap(),
+        a
+    );
+    assert_eq!(
+        client
+            .get_file("root/backups/src/sub/b.txt", None)
+            .await
+            .unwrap(),
+        b
+    );
+
+    // Nothing new was stored
+    assert_eq!(media_count(&db_pool).await, media_before);
+    assert_eq!(client.usage().await.unwrap().used_bytes, usage_before);
+
+    // Every copied node is owned by the user
+    let user_id = copy.user_id.unwrap();
+    assert!(owns(&db_pool, user_id, copy.id).await);
+    for file in client
+        .list_directory("root/backups/src/", None)
+        .await
+        .unwrap()
+    {
+        assert!(owns(&db_pool, user_id, file.id).await);
+    }
+
+    // The copy is its own file
+    let changed = unique("changed");
+    client
+        .upload_file("root/backups/src/", "a.txt", changed.clone())
+        .await
+        .unwrap();
+    assert_eq!(client.get_file("root/src/a.txt", None).await.unwrap(), a);
+    client.delete_file("root/src/sub/b.txt").await.unwrap();
+    assert_eq!(
+        client
+            .get_file("root/backups/src/sub/b.txt", None)
+            .await
+            .unwrap(),
+        b
+    );
+
+    // Not into itself
+    assert!(client
+        .copy_file("root/src", "root/src/sub/src", ConflictPolicy::Fail)
+        .await
+        .is_err());
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn copy_conflicts_follow_the_policy() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let original = unique("original");
+    let other = unique("other");
+    client
+        .upload_file("root/", "a.txt", original.clone())
+        .await
+        .unwrap();
+    client
+        .upload_file("root/", "b.txt", other.clone())
+        .await
+        .unwrap();
+
+    let result = client
+        .copy_file("root/a.txt", "root/b.txt", ConflictPolicy::Fail)
+        .await;
+    assert!(matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::CONFLICT));
+
+    let renamed = client
+        .copy_file("root/a.txt", "root/b.txt", ConflictPolicy::Rename)
+        .await
+        .unwrap();
+    assert_eq!(renamed.full_path, "root/b (1).txt");
+    let renamed = client
+        .copy_file("root/a.txt", "root/b.txt", ConflictPolicy::Rename)
+        .await
+        .unwrap();
+    assert_eq!(renamed.full_path, "root/b (2).txt");
+
+    // Overwritten files keep what they had as an older version
+    client
+        .copy_file("root/a.txt", "root/b.txt", ConflictPolicy::Overwrite)
+        .await
+        .unwrap();
+    assert_eq!(client.get_file("root/b.txt", None).await.unwrap(), original);
+    assert_eq!(client.list_versions("root/b.txt").await.unwrap().len(), 2);
+
+    // Directories are merged
+    client
+        .upload_file("root/x/", "only-x.txt", other.clone())
+        .await
+        .unwrap();
+    client
+        .upload_file("root/y/", "only-y.txt", other.clone())
+        .await
+        .unwrap();
+    client
+        .upload_file("root/x/", "both.txt", original.clone())
+        .await
+        .unwrap();
+    client
+        .upload_file("root/y/", "both.txt", other.clone())
+        .await
+        .unwrap();
+    client
+        .copy_file("root/x", "root/y", ConflictPolicy::Overwrite)
+        .await
+        .unwrap();
+    let mut names: Vec<String> = client
+        .list_directory("root/y/", None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|f| f.top_level_name)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["both.txt", "only-x.txt", "only-y.txt"]);
+    assert_eq!(
+        client.get_file("root/y/both.txt", None).await.unwrap(),
+        original
+    );
+
+    // A file can't turn into a directory
+    let result = client
+        .copy_file("root/x", "root/a.txt", ConflictPolicy::Overwrite)
+        .await;
+    assert!(matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::CONFLICT));
+
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use common::{
+    authenticate_random, blob_path, cleanup_test_database, create_test_db, sha256, unique,
+};
+use ocloud::api::{ApiClient, ApiError};
+use ocloud::config::SETTINGS;
+use ocloud::server::controllers::files::FileControllerInner;
+use ocloud::server::storage::LocalStore;
+
+async fn media_expiry(
+    db_pool: &sqlx::PgPool,
+    content: &[u8],
+) -> Option<Option<chrono::NaiveDateTime>> {
+    sqlx::query_scalar("SELECT expiring_time FROM media WHERE file_hash = $1")
+        .bind(sha256(content))
+        .fetch_optional(db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn expired_files_are_reaped() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    // Unique contents, since other tests share the files directory
+    let shared = unique("shared");
+    let temporary = unique("temporary");
+
+    client
+        .upload_file("root/", "keep.txt", shared.clone())
+        .await
+        .expect("Failed to upload file");
+    let uploaded = client
+        .upload_file_expiring("root/", "copy.txt", shared.clone(), Some("1h"))
+        .await
+        .expect("Failed to upload file");
+    assert!(uploaded[0].expires_at.is_some());
+    client
+        .upload_file_expiring(
+            "root/temp/",
+            "gone.txt",
+            temporary.clone(),
+            Some("2999-01-01T00:00:00Z"),
+        )
+        .await
+        .expect("Failed to upload file");
+
+    // A file that never expires keeps its media around
+    assert_eq!(media_expiry(&db_pool, &shared).await, Some(None));
+    assert!(media_expiry(&db_pool, &temporary).await.flatten().is_some());
+
+    let files = FileControllerInner::new_no_ws(
+        db_pool.clone(),
+        Arc::new(LocalStore::new(SETTINGS.directories.files_dir.clone())),
+    )
+    .await;
+
+    // Nothing is due yet
+    assert!(files.reap_expired().await.unwrap().is_empty());
+
+    sqlx::query("UPDATE sfiles SET expires_at = (NOW() AT TIME ZONE 'UTC') - INTERVAL '1 minute' WHERE expires_at IS NOT NULL")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    let reaped = files.reap_expired().await.expect("Failed to reap");
+    let paths: Vec<&str> = reaped.iter().map(|e| e.path.as_str()).collect();
+    assert_eq!(paths, ["root/copy.txt", "root/temp/gone.txt"]);
+
+    assert!(client.get_file("root/copy.txt", None).await.is_err());
+    assert!(client.get_file("root/temp/gone.txt", None).await.is_err());
+    assert_eq!(
+        client
+            .get_file("root/keep.txt", None)
+            .await
+            .expect("Failed to get file"),
+        shared
+    );
+
+    assert_eq!(media_expiry(&db_pool, &shared).await, Some(None));
+    assert_eq!(media_expiry(&db_pool, &temporary).await, None);
+    assert!(!blob_path(&temporary).exists());
+
+    assert!(files.reap_expired().await.unwrap().is_empty());
+
+    client.delete_file("root/keep.txt").await.unwrap();
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn bad_expiry_is_rejected() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    for expires in ["tomorrow", "2000-01-01T00:00:00Z", "0s"] {
+        let result = client
+            .upload_file_expiring("root/", "file.txt", b"hello".to_vec(), Some(expires))
+            .await;
+        assert!(
+            matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST),
+            "{expires} should be rejected"
+        );
+    }
+
+    cleanup_test_database(db_pool).await;
+}
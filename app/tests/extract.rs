@@ -0,0 +1,142 @@
+mod common;
+
+use async_compression::tokio::write::GzipEncoder;
+use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use axum::http::StatusCode;
+use common::{authenticate_random, cleanup_test_database, create_test_db, unique};
+use ocloud::api::{ApiClient, ApiError};
+use tokio::io::AsyncWriteExt;
+
+async fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipFileWriter::new(Vec::new());
+    for (name, contents) in entries {
+        let builder = ZipEntryBuilder::new(name.to_string().into(), Compression::Deflate);
+        writer.write_entry_whole(builder, contents).await.unwrap();
+    }
+    writer.close().await.unwrap()
+}
+
+async fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tokio_tar::Builder::new(GzipEncoder::new(Vec::new()));
+    for (name, contents) in entries {
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        // Written by hand, `set_path` won't take the paths these tests need
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_cksum();
+        builder.append(&header, *contents).await.unwrap();
+    }
+    let mut gzip = builder.into_inner().await.unwrap();
+    gzip.shutdown().await.unwrap();
+    gzip.into_inner()
+}
+
+#[tokio::test]
+async fn archives_are_extracted_into_the_directory() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let readme = unique("readme");
+    let main = unique("main");
+    let archive = zip(&[
+        ("project/", b""),
+        ("project/README.md", &readme),
+        ("project/src/main.rs", &main),
+        ("project/empty/", b""),
+    ])
+    .await;
+
+    let created = client
+        .upload_and_extract("root/imports/", "project.zip", archive)
+        .await
+        .expect("Failed to extract zip");
+    assert!(created
+        .iter()
+        .any(|f| f.full_path == "root/imports/project/src/main.rs"));
+
+    assert_eq!(
+        client
+            .get_file("root/imports/project/README.md", None)
+            .await
+            .unwrap(),
+        readme
+    );
+    assert_eq!(
+        client
+            .get_file("root/imports/project/src/main.rs", None)
+            .await
+            .unwrap(),
+        main
+    );
+    assert!(client
+        .list_directory("root/imports/project/empty/", None)
+        .await
+        .unwrap()
+        .is_empty());
+    // The archive itself isn't kept
+    assert!(client
+        .get_file("root/imports/project.zip", None)
+        .await
+        .is_err());
+
+    let notes = unique("notes");
+    let archive = tar_gz(&[("./notes/today.txt", &notes)]).await;
+    client
+        .upload_and_extract("root/imports/", "notes.tar.gz", archive)
+        .await
+        .expect("Failed to extract tar.gz");
+    assert_eq!(
+        client
+            .get_file("root/imports/notes/today.txt", None)
+            .await
+            .unwrap(),
+        notes
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn bad_archives_are_rejected() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    // Nothing from an archive is stored if any of it escapes the directory
+    let archive = zip(&[("fine.txt", b"fine"), ("../../escaped.txt", b"escaped")]).await;
+    let result = client
+        .upload_and_extract("root/imports/", "slip.zip", archive)
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+    let archive = tar_gz(&[("/etc/escaped.txt", b"escaped")]).await;
+    let result = client
+        .upload_and_extract("root/imports/", "slip.tar.gz", archive)
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+    assert!(client
+        .get_file("root/imports/fine.txt", None)
+        .await
+        .is_err());
+    assert!(client.get_file("root/escaped.txt", None).await.is_err());
+
+    let result = client
+        .upload_and_extract("root/imports/", "notes.txt", b"not an archive".to_vec())
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+    let result = client
+        .upload_and_extract("root/imports/", "broken.zip", b"not a zip".to_vec())
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+
+    cleanup_test_database(db_pool).await;
+}
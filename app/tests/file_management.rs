@@ -0,0 +1,44 @@
+mod common;
+
+use common::{authenticate_random, cleanup_test_database, create_test_db};
+use futures::TryStreamExt;
+use ocloud::api::ApiClient;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn directories_are_made_and_files_streamed() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let made = client
+        .make_dirs("root/a/b/c")
+        .await
+        .expect("Failed to make directories");
+    assert!(made.iter().all(|f| f.is_dir));
+    assert!(made.iter().any(|f| f.top_level_name == "c"));
+    let listed = client.list_directory("root/a/b/", None).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].top_level_name, "c");
+
+    let contents = format!("streamed {}", Uuid::new_v4()).repeat(1000);
+    client
+        .upload_file("root/a/b/c/", "big.txt", contents.clone().into_bytes())
+        .await
+        .unwrap();
+
+    let (size, stream) = client
+        .get_file_stream("root/a/b/c/big.txt", None)
+        .await
+        .expect("Failed to get file");
+    assert_eq!(size, Some(contents.len() as u64));
+    let chunks: Vec<_> = stream.try_collect().await.unwrap();
+    assert_eq!(chunks.concat(), contents.as_bytes());
+
+    assert!(client
+        .get_file_stream("root/a/b/c/missing.txt", None)
+        .await
+        .is_err());
+
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use common::{authenticate_random, blob_key, cleanup_test_database, create_test_db, sha256};
+use ocloud::api::ApiClient;
+use ocloud::config::SETTINGS;
+use ocloud::server::controllers::files::{FileController, FileControllerInner};
+use ocloud::server::models::files::{FileUploadInfo, VirtualPath};
+use ocloud::server::storage::LocalStore;
+use uuid::Uuid;
+
+async fn upload(files: &FileController, scratch: &Path, name: &str, content: &[u8], user_id: i64) {
+    let temp_path = scratch.join(format!("upload_{}", Uuid::new_v4()));
+    tokio::fs::write(&temp_path, content).await.unwrap();
+
+    files
+        .finish_upload(FileUploadInfo {
+            file_name: name.to_string(),
+            temp_path,
+            file_size: content.len() as i64,
+            file_hash: sha256(content),
+            vpath: VirtualPath::root(),
+            user_id,
+            expires_at: None,
+        })
+        .await
+        .expect("Failed to upload file");
+}
+
+/// A temp file in `files_dir` that looks like it was last touched two days ago.
+async fn old_temp_file(name: &str) -> PathBuf {
+    let path = SETTINGS.directories.files_dir.join(name);
+    tokio::fs::create_dir_all(&SETTINGS.directories.files_dir)
+        .await
+        .unwrap();
+    let file = std::fs::File::create(&path).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60))
+        .unwrap();
+    path
+}
+
+#[tokio::test]
+async fn fsck_finds_and_repairs_problems() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    let user = authenticate_random(&mut client).await;
+    let user_id = user["id"].as_i64().unwrap();
+
+    // A store of its own, so blobs from other tests don't show up as orphans
+    let scratch = std::env::temp_dir().join(format!("ocloud_fsck_{}", Uuid::new_v4()));
+    let store_root = scratch.join("blobs");
+    tokio::fs::create_dir_all(&store_root).await.unwrap();
+    let files: FileController = Arc::new(
+        FileControllerInner::new_no_ws(
+            db_pool.clone(),
+            Arc::new(LocalStore::new(store_root.clone())),
+        )
+        .await,
+    );
+
+    upload(&files, &scratch, "fine.txt", b"all good", user_id).await;
+    upload(
+        &files,
+        &scratch,
+        "broken.txt",
+        b"contents go missing",
+        user_id,
+    )
+    .await;
+    upload(
+        &files,
+        &scratch,
+        "unused.txt",
+        b"nothing points here",
+        user_id,
+    )
+    .await;
+
+    // A blob with no media row
+    let orphan = blob_key(b"orphan");
+    let orphan_path = store_root.join(&orphan);
+    tokio::fs::create_dir_all(orphan_path.parent().unwrap())
+        .await
+        .unwrap();
+    tokio::fs::write(&orphan_path, b"orphan").await.unwrap();
+
+    // A media row with no blob
+    tokio::fs::remove_file(store_root.join(blob_key(b"contents go missing")))
+        .await
+        .unwrap();
+
+    // A media row with no files
+    sqlx::query("DELETE FROM sfiles WHERE media_id = (SELECT id FROM media WHERE file_hash = $1)")
+        .bind(sha256(b"nothing points here"))
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    // A leftover temp file, and one belonging to a resumable upload still in progress
+    let stale = old_temp_file(&format!("tmp_{}_failed.bin", Uuid::new_v4())).await;
+    let upload = files
+        .create_upload(
+            &VirtualPath::root(),
+            "resumable.bin".into(),
+            100,
+            user_id,
+            None,
+        )
+        .await
+        .expect("Failed to create upload");
+    let in_progress = old_temp_file(&format!("tmp_upload_{}", upload.id)).await;
+
+    let report = files.fsck().await.expect("Failed to run fsck");
+    assert_eq!(report.orphaned_blobs, [orphan.as_str()]);
+    assert_eq!(report.dangling_media.len(), 1);
+    assert_eq!(
+        report.dangling_media[0].file_hash,
+        sha256(b"contents go missing")
+    );
+    assert_eq!(report.broken_sfiles.len(), 1);
+    assert_eq!(report.broken_sfiles[0].1, "root/broken.txt");
+    assert_eq!(report.unreferenced_media.len(), 1);
+    assert_eq!(
+        report.unreferenced_media[0].file_hash,
+        sha256(b"nothing points here")
+    );
+    assert!(report.stale_temp_files.contains(&stale));
+    assert!(!report.stale_temp_files.contains(&in_progress));
+
+    // gc leaves files with missing contents alone
+    let garbage = files.fsck().await.unwrap().garbage_only();
+    assert!(garbage.dangling_media.is_empty());
+    assert!(garbage.broken_sfiles.is_empty());
+    assert_eq!(garbage.orphaned_blobs, [orphan.as_str()]);
+
+    let broken_id = report.broken_sfiles[0].0;
+    files.repair(&report).await.expect("Failed to repair");
+
+    let report = files.fsck().await.expect("Failed to run fsck");
+    assert!(report.orphaned_blobs.is_empty());
+    assert!(report.dangling_media.is_empty());
+    assert!(report.broken_sfiles.is_empty());
+    assert!(report.unreferenced_media.is_empty());
+    assert!(!stale.exists());
+    assert!(in_progress.exists());
+
+    assert!(files
+        .get_sfile(&VirtualPath::from("root/fine.txt"), user_id)
+        .await
+        .is_ok());
+    assert!(files
+        .get_sfile(&VirtualPath::from("root/broken.txt"), user_id)
+        .await
+        .is_err());
+    // Nor are its permissions left behind
+    let (resources,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM resources WHERE resource_type = 'sfile' AND resource_id = $1",
+    )
+    .bind(broken_id)
+    .fetch_one(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(resources, 0);
+    assert!(!orphan_path.exists());
+    assert!(!store_root.join(blob_key(b"nothing points here")).exists());
+
+    files.terminate_upload(upload.id, user_id).await.unwrap();
+    let _ = tokio::fs::remove_dir_all(&scratch).await;
+    cleanup_test_database(db_pool).await;
+}
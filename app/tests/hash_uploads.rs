@@ -0,0 +1,271 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, cleanup_test_database, create_test_db, sha256, test_content};
+use ocloud::api::{ApiClient, ApiError};
+use ocloud::server::models::files::{HashChallenge, HashUploadResponse};
+
+fn answer(challenge: &HashChallenge, content: &[u8]) -> String {
+    let start = challenge.offset as usize;
+    challenge.answer(&content[start..start + challenge.length as usize])
+}
+
+fn expect_challenge(response: HashUploadResponse) -> HashChallenge {
+    match response {
+        HashUploadResponse::Challenge { challenge } => challenge,
+        other => panic!("Expected a challenge, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn own_media_links_right_away() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let content = test_content(5000);
+    client
+        .upload_file("root/", "original.bin", content.clone())
+        .await
+        .expect("Failed to upload file");
+
+    // Lowercase hashes work too
+    let response = client
+        .upload_by_hash(
+            "root/copies/copy.bin",
+            &sha256(&content).to_lowercase(),
+            content.len() as u64,
+            None,
+        )
+        .await
+        .expect("Failed to upload by hash");
+    let HashUploadResponse::Linked { file } = response else {
+        panic!("Expected the file to be linked, got {response:?}");
+    };
+    assert_eq!(file.full_path, "root/copies/copy.bin");
+
+    let downloaded = client
+        .get_file("root/copies/copy.bin", None)
+        .await
+        .expect("Failed to get linked file");
+    assert_eq!(downloaded, content);
+
+    // Can't link over an existing directory
+    let result = client
+        .upload_by_hash("root/copies", &sha256(&content), 5000, None)
+        .await;
+    assert!(matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::CONFLICT));
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn other_users_media_needs_proof() {
+    let db_pool = create_test_db().await;
+    let mut owner = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut owner).await;
+    let mut other = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut other).await;
+
+    let content = test_content(200_000);
+    let hash = sha256(&content);
+    owner
+        .upload_file("root/", "shared.bin", content.clone())
+        .await
+        .expect("Failed to upload file");
+
+    let challenge = expect_challenge(
+        other
+            .upload_by_hash("root/mine.bin", &hash, content.len() as u64, None)
+            .await
+            .expect("Failed to upload by hash"),
+    );
+    assert!(challenge.length > 0);
+    assert!(challenge.offset + challenge.length <= content.len() as u64);
+
+    let response = other
+        .upload_by_hash(
+            "root/mine.bin",
+            &hash,
+            content.len() as u64,
+            Some((challenge.id, answer(&challenge, &content))),
+        )
+        .await
+        .expect("Failed to answer challenge");
+    assert!(matches!(response, HashUploadResponse::Linked { .. }));
+
+    let downloaded = other
+        .get_file("root/mine.bin", None)
+        .await
+        .expect("Failed to get linked file");
+    assert_eq!(downloaded, content);
+
+    // Challenges can only be answered once
+    let result = other
+        .upload_by_hash(
+            "root/again.bin",
+            &hash,
+            content.len() as u64,
+            Some((challenge.id, answer(&challenge, &content))),
+        )
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn hashes_cant_be_probed() {
+    let db_pool = create_test_db().await;
+    let mut owner = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut owner).await;
+    let mut prober = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut prober).await;
+
+    let content = test_content(10_000);
+    let hash = sha256(&content);
+    owner
+        .upload_file("root/", "secret.bin", content.clone())
+        .await
+        .expect("Failed to upload file");
+
+    // Existing and unknown hashes both get a challenge
+    let existing = expect_challenge(
+        prober
+            .upload_by_hash("root/a.bin", &hash, 10_000, None)
+            .await
+            .expect("Failed to upload by hash"),
+    );
+    let unknown_content = test_content(9_999);
+    let unknown = expect_challenge(
+        prober
+            .upload_by_hash("root/b.bin", &sha256(&unknown_content), 9_999, None)
+            .await
+            .expect("Failed to upload by hash"),
+    );
+
+    // And a wrong answer fails the same way as media that doesn't exist
+    let wrong = prober
+        .upload_by_hash(
+            "root/a.bin",
+            &hash,
+            10_000,
+            Some((existing.id, answer(&existing, &test_content(20_000)[1..]))),
+        )
+        .await;
+    let missing = prober
+        .upload_by_hash(
+            "root/b.bin",
+            &sha256(&unknown_content),
+            9_999,
+            Some((unknown.id, answer(&unknown, &unknown_content))),
+        )
+        .await;
+    for result in [wrong, missing] {
+        assert!(
+            matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+        );
+    }
+    assert!(prober.get_file("root/a.bin", None).await.is_err());
+
+    // Only the user a challenge was made for can answer it
+    let challenge = expect_challenge(
+        prober
+            .upload_by_hash("root/c.bin", &hash, 10_000, None)
+            .await
+            .expect("Failed to upload by hash"),
+    );
+    let result = owner
+        .upload_by_hash(
+            "root/c.bin",
+            &hash,
+            10_000,
+            Some((challenge.id, answer(&challenge, &content))),
+        )
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+
+    // Or use it for a different path than the one it was made for
+    let challenge = expect_challenge(
+        prober
+            .upload_by_hash("root/d.bin", &hash, 10_000, None)
+            .await
+            .expect("Failed to upload by hash"),
+    );
+    let result = prober
+        .upload_by_hash(
+            "root/elsewhere.bin",
+            &hash,
+            10_000,
+            Some((challenge.id, answer(&challenge, &content))),
+        )
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+    assert!(prober.get_file("root/elsewhere.bin", None).await.is_err());
+
+    // Not a SHA-256
+    let result = prober
+        .upload_by_hash("root/d.bin", "abc", 10_000, None)
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn challenges_are_limited() {
+    let db_pool = create_test_db().await;
+    let mut owner = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut owner).await;
+    let mut other = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut other).await;
+
+    let content = test_content(10_000);
+    let hash = sha256(&content);
+    owner
+        .upload_file("root/", "shared.bin", content.clone())
+        .await
+        .expect("Failed to upload file");
+
+    // Asking again for the same path replaces the first challenge
+    let mut challenges = Vec::new();
+    for _ in 0..2 {
+        challenges.push(expect_challenge(
+            other
+                .upload_by_hash("root/a.bin", &hash, 10_000, None)
+                .await
+                .expect("Failed to upload by hash"),
+        ));
+    }
+    // And enough challenges for other paths push out the oldest
+    for i in 0..16 {
+        other
+            .upload_by_hash(&format!("root/many/{i}.bin"), &hash, 10_000, None)
+            .await
+            .expect("Failed to upload by hash");
+    }
+
+    for challenge in challenges {
+        let result = other
+            .upload_by_hash(
+                "root/a.bin",
+                &hash,
+                10_000,
+                Some((challenge.id, answer(&challenge, &content))),
+            )
+            .await;
+        assert!(
+            matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+        );
+    }
+
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, blob_path, cleanup_test_database, create_test_db, unique};
+use ocloud::api::{ApiClient, ApiError};
+
+#[tokio::test]
+async fn every_file_in_the_request_is_stored() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let a = unique("a");
+    let b = unique("b");
+    let c = unique("c");
+    let result = client
+        .upload_files(
+            "root/batch/",
+            vec![
+                ("a.txt".to_string(), a.clone()),
+                ("nested/b.txt".to_string(), b.clone()),
+                ("nested/deeper/c.txt".to_string(), c.clone()),
+            ],
+            false,
+        )
+        .await
+        .expect("Failed to upload files");
+    assert!(result.errors.is_empty());
+
+    let mut stored: Vec<&str> = result
+        .files
+        .iter()
+        .filter(|f| !f.is_dir)
+        .map(|f| f.full_path.as_str())
+        .collect();
+    stored.sort();
+    assert_eq!(
+        stored,
+        [
+            "root/batch/a.txt",
+            "root/batch/nested/b.txt",
+            "root/batch/nested/deeper/c.txt"
+        ]
+    );
+
+    for (path, contents) in [
+        ("root/batch/a.txt", &a),
+        ("root/batch/nested/b.txt", &b),
+        ("root/batch/nested/deeper/c.txt", &c),
+    ] {
+        assert_eq!(&client.get_file(path, None).await.unwrap(), contents);
+    }
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn failures_are_per_file_unless_atomic() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    // A directory is in the way of one of the files
+    client
+        .upload_file("root/batch/taken/", "inside.txt", unique("inside"))
+        .await
+        .unwrap();
+
+    let files = || {
+        vec![
+            ("ok.txt".to_string(), unique("ok")),
+            ("taken".to_string(), unique("taken")),
+            ("../escape.txt".to_string(), unique("escape")),
+        ]
+    };
+
+    let result = client
+        .upload_files("root/batch/", files(), false)
+        .await
+        .expect("Some of the files should have been stored");
+    assert_eq!(result.files.len(), 1);
+    assert_eq!(result.files[0].full_path, "root/batch/ok.txt");
+    let mut failed: Vec<&str> = result.errors.iter().map(|e| e.name.as_str()).collect();
+    failed.sort();
+    assert_eq!(failed, ["../escape.txt", "taken"]);
+
+    client.delete_file("root/batch/ok.txt").await.unwrap();
+    let result = client.upload_files("root/batch/", files(), true).await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+    assert!(client.get_file("root/batch/ok.txt", None).await.is_err());
+
+    // Everything is valid but one file conflicts when it's stored
+    let (ok, taken) = (unique("ok"), unique("taken"));
+    let files = vec![
+        ("ok.txt".to_string(), ok.clone()),
+        ("taken".to_string(), taken.clone()),
+    ];
+    let result = client.upload_files("root/batch/", files, true).await;
+    assert!(matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::CONFLICT));
+    assert!(client.get_file("root/batch/ok.txt", None).await.is_err());
+    // Nor are the contents of the files stored before it kept
+    assert!(!blob_path(&ok).exists());
+    assert!(!blob_path(&taken).exists());
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn concurrent_uploads_share_new_directories() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let names: Vec<String> = (0..8).map(|i| format!("{i}.txt")).collect();
+    let uploads = names
+        .iter()
+        .map(|name| client.upload_file("root/fresh/nested/", name, unique(name)));
+    for result in futures::future::join_all(uploads).await {
+        result.expect("Concurrent upload failed");
+    }
+    assert_eq!(
+        client
+            .list_directory("root/fresh/nested/", None)
+            .await
+            .unwrap()
+            .len(),
+        8
+    );
+
+    cleanup_test_database(db_pool).await;
+}
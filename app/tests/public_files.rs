@@ -1,7 +1,7 @@
 mod common;
 
 use axum::http::StatusCode;
-use common::{authenticate_random, cleanup_test_database, create_test_db, create_multiple_users};
+use common::{authenticate_random, cleanup_test_database, create_multiple_users, create_test_db};
 use ocloud::api::{ApiClient, ApiError};
 
 /// Test that anonymous users can access public files
@@ -332,8 +332,8 @@ async fn unified_endpoint_set_visibility_only() {
 async fn unified_endpoint_grant_permissions_only() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 2).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
-    let (mut viewer_client, _viewer_info, viewer_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (viewer_client, _viewer_info, viewer_id) = users.remove(0);
 
     // Owner uploads a private file
     let file_content = b"private file for permissions test".to_vec();
@@ -356,7 +356,7 @@ async fn unified_endpoint_grant_permissions_only() {
         relationship: "viewer".to_string(),
         action: "grant".to_string(),
     };
-    
+
     let updated_file = owner_client
         .set_permissions_and_visibility("root/permissions_test.txt", None, Some(perm_op))
         .await
@@ -381,8 +381,8 @@ async fn unified_endpoint_grant_permissions_only() {
 async fn unified_endpoint_revoke_permissions() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 2).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
-    let (mut editor_client, _editor_info, editor_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (editor_client, _editor_info, editor_id) = users.remove(0);
 
     // Owner uploads a file
     let file_content = b"file for revoke test".to_vec();
@@ -414,7 +414,10 @@ async fn unified_endpoint_revoke_permissions() {
     let result = editor_client
         .get_file("root/revoke_test.txt", files[0].user_id)
         .await;
-    assert!(result.is_err(), "Editor should not be able to access file after permission revocation");
+    assert!(
+        result.is_err(),
+        "Editor should not be able to access file after permission revocation"
+    );
 
     cleanup_test_database(db_pool).await;
 }
@@ -424,7 +427,7 @@ async fn unified_endpoint_revoke_permissions() {
 async fn unified_endpoint_set_visibility_and_permissions() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 2).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
     let (mut viewer_client, _viewer_info, viewer_id) = users.remove(0);
 
     // Owner uploads a file
@@ -442,7 +445,7 @@ async fn unified_endpoint_set_visibility_and_permissions() {
         relationship: "viewer".to_string(),
         action: "grant".to_string(),
     };
-    
+
     let updated_file = owner_client
         .set_permissions_and_visibility("root/combined_test.txt", Some(true), Some(perm_op))
         .await
@@ -474,8 +477,8 @@ async fn unified_endpoint_set_visibility_and_permissions() {
 async fn unified_endpoint_non_owner_cannot_change_permissions() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 3).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
-    let (mut non_owner_client, _non_owner_info, _non_owner_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (non_owner_client, _non_owner_info, _non_owner_id) = users.remove(0);
     let (_third_client, _third_info, third_id) = users.remove(0);
 
     // Owner uploads a file
@@ -491,7 +494,7 @@ async fn unified_endpoint_non_owner_cannot_change_permissions() {
         relationship: "viewer".to_string(),
         action: "grant".to_string(),
     };
-    
+
     let result = non_owner_client
         .set_permissions_and_visibility("root/protected.txt", None, Some(perm_op))
         .await;
@@ -511,7 +514,7 @@ async fn unified_endpoint_non_owner_cannot_change_permissions() {
 async fn unified_endpoint_invalid_permission_action() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 2).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
     let (_viewer_client, _viewer_info, viewer_id) = users.remove(0);
 
     // Owner uploads a file
@@ -527,7 +530,7 @@ async fn unified_endpoint_invalid_permission_action() {
         relationship: "viewer".to_string(),
         action: "invalid_action".to_string(),
     };
-    
+
     let result = owner_client
         .set_permissions_and_visibility("root/test.txt", None, Some(perm_op))
         .await;
@@ -569,8 +572,8 @@ async fn unified_endpoint_nonexistent_file() {
 async fn permission_hierarchy_editor_limitations() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 3).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
-    let (mut editor_client, _editor_info, editor_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (editor_client, _editor_info, editor_id) = users.remove(0);
     let (_viewer_client, _viewer_info, viewer_id) = users.remove(0);
 
     // Owner uploads a file
@@ -599,7 +602,7 @@ async fn permission_hierarchy_editor_limitations() {
         relationship: "viewer".to_string(),
         action: "grant".to_string(),
     };
-    
+
     let result = editor_client
         .set_permissions_and_visibility("root/hierarchy_test.txt", None, Some(perm_op))
         .await;
@@ -619,8 +622,8 @@ async fn permission_hierarchy_editor_limitations() {
 async fn permission_hierarchy_viewer_read_only() {
     let db_pool = create_test_db().await;
     let mut users = create_multiple_users(&db_pool, 2).await;
-    let (mut owner_client, _owner_info, _owner_id) = users.remove(0);
-    let (mut viewer_client, _viewer_info, viewer_id) = users.remove(0);
+    let (owner_client, _owner_info, _owner_id) = users.remove(0);
+    let (viewer_client, _viewer_info, viewer_id) = users.remove(0);
 
     // Owner uploads a file
     let file_content = b"file for viewer test".to_vec();
@@ -643,9 +646,7 @@ async fn permission_hierarchy_viewer_read_only() {
     assert_eq!(content, file_content);
 
     // Viewer should NOT be able to delete the file
-    let result = viewer_client
-        .delete_file("root/viewer_test.txt")
-        .await;
+    let result = viewer_client.delete_file("root/viewer_test.txt").await;
     assert!(result.is_err());
 
     // Viewer should NOT be able to change visibility
@@ -661,4 +662,4 @@ async fn permission_hierarchy_viewer_read_only() {
     }
 
     cleanup_test_database(db_pool).await;
-}
\ No newline at end of file
+}
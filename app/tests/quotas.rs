@@ -0,0 +1,130 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, cleanup_test_database, create_test_db, unique_content};
+use ocloud::api::{ApiClient, ApiError};
+use ocloud::server::controllers::auth::AuthController;
+
+fn is_over_quota<T>(result: Result<T, ApiError>) -> bool {
+    matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::PAYLOAD_TOO_LARGE)
+}
+
+#[tokio::test]
+async fn uploads_stop_at_the_quota() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    let user = authenticate_random(&mut client).await;
+    let username = user["username"].as_str().unwrap();
+
+    let usage = client.usage().await.expect("Failed to get usage");
+    assert_eq!(usage.used_bytes, 0);
+    assert_eq!(usage.quota_bytes, None);
+
+    AuthController::new(db_pool.clone())
+        .set_quota(username, Some(200))
+        .await
+        .expect("Failed to set quota");
+
+    let content = unique_content(60);
+    client
+        .upload_file("root/", "first.txt", content.clone())
+        .await
+        .expect("Failed to upload file");
+
+    // The same contents again don't count twice, though they're
+    // checked against the quota at full size while being sent
+    client
+        .upload_file("root/", "copy.txt", content.clone())
+        .await
+        .expect("Failed to upload file");
+
+    let usage = client.usage().await.unwrap();
+    assert_eq!(usage.used_bytes, 60);
+    assert_eq!(usage.quota_bytes, Some(200));
+
+    // Aborted partway through, nothing is kept
+    let result = client
+        .upload_file("root/", "big.txt", unique_content(150))
+        .await;
+    assert!(is_over_quota(result));
+    assert!(client.get_file("root/big.txt", None).await.is_err());
+    assert_eq!(client.usage().await.unwrap().used_bytes, 60);
+
+    // Resumable uploads are turned away up front
+    assert!(is_over_quota(
+        client.create_upload("root/", "big.bin", 150).await
+    ));
+
+    // And again once they're complete, in case other uploads filled the quota meanwhile
+    let location = client
+        .create_upload("root/", "late.bin", 100)
+        .await
+        .expect("Failed to create upload");
+    client
+        .upload_file("root/", "filler.txt", unique_content(100))
+        .await
+        .expect("Failed to upload file");
+    assert!(is_over_quota(
+        client
+            .append_to_upload(&location, 0, unique_content(100))
+            .await
+    ));
+    assert!(client.get_file("root/late.bin", None).await.is_err());
+    client.delete_file("root/filler.txt").await.unwrap();
+    client.purge_trash(None).await.unwrap();
+
+    client
+        .upload_file("root/", "small.txt", unique_content(140))
+        .await
+        .expect("Failed to upload file that fits");
+    assert_eq!(client.usage().await.unwrap().used_bytes, 200);
+
+    // Back to the default, which is no limit
+    AuthController::new(db_pool.clone())
+        .set_quota(username, None)
+        .await
+        .unwrap();
+    client
+        .upload_file("root/", "big.txt", unique_content(150))
+        .await
+        .expect("Failed to upload file without a quota");
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn shared_media_is_split_between_users() {
+    let db_pool = create_test_db().await;
+    let mut alice = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut alice).await;
+    let mut bob = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut bob).await;
+
+    let content = unique_content(1000);
+    alice
+        .upload_file("root/", "shared.bin", content.clone())
+        .await
+        .unwrap();
+    alice
+        .upload_file("root/", "own.bin", unique_content(300))
+        .await
+        .unwrap();
+    assert_eq!(alice.usage().await.unwrap().used_bytes, 1300);
+
+    bob.upload_file("root/", "shared.bin", content.clone())
+        .await
+        .unwrap();
+    assert_eq!(alice.usage().await.unwrap().used_bytes, 800);
+    assert_eq!(bob.usage().await.unwrap().used_bytes, 500);
+
+    // Files in the trash still count
+    bob.delete_file("root/shared.bin").await.unwrap();
+    assert_eq!(bob.usage().await.unwrap().used_bytes, 500);
+
+    // Whoever keeps it pays for all of it
+    bob.purge_trash(None).await.unwrap();
+    assert_eq!(alice.usage().await.unwrap().used_bytes, 1300);
+    assert_eq!(bob.usage().await.unwrap().used_bytes, 0);
+
+    cleanup_test_database(db_pool).await;
+}
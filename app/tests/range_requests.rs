@@ -0,0 +1,133 @@
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::{authenticate_random, cleanup_test_database, create_test_db};
+use ocloud::api::ApiClient;
+
+const CONTENT: &[u8] = b"abcdefghijklmnopqrstuvwxyz range test";
+
+/// Uploads CONTENT and returns an authenticated client plus the owner's id
+async fn setup(db_pool: &sqlx::PgPool) -> (ApiClient, Option<i64>) {
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let files = client
+        .upload_file("root/", "alphabet.txt", CONTENT.to_vec())
+        .await
+        .expect("Failed to upload file");
+
+    let user_id = files[0].user_id;
+    (client, user_id)
+}
+
+#[tokio::test]
+async fn single_range_returns_partial_content() {
+    let db_pool = create_test_db().await;
+    let (client, user_id) = setup(&db_pool).await;
+
+    let (status, headers, body) = client
+        .get_file_range("root/alphabet.txt", user_id, "bytes=2-5")
+        .await
+        .expect("Range request failed");
+
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, b"cdef");
+    assert_eq!(
+        headers[header::CONTENT_RANGE],
+        format!("bytes 2-5/{}", CONTENT.len())
+    );
+    assert_eq!(headers[header::CONTENT_LENGTH], "4");
+
+    // open ended and suffix ranges
+    let (status, _, body) = client
+        .get_file_range("root/alphabet.txt", user_id, "bytes=-4")
+        .await
+        .expect("Range request failed");
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, b"test");
+
+    let (status, _, body) = client
+        .get_file_range("root/alphabet.txt", user_id, "bytes=20-")
+        .await
+        .expect("Range request failed");
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, &CONTENT[20..]);
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn multiple_ranges_return_multipart_byteranges() {
+    let db_pool = create_test_db().await;
+    let (client, user_id) = setup(&db_pool).await;
+
+    let (status, headers, body) = client
+        .get_file_range("root/alphabet.txt", user_id, "bytes=0-2, 23-25")
+        .await
+        .expect("Range request failed");
+
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+
+    let content_type = headers[header::CONTENT_TYPE].to_str().unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .expect("Expected a multipart/byteranges response");
+
+    assert_eq!(
+        headers[header::CONTENT_LENGTH],
+        body.len().to_string().as_str()
+    );
+
+    let body = String::from_utf8(body).unwrap();
+    let expected = format!(
+        "--{boundary}\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Range: bytes 0-2/{len}\r\n\r\n\
+        abc\r\n\
+        --{boundary}\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Range: bytes 23-25/{len}\r\n\r\n\
+        xyz\r\n\
+        --{boundary}--\r\n",
+        len = CONTENT.len()
+    );
+    assert_eq!(body, expected);
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn unsatisfiable_range_returns_416() {
+    let db_pool = create_test_db().await;
+    let (client, user_id) = setup(&db_pool).await;
+
+    let (status, headers, _) = client
+        .get_file_range("root/alphabet.txt", user_id, "bytes=1000-2000")
+        .await
+        .expect("Range request failed");
+
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        headers[header::CONTENT_RANGE],
+        format!("bytes */{}", CONTENT.len())
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn invalid_range_serves_whole_file() {
+    let db_pool = create_test_db().await;
+    let (client, user_id) = setup(&db_pool).await;
+
+    let (status, headers, body) = client
+        .get_file_range("root/alphabet.txt", user_id, "bytes=5-1")
+        .await
+        .expect("Range request failed");
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, CONTENT);
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+
+    cleanup_test_database(db_pool).await;
+}
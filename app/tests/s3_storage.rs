@@ -0,0 +1,312 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{any, get},
+    Router,
+};
+use common::test_content;
+use futures::StreamExt;
+use ocloud::config::settings::S3Settings;
+use ocloud::server::storage::{BlobStore, BlobStream, S3Store};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+const BUCKET: &str = "ocloud-test";
+
+/// A tiny stand-in for an S3 compatible service like MinIO.
+/// Only knows the handful of calls the S3 store makes.
+#[derive(Default)]
+struct FakeS3 {
+    objects: HashMap<String, Vec<u8>>,
+    /// Parts of unfinished multipart uploads, keyed by upload id
+    uploads: HashMap<String, Vec<(u32, Vec<u8>)>>,
+    /// How many parts were sent to multipart uploads
+    parts_received: usize,
+}
+
+type Fake = Arc<Mutex<FakeS3>>;
+
+async fn handle(
+    State(fake): State<Fake>,
+    method: Method,
+    Path((bucket, key)): Path<(String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if bucket != BUCKET
+        || !authorization.starts_with("AWS4-HMAC-SHA256 Credential=test-key/")
+        || !headers.contains_key("x-amz-date")
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let mut fake = fake.lock().unwrap();
+
+    match (method, query.get("uploadId")) {
+        (Method::POST, None) if query.contains_key("uploads") => {
+            let upload_id = Uuid::new_v4().to_string();
+            fake.uploads.insert(upload_id.clone(), Vec::new());
+            format!(
+                "<InitiateMultipartUploadResult><Bucket>{BUCKET}</Bucket><Key>{key}</Key>\
+                <UploadId>{upload_id}</UploadId></InitiateMultipartUploadResult>"
+            )
+            .into_response()
+        }
+        (Method::PUT, Some(upload_id)) => {
+            let part_number: u32 = query["partNumber"].parse().unwrap();
+            let Some(parts) = fake.uploads.get_mut(upload_id) else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            parts.push((part_number, body.to_vec()));
+            fake.parts_received += 1;
+            ([(header::ETAG, format!("\"etag-{part_number}\""))], "").into_response()
+        }
+        (Method::POST, Some(upload_id)) => {
+            let Some(mut parts) = fake.uploads.remove(upload_id) else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            let listed = String::from_utf8_lossy(&body);
+            assert_eq!(listed.matches("<Part>").count(), parts.len());
+            parts.sort_by_key(|(n, _)| *n);
+            let content = parts.into_iter().flat_map(|(_, data)| data).collect();
+            fake.objects.insert(key, content);
+            "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".into_response()
+        }
+        (Method::DELETE, Some(upload_id)) => {
+            fake.uploads.remove(upload_id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        (Method::PUT, None) => {
+            fake.objects.insert(key, body.to_vec());
+            ([(header::ETAG, "\"etag\"")], "").into_response()
+        }
+        (Method::GET, None) => {
+            let Some(object) = fake.objects.get(&key) else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            let range = headers
+                .get(header::RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("bytes="))
+                .and_then(|v| v.split_once('-'))
+                .map(|(start, end)| (start.parse().unwrap(), end.parse::<usize>().unwrap()));
+            match range {
+                Some((start, end)) => (
+                    StatusCode::PARTIAL_CONTENT,
+                    object[start..=end.min(object.len() - 1)].to_vec(),
+                )
+                    .into_response(),
+                None => object.clone().into_response(),
+            }
+        }
+        (Method::DELETE, None) => {
+            fake.objects.remove(&key);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        _ => StatusCode::NOT_IMPLEMENTED.into_response(),
+    }
+}
+
+/// Lists keys two at a time, so paging gets exercised.
+async fn list(
+    State(fake): State<Fake>,
+    Path(bucket): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if bucket != BUCKET || query.get("list-type").map(String::as_str) != Some("2") {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let fake = fake.lock().unwrap();
+    let mut keys: Vec<&String> = fake.objects.keys().collect();
+    keys.sort();
+    let start: usize = query
+        .get("continuation-token")
+        .map(|t| t.parse().unwrap())
+        .unwrap_or(0);
+    let page: String = keys
+        .iter()
+        .skip(start)
+        .take(2)
+        .map(|k| format!("<Contents><Key>{k}</Key></Contents>"))
+        .collect();
+    let truncated = start + 2 < keys.len();
+
+    format!(
+        "<ListBucketResult>{page}<IsTruncated>{truncated}</IsTruncated>{}</ListBucketResult>",
+        if truncated {
+            format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                start + 2
+            )
+        } else {
+            String::new()
+        }
+    )
+    .into_response()
+}
+
+async fn spawn_fake_s3() -> (String, Fake) {
+    let fake = Fake::default();
+    let app = Router::new()
+        .route("/:bucket", get(list))
+        .route("/:bucket/*key", any(handle))
+        .with_state(fake.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (format!("http://{addr}"), fake)
+}
+
+fn store(endpoint: String) -> S3Store {
+    S3Store::new(&S3Settings {
+        endpoint,
+        bucket: BUCKET.to_string(),
+        region: "us-east-1".to_string(),
+        access_key: "test-key".to_string(),
+        secret_key: "test-secret".to_string(),
+    })
+    .expect("Failed to create S3 store")
+}
+
+async fn temp_file(content: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ocloud_s3_test_{}", Uuid::new_v4()));
+    tokio::fs::write(&path, content).await.unwrap();
+    path
+}
+
+async fn collect(mut stream: BlobStream) -> Vec<u8> {
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk.expect("Failed to read blob"));
+    }
+    data
+}
+
+#[tokio::test]
+async fn put_get_and_delete() {
+    let (endpoint, fake) = spawn_fake_s3().await;
+    let store = store(endpoint);
+
+    let content = test_content(10_000);
+    let temp = temp_file(&content).await;
+    store
+        .put("AB/CD/EF01", &temp)
+        .await
+        .expect("Failed to put blob");
+    assert!(!temp.exists(), "The temp file should be gone");
+    assert!(store.local_path("AB/CD/EF01").is_none());
+
+    assert_eq!(
+        collect(store.get("AB/CD/EF01").await.unwrap()).await,
+        content
+    );
+    assert_eq!(
+        collect(store.get_range("AB/CD/EF01", 1000, 500).await.unwrap()).await,
+        content[1000..1500]
+    );
+    assert!(collect(store.get_range("AB/CD/EF01", 10, 0).await.unwrap())
+        .await
+        .is_empty());
+
+    store
+        .delete("AB/CD/EF01")
+        .await
+        .expect("Failed to delete blob");
+    assert!(fake.lock().unwrap().objects.is_empty());
+    assert!(store.get("AB/CD/EF01").await.is_err());
+}
+
+#[tokio::test]
+async fn big_files_are_sent_in_parts() {
+    let (endpoint, fake) = spawn_fake_s3().await;
+    let store = store(endpoint).with_part_size(4096);
+
+    let content = test_content(10_000);
+    let temp = temp_file(&content).await;
+    store
+        .put("12/34/5678", &temp)
+        .await
+        .expect("Failed to put blob");
+    assert!(!temp.exists(), "The temp file should be gone");
+
+    {
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.objects["12/34/5678"], content);
+        assert!(fake.uploads.is_empty());
+        assert_eq!(fake.parts_received, 3);
+    }
+
+    assert_eq!(
+        collect(store.get("12/34/5678").await.unwrap()).await,
+        content
+    );
+}
+
+#[tokio::test]
+async fn list_every_key() {
+    let (endpoint, _fake) = spawn_fake_s3().await;
+    let store = store(endpoint);
+
+    assert!(store.list().await.unwrap().is_empty());
+
+    let mut keys: Vec<String> = (0..5).map(|i| format!("0{i}/AA/BBBB")).collect();
+    for key in &keys {
+        let temp = temp_file(key.as_bytes()).await;
+        store.put(key, &temp).await.expect("Failed to put blob");
+    }
+
+    let mut listed = store.list().await.expect("Failed to list blobs");
+    listed.sort();
+    keys.sort();
+    assert_eq!(listed, keys);
+}
+
+#[tokio::test]
+async fn failed_requests_are_errors() {
+    let (endpoint, _fake) = spawn_fake_s3().await;
+
+    // The fake only knows one bucket
+    let wrong_bucket = S3Store::new(&S3Settings {
+        endpoint: endpoint.clone(),
+        bucket: "some-other-bucket".to_string(),
+        region: "us-east-1".to_string(),
+        access_key: "test-key".to_string(),
+        secret_key: "test-secret".to_string(),
+    })
+    .unwrap();
+    let temp = temp_file(b"hello").await;
+    assert!(wrong_bucket.put("aa/bb/cc", &temp).await.is_err());
+    assert!(
+        !temp.exists(),
+        "The temp file should be gone even on failure"
+    );
+
+    assert!(store(endpoint).get("aa/bb/cc").await.is_err());
+
+    let result = S3Store::new(&S3Settings {
+        endpoint: "not a url".to_string(),
+        bucket: BUCKET.to_string(),
+        region: "us-east-1".to_string(),
+        access_key: "test-key".to_string(),
+        secret_key: "test-secret".to_string(),
+    });
+    assert!(result.is_err());
+}
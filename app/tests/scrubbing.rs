@@ -0,0 +1,85 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use common::{
+    authenticate_random, blob_path, cleanup_test_database, create_test_db, sha256, unique,
+};
+use ocloud::api::{ApiClient, ApiError};
+use ocloud::config::{settings::ScrubberSettings, SETTINGS};
+use ocloud::server::controllers::auth::AuthController;
+use ocloud::server::controllers::scrub::ScrubControllerInner;
+use ocloud::server::storage::LocalStore;
+
+#[tokio::test]
+async fn scrubber_catches_corrupt_blobs() {
+    let db_pool = create_test_db().await;
+    let mut admin = ApiClient::new_local(db_pool.clone()).await;
+    let admin_info = authenticate_random(&mut admin).await;
+    let mut user = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut user).await;
+
+    AuthController::new(db_pool.clone())
+        .make_admin(admin_info["username"].as_str().unwrap())
+        .await
+        .expect("Failed to make admin");
+
+    // Unique contents, since other tests share the files directory
+    let intact = unique("intact");
+    let corrupt = unique("corrupt");
+    for (name, content) in [("intact.txt", &intact), ("corrupt.txt", &corrupt)] {
+        admin
+            .upload_file("root/", name, content.clone())
+            .await
+            .expect("Failed to upload file");
+    }
+
+    // Flip some bytes on disk
+    let hash = sha256(&corrupt);
+    let corrupt_blob = blob_path(&corrupt);
+    let mut rotten = corrupt.clone();
+    rotten[0] ^= 0xFF;
+    tokio::fs::write(&corrupt_blob, &rotten).await.unwrap();
+
+    let scrubber = ScrubControllerInner::new(
+        db_pool.clone(),
+        Arc::new(LocalStore::new(SETTINGS.directories.files_dir.clone())),
+        ScrubberSettings {
+            enabled: true,
+            bytes_per_second: 0,
+            recheck_after_hours: 1,
+        },
+    );
+
+    let mut scrubbed = 0;
+    while scrubber.scrub_next().await.expect("Failed to scrub") {
+        scrubbed += 1;
+    }
+    assert_eq!(scrubbed, 2);
+
+    // Nothing is due again until recheck_after_hours passes
+    assert!(!scrubber.scrub_next().await.unwrap());
+
+    let report = admin.scrub_report().await.expect("Failed to get report");
+    assert_eq!(report.total_media, 2);
+    assert_eq!(report.checked_media, 2);
+    assert!(report.oldest_check.is_some());
+    assert_eq!(report.failures.len(), 1);
+    let failure = &report.failures[0];
+    assert_eq!(failure.file_hash, hash);
+    assert_eq!(
+        failure.actual_hash.as_deref(),
+        Some(sha256(&rotten).as_str())
+    );
+    assert!(failure.verified_at.is_none());
+
+    // Admins only
+    let result = user.scrub_report().await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::FORBIDDEN)
+    );
+
+    tokio::fs::remove_file(&corrupt_blob).await.unwrap();
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, cleanup_test_database, create_test_db};
+use ocloud::api::{ApiClient, ApiError};
+
+fn ebml(id: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut buf = id.to_vec();
+    // 8 byte size vint
+    buf.push(0x01);
+    buf.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// A VP9-only WebM with a 2 second frame interval and a keyframe every 3 frames,
+/// so it remuxes into `clusters` segments of 6 seconds.
+fn test_webm(clusters: u8) -> Vec<u8> {
+    let mut file = ebml(&[0x1A, 0x45, 0xDF, 0xA3], &ebml(&[0x42, 0x82], b"webm"));
+
+    let mut segment = ebml(
+        &[0x15, 0x49, 0xA9, 0x66],
+        &ebml(&[0x2A, 0xD7, 0xB1], &1_000_000u32.to_be_bytes()),
+    );
+    let track = [
+        ebml(&[0xD7], &[1]),
+        ebml(&[0x83], &[1]),
+        ebml(&[0x86], b"V_VP9"),
+        ebml(
+            &[0xE0],
+            &[ebml(&[0xB0], &[64]), ebml(&[0xBA], &[48])].concat(),
+        ),
+    ]
+    .concat();
+    segment.extend(ebml(&[0x16, 0x54, 0xAE, 0x6B], &ebml(&[0xAE], &track)));
+
+    for cluster in 0..clusters {
+        let mut payload = ebml(&[0xE7], &(cluster as u16 * 6000).to_be_bytes());
+        for frame in 0..3u8 {
+            let relative = frame as i16 * 2000;
+            let mut block = vec![0x81];
+            block.extend_from_slice(&relative.to_be_bytes());
+            block.push(if frame == 0 { 0x80 } else { 0 });
+            block.extend_from_slice(&[cluster * 3 + frame; 32]);
+            payload.extend(ebml(&[0xA3], &block));
+        }
+        segment.extend(ebml(&[0x1F, 0x43, 0xB6, 0x75], &payload));
+    }
+
+    file.extend(ebml(&[0x18, 0x53, 0x80, 0x67], &segment));
+    file
+}
+
+#[tokio::test]
+async fn owner_can_stream_video() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    client
+        .upload_file("root/videos/", "clip.webm", test_webm(2))
+        .await
+        .expect("Failed to upload video");
+
+    let playlist = client
+        .get_stream("root/videos/clip.webm", "index.m3u8", None)
+        .await
+        .expect("Failed to get playlist");
+    let playlist = String::from_utf8(playlist).unwrap();
+
+    assert!(playlist.starts_with("#EXTM3U\n"));
+    assert!(playlist.contains("#EXT-X-MAP:URI=\"init.mp4\"\n"));
+    assert!(playlist.contains("#EXTINF:6.000,\n0.m4s\n#EXTINF:6.000,\n1.m4s\n"));
+    assert!(playlist.ends_with("#EXT-X-ENDLIST\n"));
+
+    let init = client
+        .get_stream("root/videos/clip.webm", "init.mp4", None)
+        .await
+        .expect("Failed to get init segment");
+    assert_eq!(&init[4..8], b"ftyp");
+
+    for segment in ["0.m4s", "1.m4s"] {
+        let segment = client
+            .get_stream("root/videos/clip.webm", segment, None)
+            .await
+            .expect("Failed to get segment");
+        assert_eq!(&segment[4..8], b"moof");
+    }
+
+    // Segments that don't exist, and names that aren't segments
+    for resource in ["2.m4s", "..", "index.m3u8.bak"] {
+        let result = client
+            .get_stream("root/videos/clip.webm", resource, None)
+            .await;
+        assert!(
+            matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND),
+            "Expected 404 for {resource}"
+        );
+    }
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn stream_respects_visibility() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let files = client
+        .upload_file("root/", "clip.webm", test_webm(1))
+        .await
+        .expect("Failed to upload video");
+    let owner_id = files[0].user_id;
+
+    let mut anon = ApiClient::new_local(db_pool.clone()).await;
+    anon.clear_session();
+
+    let result = anon
+        .get_stream("root/clip.webm", "index.m3u8", owner_id)
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::UNAUTHORIZED)
+    );
+
+    client
+        .change_file_visibility("root/clip.webm", true)
+        .await
+        .expect("Failed to make video public");
+
+    // The segment URIs carry the user query along
+    let playlist = anon
+        .get_stream("root/clip.webm", "index.m3u8", owner_id)
+        .await
+        .expect("Failed to get public playlist");
+    let playlist = String::from_utf8(playlist).unwrap();
+    let query = format!("?u={}", owner_id.unwrap());
+    assert!(playlist.contains(&format!("#EXT-X-MAP:URI=\"init.mp4{query}\"\n")));
+    assert!(playlist.contains(&format!("\n0.m4s{query}\n")));
+
+    anon.get_stream("root/clip.webm", "0.m4s", owner_id)
+        .await
+        .expect("Failed to get public segment");
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn non_video_files_are_unsupported() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    client
+        .upload_file("root/", "notes.txt", b"not a video at all".to_vec())
+        .await
+        .expect("Failed to upload file");
+
+    let result = client
+        .get_stream("root/notes.txt", "index.m3u8", None)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ApiError::Http { status, .. }) if status == StatusCode::UNSUPPORTED_MEDIA_TYPE
+    ));
+
+    cleanup_test_database(db_pool).await;
+}
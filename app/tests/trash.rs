@@ -0,0 +1,143 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use common::{
+    authenticate_random, blob_path, cleanup_test_database, create_test_db, has_status, unique,
+};
+use ocloud::api::ApiClient;
+use ocloud::config::SETTINGS;
+use ocloud::server::controllers::files::FileControllerInner;
+use ocloud::server::storage::LocalStore;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn deleted_files_can_be_restored() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    // Unique contents, since other tests share the files directory
+    let original = unique("original");
+    client
+        .upload_file("root/docs/", "a.txt", original.clone())
+        .await
+        .expect("Failed to upload file");
+
+    client.delete_file("root/docs/a.txt").await.unwrap();
+    assert!(client.get_file("root/docs/a.txt", None).await.is_err());
+    assert!(blob_path(&original).exists());
+
+    let trash = client.list_trash().await.expect("Failed to list trash");
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].original_path, "root/docs/a.txt");
+    assert_eq!(trash[0].file_size, original.len() as i64);
+    assert!(trash[0].purge_at.is_some());
+    let id = trash[0].id;
+
+    // The path is free again
+    let replacement = unique("replacement");
+    client
+        .upload_file("root/docs/", "a.txt", replacement.clone())
+        .await
+        .expect("Failed to upload file");
+    assert!(has_status(
+        client.restore_from_trash(id, None).await,
+        StatusCode::CONFLICT
+    ));
+
+    // Somewhere else, with the directories made on the way
+    let restored = client
+        .restore_from_trash(id, Some("root/old/docs/a.txt"))
+        .await
+        .expect("Failed to restore file");
+    assert_eq!(restored.full_path, "root/old/docs/a.txt");
+    assert_eq!(
+        client.get_file("root/old/docs/a.txt", None).await.unwrap(),
+        original
+    );
+    assert!(client.list_trash().await.unwrap().is_empty());
+
+    // Restoring to where it was
+    client.delete_file("root/docs/a.txt").await.unwrap();
+    let id = client.list_trash().await.unwrap()[0].id;
+    client.restore_from_trash(id, None).await.unwrap();
+    assert_eq!(
+        client.get_file("root/docs/a.txt", None).await.unwrap(),
+        replacement
+    );
+
+    // Only files go in the trash, even when a directory is named like one
+    assert!(has_status(
+        client.delete_file("root/docs").await,
+        StatusCode::NOT_FOUND
+    ));
+    assert!(client.get_file("root/docs/a.txt", None).await.is_ok());
+    assert!(client.list_trash().await.unwrap().is_empty());
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn purged_files_are_gone() {
+    let db_pool = create_test_db().await;
+    let mut alice = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut alice).await;
+    let mut bob = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut bob).await;
+
+    let contents: Vec<Vec<u8>> = (0..3)
+        .map(|i| format!("file {i} {}", Uuid::new_v4()).into_bytes())
+        .collect();
+    for (i, content) in contents.iter().enumerate() {
+        let name = format!("{i}.txt");
+        alice
+            .upload_file("root/", &name, content.clone())
+            .await
+            .unwrap();
+        alice.delete_file(&format!("root/{name}")).await.unwrap();
+    }
+    let trash = alice.list_trash().await.unwrap();
+    assert_eq!(trash.len(), 3);
+
+    // Only the owner sees their trash
+    assert!(bob.list_trash().await.unwrap().is_empty());
+    assert!(has_status(
+        bob.restore_from_trash(trash[0].id, None).await,
+        StatusCode::NOT_FOUND
+    ));
+    assert!(has_status(
+        bob.purge_trash(Some(trash[0].id)).await,
+        StatusCode::NOT_FOUND
+    ));
+
+    // Most recently deleted first
+    assert_eq!(trash[0].original_path, "root/2.txt");
+    assert_eq!(alice.purge_trash(Some(trash[0].id)).await.unwrap(), 1);
+    assert!(!blob_path(&contents[2]).exists());
+    assert!(has_status(
+        alice.restore_from_trash(trash[0].id, None).await,
+        StatusCode::NOT_FOUND
+    ));
+
+    // Left long enough, it goes on its own
+    sqlx::query("UPDATE sfiles SET trashed_at = trashed_at - INTERVAL '31 days' WHERE id = $1")
+        .bind(trash[1].id as i64)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let files = FileControllerInner::new_no_ws(
+        db_pool.clone(),
+        Arc::new(LocalStore::new(SETTINGS.directories.files_dir.clone())),
+    )
+    .await;
+    assert_eq!(files.purge_old_trash().await.unwrap(), 1);
+    assert!(!blob_path(&contents[1]).exists());
+
+    assert_eq!(alice.purge_trash(None).await.unwrap(), 1);
+    assert!(!blob_path(&contents[0]).exists());
+    assert!(alice.list_trash().await.unwrap().is_empty());
+
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, cleanup_test_database, create_test_db, test_content};
+use ocloud::api::{ApiClient, ApiError};
+
+#[tokio::test]
+async fn server_advertises_tus_support() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let headers = client
+        .upload_capabilities()
+        .await
+        .expect("Failed to get tus capabilities");
+    assert_eq!(headers["tus-version"], "1.0.0");
+    assert_eq!(headers["tus-resumable"], "1.0.0");
+    assert_eq!(headers["tus-extension"], "creation,termination");
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn upload_in_chunks_and_resume() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let content = test_content(100_000);
+    let location = client
+        .create_upload("root/big/", "data.bin", content.len() as u64)
+        .await
+        .expect("Failed to create upload");
+    assert!(location.starts_with("/uploads/"));
+
+    assert_eq!(client.upload_offset(&location).await.unwrap(), 0);
+
+    let offset = client
+        .append_to_upload(&location, 0, content[..40_000].to_vec())
+        .await
+        .expect("Failed to send first chunk");
+    assert_eq!(offset, 40_000);
+
+    // Resuming from the wrong offset is a conflict
+    let result = client
+        .append_to_upload(&location, 10_000, content[10_000..].to_vec())
+        .await;
+    assert!(matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::CONFLICT));
+
+    // The file doesn't exist until the upload is done
+    assert!(client.get_file("root/big/data.bin", None).await.is_err());
+
+    let offset = client.upload_offset(&location).await.unwrap();
+    assert_eq!(offset, 40_000);
+    let offset = client
+        .append_to_upload(&location, offset, content[40_000..].to_vec())
+        .await
+        .expect("Failed to send the rest");
+    assert_eq!(offset, content.len() as u64);
+
+    let downloaded = client
+        .get_file("root/big/data.bin", None)
+        .await
+        .expect("Failed to get uploaded file");
+    assert_eq!(downloaded, content);
+
+    // Finished uploads are gone
+    let result = client.upload_offset(&location).await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn upload_rejects_extra_bytes() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    // Longer than a file can be
+    let result = client.create_upload("root/", "huge.bin", u64::MAX).await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+
+    let location = client
+        .create_upload("root/", "small.txt", 4)
+        .await
+        .expect("Failed to create upload");
+
+    let result = client
+        .append_to_upload(&location, 0, b"too long".to_vec())
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+
+    client
+        .append_to_upload(&location, 0, b"fits".to_vec())
+        .await
+        .expect("Failed to upload the right amount");
+    assert_eq!(
+        client.get_file("root/small.txt", None).await.unwrap(),
+        b"fits"
+    );
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn terminate_upload() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let location = client
+        .create_upload("root/", "cancelled.bin", 1000)
+        .await
+        .expect("Failed to create upload");
+    client
+        .append_to_upload(&location, 0, test_content(500))
+        .await
+        .expect("Failed to send chunk");
+
+    client
+        .terminate_upload(&location)
+        .await
+        .expect("Failed to terminate upload");
+
+    let result = client
+        .append_to_upload(&location, 500, test_content(500))
+        .await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+    assert!(client.get_file("root/cancelled.bin", None).await.is_err());
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn uploads_belong_to_their_creator() {
+    let db_pool = create_test_db().await;
+    let mut owner = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut owner).await;
+    let mut other = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut other).await;
+
+    let location = owner
+        .create_upload("root/", "mine.bin", 10)
+        .await
+        .expect("Failed to create upload");
+
+    let result = other.append_to_upload(&location, 0, test_content(10)).await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+    let result = other.terminate_upload(&location).await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND)
+    );
+
+    assert_eq!(owner.upload_offset(&location).await.unwrap(), 0);
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn upload_to_existing_path_fails_early() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    // Files get a new version, but a directory can't be uploaded over
+    client
+        .upload_file("root/taken/", "file.txt", b"already here".to_vec())
+        .await
+        .expect("Failed to upload file");
+
+    let result = client.create_upload("root/", "taken", 10).await;
+    assert!(matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::CONFLICT));
+
+    // Missing file names are rejected
+    let result = client.create_upload("root/", "", 10).await;
+    assert!(
+        matches!(result, Err(ApiError::Http { status, .. }) if status == StatusCode::BAD_REQUEST)
+    );
+
+    cleanup_test_database(db_pool).await;
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{authenticate_random, blob_path, cleanup_test_database, create_test_db, sha256};
+use ocloud::api::{ApiClient, ApiError};
+use ocloud::config::SETTINGS;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn uploads_make_new_versions() {
+    let db_pool = create_test_db().await;
+    let mut alice = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut alice).await;
+    let mut bob = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut bob).await;
+
+    // Unique contents, since other tests share the files directory
+    let contents: Vec<Vec<u8>> = (0..3)
+        .map(|i| format!("version {i} {}", Uuid::new_v4()).into_bytes())
+        .collect();
+    for content in &contents {
+        alice
+            .upload_file("root/", "notes.txt", content.clone())
+            .await
+            .expect("Failed to upload over the file");
+    }
+    assert_eq!(
+        alice.get_file("root/notes.txt", None).await.unwrap(),
+        contents[2]
+    );
+
+    let versions = alice
+        .list_versions("root/notes.txt")
+        .await
+        .expect("Failed to list versions");
+    let hashes: Vec<&str> = versions.iter().map(|v| v.file_hash.as_str()).collect();
+    assert_eq!(
+        hashes,
+        [
+            sha256(&contents[2]),
+            sha256(&contents[1]),
+            sha256(&contents[0])
+        ]
+    );
+    assert!(versions[0].is_current);
+    assert!(!versions[1].is_current);
+
+    assert_eq!(
+        alice
+            .get_file_version("root/notes.txt", versions[2].id)
+            .await
+            .expect("Failed to get old version"),
+        contents[0]
+    );
+
+    // Old versions count towards the quota
+    let total: usize = contents.iter().map(Vec::len).sum();
+    assert_eq!(alice.usage().await.unwrap().used_bytes, total as u64);
+
+    // Rolling back is a new version, nothing in between is lost
+    alice
+        .rollback("root/notes.txt", versions[2].id)
+        .await
+        .expect("Failed to roll back");
+    assert_eq!(
+        alice.get_file("root/notes.txt", None).await.unwrap(),
+        contents[0]
+    );
+    let versions = alice.list_versions("root/notes.txt").await.unwrap();
+    assert_eq!(versions.len(), 4);
+    assert_eq!(versions[0].file_hash, sha256(&contents[0]));
+
+    // The same contents again aren't a new version
+    alice
+        .upload_file("root/", "notes.txt", contents[0].clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        alice.list_versions("root/notes.txt").await.unwrap().len(),
+        4
+    );
+
+    // Only the owner sees the history
+    assert!(matches!(
+        bob.list_versions("root/notes.txt").await,
+        Err(ApiError::Http { status, .. }) if status == StatusCode::NOT_FOUND
+    ));
+
+    // Purging the file takes every version with it
+    alice.delete_file("root/notes.txt").await.unwrap();
+    alice.purge_trash(None).await.unwrap();
+    for content in &contents {
+        assert!(!blob_path(content).exists());
+    }
+
+    cleanup_test_database(db_pool).await;
+}
+
+#[tokio::test]
+async fn old_versions_are_pruned() {
+    let db_pool = create_test_db().await;
+    let mut client = ApiClient::new_local(db_pool.clone()).await;
+    authenticate_random(&mut client).await;
+
+    let keep = SETTINGS.versions.keep_versions as usize;
+    assert!(keep > 0, "The tests expect a version limit");
+
+    let contents: Vec<Vec<u8>> = (0..keep + 2)
+        .map(|i| format!("revision {i} {}", Uuid::new_v4()).into_bytes())
+        .collect();
+    for content in &contents {
+        client
+            .upload_file("root/", "draft.txt", content.clone())
+            .await
+            .unwrap();
+    }
+
+    let versions = client.list_versions("root/draft.txt").await.unwrap();
+    assert_eq!(versions.len(), keep);
+    assert_eq!(
+        versions.last().unwrap().file_hash,
+        sha256(&contents[2]),
+        "The oldest versions should go first"
+    );
+    assert!(!blob_path(&contents[0]).exists());
+    assert!(!blob_path(&contents[1]).exists());
+    assert!(blob_path(&contents[2]).exists());
+
+    client.delete_file("root/draft.txt").await.unwrap();
+    client.purge_trash(None).await.unwrap();
+    cleanup_test_database(db_pool).await;
+}
@@ -15,6 +15,13 @@ interface SFileRaw {
     user_id?: number
 }
 
+// What a POST to /files returns
+interface UploadResultRaw {
+    files: SFileRaw[],
+    // Files that couldn't be stored, and why
+    errors: { name: string, error: string }[]
+}
+
 export interface SFile {
     id: number,
     referencesMediaId?: number,
@@ -251,7 +258,7 @@ export class MediaApi extends BaseClient {
         const uploadPath = dir.asDir();
         const formData = new FormData();
         formData.append(file.name, file);
-        const raw = await this.request<SFileRaw[]>(
+        const raw = await this.request<UploadResultRaw>(
             `/files/${uploadPath}`, 
             {
                 method: "POST",
@@ -259,15 +266,15 @@ export class MediaApi extends BaseClient {
             }
         );
 
-        if (!raw)
+        if (!raw || raw.files.length === 0)
             return null;
 
-        return sfile_from_raw(raw[0]);
+        return sfile_from_raw(raw.files[0]);
     }
 
     async mkDirs(dir: Path): Promise<SFile[] | null> {
         const uploadPath = dir.asDir();
-        const raw = await this.request<SFileRaw[]>(
+        const raw = await this.request<UploadResultRaw>(
             `/files/${uploadPath}`, 
             {
                 method: "POST",
@@ -277,7 +284,7 @@ export class MediaApi extends BaseClient {
         if (!raw)
             return null;
 
-        return raw.map(sfile_from_raw);
+        return raw.files.map(sfile_from_raw);
     }
 
     async moveFile(sourceFile: SFile, targetDir: Path): Promise<SFile | null> {